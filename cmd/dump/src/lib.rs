@@ -27,6 +27,19 @@
 //! humility: core resumed
 //! ```
 //!
+//! On a host with several debug probes attached, the probe to dump through
+//! can be selected with `-p` by its index or by its serial number; the
+//! selected probe is reported when attaching:
+//!
+//! ```console
+//! $ humility -p usb-0023004F3438510A33353739 dump
+//! humility: attached via ST-Link V3 (usb-1, serial 0023004F3438510A33353739)
+//! humility: core halted
+//! humility: dumping to hubris.core.0
+//! humility: dumped 1.12MB in 24 seconds
+//! humility: core resumed
+//! ```
+//!
 //! The resulting dump can be used with many commands (including `manifest`,
 //! `map`, `readvar`, and `tasks`) -- and need not be run on the same machine
 //! as the debugged MCU, e.g.:
@@ -64,10 +77,239 @@
 //! 25 idle                         0   8 RUNNING
 //! ```
 //!
+//! Reading a large dump from the dump agent can take some time, and a
+//! transient failure (e.g., a network blip) would otherwise require the dump
+//! to be read again from the beginning.  To guard against this, `--resume`
+//! records each dump area as it is read in a progress file (the dump file
+//! name with a `.progress` suffix); if the read fails, the dump can then be
+//! read with `--force-read --resume`, which will skip any areas already read.
+//! The progress file is removed once the dump has been successfully read.
+//!
+//! When taking a whole-system dump through the dump agent while attached
+//! with a debug probe (that is, with `--force-dump-agent`), the probe must be
+//! unplugged for the dump to proceed.  By default, the dump starts 10
+//! seconds after it is requested, with the remaining time counted down; the
+//! wait can be changed with `--unplug-wait` (e.g., to lengthen it, or to
+//! shorten it on a rig that disconnects the probe with a relay).  If the
+//! probe is seen to disconnect, `humility dump` reports how long remains
+//! before the dump starts and exits; reset the RoT via SWD once the dump is
+//! complete to re-attach, and then retrieve the dump with `humility dump`.
+//!
+//! The dumps held by the dump agent can be listed with `--list`; adding
+//! `--json` emits the list as a JSON array of records, each of which
+//! contains the area index, the task name (or `null` for a whole-system dump
+//! or an unknown task), the dump time, the size, and the contents type
+//! (`system`, `task`, or `task-region`).  Similarly, `--dump-agent-status
+//! --json` emits the raw header of each dump area as a JSON record that
+//! includes its address, length, bytes written, dumper, contents, number of
+//! segments, the address of the next area, and the task (if any) whose dump
+//! starts in the area.
+//!
+//! When only a portion of memory is of interest, the dump can be restricted
+//! to a comma-separated list of `start..end` address ranges with
+//! `--segment-filter`.  Any dump segment that partially overlaps a range is
+//! clipped to it, and segments outside of every range are omitted:
+//!
+//! ```console
+//! $ humility dump --segment-filter 0x24000000..0x24008000,0x30000000..0x30004000
+//! ```
+//!
+//! If the dump file is specified as `-`, the dump is written to standard
+//! output (with all other output going to standard error), allowing it to be
+//! piped elsewhere:
+//!
+//! ```console
+//! $ humility dump - | gzip > hubris.core.gz
+//! ```
+//!
+//! A dump of a single task taken with `--task` records the task's registers
+//! (as recovered from its saved state), allowing its stack to be unwound
+//! from the dump, e.g. with `humility -d hubris.core.net.0 tasks -s`.
+//!
+//! Rather than dumping an entire task with `--task`, one of a task's memory
+//! regions can be dumped with `--task-region`, which takes the task name and
+//! the index of the region among the task's writable memory regions.  If the
+//! index is invalid, the task's regions are listed along with their sizes:
+//!
+//! ```console
+//! $ humility dump --task-region net:1
+//! ```
+//!
+//! To dump every task at once (e.g., after a system-wide anomaly), use
+//! `--all-tasks`.  Each task other than the supervisor is dumped in turn
+//! (as with `--task`), with the dumps written to a directory (named by the
+//! dump file name, or `hubris.tasks.N` by default).  Any task that cannot
+//! be dumped is skipped with a warning:
+//!
+//! ```console
+//! $ humility dump --all-tasks
+//! humility: dumping all tasks to hubris.tasks.0
+//! humility: dumping to hubris.tasks.0/hubris.core.jefe
+//! ...
+//! humility: warning: skipping dump_agent: failed to dump task: ...
+//! ...
+//! humility: dumped 24 of 25 tasks to hubris.tasks.0
+//! ```
+//!
+//! Several dump areas can be read at once with `--areas`, which takes a
+//! comma-separated list of area indices; each area is written to its own dump
+//! file, named by suffixing the dump file name (or `hubris.core`) with
+//! `.area` and the area index (and, should that file already exist, a
+//! further numeric suffix).  Areas that are empty, or that continue a dump
+//! begun in an earlier area, are skipped with a warning:
+//!
+//! ```console
+//! $ humility dump --areas 1,3,4 crash
+//! humility: dumping area 1
+//! ...
+//! humility: dumping to crash.area1
+//! ...
+//! ```
+//!
+//! When reading a dump from the dump agent, `--manifest` will additionally
+//! write a JSON manifest alongside the dump file (with a `.json` suffix)
+//! that contains the archive's git revision, the task (if any) and time of
+//! the dump, the number of segments, the total and compressed sizes, the
+//! compression ratio, and whether the UDP or hiffy dump agent was used.
+//!
+//! By default, the contents of a dump read from the dump agent are held in
+//! memory until the dump file is written.  On a machine with less memory
+//! than the target's dumped footprint, `--max-segment-size` can be used to
+//! instead write contents to the dump file as they are read, holding no
+//! more than the specified number of bytes in memory at once.
+//!
+//! Reading a dump from the dump agent is done a chunk at a time.  A read
+//! that fails transiently (e.g., a lost packet with the UDP dump agent) is
+//! retried with exponential backoff, up to `--read-retries` times (3 by
+//! default).  How long to wait for each read is determined by
+//! `--timeout-per-area` (in milliseconds), which is independent of both the
+//! overall `--timeout` and the minute that is allowed for the dump agent to
+//! take a dump.  Note that these compose:  a read that keeps timing out will
+//! take roughly `--read-retries` + 1 times `--timeout-per-area` (plus
+//! backoff) to fail, so a lower per-area timeout with more retries favors
+//! recovering quickly from lost packets, while a higher one accommodates a
+//! slow agent:
+//!
+//! ```console
+//! $ humility dump --timeout-per-area 2000 --read-retries 8
+//! ```
+//!
+//! When attached directly to a target with a debug probe, `--verify` will,
+//! after the dump has been written, read a random sample of the dumped RAM
+//! regions (5% by default; see `--verify-fraction`) back from the target and
+//! compare them against the dump, warning of any mismatch.  Note that as
+//! the target runs once the dump has been taken, any memory that it has
+//! modified since will also be reported as a mismatch.  (Verification is
+//! skipped when reading back a dump already in situ with `--force-read` or
+//! `--area`, as there is no telling what the target has done since.)
+//!
+//! Two dumps of the same archive can be compared by specifying one with `-d`
+//! and the other with `--diff`.  Each range of memory that differs between
+//! the two dumps is reported along with the variable (or, failing that, the
+//! memory region) that contains it, followed by the differing contents side
+//! by side:
+//!
+//! ```console
+//! $ humility -d hubris.core.good dump --diff hubris.core.bad
+//! humility: attached to dump
+//! humility: comparing hubris.core.good with hubris.core.bad
+//! 0x24001a50-0x24001a58 (8 bytes): task_thermal::CONTROL_STATE+0x10
+//!     0x24001a50 | 01 00 00 00 2c 01 00 00 | 02 00 00 00 2c 01 00 00
+//! ...
+//! humility: 14 ranges differ (1.02KB)
+//! ```
+//!
+//! Each dump records a checksum of the contents of each of its segments.
+//! To detect a dump that has been corrupted since it was taken, specify it
+//! with `-d` and use `--check` to verify these checksums:
+//!
+//! ```console
+//! $ humility -d hubris.core.0 dump --check
+//! humility: attached to dump
+//! humility: segment at 0x24000000 (0x800 bytes) is corrupt: checksum is 0x4c1a2d0b; expected 0x9e07f1c3
+//! humility dump failed: 1 of 27 segments corrupt
+//! ```
+//!
+//! The in situ dump held by the dump agent is compressed segment by segment.
+//! To check that the dump agent's compression is sound, `--decompress-check`
+//! will, as the dump is read, verify that each segment decompresses cleanly
+//! to the length recorded in its header, reporting any that do not:
+//!
+//! ```console
+//! $ humility dump --force-dump-agent --force-read --decompress-check
+//! humility: attached via ST-Link V3
+//! humility: dump compressed 1.12MB to 412.08KB (35.9%)
+//! humility: all 27 segments decompressed cleanly
+//! humility: dumping to hubris.core.0
+//! humility: dumped 1.12MB in 11 seconds
+//! ```
+//!
+//! Dumps can be large; to compress a dump as it is written, use
+//! `--compress` to specify either `zstd` or `gzip`.  The extension for the
+//! specified algorithm is added to the name of the dump file:
+//!
+//! ```console
+//! $ humility dump --compress zstd
+//! humility: attached via ST-Link V3
+//! humility: dumping to hubris.core.0.zst
+//! humility: dumped 1.12MB in 24 seconds
+//! ```
+//!
+//! A compressed dump can be used with `-d` (or `HUMILITY_DUMP`) as-is;
+//! it will be decompressed transparently (albeit into memory):
+//!
+//! ```console
+//! $ humility -d hubris.core.0.zst tasks
+//! ```
+//!
+//! Dumps are written as ELF core files by default.  For consumption by tools
+//! that instead expect minidumps, use `--format minidump`; the dump will
+//! consist of the same memory, along with the register state of the core
+//! (for a whole-system dump) as the context of a single thread.  The
+//! extension `.dmp` is added to the name of the dump file:
+//!
+//! ```console
+//! $ humility dump --format minidump
+//! humility: attached via ST-Link V3
+//! humility: dumping minidump to hubris.core.0.dmp
+//! humility: dumped 1.12MB in 24 seconds
+//! ```
+//!
+//! Note that a minidump cannot be used with `-d`.
+//!
+//! When attached via a debug probe to a target without a dump agent (e.g.,
+//! a board in bring-up), the dump is taken by halting the target and
+//! reading its memory directly.  To ask for this explicitly -- a complete,
+//! uncompressed ELF core file of every dumpable segment, without involving
+//! the dump agent -- use `--stock`:
+//!
+//! ```console
+//! $ humility dump --stock
+//! humility: attached via ST-Link V3
+//! humility: core halted
+//! humility: dumping to hubris.core.0
+//! humility: dumped 1.12MB in 24 seconds
+//! humility: core resumed
+//! ```
+//!
+//! To simply read a range of memory from the target and write it out as raw
+//! bytes, use `--range` to specify the range (as `start..end`) and
+//! `--output` to specify the file.  The core is halted while the range is
+//! read.  This requires neither an archive nor a dump agent, and is
+//! therefore useful when the target's archive isn't at hand:
+//!
+//! ```console
+//! $ humility dump --range 0x30000000..0x30020000 --output sram1.bin
+//! humility: attached via ST-Link V3
+//! humility: core halted
+//! humility: read 128.00KB from 0x30000000 to sram1.bin
+//! humility: core resumed
+//! ```
+//!
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{ArgGroup, CommandFactory, Parser};
-use humility::core::Core;
+use humility::core::{Core, CORE_REG_MAX};
 use humility::hubris::*;
 use humility_arch_arm::ARMRegister;
 use humility_cli::{ExecutionContext, Subcommand};
@@ -78,9 +320,17 @@ use humility_dump_agent::{
 };
 use humpty::DumpTask;
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
-use num_traits::FromPrimitive;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rand::seq::SliceRandom;
+use rand::Rng;
 use std::cell::RefCell;
-use std::time::Instant;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+mod minidump;
 
 #[derive(Clone, Parser, Debug)]
 #[clap(
@@ -115,6 +365,14 @@ struct DumpArgs {
     )]
     force_manual_initiation: bool,
 
+    /// seconds to wait for the probe to be unplugged before taking a dump
+    #[clap(
+        long, requires = "force-dump-agent", default_value_t = 10,
+        value_name = "seconds", conflicts_with = "force-manual-initiation",
+        parse(try_from_str = parse_int::parse)
+    )]
+    unplug_wait: u64,
+
     /// force existing in situ dump to be read
     #[clap(long, conflicts_with_all = &["simulation", "task", "all"])]
     force_read: bool,
@@ -161,6 +419,29 @@ struct DumpArgs {
     )]
     task: Option<String>,
 
+    /// dumps one of a task's memory regions, specified as task:index
+    #[clap(
+        long, value_name = "task:index",
+        parse(try_from_str = parse_task_region),
+        conflicts_with_all = &[
+            "emulate-dumper", "simulate-task-dump", "task", "list", "area",
+            "areas", "all", "dump-agent-status", "segment-filter",
+            "max-segment-size",
+        ]
+    )]
+    task_region: Option<TaskRegion>,
+
+    /// dumps every task, each to its own dump file within a directory
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "simulation", "task", "task-region", "all", "area", "areas",
+            "list", "dump-agent-status", "segment-filter", "resume", "verify",
+            "max-segment-size", "manifest", "force-read",
+        ]
+    )]
+    all_tasks: bool,
+
     /// extracts every available dump
     #[clap(
         long,
@@ -171,6 +452,16 @@ struct DumpArgs {
     #[clap(short, long, conflicts_with_all = &["simulation", "list"])]
     area: Option<usize>,
 
+    /// reads a comma-separated list of areas, each to its own dump file
+    #[clap(
+        long, value_name = "areas", use_value_delimiter = true,
+        conflicts_with_all = &[
+            "simulation", "list", "area", "task", "all", "dump-agent-status",
+            "segment-filter", "resume", "verify", "max-segment-size",
+        ]
+    )]
+    areas: Option<Vec<usize>>,
+
     /// leave the target halted
     #[clap(long, conflicts_with = "simulation")]
     leave_halted: bool,
@@ -178,146 +469,899 @@ struct DumpArgs {
     #[clap(long, short, conflicts_with_all = &["simulation", "area"])]
     list: bool,
 
-    dumpfile: Option<String>,
-}
+    /// generate JSON output (with --list or --dump-agent-status)
+    #[clap(long)]
+    json: bool,
 
-////////////////////////////////////////////////////////////////////////////////
+    /// restrict dump to a comma-separated list of start..end address ranges
+    #[clap(
+        long, value_name = "ranges",
+        parse(try_from_str = parse_segment_filter),
+        conflicts_with_all = &["task", "all", "list", "dump-agent-status"]
+    )]
+    segment_filter: Option<SegmentFilter>,
 
-fn emulate_dump(
-    core: &mut dyn Core,
-    task: Option<DumpTask>,
-    base: u32,
-    total: u32,
-) -> Result<()> {
-    let shared = RefCell::new(core);
-    let started = Instant::now();
-    let bar = ProgressBar::new(total as u64);
-    let mut nread = 0;
-    let mut nwritten = 0;
+    /// record progress while reading a dump, resuming any previous attempt
+    #[clap(
+        long,
+        conflicts_with_all = &["simulate-dumper", "task", "all", "list"]
+    )]
+    resume: bool,
 
-    bar.set_style(ProgressStyle::default_bar().template(
-        "humility: dumping in situ [{bar:30}] {bytes}/{total_bytes}",
-    ));
+    /// number of reads to have in flight at once with the UDP dump agent
+    #[clap(
+        long, default_value_t = 4, value_name = "count",
+        parse(try_from_str = parse_int::parse)
+    )]
+    inflight: usize,
 
-    let mut rnum = 0;
+    /// number of times to retry a dump read that fails transiently
+    #[clap(
+        long, default_value_t = 3, value_name = "retries",
+        parse(try_from_str = parse_int::parse)
+    )]
+    read_retries: u32,
 
-    let r = humpty::dump::<anyhow::Error, 2048, { humpty::DUMPER_EMULATED }>(
-        base,
-        task,
-        || {
-            let start = rnum;
+    /// timeout for each read of the dump areas from the dump agent
+    /// (defaults to --timeout for the hiffy agent)
+    #[clap(
+        long, value_name = "timeout_ms",
+        parse(try_from_str = parse_int::parse)
+    )]
+    timeout_per_area: Option<u32>,
 
-            for i in start..=31 {
-                if let Some(reg) = ARMRegister::from_u16(i) {
-                    let val = shared.borrow_mut().read_reg(reg)?;
-                    rnum = i + 1;
-                    return Ok(Some(humpty::RegisterRead(i, val)));
-                }
-            }
-            Ok(None)
-        },
-        |addr, buf, _meta| {
-            nread += buf.len();
-            bar.set_position(nread as u64);
-            shared.borrow_mut().read_8(addr, buf)
-        },
-        |addr, buf| {
-            nwritten += buf.len();
-            shared.borrow_mut().write_8(addr, buf)
-        },
-    );
+    /// after writing the dump, compare a sample of it against the target
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "dump-agent-status", "simulation"]
+    )]
+    verify: bool,
 
-    bar.finish_and_clear();
+    /// fraction of dumped regions to compare when verifying
+    #[clap(
+        long, requires = "verify", default_value_t = 0.05,
+        value_name = "fraction", parse(try_from_str = parse_fraction)
+    )]
+    verify_fraction: f64,
 
-    humility::msg!(
-        "dumped {} in situ (compressed to {}) in {}",
-        HumanBytes(total as u64),
-        HumanBytes(nwritten as u64),
-        HumanDuration(started.elapsed())
-    );
+    /// write a JSON manifest describing the dump alongside the dump file
+    #[clap(long, conflicts_with_all = &["simulation", "list", "dump-agent-status"])]
+    manifest: bool,
 
-    if let Err(e) = r {
-        bail!("dump failed: {:x?}", e);
-    }
+    /// write dump contents to the dump file as they are read, holding no
+    /// more than the specified number of bytes in memory
+    #[clap(
+        long, value_name = "bytes",
+        parse(try_from_str = parse_int::parse),
+        conflicts_with_all = &[
+            "simulation", "task", "all", "list", "area", "force-read",
+            "dump-agent-status", "resume", "verify",
+            "initialize-dump-agent", "force-manual-initiation",
+        ]
+    )]
+    max_segment_size: Option<usize>,
 
-    Ok(())
+    /// compare the dump specified with -d against the specified dump
+    #[clap(
+        long, value_name = "dumpfile",
+        conflicts_with_all = &[
+            "simulation", "task", "task-region", "all", "area", "areas",
+            "list", "dump-agent-status", "manifest", "dumpfile",
+        ]
+    )]
+    diff: Option<String>,
+
+    /// verify the checksums of the segments of the dump specified with -d
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "simulation", "task", "task-region", "all", "area", "areas",
+            "list", "dump-agent-status", "manifest", "dumpfile", "diff",
+        ]
+    )]
+    check: bool,
+
+    /// check that each segment of an in situ dump decompresses to the
+    /// length recorded in its header
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "simulation", "task-region", "list", "dump-agent-status", "diff",
+            "check",
+        ]
+    )]
+    decompress_check: bool,
+
+    /// compress the dump file with the specified algorithm, adding the
+    /// corresponding extension to the dump file name
+    #[clap(
+        long, arg_enum, value_name = "algorithm",
+        conflicts_with_all = &[
+            "list", "dump-agent-status", "max-segment-size", "diff",
+            "check",
+        ]
+    )]
+    compress: Option<DumpCompression>,
+
+    /// write the dump in the specified format
+    #[clap(
+        long, arg_enum, value_name = "format", default_value_t = Format::Elf,
+        conflicts_with_all = &[
+            "list", "dump-agent-status", "max-segment-size", "diff",
+            "check", "compress",
+        ]
+    )]
+    format: Format,
+
+    /// read the specified start..end range of memory directly from the
+    /// target, writing it as raw bytes to the file specified by --output
+    #[clap(
+        long, value_name = "start..end", requires = "output",
+        parse(try_from_str = parse_range),
+        conflicts_with_all = &[
+            "simulation", "task", "task-region", "all-tasks", "all", "area",
+            "areas", "list", "dump-agent-status", "segment-filter", "resume",
+            "verify", "manifest", "max-segment-size", "diff", "check",
+            "decompress-check", "compress", "force-dump-agent",
+            "force-read", "initialize-dump-agent", "dumpfile",
+        ]
+    )]
+    range: Option<(u32, u32)>,
+
+    /// take a complete, uncompressed dump by reading memory directly via
+    /// the debug probe, without involving the dump agent
+    #[clap(
+        long, alias = "stock-only",
+        conflicts_with_all = &[
+            "simulation", "force-dump-agent", "force-read", "area", "areas",
+            "all", "list", "task", "task-region", "all-tasks",
+            "dump-agent-status", "initialize-dump-agent", "segment-filter",
+            "resume", "max-segment-size", "compress", "format", "diff",
+            "check", "decompress-check", "range",
+        ]
+    )]
+    stock: bool,
+
+    /// file to which to write the memory read with --range
+    #[clap(long, value_name = "filename", requires = "range")]
+    output: Option<String>,
+
+    dumpfile: Option<String>,
 }
 
-fn emulate_task_dump_prep(
-    core: &mut dyn Core,
-    segments: &Vec<(u32, u32)>,
-    base: u32,
-) -> Result<u32> {
-    let shared = RefCell::new(core);
+#[derive(clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Format {
+    Elf,
+    Minidump,
+}
 
-    let area = match humpty::claim_dump_area::<anyhow::Error>(
-        base,
-        humpty::DumpContents::SingleTask,
-        |addr, buf, _meta| shared.borrow_mut().read_8(addr, buf),
-        |addr, buf| shared.borrow_mut().write_8(addr, buf),
-    ) {
-        Ok(area) => area,
-        Err(e) => {
-            bail!("dump area allocation failed: {:x?}", e);
-        }
-    };
+fn parse_fraction(fraction: &str) -> Result<f64> {
+    let fraction = fraction.parse::<f64>()?;
 
-    let area = match area {
-        Some(area) => area,
-        None => {
-            bail!("no dump area is available");
-        }
-    };
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        bail!("fraction must be greater than 0 and no more than 1");
+    }
 
-    let mut total = 0;
+    Ok(fraction)
+}
 
-    for (base, size) in segments {
-        total += size;
+fn parse_range(range: &str) -> Result<(u32, u32)> {
+    let (start, end) = range.split_once("..").ok_or_else(|| {
+        anyhow!("range \"{range}\" must be of the form start..end")
+    })?;
 
-        if let Err(e) = humpty::add_dump_segment_header::<anyhow::Error>(
-            area.region.address,
-            *base,
-            *size,
-            |addr, buf, _meta| shared.borrow_mut().read_8(addr, buf),
-            |addr, buf| shared.borrow_mut().write_8(addr, buf),
-        ) {
-            bail!("adding segment at {base:#x} (length {size}) failed: {e:x?}");
-        }
+    let start = parse_int::parse::<u32>(start.trim())
+        .with_context(|| format!("invalid start in \"{range}\""))?;
+    let end = parse_int::parse::<u32>(end.trim())
+        .with_context(|| format!("invalid end in \"{range}\""))?;
+
+    if end <= start {
+        bail!("range \"{range}\" is empty");
     }
 
-    Ok(area.region.address)
+    Ok((start, end))
 }
 
-fn get_dump_agent<'a>(
-    hubris: &'a HubrisArchive,
-    core: &'a mut dyn Core,
-    subargs: &DumpArgs,
-) -> Result<Box<dyn DumpAgent + 'a>> {
-    // Find the dump agent task name.  This is usually `dump_agent`, but that's
-    // not guaranteed; what *is* guaranteed is that it implements the DumpAgent
-    // interface.
-    let dump_agent_task =
-        hubris.lookup_module_by_iface("DumpAgent").map(|t| t.task);
+/// A task and the index of one of its memory regions
+#[derive(Clone, Debug)]
+struct TaskRegion {
+    task: String,
+    index: usize,
+}
 
-    if core.is_net()
-        && !subargs.force_hiffy_agent
-        && dump_agent_task
-            .map(|t| hubris.does_task_have_feature(t, "net").unwrap())
-            .unwrap_or(false)
-    {
-        humility::msg!("using UDP dump agent");
+fn parse_task_region(spec: &str) -> Result<TaskRegion> {
+    let (task, index) = spec
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("task region must be of the form task:index"))?;
 
-        let imageid = &hubris
-            .imageid
-            .as_ref()
-            .ok_or_else(|| anyhow!("missing image ID"))?
-            .1;
+    let index = parse_int::parse::<usize>(index)
+        .with_context(|| format!("invalid region index \"{index}\""))?;
 
-        Ok(Box::new(UdpDumpAgent::new(core, imageid)?))
-    } else {
-        humility::msg!("using hiffy dump agent");
-        Ok(Box::new(HiffyDumpAgent::new(hubris, core, subargs.timeout)?))
-    }
+    Ok(TaskRegion { task: task.to_owned(), index })
+}
+
+/// A set of sorted, non-overlapping `(start, end)` address ranges
+#[derive(Clone, Debug)]
+struct SegmentFilter(Vec<(u32, u32)>);
+
+fn parse_segment_filter(filter: &str) -> Result<SegmentFilter> {
+    let mut ranges =
+        filter.split(',').map(parse_range).collect::<Result<Vec<_>>>()?;
+
+    ranges.sort_unstable();
+
+    //
+    // Coalesce any overlapping ranges so that we never emit the same memory
+    // in more than one segment.
+    //
+    let mut rval: Vec<(u32, u32)> = vec![];
+
+    for (start, end) in ranges {
+        match rval.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => rval.push((start, end)),
+        }
+    }
+
+    Ok(SegmentFilter(rval))
+}
+
+impl SegmentFilter {
+    /// Clips the given `(start, size)` segments to our ranges, dropping any
+    /// segment that falls entirely outside of them.
+    fn apply(&self, segments: &[(u32, u32)]) -> Result<Vec<(u32, u32)>> {
+        let mut rval = vec![];
+
+        for &(base, size) in segments {
+            let limit = base as u64 + size as u64;
+
+            for &(start, end) in &self.0 {
+                let lo = base.max(start);
+                let hi = limit.min(end as u64);
+
+                if (lo as u64) < hi {
+                    rval.push((lo, (hi - lo as u64) as u32));
+                }
+            }
+        }
+
+        if rval.is_empty() {
+            bail!("segment filter does not intersect any dump segment");
+        }
+
+        Ok(rval)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+//
+// Resolves a task region to the index of its task and the base and size of
+// the region, which is indexed among the task's writable memory regions.
+//
+fn lookup_task_region(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    spec: &TaskRegion,
+) -> Result<(u32, (u32, u32))> {
+    let ndx = match hubris.lookup_task(&spec.task) {
+        Some(HubrisTask::Task(ndx)) => *ndx,
+        _ => {
+            bail!("invalid task \"{}\"", spec.task);
+        }
+    };
+
+    if ndx == 0 {
+        bail!("cannot dump supervisor");
+    }
+
+    let t = HubrisTask::Task(ndx);
+
+    let regions = hubris
+        .regions(core)?
+        .into_values()
+        .filter(|r| !r.attr.device && !r.attr.external && r.attr.write)
+        .filter(|r| r.tasks.contains(&t))
+        .map(|r| (r.base, r.size))
+        .collect::<Vec<_>>();
+
+    match regions.get(spec.index) {
+        Some(&region) => Ok((ndx, region)),
+        None => {
+            humility::msg!("{} has {} regions:", spec.task, regions.len());
+
+            for (i, (base, size)) in regions.iter().enumerate() {
+                humility::msg!("{i:>4} {base:#010x} {size:>8} bytes");
+            }
+
+            bail!("region {} is invalid for {}", spec.index, spec.task);
+        }
+    }
+}
+
+//
+// Returns our dump segments, subject to any segment filter.
+//
+fn dump_segments(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: Option<DumpTask>,
+    include_nonwritable: bool,
+    subargs: &DumpArgs,
+) -> Result<Vec<(u32, u32)>> {
+    let segments = hubris.dump_segments(core, task, include_nonwritable)?;
+
+    match &subargs.segment_filter {
+        Some(filter) => filter.apply(&segments),
+        None => Ok(segments),
+    }
+}
+
+//
+// Returns the name of the file to which a dump is to be written, with an
+// extension denoting any requested compression (or, for a minidump, the
+// minidump extension).
+//
+fn dump_filename(
+    hubris: &HubrisArchive,
+    task: Option<DumpTask>,
+    dumpfile: Option<&str>,
+    subargs: &DumpArgs,
+) -> Result<String> {
+    let filename = hubris.dump_filename(task, dumpfile, subargs.compress)?;
+
+    Ok(match subargs.format {
+        Format::Minidump
+            if filename != "-"
+                && std::path::Path::new(&filename).extension()
+                    != Some(std::ffi::OsStr::new("dmp")) =>
+        {
+            format!("{filename}.dmp")
+        }
+        _ => filename,
+    })
+}
+
+//
+// Writes a dump consisting of the specified segments in the requested
+// format.  ELF dumps are written by the archive; minidumps are written by
+// us, but are otherwise treated in the same way.
+//
+fn write_dump_segments(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: Option<DumpTask>,
+    segments: &[(u32, u32)],
+    dumpfile: &str,
+    started: Option<Instant>,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    use std::io::Write;
+
+    if subargs.format == Format::Elf {
+        return hubris.dump_with_segments(
+            core,
+            task,
+            segments,
+            Some(dumpfile),
+            started,
+        );
+    }
+
+    //
+    // As with an ELF dump, we only have registers for a whole-system dump.
+    //
+    let regs = match task {
+        Some(_) => None,
+        None => Some(
+            core.read_reg_all()?
+                .into_iter()
+                .map(|(reg, val)| (reg.to_u16().unwrap(), val))
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    let started = started.unwrap_or_else(Instant::now);
+    let total = segments.iter().map(|(_, size)| *size as u64).sum::<u64>();
+
+    let mut out: Box<dyn Write> = if dumpfile == "-" {
+        humility::msg!("dumping minidump to standard output");
+        Box::new(std::io::BufWriter::new(std::io::stdout().lock()))
+    } else {
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dumpfile)?;
+        humility::msg!("dumping minidump to {dumpfile}");
+        Box::new(std::io::BufWriter::new(file))
+    };
+
+    minidump::write_minidump(core, regs.as_deref(), segments, &mut out)?;
+    out.flush()?;
+
+    humility::msg!(
+        "dumped {} in {}",
+        HumanBytes(total),
+        HumanDuration(started.elapsed())
+    );
+
+    Ok(())
+}
+
+fn write_dump(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task: Option<DumpTask>,
+    dumpfile: Option<&str>,
+    started: Option<Instant>,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    let segments = dump_segments(hubris, core, task, true, subargs)?;
+    let dumpfile = dump_filename(hubris, task, dumpfile, subargs)?;
+    write_dump_segments(
+        hubris, core, task, &segments, &dumpfile, started, subargs,
+    )
+}
+
+//
+// Returns the name of the file used to record progress when reading a dump
+// with --resume.
+//
+fn progress_file(subargs: &DumpArgs) -> PathBuf {
+    let dumpfile = match subargs.dumpfile.as_deref() {
+        Some("-") | None => "hubris.core",
+        Some(dumpfile) => dumpfile,
+    };
+
+    PathBuf::from(format!("{dumpfile}.progress"))
+}
+
+//
+// Reports how well the in situ dump compressed, warning if it compressed
+// poorly enough that a future dump could overflow the dump region.
+//
+fn report_compression(out: &DumpAgentCore) {
+    let (compressed, uncompressed) = out.compression();
+
+    if uncompressed == 0 {
+        return;
+    }
+
+    let ratio = compressed as f64 / uncompressed as f64;
+
+    humility::msg!(
+        "dump compressed {} to {} ({:.1}%)",
+        HumanBytes(uncompressed as u64),
+        HumanBytes(compressed as u64),
+        ratio * 100.0
+    );
+
+    if ratio > 0.9 {
+        humility::warn!(
+            "memory compressed poorly; a future dump may overflow \
+            the dump region"
+        );
+    }
+}
+
+//
+// Reports any in situ segments that failed to decompress cleanly, failing if
+// there were any.
+//
+fn report_decompression(out: &DumpAgentCore) -> Result<()> {
+    let failures = out.decompression_failures();
+
+    for failure in failures {
+        humility::warn!("{failure}");
+    }
+
+    if !failures.is_empty() {
+        bail!(
+            "{} decompression failure{} in {} segments",
+            failures.len(),
+            if failures.len() == 1 { "" } else { "s" },
+            out.nsegments()
+        );
+    }
+
+    humility::msg!("all {} segments decompressed cleanly", out.nsegments());
+    Ok(())
+}
+
+//
+// An interrupt shouldn't leave the target halted, so while we have it halted,
+// our SIGINT handler merely notes the interrupt (which our loops check for)
+// and leaves it to us to resume the target before we exit.  The same is true
+// while we are counting down the wait for the probe to be unplugged, so that
+// we can explain what an interrupt there does and doesn't do.  Otherwise, the
+// handler exits immediately.
+//
+static HALTED: AtomicBool = AtomicBool::new(false);
+static WAITING: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn halt(core: &mut dyn Core) -> Result<()> {
+    core.halt()?;
+    HALTED.store(true, Ordering::SeqCst);
+    humility::msg!("core halted");
+    Ok(())
+}
+
+fn resume(core: &mut dyn Core) -> Result<()> {
+    core.run()?;
+    HALTED.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+fn check_interrupted() -> Result<()> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        bail!("interrupted");
+    }
+
+    Ok(())
+}
+
+fn emulate_dump(
+    core: &mut dyn Core,
+    task: Option<DumpTask>,
+    base: u32,
+    total: u32,
+) -> Result<()> {
+    let regs = core.read_reg_all()?.into_iter().collect::<Vec<_>>();
+    let shared = RefCell::new(core);
+    let started = Instant::now();
+    let bar = ProgressBar::new(total as u64);
+    let mut nread = 0;
+    let mut nwritten = 0;
+
+    bar.set_style(ProgressStyle::default_bar().template(
+        "humility: dumping in situ [{bar:30}] {bytes}/{total_bytes}",
+    ));
+
+    let mut rnum = 0;
+
+    let r = humpty::dump::<anyhow::Error, 2048, { humpty::DUMPER_EMULATED }>(
+        base,
+        task,
+        || {
+            let Some(&(reg, val)) = regs.get(rnum) else {
+                return Ok(None);
+            };
+
+            rnum += 1;
+            Ok(Some(humpty::RegisterRead(reg.to_u16().unwrap(), val)))
+        },
+        |addr, buf, _meta| {
+            check_interrupted()?;
+            nread += buf.len();
+            bar.set_position(nread as u64);
+            shared.borrow_mut().read_8(addr, buf)
+        },
+        |addr, buf| {
+            nwritten += buf.len();
+            shared.borrow_mut().write_8(addr, buf)
+        },
+    );
+
+    bar.finish_and_clear();
+
+    humility::msg!(
+        "dumped {} in situ (compressed to {}) in {}",
+        HumanBytes(total as u64),
+        HumanBytes(nwritten as u64),
+        HumanDuration(started.elapsed())
+    );
+
+    if let Err(e) = r {
+        bail!("dump failed: {:x?}", e);
+    }
+
+    Ok(())
+}
+
+//
+// Estimates the size of an in situ dump of the specified segments by
+// compressing a sample of each of a few of them, and assuming that the
+// entire dump compresses as poorly as the worst of these.  This includes the
+// overhead of the segment headers and of the header of each compressed chunk.
+//
+fn estimate_dump_size(
+    core: &mut dyn Core,
+    segments: &[(u32, u32)],
+) -> Result<u32> {
+    const NSAMPLES: usize = 4;
+    const SAMPLE_SIZE: usize = 16 * 1024;
+
+    let mut bytes = vec![0; 1024];
+    let input_len = (bytes.len() / 2) - (bytes.len() / 8);
+    let stride = std::cmp::max(segments.len() / NSAMPLES, 1);
+    let mut worst = 0.0f64;
+
+    for (base, size) in segments.iter().step_by(stride).take(NSAMPLES) {
+        let mut remain = std::cmp::min(*size as usize, SAMPLE_SIZE);
+        let mut addr = *base;
+        let (mut nread, mut ncompressed) = (0, 0);
+
+        while remain > 0 {
+            let nbytes = std::cmp::min(remain, input_len);
+            let offs = bytes.len() - nbytes;
+            let len = bytes.len();
+
+            core.read_8(addr, &mut bytes[offs..len])?;
+
+            let (compressed, _) =
+                humpty::DumpLzss::compress_in_place(&mut bytes, offs);
+
+            ncompressed += compressed;
+            nread += nbytes;
+            remain -= nbytes;
+            addr += nbytes as u32;
+        }
+
+        if nread > 0 {
+            worst = worst.max(ncompressed as f64 / nread as f64);
+        }
+    }
+
+    let total = segments.iter().map(|(_, size)| *size as usize).sum::<usize>();
+    let nchunks = (total + input_len - 1) / input_len;
+
+    let overhead = segments.len()
+        * std::mem::size_of::<humpty::DumpSegmentHeader>()
+        + nchunks * (std::mem::size_of::<humpty::DumpSegmentData>() + 3);
+
+    Ok((total as f64 * worst) as u32 + overhead as u32)
+}
+
+fn emulate_task_dump_prep(
+    core: &mut dyn Core,
+    segments: &Vec<(u32, u32)>,
+    base: u32,
+) -> Result<u32> {
+    let shared = RefCell::new(core);
+
+    let area = match humpty::claim_dump_area::<anyhow::Error>(
+        base,
+        humpty::DumpContents::SingleTask,
+        |addr, buf, _meta| shared.borrow_mut().read_8(addr, buf),
+        |addr, buf| shared.borrow_mut().write_8(addr, buf),
+    ) {
+        Ok(area) => area,
+        Err(e) => {
+            bail!("dump area allocation failed: {:x?}", e);
+        }
+    };
+
+    let area = match area {
+        Some(area) => area,
+        None => {
+            bail!("no dump area is available");
+        }
+    };
+
+    let mut total = 0;
+
+    for (base, size) in segments {
+        total += size;
+
+        if let Err(e) = humpty::add_dump_segment_header::<anyhow::Error>(
+            area.region.address,
+            *base,
+            *size,
+            |addr, buf, _meta| shared.borrow_mut().read_8(addr, buf),
+            |addr, buf| shared.borrow_mut().write_8(addr, buf),
+        ) {
+            bail!("adding segment at {base:#x} (length {size}) failed: {e:x?}");
+        }
+    }
+
+    Ok(area.region.address)
+}
+
+//
+// Compare a random sample of the RAM regions that we pulled from the dump
+// agent against the target itself.  This is intended to catch corruption in
+// compression or in transport -- but because the target has been running
+// since the dump was taken, memory that it has since modified will also be
+// flagged, so mismatches are warned about rather than being fatal.
+//
+fn verify_dump(
+    core: &mut dyn Core,
+    out: &DumpAgentCore,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    if core.is_dump() || core.is_archive() {
+        humility::msg!("not attached to a live target; skipping verification");
+        return Ok(());
+    }
+
+    if core.is_net() {
+        humility::msg!(
+            "cannot verify dump over the network; skipping verification"
+        );
+        return Ok(());
+    }
+
+    let mut rng = rand::thread_rng();
+    let regions = out.ram_regions().collect::<Vec<_>>();
+
+    let mut sample = regions
+        .iter()
+        .filter(|_| rng.gen_bool(subargs.verify_fraction))
+        .collect::<Vec<_>>();
+
+    //
+    // Always verify at least one region, lest a small dump go unverified.
+    //
+    if sample.is_empty() {
+        match regions.choose(&mut rng) {
+            Some(region) => sample.push(region),
+            None => {
+                humility::msg!("dump contains no RAM regions to verify");
+                return Ok(());
+            }
+        }
+    }
+
+    let max = humility::core::CORE_MAX_READSIZE;
+    let mut buf = vec![0u8; max];
+    let mut nbytes = 0;
+    let mut nmismatches = 0;
+
+    for (base, contents) in &sample {
+        for (i, chunk) in contents.chunks(max).enumerate() {
+            let addr = base + (i * max) as u32;
+            let data = &mut buf[..chunk.len()];
+
+            core.read_8(addr, data)
+                .with_context(|| format!("failed to read {addr:#x}"))?;
+
+            if let Some(first) =
+                chunk.iter().zip(data.iter()).position(|(a, b)| a != b)
+            {
+                let ndiffs = chunk
+                    .iter()
+                    .zip(data.iter())
+                    .filter(|(a, b)| a != b)
+                    .count();
+
+                humility::warn!(
+                    "mismatch at {:#x}: {ndiffs} of {} bytes differ \
+                    (dump has {:#04x}, target has {:#04x})",
+                    addr + first as u32,
+                    chunk.len(),
+                    chunk[first],
+                    data[first],
+                );
+
+                nmismatches += 1;
+            }
+
+            nbytes += chunk.len();
+        }
+    }
+
+    if nmismatches > 0 {
+        humility::warn!(
+            "dump verification found {nmismatches} mismatches; if the \
+            target has modified this memory since the dump was taken, \
+            these are to be expected"
+        );
+        return Ok(());
+    }
+
+    humility::msg!(
+        "verified {} in {} of {} regions",
+        HumanBytes(nbytes as u64),
+        sample.len(),
+        regions.len()
+    );
+
+    Ok(())
+}
+
+//
+// Returns true if we will use the UDP dump agent, false if hiffy.
+//
+fn use_udp_agent(
+    hubris: &HubrisArchive,
+    core: &dyn Core,
+    subargs: &DumpArgs,
+) -> bool {
+    // Find the dump agent task name.  This is usually `dump_agent`, but that's
+    // not guaranteed; what *is* guaranteed is that it implements the DumpAgent
+    // interface.
+    let dump_agent_task =
+        hubris.lookup_module_by_iface("DumpAgent").map(|t| t.task);
+
+    core.is_net()
+        && !subargs.force_hiffy_agent
+        && dump_agent_task
+            .map(|t| hubris.does_task_have_feature(t, "net").unwrap())
+            .unwrap_or(false)
+}
+
+//
+// Writes a JSON manifest describing a dump that we have read from the dump
+// agent alongside the dump file.
+//
+fn write_manifest(
+    hubris: &HubrisArchive,
+    out: &DumpAgentCore,
+    task: Option<DumpTask>,
+    dumpfile: &str,
+    agent: &str,
+) -> Result<()> {
+    let (compressed, uncompressed) = out.compression();
+
+    let task = task.map(|task| {
+        let (id, time) = (task.id, task.time);
+        serde_json::json!({ "id": id, "time": time })
+    });
+
+    let ratio = if uncompressed > 0 {
+        Some(compressed as f64 / uncompressed as f64)
+    } else {
+        None
+    };
+
+    let manifest = serde_json::json!({
+        "gitrev": hubris.manifest.gitrev,
+        "task": task,
+        "segments": out.nsegments(),
+        "bytes": uncompressed,
+        "compressed": compressed,
+        "ratio": ratio,
+        "agent": agent,
+    });
+
+    let filename = format!("{dumpfile}.json");
+    let file = std::fs::File::create(&filename)
+        .with_context(|| format!("failed to create {filename}"))?;
+
+    serde_json::to_writer_pretty(file, &manifest)?;
+    humility::msg!("wrote manifest to {filename}");
+
+    Ok(())
+}
+
+fn get_dump_agent<'a>(
+    hubris: &'a HubrisArchive,
+    core: &'a mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<Box<dyn DumpAgent + 'a>> {
+    if use_udp_agent(hubris, core, subargs) {
+        humility::msg!("using UDP dump agent");
+
+        let imageid = &hubris
+            .imageid
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing image ID"))?
+            .1;
+
+        let mut agent = UdpDumpAgent::new(core, imageid)?;
+        agent.set_inflight(subargs.inflight);
+        agent.set_read_retries(subargs.read_retries);
+
+        if let Some(timeout) = subargs.timeout_per_area {
+            agent.set_read_timeout(Duration::from_millis(timeout.into()));
+        }
+
+        Ok(Box::new(agent))
+    } else {
+        humility::msg!("using hiffy dump agent");
+        let mut agent = HiffyDumpAgent::new(hubris, core, subargs.timeout)?;
+        agent.set_read_retries(subargs.read_retries);
+
+        if let Some(timeout) = subargs.timeout_per_area {
+            agent.set_read_timeout(timeout);
+        }
+
+        agent.set_unplug_wait(Duration::from_secs(subargs.unplug_wait));
+        agent.set_interrupt_check(|| INTERRUPTED.load(Ordering::SeqCst));
+
+        Ok(Box::new(agent))
+    }
 }
 
 fn dump_via_agent(
@@ -325,10 +1369,23 @@ fn dump_via_agent(
     core: &mut dyn Core,
     subargs: &DumpArgs,
 ) -> Result<()> {
+    //
+    // Note that we always retrieve the dump in its entirety:  there is no
+    // way to take a dump incrementally against a baseline dump.  The dump
+    // is taken by the dumper on the target and retrieved as compressed dump
+    // areas; to skip pages that are unchanged relative to a baseline, the
+    // target would need to hash its memory page by page, and neither the
+    // dump agent nor hiffy offers such an operation.  (Reading each page to
+    // determine that it's unchanged would defeat the purpose -- and flash
+    // is already taken from the archive rather than from the target.)
+    //
     let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
     let started = Some(Instant::now());
     let mut area = subargs.area.map(DumpArea::ByIndex);
 
+    let agent_kind =
+        if use_udp_agent(hubris, core, subargs) { "udp" } else { "hiffy" };
+
     //
     // Our task can come from a couple of different spots:  we can either
     // be explicitly told our task (in which case we are simulated or
@@ -353,17 +1410,29 @@ fn dump_via_agent(
         None => None,
     };
 
+    //
+    // If we're simulating a dump of a task region, our task is that of the
+    // region -- and the region is the only segment in our dump.
+    //
+    let region = match &subargs.task_region {
+        Some(spec) => {
+            let (ndx, region) = lookup_task_region(hubris, core, spec)?;
+            task = Some(DumpTask::new(ndx as u16, hubris.ticks(core)?));
+            Some(region)
+        }
+        None => None,
+    };
+
     if subargs.simulate_dumper {
         //
         // We are being asked to simulate our dumper:  we are going to pull
         // our dynamic memory directly from our target -- and determine what
         // our compression ratio would be along the way.
         //
-        core.halt()?;
-        humility::msg!("core halted");
+        halt(core)?;
 
         if let Some(ref stock) = subargs.stock_dumpfile {
-            hubris.dump(core, task, Some(stock), None)?;
+            write_dump(hubris, core, task, Some(stock), None, subargs)?;
         }
 
         match task {
@@ -371,21 +1440,32 @@ fn dump_via_agent(
                 if hubris.current_task(core)?
                     == Some(HubrisTask::Task(task.id as u32))
                 {
-                    core.run()?;
+                    resume(core)?;
                     bail!("cannot dump a task while it is running");
                 }
             }
             None => {
-                for i in 0..=ARMRegister::max() {
+                for (reg, val) in core.read_reg_all()? {
+                    out.add_register(reg, val);
+                }
+
+                //
+                // The floating point registers aren't part of the core
+                // register file, so we read them individually.
+                //
+                for i in CORE_REG_MAX + 1..=ARMRegister::max() {
                     if let Some(reg) = ARMRegister::from_u16(i) {
-                        let val = core.read_reg(reg)?;
-                        out.add_register(reg, val);
+                        out.add_register(reg, core.read_reg(reg)?);
                     }
                 }
             }
         }
 
-        let segments = hubris.dump_segments(core, task, false)?;
+        let segments = match region {
+            Some(region) => vec![region],
+            None => dump_segments(hubris, core, task, false, subargs)?,
+        };
+
         let total = segments.iter().fold(0, |ttl, (_, size)| ttl + size);
 
         let started = Instant::now();
@@ -398,6 +1478,7 @@ fn dump_via_agent(
 
         let mut nread = 0;
         let mut ncompressed = 0;
+        let mut unreadable: Vec<(u32, usize)> = vec![];
 
         for (base, size) in &segments {
             let mut remain = *size as usize;
@@ -407,11 +1488,27 @@ fn dump_via_agent(
             let mut addr = *base;
 
             while remain > 0 {
+                check_interrupted()?;
+
                 let nbytes = core::cmp::min(remain, input_len);
                 let offs = bytes.len() - nbytes;
                 let len = bytes.len();
 
-                core.read_8(addr, &mut bytes[offs..len])?;
+                //
+                // Because we are reading memory directly rather than via
+                // the agent, we can tolerate memory that cannot be read:
+                // it is left zero-filled in the dump.
+                //
+                for r in core.try_read_8(addr, &mut bytes[offs..len])? {
+                    let a = addr + r.start as u32;
+
+                    match unreadable.last_mut() {
+                        Some((base, size)) if *base + *size as u32 == a => {
+                            *size += r.len();
+                        }
+                        _ => unreadable.push((a, r.len())),
+                    }
+                }
 
                 let mut output = vec![0; 2048];
                 let mut compare: Vec<u8> = vec![];
@@ -459,10 +1556,44 @@ fn dump_via_agent(
             HumanDuration(started.elapsed())
         );
 
-        core.run()?;
+        for (addr, size) in &unreadable {
+            humility::warn!(
+                "could not read {size} bytes at {addr:#x}; zero-filled in dump"
+            );
+        }
+
+        resume(core)?;
         humility::msg!("core resumed");
     } else {
-        let segments = hubris.dump_segments(core, None, false)?;
+        let segments = dump_segments(hubris, core, None, false, subargs)?;
+
+        //
+        // If we are about to take a whole-system dump, estimate how large it
+        // will be so that we can make sure it will fit.  (We can only do this
+        // when directly attached, as reading memory over the network itself
+        // consumes dump areas.)
+        //
+        let estimate = if !core.is_net()
+            && !subargs.force_read
+            && subargs.area.is_none()
+            && !subargs.initialize_dump_agent
+            && task.is_none()
+        {
+            Some(estimate_dump_size(core, &segments)?)
+        } else {
+            None
+        };
+
+        //
+        // If we have been asked to limit how much we hold in memory, we
+        // need to know the segments in our dump before we read it.
+        //
+        if let Some(max) = subargs.max_segment_size {
+            let segments = dump_segments(hubris, core, None, true, subargs)?;
+            let dumpfile = subargs.dumpfile.as_deref();
+            out.stream_to(hubris.dump_stream(None, &segments, dumpfile)?, max);
+        }
+
         let mut agent = get_dump_agent(hubris, core, subargs)?;
         let header = agent.read_dump_header()?;
 
@@ -480,6 +1611,18 @@ fn dump_via_agent(
                 )
             }
 
+            if let Some(estimate) = estimate {
+                let capacity = agent.dump_region_capacity()?;
+
+                if estimate > capacity {
+                    bail!(
+                        "dump region too small by {} bytes (estimated dump \
+                        size is {estimate} bytes; capacity is {capacity} bytes)",
+                        estimate - capacity
+                    );
+                }
+            }
+
             if task.is_none() || subargs.initialize_dump_agent {
                 humility::msg!("initializing dump agent state");
                 agent.initialize_dump()?;
@@ -496,11 +1639,11 @@ fn dump_via_agent(
         }
 
         if subargs.emulate_dumper {
-            agent.core().halt()?;
-            humility::msg!("core halted");
+            halt(agent.core())?;
 
             if let Some(ref stock) = subargs.stock_dumpfile {
-                hubris.dump(agent.core(), task, Some(stock), None)?;
+                let core = agent.core();
+                write_dump(hubris, core, task, Some(stock), None, subargs)?;
             }
 
             let base = header.address;
@@ -509,7 +1652,7 @@ fn dump_via_agent(
             let address = if task.is_some() {
                 match emulate_task_dump_prep(agent.core(), &segments, base) {
                     Err(e) => {
-                        agent.core().run()?;
+                        resume(agent.core())?;
                         humility::msg!("core resumed after failure");
                         return Err(e);
                     }
@@ -524,7 +1667,7 @@ fn dump_via_agent(
             };
 
             emulate_dump(agent.core(), task, address, total)?;
-            agent.core().run()?;
+            resume(agent.core())?;
             humility::msg!("core resumed");
         } else if !subargs.force_read && subargs.area.is_none() {
             if subargs.force_manual_initiation {
@@ -551,38 +1694,196 @@ fn dump_via_agent(
             //
             agent.core().set_timeout(std::time::Duration::new(60, 0))?;
 
-            //
-            // Tell the thing to take a dump
-            //
-            agent.take_dump()?;
-        }
+            //
+            // Tell the thing to take a dump
+            //
+            WAITING.store(true, Ordering::SeqCst);
+            let rval = agent.take_dump();
+            WAITING.store(false, Ordering::SeqCst);
+            rval?;
+        }
+
+        //
+        // If we're here, we have a dump in situ -- time to pull it.
+        //
+        let progress = subargs.resume.then(|| progress_file(subargs));
+
+        if subargs.decompress_check {
+            out.check_decompression();
+        }
+
+        task = agent.read_dump(area, &mut out, true, progress.as_deref())?;
+        report_compression(&out);
+
+        if subargs.decompress_check {
+            report_decompression(&out)?;
+        }
+
+        //
+        // If this was a whole-system dump, we will leave our state initialized
+        // to assure that it will be ready to take subsequent task dumps (unless
+        // explicitly asked not to).
+        //
+        if task.is_none() {
+            if !subargs.retain_state {
+                humility::msg!("resetting dump agent state");
+                agent.initialize_dump()?;
+            } else {
+                humility::msg!("retaining dump agent state");
+            }
+        }
+    }
+
+    let dumpfile = if let Some(stream) = out.take_stream()? {
+        if task.is_some() {
+            bail!("cannot stream a task dump");
+        }
+
+        let dumpfile = stream.filename().to_owned();
+        stream.finish(hubris, &mut out, started)?;
+        dumpfile
+    } else {
+        let dumpfile =
+            dump_filename(hubris, task, subargs.dumpfile.as_deref(), subargs)?;
+
+        if let Some(region) = region {
+            write_dump_segments(
+                hubris,
+                &mut out,
+                task,
+                &[region],
+                &dumpfile,
+                started,
+                subargs,
+            )?;
+        } else {
+            let f = Some(dumpfile.as_str());
+            write_dump(hubris, &mut out, task, f, started, subargs)?;
+        }
+
+        dumpfile
+    };
+
+    if subargs.manifest {
+        write_manifest(hubris, &out, task, &dumpfile, agent_kind)?;
+    }
+
+    if subargs.verify {
+        if subargs.force_read || subargs.area.is_some() {
+            humility::msg!("dump was already in situ; skipping verification");
+        } else {
+            verify_dump(core, &out, subargs)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn dump_task_via_agent(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
+    let started = Some(Instant::now());
+
+    let mut agent = get_dump_agent(hubris, core, subargs)?;
+
+    let task = subargs.task.as_ref().unwrap();
+    let ndx = match hubris.lookup_task(task) {
+        Some(HubrisTask::Task(ndx)) => *ndx,
+        _ => {
+            bail!("invalid task \"{task}\"");
+        }
+    };
+    if ndx == 0 {
+        bail!("cannot dump supervisor");
+    }
+    let area = agent.dump_task(ndx)?;
+    let task = agent.read_dump(
+        Some(DumpArea::ByIndex(area as usize)),
+        &mut out,
+        true,
+        None,
+    )?;
+    assert!(task.is_some());
+
+    let dumpfile =
+        dump_filename(hubris, task, subargs.dumpfile.as_deref(), subargs)?;
+    let segments = hubris.dump_segments(&mut out, task, true)?;
+    write_dump_segments(
+        hubris, &mut out, task, &segments, &dumpfile, started, subargs,
+    )?;
+
+    Ok(())
+}
+
+//
+// Dumps every task (save the supervisor) via the dump agent, writing each to
+// its own dump file within a directory.  A task that can't be dumped is
+// skipped with a warning rather than failing the entire batch.
+//
+fn dump_all_tasks(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    let dir = match subargs.dumpfile.as_deref() {
+        Some("-") => bail!("cannot dump all tasks to standard output"),
+        Some(dir) => dir.to_string(),
+        None => (0..)
+            .map(|i| format!("hubris.tasks.{i}"))
+            .find(|d| !std::path::Path::new(d).exists())
+            .unwrap(),
+    };
+
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create {dir}"))?;
+
+    humility::msg!("dumping all tasks to {dir}");
 
-        //
-        // If we're here, we have a dump in situ -- time to pull it.
-        //
-        task = agent.read_dump(area, &mut out, true)?;
+    let mut agent = get_dump_agent(hubris, core, subargs)?;
 
-        //
-        // If this was a whole-system dump, we will leave our state initialized
-        // to assure that it will be ready to take subsequent task dumps (unless
-        // explicitly asked not to).
-        //
-        if task.is_none() {
-            if !subargs.retain_state {
-                humility::msg!("resetting dump agent state");
-                agent.initialize_dump()?;
-            } else {
-                humility::msg!("retaining dump agent state");
-            }
+    let mut dump_task = |ndx: u32, name: &str| -> Result<()> {
+        let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
+        let started = Some(Instant::now());
+
+        let area = agent.dump_task(ndx)?;
+        let task = agent.read_dump(
+            Some(DumpArea::ByIndex(area as usize)),
+            &mut out,
+            true,
+            None,
+        )?;
+
+        let dumpfile = format!("{dir}/hubris.core.{name}");
+        let dumpfile =
+            dump_filename(hubris, task, Some(dumpfile.as_str()), subargs)?;
+        let segments = hubris.dump_segments(&mut out, task, true)?;
+
+        write_dump_segments(
+            hubris, &mut out, task, &segments, &dumpfile, started, subargs,
+        )
+    };
+
+    let ntasks = hubris.ntasks() as u32;
+    let mut ndumped = 0;
+
+    for ndx in 1..ntasks {
+        let name = hubris.task_name(ndx as usize).unwrap_or("<unknown>");
+
+        match dump_task(ndx, name) {
+            Ok(_) => ndumped += 1,
+            Err(e) => humility::warn!("skipping {name}: {e}"),
         }
     }
 
-    hubris.dump(&mut out, task, subargs.dumpfile.as_deref(), started)?;
+    humility::msg!("dumped {ndumped} of {} tasks to {dir}", ntasks - 1);
 
     Ok(())
 }
 
-fn dump_task_via_agent(
+fn dump_task_region_via_agent(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
     subargs: &DumpArgs,
@@ -590,28 +1891,32 @@ fn dump_task_via_agent(
     let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
     let started = Some(Instant::now());
 
+    let spec = subargs.task_region.as_ref().unwrap();
+    let (ndx, (base, size)) = lookup_task_region(hubris, core, spec)?;
+
     let mut agent = get_dump_agent(hubris, core, subargs)?;
+    let area = agent.dump_task_region(ndx, base, size)?;
 
-    let task = subargs.task.as_ref().unwrap();
-    let ndx = match hubris.lookup_task(task) {
-        Some(HubrisTask::Task(ndx)) => *ndx,
-        _ => {
-            bail!("invalid task \"{task}\"");
-        }
-    };
-    if ndx == 0 {
-        bail!("cannot dump supervisor");
-    }
-    let area = agent.dump_task(ndx)?;
     let task = agent.read_dump(
         Some(DumpArea::ByIndex(area as usize)),
         &mut out,
         true,
+        None,
     )?;
+
     assert!(task.is_some());
-    hubris.dump(&mut out, task, subargs.dumpfile.as_deref(), started)?;
 
-    Ok(())
+    let dumpfile =
+        dump_filename(hubris, task, subargs.dumpfile.as_deref(), subargs)?;
+    write_dump_segments(
+        hubris,
+        &mut out,
+        task,
+        &[(base, size)],
+        &dumpfile,
+        started,
+        subargs,
+    )
 }
 
 fn dump_list(
@@ -621,10 +1926,17 @@ fn dump_list(
 ) -> Result<()> {
     let mut agent = get_dump_agent(hubris, core, subargs)?;
 
-    println!("{:4} {:21} {:10} SIZE", "AREA", "TASK", "TIME");
+    if !subargs.json {
+        println!("{:4} {:21} {:10} SIZE", "AREA", "TASK", "TIME");
+    }
+
     let headers = agent.read_dump_headers(false)?;
+    let mut records = vec![];
 
     if headers.is_empty() || headers[0].0.dumper == humpty::DUMPER_NONE {
+        if subargs.json {
+            println!("{}", serde_json::to_string(&records)?);
+        }
         return Ok(());
     }
 
@@ -634,7 +1946,19 @@ fn dump_list(
             .filter(|&(h, _)| h.dumper != humpty::DUMPER_NONE)
             .fold(0, |ttl, (h, _)| ttl + h.written);
 
-        println!("{:>4} {:21} {:<10} {size}", 0, "<system>", "-");
+        if subargs.json {
+            records.push(serde_json::json!({
+                "area": 0,
+                "task": None::<String>,
+                "time": None::<u64>,
+                "size": size,
+                "contents": "system",
+            }));
+            println!("{}", serde_json::to_string(&records)?);
+        } else {
+            println!("{:>4} {:21} {:<10} {size}", 0, "<system>", "-");
+        }
+
         return Ok(());
     }
 
@@ -643,21 +1967,49 @@ fn dump_list(
     for (area, (task, headers)) in &areas {
         let size = headers.iter().fold(0, |ttl, h| ttl + h.written);
 
+        let contents = match headers[0].contents {
+            humpty::DUMP_CONTENTS_SINGLETASK => "task",
+            humpty::DUMP_CONTENTS_TASKREGION => "task-region",
+            c => bail!("unknown contents type: {c}"),
+        };
+
+        let name = hubris
+            .lookup_module(HubrisTask::Task(task.id.into()))
+            .ok()
+            .map(|module| module.name.to_owned());
+
+        let time = task.time;
+
+        if subargs.json {
+            records.push(serde_json::json!({
+                "area": area,
+                "task": name,
+                "time": time,
+                "size": size,
+                "contents": contents,
+            }));
+            continue;
+        }
+
         println!(
             "{area:>4} {:21} {:<10} {size}",
-            match hubris.lookup_module(HubrisTask::Task(task.id.into())) {
-                Ok(module) => match headers[0].contents {
-                    humpty::DUMP_CONTENTS_SINGLETASK => module.name.to_owned(),
-                    humpty::DUMP_CONTENTS_TASKREGION =>
-                        format!("{} [region]", module.name.to_owned()),
-                    c => bail!("unknown contents type: {c}"),
+            match name {
+                Some(name) => match headers[0].contents {
+                    humpty::DUMP_CONTENTS_TASKREGION => {
+                        format!("{name} [region]")
+                    }
+                    _ => name,
                 },
-                _ => "<unknown>".to_owned(),
+                None => "<unknown>".to_owned(),
             },
-            task.time,
+            time,
         );
     }
 
+    if subargs.json {
+        println!("{}", serde_json::to_string(&records)?);
+    }
+
     Ok(())
 }
 
@@ -697,8 +2049,11 @@ fn dump_all(
 
             let dumpfile = (0..)
                 .map(|i| format!("hubris.core.{task_name}.{i}"))
-                .find(|f| std::fs::File::open(f).is_err())
-                .unwrap();
+                .map(|f| dump_filename(hubris, None, Some(&f), subargs))
+                .find(|f| {
+                    f.as_ref().map_or(true, |f| std::fs::File::open(f).is_err())
+                })
+                .unwrap()?;
             humility::msg!("dumping {task_name} (area {area})");
 
             let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
@@ -707,9 +2062,14 @@ fn dump_all(
                 Some(DumpArea::ByIndex(*area)),
                 &mut out,
                 true,
+                None,
             )?;
             assert!(task.is_some());
-            hubris.dump(&mut out, task, Some(&dumpfile), started)?;
+
+            let segments = hubris.dump_segments(&mut out, task, true)?;
+            write_dump_segments(
+                hubris, &mut out, task, &segments, &dumpfile, started, subargs,
+            )?;
         }
 
         if !subargs.retain_state {
@@ -722,6 +2082,92 @@ fn dump_all(
     }
 }
 
+fn dump_areas(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    let areas = subargs.areas.as_ref().unwrap();
+    let prefix = subargs.dumpfile.as_deref().unwrap_or("hubris.core");
+
+    if prefix == "-" {
+        bail!("cannot dump multiple areas to standard output");
+    }
+
+    let mut agent = get_dump_agent(hubris, core, subargs)?;
+    let headers = agent.read_dump_headers(true)?;
+
+    //
+    // A dump can span several areas, but only the first area of a dump can
+    // be read; if the first area holds a whole-system dump, it is the only
+    // dump there is.
+    //
+    let starts = match headers.first() {
+        Some((header, None)) if header.dumper != humpty::DUMPER_NONE => {
+            vec![0]
+        }
+        _ => task_areas(&headers).keys().copied().collect::<Vec<_>>(),
+    };
+
+    for &area in areas {
+        match headers.get(area) {
+            None => {
+                bail!("area {area} is invalid (--list to list)");
+            }
+            Some((header, _)) if header.dumper == humpty::DUMPER_NONE => {
+                humility::warn!("area {area} is empty; skipping");
+                continue;
+            }
+            Some(_) if !starts.contains(&area) => {
+                humility::warn!(
+                    "area {area} continues a dump that starts in an \
+                    earlier area; skipping"
+                );
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        //
+        // Name each dump by its area, taking care not to clobber a dump
+        // file that already exists.
+        //
+        let base = format!("{prefix}.area{area}");
+        let dumpfile = std::iter::once(base.clone())
+            .chain((0..).map(|i| format!("{base}.{i}")))
+            .find(|f| {
+                dump_filename(hubris, None, Some(f), subargs)
+                    .map_or(true, |f| !std::path::Path::new(&f).exists())
+            })
+            .unwrap();
+
+        humility::msg!("dumping area {area}");
+
+        let mut out = DumpAgentCore::new(HubrisFlashMap::new(hubris)?);
+        let started = Some(Instant::now());
+
+        if subargs.decompress_check {
+            out.check_decompression();
+        }
+
+        let task = agent.read_dump(
+            Some(DumpArea::ByIndex(area)),
+            &mut out,
+            true,
+            None,
+        )?;
+        report_compression(&out);
+
+        if subargs.decompress_check {
+            report_decompression(&out)?;
+        }
+
+        write_dump(hubris, &mut out, task, Some(&dumpfile), started, subargs)?;
+    }
+
+    Ok(())
+}
+
 fn dump_agent_status(
     hubris: &HubrisArchive,
     core: &mut dyn Core,
@@ -729,7 +2175,351 @@ fn dump_agent_status(
 ) -> Result<()> {
     let mut agent = get_dump_agent(hubris, core, subargs)?;
     let headers = agent.read_dump_headers(true)?;
-    println!("{:#x?}", headers);
+
+    if !subargs.json {
+        println!("{:#x?}", headers);
+        return Ok(());
+    }
+
+    let mut records = vec![];
+
+    for (area, (header, task)) in headers.iter().enumerate() {
+        let (address, length, written) =
+            (header.address, header.length, header.written);
+        let (dumper, contents, nsegments, next) =
+            (header.dumper, header.contents, header.nsegments, header.next);
+
+        let task = task.map(|task| {
+            let (id, time) = (task.id, task.time);
+
+            let name = hubris
+                .lookup_module(HubrisTask::Task(id.into()))
+                .ok()
+                .map(|module| module.name.to_owned());
+
+            serde_json::json!({ "id": id, "name": name, "time": time })
+        });
+
+        records.push(serde_json::json!({
+            "area": area,
+            "address": address,
+            "length": length,
+            "written": written,
+            "dumper": dumper,
+            "contents": contents,
+            "nsegments": nsegments,
+            "next": next,
+            "task": task,
+        }));
+    }
+
+    println!("{}", serde_json::to_string(&records)?);
+
+    Ok(())
+}
+
+//
+// Returns the address ranges (as start and end) of the loadable segments of
+// the specified dump file.
+//
+fn dumpfile_segments(dumpfile: &str) -> Result<Vec<(u32, u32)>> {
+    let contents = dump_contents(dumpfile)?;
+
+    let elf = goblin::elf::Elf::parse(&contents).map_err(|e| {
+        anyhow!("failed to parse {} as an ELF file: {}", dumpfile, e)
+    })?;
+
+    Ok(elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|phdr| {
+            let base = phdr.p_vaddr as u32;
+            (base, base + phdr.p_memsz as u32)
+        })
+        .collect())
+}
+
+//
+// Differences separated by no more than this many bytes are reported as a
+// single range.
+//
+const DIFF_GAP: u32 = 16;
+
+//
+// The maximum number of lines of contents to display for each range.
+//
+const DIFF_MAXLINES: u32 = 4;
+
+fn dump_diff(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    dumpfile: &str,
+    other: &str,
+) -> Result<()> {
+    let mut ocore = humility::core::attach_dump(other, hubris)?;
+    hubris
+        .validate(&mut *ocore, HubrisValidate::ArchiveMatch)
+        .with_context(|| format!("{other} is not a dump of this archive"))?;
+
+    humility::msg!("comparing {dumpfile} with {other}");
+
+    //
+    // We can only compare the memory that is present in both dumps.
+    //
+    let osegments = dumpfile_segments(other)?;
+    let mut common = vec![];
+
+    for (start, end) in dumpfile_segments(dumpfile)? {
+        for &(ostart, oend) in &osegments {
+            let (start, end) = (start.max(ostart), end.min(oend));
+
+            if start < end {
+                common.push((start, end));
+            }
+        }
+    }
+
+    common.sort_unstable();
+
+    let max = humility::core::CORE_MAX_READSIZE as u32;
+    let mut diffs: Vec<(u32, u32)> = vec![];
+
+    for (start, end) in common {
+        let mut addr = start;
+
+        while addr < end {
+            let len = (end - addr).min(max) as usize;
+            let mut ours = vec![0u8; len];
+            let mut theirs = vec![0u8; len];
+
+            core.read_8(addr, &mut ours)?;
+            ocore.read_8(addr, &mut theirs)?;
+
+            for (i, _) in ours
+                .iter()
+                .zip(theirs.iter())
+                .enumerate()
+                .filter(|(_, (a, b))| a != b)
+            {
+                let daddr = addr + i as u32;
+
+                match diffs.last_mut() {
+                    Some(last) if daddr <= last.1 + DIFF_GAP => {
+                        last.1 = daddr + 1;
+                    }
+                    _ => diffs.push((daddr, daddr + 1)),
+                }
+            }
+
+            addr += len as u32;
+        }
+    }
+
+    //
+    // To annotate each difference, we look for the variable that contains
+    // it -- and failing that, the memory region.
+    //
+    let variables = hubris
+        .qualified_variables()
+        .filter(|(_, v)| v.size > 0)
+        .map(|(name, v)| (v.addr, (name, v.size as u32)))
+        .collect::<BTreeMap<_, _>>();
+
+    let regions = hubris.regions(core)?;
+
+    let annotate = |addr: u32| -> String {
+        if let Some((&base, &(name, size))) =
+            variables.range(..=addr).next_back()
+        {
+            if addr < base + size {
+                return format!("{}+{:#x}", name, addr - base);
+            }
+        }
+
+        if let Some((&base, region)) = regions.range(..=addr).next_back() {
+            if addr < base + region.size {
+                let owners = region
+                    .tasks
+                    .iter()
+                    .map(|&t| match hubris.lookup_module(t) {
+                        Ok(module) => module.name.as_str(),
+                        Err(_) => "<unknown>",
+                    })
+                    .collect::<Vec<_>>();
+
+                return format!("{} region at {:#x}", owners.join(", "), base);
+            }
+        }
+
+        "unknown".to_string()
+    };
+
+    let hex = |bytes: &[u8]| -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(" ")
+    };
+
+    let mut nbytes = 0;
+
+    for &(start, end) in &diffs {
+        println!(
+            "{start:#x}-{end:#x} ({} bytes): {}",
+            end - start,
+            annotate(start)
+        );
+
+        //
+        // Display the contents side by side, eight bytes at a time.
+        //
+        let base = start & !7;
+        let nlines = ((end - base) + 7) / 8;
+
+        for line in 0..nlines.min(DIFF_MAXLINES) {
+            let addr = base + line * 8;
+            let len = 8.min(end - addr) as usize;
+            let mut ours = vec![0u8; len];
+            let mut theirs = vec![0u8; len];
+
+            core.read_8(addr, &mut ours)?;
+            ocore.read_8(addr, &mut theirs)?;
+
+            println!("    {addr:#x} | {:23} | {}", hex(&ours), hex(&theirs));
+        }
+
+        if nlines > DIFF_MAXLINES {
+            println!("    ...");
+        }
+
+        nbytes += end - start;
+    }
+
+    if diffs.is_empty() {
+        humility::msg!("dumps are identical");
+    } else {
+        humility::msg!(
+            "{} range{} differ{} ({})",
+            diffs.len(),
+            if diffs.len() == 1 { "" } else { "s" },
+            if diffs.len() == 1 { "s" } else { "" },
+            HumanBytes(nbytes as u64)
+        );
+    }
+
+    Ok(())
+}
+
+fn dump_check(dumpfile: &str) -> Result<()> {
+    let Some(segments) = verify_dump_checksums(dumpfile)? else {
+        bail!("{dumpfile} does not contain checksums");
+    };
+
+    let mut corrupt = 0;
+
+    for segment in segments.iter().filter(|s| s.is_corrupt()) {
+        humility::msg!(
+            "segment at {:#x} ({:#x} bytes) is corrupt: \
+            checksum is {:#010x}; expected {:#010x}",
+            segment.base,
+            segment.size,
+            segment.computed,
+            segment.recorded
+        );
+
+        corrupt += 1;
+    }
+
+    if corrupt == 0 {
+        humility::msg!("all {} segments verified", segments.len());
+        Ok(())
+    } else {
+        bail!("{} of {} segments corrupt", corrupt, segments.len());
+    }
+}
+
+//
+// Takes a dump by halting the target and reading its memory directly,
+// without involving the dump agent.
+//
+fn dump_stock(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    halt(core)?;
+
+    let dumpfile = subargs.dumpfile.as_deref();
+    let rval = write_dump(hubris, core, None, dumpfile, None, subargs);
+
+    if !subargs.leave_halted {
+        resume(core)?;
+        humility::msg!("core resumed");
+    } else {
+        HALTED.store(false, Ordering::SeqCst);
+        humility::msg!("core left halted");
+    }
+
+    rval
+}
+
+//
+// Reads a range of memory directly from the (halted) target and writes it
+// out as raw bytes.  This deliberately involves neither the archive nor the
+// dump agent.
+//
+fn dump_range(
+    core: &mut dyn Core,
+    (start, end): (u32, u32),
+    output: &str,
+    subargs: &DumpArgs,
+) -> Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(output)
+        .with_context(|| format!("failed to create {output}"))?;
+
+    let total = (end - start) as usize;
+    let max = humility::core::CORE_MAX_READSIZE;
+    let mut buf = vec![0u8; max];
+
+    halt(core)?;
+
+    let bar = ProgressBar::new(total as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("humility: reading [{bar:30}] {bytes}/{total_bytes}"),
+    );
+
+    let mut addr = start;
+
+    while addr < end {
+        check_interrupted()?;
+
+        let len = std::cmp::min(max, (end - addr) as usize);
+        let data = &mut buf[..len];
+
+        core.read_8(addr, data)
+            .with_context(|| format!("failed to read {addr:#x}"))?;
+        file.write_all(data)
+            .with_context(|| format!("failed to write {output}"))?;
+
+        addr += len as u32;
+        bar.set_position((addr - start) as u64);
+    }
+
+    bar.finish_and_clear();
+
+    humility::msg!(
+        "read {} from {start:#x} to {output}",
+        HumanBytes(total as u64)
+    );
+
+    if !subargs.leave_halted {
+        resume(core)?;
+        humility::msg!("core resumed");
+    } else {
+        HALTED.store(false, Ordering::SeqCst);
+        humility::msg!("core left halted");
+    }
 
     Ok(())
 }
@@ -741,21 +2531,109 @@ fn dumpcmd(context: &mut ExecutionContext) -> Result<()> {
 
     let subargs = DumpArgs::try_parse_from(subargs)?;
 
+    if !hubris.loaded() && subargs.range.is_none() {
+        bail!("must provide an archive (or use --range)");
+    }
+
+    if let Some(ref other) = subargs.diff {
+        return match &context.cli.dump {
+            Some(dumpfile) if core.is_dump() => {
+                dump_diff(hubris, core, dumpfile, other)
+            }
+            _ => bail!("--diff requires a dump to compare against (use -d)"),
+        };
+    }
+
+    if subargs.check {
+        return match &context.cli.dump {
+            Some(dumpfile) if core.is_dump() => dump_check(dumpfile),
+            _ => bail!("--check requires a dump to verify (use -d)"),
+        };
+    }
+
+    if core.is_dump() || core.is_archive() {
+        bail!("must be run against a live system");
+    }
+
+    if subargs.range.is_some() && core.is_net() {
+        bail!("--range requires being attached via debug probe");
+    }
+
+    if subargs.stock && core.is_net() {
+        bail!("--stock requires being attached via debug probe");
+    }
+
     if subargs.force_dump_agent && core.is_net() {
         bail!("can only force the dump agent when attached via debug probe");
     }
 
-    if subargs.all {
+    if subargs.json && !subargs.list && !subargs.dump_agent_status {
+        bail!("--json requires --list or --dump-agent-status");
+    }
+
+    if subargs.dumpfile.as_deref() == Some("-")
+        && (subargs.list || subargs.dump_agent_status || subargs.manifest)
+    {
+        bail!(
+            "cannot dump to standard output with --list, \
+            --dump-agent-status, or --manifest"
+        );
+    }
+
+    if subargs.dumpfile.as_deref() == Some("-") && subargs.compress.is_some() {
+        bail!("cannot compress a dump to standard output");
+    }
+
+    //
+    // We may be run more than once in the same process (e.g., from the
+    // repl), so we install our handler only once -- and clear any state
+    // left over from a previous run.
+    //
+    static HANDLER: Once = Once::new();
+    let mut handler = Ok(());
+
+    HANDLER.call_once(|| {
+        handler = ctrlc::set_handler(|| {
+            if HALTED.load(Ordering::SeqCst) || WAITING.load(Ordering::SeqCst) {
+                INTERRUPTED.store(true, Ordering::SeqCst);
+            } else {
+                std::process::exit(1);
+            }
+        });
+    });
+
+    handler?;
+
+    HALTED.store(false, Ordering::SeqCst);
+    WAITING.store(false, Ordering::SeqCst);
+    INTERRUPTED.store(false, Ordering::SeqCst);
+
+    let rval = if let (Some(range), Some(output)) =
+        (subargs.range, subargs.output.as_deref())
+    {
+        dump_range(core, range, output, &subargs)
+    } else if subargs.stock {
+        dump_stock(hubris, core, &subargs)
+    } else if subargs.all {
         dump_all(hubris, core, &subargs)
+    } else if subargs.areas.is_some() {
+        dump_areas(hubris, core, &subargs)
     } else if subargs.list {
         dump_list(hubris, core, &subargs)
     } else if subargs.dump_agent_status {
         dump_agent_status(hubris, core, &subargs)
+    } else if subargs.all_tasks {
+        if subargs.force_dump_agent {
+            humility::msg!("--force-dump-agent is implied by --all-tasks");
+        }
+        dump_all_tasks(hubris, core, &subargs)
     } else if subargs.task.is_some() {
         if subargs.force_dump_agent {
             humility::msg!("--force-dump-agent is implied by --task");
         }
         dump_task_via_agent(hubris, core, &subargs)
+    } else if subargs.task_region.is_some() && !subargs.simulate_dumper {
+        dump_task_region_via_agent(hubris, core, &subargs)
     } else if core.is_net()
         || subargs.force_dump_agent
         || subargs.force_read
@@ -767,20 +2645,24 @@ fn dumpcmd(context: &mut ExecutionContext) -> Result<()> {
             bail!("must also use --force-dump-agent to initialize dump agent");
         }
 
-        core.halt()?;
-        humility::msg!("core halted");
+        dump_stock(hubris, core, &subargs)
+    };
 
-        let rval = hubris.dump(core, None, subargs.dumpfile.as_deref(), None);
+    //
+    // If we are leaving with the core still halted, we were either
+    // interrupted or failed while dumping; either way, resume it.
+    //
+    if HALTED.load(Ordering::SeqCst) {
+        resume(core)?;
 
-        if !subargs.leave_halted {
-            core.run()?;
-            humility::msg!("core resumed");
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            humility::msg!("core resumed after interrupt");
         } else {
-            humility::msg!("core left halted");
+            humility::msg!("core resumed after failure");
         }
-
-        rval
     }
+
+    rval
 }
 
 pub fn init() -> Command {
@@ -789,8 +2671,8 @@ pub fn init() -> Command {
         name: "dump",
         run: dumpcmd,
         kind: CommandKind::Attached {
-            archive: Archive::Required,
-            attach: Attach::LiveOnly,
+            archive: Archive::Optional,
+            attach: Attach::Any,
             validate: Validate::Match,
         },
     }
@@ -0,0 +1,219 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//
+// Support for writing a dump as a minidump rather than as an ELF core file,
+// for the benefit of tools that consume the former but not the latter.  We
+// emit only the streams that such tools need to make sense of a dump:  a
+// system information stream (denoting an ARM processor), a thread list
+// stream (consisting of a single thread, bearing the register state of the
+// core, if we have it), and a memory list stream.  The thread context is
+// that of the Breakpad ARM context, which is what consumers of minidumps
+// from non-Windows systems expect.
+//
+
+use anyhow::{bail, Result};
+use humility::core::Core;
+use std::io::Write;
+
+const MINIDUMP_SIGNATURE: u32 = 0x504d_444d; // "MDMP"
+const MINIDUMP_VERSION: u32 = 0xa793;
+
+const STREAM_THREAD_LIST: u32 = 3;
+const STREAM_MEMORY_LIST: u32 = 5;
+const STREAM_SYSTEM_INFO: u32 = 7;
+
+const PROCESSOR_ARCHITECTURE_ARM: u16 = 5;
+const CONTEXT_ARM_INTEGER: u32 = 0x4000_0002;
+
+const HEADER_SIZE: u32 = 32;
+const DIRECTORY_ENTRY_SIZE: u32 = 12;
+const SYSTEM_INFO_SIZE: u32 = 56;
+const STRING_SIZE: u32 = 8;
+const THREAD_SIZE: u32 = 48;
+const CONTEXT_ARM_SIZE: u32 = 368;
+const MEMORY_DESCRIPTOR_SIZE: u32 = 16;
+
+//
+// The register selectors of the integer registers (R0 through R15) and of
+// the PSR, as found in the register values that we are handed.
+//
+const REG_SP: u16 = 13;
+const REG_PC: u16 = 15;
+const REG_PSR: u16 = 16;
+
+fn put16(buf: &mut Vec<u8>, val: u16) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, val: u64) {
+    buf.extend_from_slice(&val.to_le_bytes());
+}
+
+///
+/// Writes a minidump consisting of the specified `(start, size)` segments
+/// (as read from `core`) and the specified register values (as pairs of
+/// register selector and value), if any.
+///
+pub fn write_minidump(
+    core: &mut dyn Core,
+    regs: Option<&[(u16, u32)]>,
+    segments: &[(u32, u32)],
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut contents = vec![];
+
+    for &(base, size) in segments {
+        let mut bytes = vec![0; size as usize];
+        let mut addr = base;
+
+        for chunk in bytes.chunks_mut(1024) {
+            core.read_8(addr, chunk)?;
+            addr += chunk.len() as u32;
+        }
+
+        contents.push(bytes);
+    }
+
+    let nthreads = u32::from(regs.is_some());
+    let nsegments = segments.len() as u32;
+
+    //
+    // Lay out our file:  our header and stream directory, followed by each
+    // of our streams, followed by the contents of memory.
+    //
+    let directory = HEADER_SIZE;
+    let sysinfo = directory + 3 * DIRECTORY_ENTRY_SIZE;
+    let csdversion = sysinfo + SYSTEM_INFO_SIZE;
+    let threads = csdversion + STRING_SIZE;
+    let context = threads + 4 + nthreads * THREAD_SIZE;
+    let memlist = context + nthreads * CONTEXT_ARM_SIZE;
+    let memory = memlist + 4 + nsegments * MEMORY_DESCRIPTOR_SIZE;
+
+    let mut offsets = vec![];
+    let mut offset = memory;
+
+    for &(_, size) in segments {
+        offsets.push(offset);
+
+        offset = match offset.checked_add(size) {
+            Some(offset) => offset,
+            None => bail!("dump is too large to be written as a minidump"),
+        };
+    }
+
+    let mut buf = vec![];
+
+    //
+    // Our header...
+    //
+    put32(&mut buf, MINIDUMP_SIGNATURE);
+    put32(&mut buf, MINIDUMP_VERSION);
+    put32(&mut buf, 3);
+    put32(&mut buf, directory);
+    put32(&mut buf, 0);
+    put32(&mut buf, 0);
+    put64(&mut buf, 0);
+
+    //
+    // ...our stream directory...
+    //
+    for (kind, size, rva) in [
+        (STREAM_SYSTEM_INFO, SYSTEM_INFO_SIZE, sysinfo),
+        (STREAM_THREAD_LIST, 4 + nthreads * THREAD_SIZE, threads),
+        (STREAM_MEMORY_LIST, 4 + nsegments * MEMORY_DESCRIPTOR_SIZE, memlist),
+    ] {
+        put32(&mut buf, kind);
+        put32(&mut buf, size);
+        put32(&mut buf, rva);
+    }
+
+    //
+    // ...our system information, which is followed by the (empty) string
+    // that it requires to denote the service pack...
+    //
+    put16(&mut buf, PROCESSOR_ARCHITECTURE_ARM);
+    put16(&mut buf, 0);
+    put16(&mut buf, 0);
+    buf.push(1);
+    buf.push(0);
+    put32(&mut buf, 0);
+    put32(&mut buf, 0);
+    put32(&mut buf, 0);
+    put32(&mut buf, 0);
+    put32(&mut buf, csdversion);
+    put16(&mut buf, 0);
+    put16(&mut buf, 0);
+    buf.extend_from_slice(&[0; 24]);
+
+    put32(&mut buf, 0);
+    put32(&mut buf, 0);
+
+    //
+    // ...our thread list, the stack of which is the memory from the stack
+    // pointer to the end of the segment containing it...
+    //
+    put32(&mut buf, nthreads);
+
+    if let Some(regs) = regs {
+        let reg = |sel| regs.iter().find(|&&(r, _)| r == sel).map(|&(_, v)| v);
+        let sp = reg(REG_SP).unwrap_or(0);
+
+        let stack = segments
+            .iter()
+            .zip(offsets.iter())
+            .find(|(&(base, size), _)| sp >= base && sp - base < size)
+            .map(|(&(base, size), &offset)| {
+                (size - (sp - base), offset + (sp - base))
+            });
+
+        put32(&mut buf, 0);
+        put32(&mut buf, 0);
+        put32(&mut buf, 0);
+        put32(&mut buf, 0);
+        put64(&mut buf, 0);
+        put64(&mut buf, sp as u64);
+        put32(&mut buf, stack.map_or(0, |s| s.0));
+        put32(&mut buf, stack.map_or(0, |s| s.1));
+        put32(&mut buf, CONTEXT_ARM_SIZE);
+        put32(&mut buf, context);
+
+        //
+        // ...the context for our thread (which has only integer registers;
+        // the floating point state is left zeroed)...
+        //
+        put32(&mut buf, CONTEXT_ARM_INTEGER);
+
+        for sel in 0..=REG_PC {
+            put32(&mut buf, reg(sel).unwrap_or(0));
+        }
+
+        put32(&mut buf, reg(REG_PSR).unwrap_or(0));
+        buf.resize(buf.len() + (CONTEXT_ARM_SIZE as usize - 4 * 18), 0);
+    }
+
+    //
+    // ...and finally, our memory list.
+    //
+    put32(&mut buf, nsegments);
+
+    for (&(base, size), &offset) in segments.iter().zip(offsets.iter()) {
+        put64(&mut buf, base as u64);
+        put32(&mut buf, size);
+        put32(&mut buf, offset);
+    }
+
+    assert_eq!(buf.len(), memory as usize);
+    out.write_all(&buf)?;
+
+    for bytes in &contents {
+        out.write_all(bytes)?;
+    }
+
+    Ok(())
+}
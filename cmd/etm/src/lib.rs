@@ -5,10 +5,147 @@
 //! ## `humility etm`
 //!
 //! Enables and operates upon the Embedded Trace Macrocell (ETM) found in
-//! some ARM Cortex-M parts.
+//! some ARM Cortex-M parts.  Both ETMv3.5 (as found in, e.g., the
+//! STM32F407) and ETMv4 (as found in the Cortex-M7) are supported; the
+//! version is determined from the ETM's ID register.  When ingesting
+//! captured ETMv4 trace data from a file, `--etmv4` must be specified.
+//!
+//! Decoded trace can be written to a file as newline-delimited JSON with
+//! `--save`, and later rendered with `--replay` (which does not require
+//! an attached device):
+//!
+//! ```console
+//! % humility etm --attach --save trace.json
+//! ...
+//! % humility etm --replay trace.json --flowindent
+//! ```
+//!
+//! Raw trace data can be captured from the attached device as CSV (suitable
+//! for `--ingest`) with `--output`.
+//!
+//! Decoded trace can be restricted to a single task with `--task` and/or to
+//! an address range with `--range`.  Execution outside of the filter is
+//! collapsed to a single `-> elsewhere` line; indentation is still tracked
+//! while elsewhere, so `--flowindent` remains consistent when execution
+//! returns:
+//!
+//! ```console
+//! % humility etm --replay trace.json --flowindent --task spi_driver
+//! % humility etm --replay trace.json --range 0x8020000..0x8024000
+//! ```
+//!
+//! Rather than displaying each instruction, `--folded` accumulates the time
+//! spent in each unique call stack and emits the result in the folded stack
+//! format consumed by `inferno` and `flamegraph.pl`.  Each frame is denoted
+//! as `module:symbol`, and each stack is weighted by the time (in
+//! nanoseconds) between its instruction and the one that follows.  When
+//! ingesting from an attached device, hit Ctrl-C to stop ingesting and emit
+//! the folded stacks:
+//!
+//! ```console
+//! % humility etm --replay trace.json --folded | inferno-flamegraph > etm.svg
+//! ```
+//!
+//! Timestamps of ingested data are those of the capture, and are therefore
+//! imprecise with respect to the instructions themselves.  For ETMv3.5,
+//! cycle-accurate tracing can be enabled by specifying `--cycle-accurate`
+//! with `--enable`; when ingesting cycle-accurate data from a file,
+//! `--cycle-accurate` must also be specified.  (When ingesting from an
+//! attached device, the ETM's configuration is used.)  Cycle counts are
+//! included in `--save`, and with `--flowindent`, each return is
+//! annotated with the number of cycles spent in the function since its
+//! call.  With `--folded`, stacks are weighted by cycles rather than time:
+//!
+//! ```console
+//! % humility etm --ingest trace.csv --cycle-accurate --flowindent
+//! ...
+//!   17853040       -> kernel:safe_copy
+//!   17853120       <- kernel:safe_copy (42 cycles)
+//! ...
+//! ```
+//!
+//! When ingesting (from a file or from an attached device, in which case
+//! ingesting ends on Ctrl-C), a summary of decoding coverage is displayed
+//! at the end:  the proportion of instructions that could be decoded, and
+//! each address at which decoding lost sync because the instruction there
+//! was unknown.  An address that isn't within any module of the archive
+//! suggests that the archive doesn't match the traced code; an address that
+//! is within a module suggests that the trace itself is corrupt:
+//!
+//! ```console
+//! % humility etm --ingest trace.csv > trace.out
+//! humility: decoded 94.12% of instructions (158211 of 168096)
+//! humility: lost sync 2 times: at 0x8024a10 (not in archive), 0x8003c4e (in kernel)
+//! ```
+//!
+//! For ETMv3.5, tracing of data accesses (both addresses and values) can be
+//! enabled by specifying `--data` with `--enable`; not all ETMs implement
+//! data trace (notably, the ETM-M3 and ETM-M4 do not), in which case a
+//! warning is emitted and only instructions are traced.  When ingesting
+//! data trace from a file, `--data` must also be specified.  Each data
+//! access is displayed with a `D` following the instruction that issued
+//! it, along with its address (and the variable or symbol that contains
+//! it, if any) and the value loaded or stored:
+//!
+//! ```console
+//! % humility etm --ingest trace.csv --data
+//! ...
+//!   17852960 0800a4c2 E kernel:safe_copy+12 None
+//!   17852960 0800a4c2 D [0x20001f04 TASK_TABLE_BASE] = 0x20000400
+//! ...
+//! ```
+//!
+//! For ETMv3.5, the TPIU formatter interleaves the frames of every trace
+//! source, allowing ETM trace and ITM instrumentation to be captured at
+//! once.  To decode ITM trace from the same capture (whether ingested from
+//! a file or from an attached device), specify its trace identifier with
+//! `--itm`; ITM stimulus port output is written to standard error, or to
+//! the file specified with `--itm-output`.  (ITM must itself be enabled,
+//! e.g. with `humility itm --enable`.)
+//!
+//! ```console
+//! % humility etm --ingest trace.csv --itm 0x3a --itm-output itm.out
+//! ```
+//!
+//! When ingesting or replaying, the call stack is tracked, and the maximum
+//! call depth is displayed at the end along with the call chain at that
+//! depth (that is, the function containing each call site followed by the
+//! function called), which can be useful for bounding stack usage.  Note
+//! that the depth is relative to wherever the trace began.  To flag
+//! runaway recursion as the trace is processed, specify `--max-depth`; a
+//! warning (with the call chain) is emitted each time the call depth
+//! exceeds it:
+//!
+//! ```console
+//! % humility etm --ingest trace.csv --max-depth 12 > trace.out
+//! humility: WARNING: call depth of 13 exceeds maximum at 17853040: ...
+//! humility: decoded 100.00% of instructions (168096 of 168096)
+//! humility: maximum call depth of 14 at 17853120:
+//! humility:     0 spi:main
+//! humility:     1 spi:sys_send_stub
+//! ...
+//! ```
+//!
+//! If a capture was taken with a trace identifier other than the one
+//! expected (by default, 0x54), decoding it will find no instructions.  To
+//! determine the trace identifiers actually present in a capture, use
+//! `--detect-traceid` with `--ingest`; the capture can then be decoded by
+//! specifying the detected identifier with `--traceid`, without needing to
+//! re-enable ETM or take another capture:
+//!
+//! ```console
+//! % humility etm --ingest trace.csv --detect-traceid
+//! humility: TPIU sync packet found at offset 12
+//! humility: 28164 valid TPIU frames
+//! humility: trace identifiers present in trace.csv:
+//! humility:   0x3a         1208 bytes
+//! humility:   0x55       402718 bytes
+//! humility: no trace with identifier 0x54; use "--traceid 0x55" to decode
+//! % humility etm --ingest trace.csv --traceid 0x55
+//! ```
 //!
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
@@ -18,9 +155,15 @@ use humility_cmd::{attach_live, CommandKind};
 use humility_cmd::{Archive, Command};
 use humility_cortex::debug::*;
 use humility_cortex::etm::*;
+use humility_cortex::etmv4::*;
+use humility_cortex::itm::{itm_decoder, trace_port_enable, ITMPayload};
 use humility_cortex::scs::*;
 use humility_cortex::tpiu::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
 #[derive(Parser, Debug)]
@@ -28,14 +171,17 @@ use std::time::Instant;
 struct EtmArgs {
     /// probe for ETM capability on attached device
     #[clap(
-        long, short, conflicts_with_all = &["enable", "disable", "ingest"]
+        long, short,
+        conflicts_with_all = &["enable", "disable", "ingest", "replay"]
     )]
     probe: bool,
     /// enable ETM on attached device
-    #[clap(long, short, conflicts_with_all = &["disable", "ingest"])]
+    #[clap(
+        long, short, conflicts_with_all = &["disable", "ingest", "replay"]
+    )]
     enable: bool,
     /// disable ETM on attached device
-    #[clap(long, short)]
+    #[clap(long, short, conflicts_with = "replay")]
     disable: bool,
     /// sets ETM trace identifier
     #[clap(
@@ -44,11 +190,25 @@ struct EtmArgs {
     )]
     traceid: u8,
     /// ingest ETM data as CSV
-    #[clap(long, short, value_name = "filename")]
+    #[clap(long, short, value_name = "filename", conflicts_with = "replay")]
     ingest: Option<String>,
+    /// ingested data is ETMv4 (rather than ETMv3.5)
+    #[clap(long, requires = "ingest")]
+    etmv4: bool,
+    /// enable cycle-accurate tracing (with --enable), or denote that
+    /// ingested ETMv3.5 data is cycle-accurate (with --ingest)
+    #[clap(long, conflicts_with_all = &["etmv4", "replay"])]
+    cycle_accurate: bool,
+    /// enable data tracing (with --enable), or denote that ingested
+    /// ETMv3.5 data includes data addresses (with --ingest)
+    #[clap(long, conflicts_with_all = &["etmv4", "replay"])]
+    data: bool,
     /// flowindent ingested data
     #[clap(long, short = 'F')]
     flowindent: bool,
+    /// emit time spent in each call stack as folded stacks
+    #[clap(long, conflicts_with_all = &["flowindent", "output"])]
+    folded: bool,
     /// sets the value of SWOSCALER
     #[clap(
         long, short, value_name = "scaler", requires = "enable",
@@ -56,12 +216,76 @@ struct EtmArgs {
     )]
     clockscaler: Option<u16>,
     /// output ETM data as CSV
-    #[clap(long, short, conflicts_with = "ingest")]
+    #[clap(long, short, conflicts_with_all = &["ingest", "replay"])]
     output: bool,
+    /// ingest directly from attached device
+    #[clap(
+        long, short,
+        conflicts_with_all = &["disable", "ingest", "output", "replay"]
+    )]
+    attach: bool,
+    /// save decoded trace to a file as newline-delimited JSON
+    #[clap(
+        long, value_name = "filename",
+        conflicts_with_all = &["output", "replay"]
+    )]
+    save: Option<String>,
+    /// render decoded trace previously written with --save
+    #[clap(long, short, value_name = "filename")]
+    replay: Option<String>,
+    /// only display decoded trace within the specified task
+    #[clap(long, value_name = "task")]
+    task: Option<String>,
+    /// only display decoded trace within the specified address range
+    #[clap(
+        long, value_name = "start..end",
+        parse(try_from_str = parse_range)
+    )]
+    range: Option<(u32, u32)>,
+    /// also decode ITM trace with the specified trace identifier that is
+    /// interleaved with ETM trace
+    #[clap(
+        long, value_name = "identifier",
+        parse(try_from_str = parse_int::parse),
+        conflicts_with_all = &["etmv4", "output", "replay"]
+    )]
+    itm: Option<u8>,
+    /// write ITM stimulus port output to a file rather than standard error
+    #[clap(long, value_name = "filename", requires = "itm")]
+    itm_output: Option<String>,
+    /// report the trace identifiers present in ingested data rather than
+    /// decoding it
+    #[clap(long, requires = "ingest", conflicts_with_all = &["etmv4", "itm"])]
+    detect_traceid: bool,
+    /// warn when the call depth exceeds the specified depth
+    #[clap(
+        long, value_name = "depth",
+        parse(try_from_str = parse_int::parse),
+        conflicts_with_all = &["output", "detect-traceid"]
+    )]
+    max_depth: Option<usize>,
+}
+
+fn parse_range(range: &str) -> Result<(u32, u32)> {
+    let (start, end) = range.split_once("..").ok_or_else(|| {
+        anyhow!("range \"{range}\" must be of the form start..end")
+    })?;
+
+    let start = parse_int::parse::<u32>(start.trim())
+        .with_context(|| format!("invalid start in \"{range}\""))?;
+    let end = parse_int::parse::<u32>(end.trim())
+        .with_context(|| format!("invalid end in \"{range}\""))?;
+
+    if end <= start {
+        bail!("range \"{range}\" is empty");
+    }
+
+    Ok((start, end))
 }
 
 struct TraceInstruction {
     nsecs: u64,
+    cycles: Option<u64>,
     addr: u32,
     _len: u32,
     target: Option<HubrisTarget>,
@@ -73,11 +297,73 @@ struct TraceException {
     exception: ETM3Exception,
 }
 
+struct TraceData {
+    nsecs: u64,
+    pc: Option<u32>,
+    addr: Option<u32>,
+    value: Option<u32>,
+}
+
 #[derive(Debug)]
 struct TraceConfig<'a> {
     hubris: &'a HubrisArchive,
     flowindent: bool,
+    folded: bool,
     traceid: u8,
+    cycle_accurate: bool,
+    data: bool,
+    save: Option<String>,
+    task: Option<String>,
+    range: Option<(u32, u32)>,
+    variables: BTreeMap<u32, (&'a str, usize)>,
+    itm: Option<u8>,
+    itm_output: Option<String>,
+    max_depth: Option<usize>,
+}
+
+impl TraceConfig<'_> {
+    //
+    // Determines if an instruction should be displayed, given its address
+    // and the module containing it.
+    //
+    fn displayed(&self, addr: u32, module: &str) -> bool {
+        if let Some(task) = &self.task {
+            if module != task {
+                return false;
+            }
+        }
+
+        match self.range {
+            Some((start, end)) => addr >= start && addr < end,
+            None => true,
+        }
+    }
+
+    //
+    // Determines the variable (or failing that, the symbol) that contains
+    // the target of a data access.
+    //
+    fn data_sym(&self, addr: u32) -> Option<String> {
+        if let Some((&base, &(name, size))) =
+            self.variables.range(..=addr).next_back()
+        {
+            if ((addr - base) as usize) < size {
+                return Some(if addr == base {
+                    name.to_string()
+                } else {
+                    format!("{}+0x{:x}", name, addr - base)
+                });
+            }
+        }
+
+        self.hubris.instr_sym(addr).map(|(name, base)| {
+            if addr == base {
+                name.to_string()
+            } else {
+                format!("{}+0x{:x}", name, addr - base)
+            }
+        })
+    }
 }
 
 #[derive(Debug, Default)]
@@ -85,12 +371,181 @@ struct TraceState {
     indent: usize,
     target: Option<HubrisTarget>,
     inlined: Vec<HubrisGoff>,
-    stack: Vec<(usize, Vec<HubrisGoff>, u32)>,
+    stack: Vec<(usize, Vec<HubrisGoff>, u32, Option<u64>)>,
+    save: Option<File>,
+    elsewhere: bool,
+    unfolded: Option<(u64, String)>,
+    folded: BTreeMap<String, u64>,
+    deepest: Option<(usize, u64, Vec<String>)>,
+}
+
+//
+// Returns the frame of an instruction address, as `module:symbol`.
+//
+fn etmcmd_frame(hubris: &HubrisArchive, addr: u32) -> String {
+    format!(
+        "{}:{}",
+        hubris.instr_mod(addr).unwrap_or("<unknown>"),
+        hubris.instr_sym(addr).map_or("<unknown>", |s| s.0)
+    )
+}
+
+//
+// A decoded trace record, as written with --save (and read with
+// --replay).
+//
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum TraceRecord {
+    Instruction {
+        nsecs: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cycles: Option<u64>,
+        addr: u32,
+        module: String,
+        symbol: String,
+        offset: u32,
+        target: Option<HubrisTarget>,
+        skipped: bool,
+    },
+    Exception {
+        nsecs: u64,
+        exception: ETM3Exception,
+    },
+    Data {
+        nsecs: u64,
+        pc: Option<u32>,
+        addr: Option<u32>,
+        value: Option<u32>,
+    },
+}
+
+impl TraceState {
+    fn record(&mut self, record: &TraceRecord) -> Result<()> {
+        //
+        // We write each record in its entirety as we go (rather than
+        // buffering), as we are very likely to be interrupted when ingesting
+        // from an attached device.
+        //
+        if let Some(save) = &mut self.save {
+            let mut line = serde_json::to_string(record)?;
+            line.push('\n');
+            save.write_all(line.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    //
+    // Accumulates time for folded output:  the time since the previous
+    // instruction is attributed to that instruction's call stack.  The
+    // call stack of the current instruction (if it is displayed at all) is
+    // formed from the call sites on our stack.
+    //
+    fn fold(&mut self, hubris: &HubrisArchive, nsecs: u64, addr: Option<u32>) {
+        if let Some((then, stack)) = self.unfolded.take() {
+            *self.folded.entry(stack).or_default() +=
+                nsecs.saturating_sub(then);
+        }
+
+        if let Some(addr) = addr {
+            let stack = self
+                .stack
+                .iter()
+                .map(|&(_, _, caller, _)| etmcmd_frame(hubris, caller))
+                .chain(std::iter::once(etmcmd_frame(hubris, addr)))
+                .collect::<Vec<_>>()
+                .join(";");
+
+            self.unfolded = Some((nsecs, stack));
+        }
+    }
+
+    //
+    // Notes a call that has just been pushed on our stack, warning if it
+    // takes us past our maximum depth (if any) and remembering the call
+    // chain if it's the deepest that we've seen.  Our depth is relative to
+    // the beginning of the trace, and the chain consists of the function
+    // containing each call site, followed by the function being called.
+    //
+    fn call(&mut self, config: &TraceConfig, instr: &TraceInstruction) {
+        let depth = self.stack.len();
+        let deeper = self.deepest.as_ref().map_or(true, |d| depth > d.0);
+        let exceeded = config.max_depth.map_or(false, |max| depth == max + 1);
+
+        if !deeper && !exceeded {
+            return;
+        }
+
+        let hubris = config.hubris;
+
+        let chain = self
+            .stack
+            .iter()
+            .map(|&(_, _, caller, _)| etmcmd_frame(hubris, caller))
+            .chain(std::iter::once(match instr.target {
+                Some(HubrisTarget::Call(target)) => {
+                    etmcmd_frame(hubris, target)
+                }
+                _ => "<indirect>".to_string(),
+            }))
+            .collect::<Vec<_>>();
+
+        if exceeded {
+            warn!(
+                "call depth of {} exceeds maximum at {}: {}",
+                depth,
+                instr.nsecs,
+                chain.join(" -> ")
+            );
+        }
+
+        if deeper {
+            self.deepest = Some((depth, instr.nsecs, chain));
+        }
+    }
+
+    fn print_deepest(&self) {
+        let Some((depth, nsecs, chain)) = &self.deepest else {
+            humility::msg!("no calls traced");
+            return;
+        };
+
+        humility::msg!("maximum call depth of {} at {}:", depth, nsecs);
+
+        for (i, frame) in chain.iter().enumerate() {
+            humility::msg!("  {:>3} {}", i, frame);
+        }
+    }
+
+    fn print_folded(&self) {
+        for (stack, nsecs) in &self.folded {
+            if *nsecs > 0 {
+                println!("{} {}", stack, nsecs);
+            }
+        }
+    }
 }
 
 const HUMILITY_ETM_SWOSCALER: u16 = 7;
 const HUMILITY_ETM_TRACEID_MAX: u8 = 0x7f;
 const HUMILITY_ETM_ALWAYSTRUE: u32 = 0b110_1111;
+const HUMILITY_ETMV4_ALWAYSTRUE: u32 = 0b1;
+const HUMILITY_ETMV4_SYNCPERIOD: u32 = 10;
+
+//
+// ETMv4 has its ID register 1 (TRCIDR1) at the same location as the ETMv3
+// ETMIDR, with its version in the same bits -- but where ETMv3 encodes its
+// major version less one, ETMv4 encodes it directly.  (Note that we can't
+// consult the ETMv3 ETMCCR first, as it is at the same location as the
+// ETMv4 TRCPRGCTLR.)
+//
+fn etmcmd_is_etmv4(core: &mut dyn Core) -> Result<bool> {
+    let trcidr1 = TRCIDR1::read(core)?;
+    log::trace!("{:?}", trcidr1);
+
+    Ok(trcidr1.trcarchmaj() == 4)
+}
 
 fn etmcmd_probe(core: &mut dyn Core) -> Result<()> {
     let coreinfo = CoreInfo::read(core)?;
@@ -110,6 +565,22 @@ fn etmcmd_probe(core: &mut dyn Core) -> Result<()> {
         );
     }
 
+    if etmcmd_is_etmv4(core)? {
+        let trcidr0 = TRCIDR0::read(core)?;
+        humility::msg!("{trcidr0:#x?}");
+
+        let trcidr1 = TRCIDR1::read(core)?;
+        humility::msg!("{trcidr1:#x?}");
+
+        let trcidr2 = TRCIDR2::read(core)?;
+        humility::msg!("{trcidr2:#x?}");
+
+        let trcconfigr = TRCCONFIGR::read(core)?;
+        humility::msg!("{trcconfigr:#x?}");
+
+        return Ok(());
+    }
+
     let etmccr = ETMCCR::read(core)?;
     humility::msg!("{etmccr:#x?}");
 
@@ -127,11 +598,146 @@ fn etmcmd_probe(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
+fn etmcmd_enable_etmv4(
+    core: &mut dyn Core,
+    clockscaler: Option<u16>,
+    traceid: u8,
+) -> Result<()> {
+    let coreinfo = CoreInfo::read(core)?;
+    let trcidr1 = TRCIDR1::read(core)?;
+
+    humility::msg!(
+        "ETMv{}.{} found",
+        trcidr1.trcarchmaj(),
+        trcidr1.trcarchmin()
+    );
+
+    //
+    // Enable TRCENA in the DEMCR and set up the SWO or TPIU, as the part
+    // dictates.
+    //
+    trace_port_enable(
+        core,
+        &coreinfo,
+        clockscaler.unwrap_or(HUMILITY_ETM_SWOSCALER),
+    )?;
+
+    //
+    // Now unlock the ETM, and clear the OS lock.
+    //
+    ETMLAR::unlock(core)?;
+
+    let mut oslar = TRCOSLAR::read(core)?;
+    oslar.set_oslk(false);
+    oslar.write(core)?;
+
+    //
+    // Disable the trace unit, and wait for it to become idle before we
+    // program it.
+    //
+    let mut prgctlr = TRCPRGCTLR::read(core)?;
+    prgctlr.set_enable(false);
+    prgctlr.write(core)?;
+
+    while !TRCSTATR::read(core)?.idle() {
+        continue;
+    }
+
+    //
+    // We want instruction trace only:  no branch broadcasting, cycle
+    // counting, context IDs, conditional tracing, or timestamps.
+    //
+    let mut configr = TRCCONFIGR::read(core)?;
+    configr.set_branch_broadcast(false);
+    configr.set_cycle_counting(false);
+    configr.set_context_id(false);
+    configr.set_vmid(false);
+    configr.set_cond(0);
+    configr.set_timestamp(false);
+    configr.set_return_stack(false);
+    log::trace!("will write {:#x?}", configr);
+    configr.write(core)?;
+
+    let mut eventctl0r = TRCEVENTCTL0R::read(core)?;
+    eventctl0r.set_event(0);
+    eventctl0r.write(core)?;
+
+    let mut eventctl1r = TRCEVENTCTL1R::read(core)?;
+    eventctl1r.set_insten(0);
+    eventctl1r.set_atb(false);
+    eventctl1r.set_lpoverride(false);
+    eventctl1r.write(core)?;
+
+    let mut stallctlr = TRCSTALLCTLR::read(core)?;
+    stallctlr.set_istall(true);
+    stallctlr.write(core)?;
+
+    let mut tsctlr = TRCTSCTLR::read(core)?;
+    tsctlr.set_event(0);
+    tsctlr.write(core)?;
+
+    let mut syncpr = TRCSYNCPR::read(core)?;
+    syncpr.set_period(HUMILITY_ETMV4_SYNCPERIOD);
+    syncpr.write(core)?;
+
+    let mut val = TRCTRACEIDR::read(core)?;
+    val.set_traceid(traceid.into());
+    val.write(core)?;
+    log::trace!("{:#x?}", TRCTRACEIDR::read(core)?);
+
+    //
+    // Trace everything:  set ViewInst to the always-true resource with the
+    // start/stop logic started, and with no address filtering.
+    //
+    let mut victlr = TRCVICTLR::read(core)?;
+    victlr.set_event(HUMILITY_ETMV4_ALWAYSTRUE);
+    victlr.set_ssstatus(true);
+    victlr.write(core)?;
+
+    let mut viiectlr = TRCVIIECTLR::read(core)?;
+    viiectlr.set_include(0);
+    viiectlr.set_exclude(0);
+    viiectlr.write(core)?;
+
+    let mut visssctlr = TRCVISSCTLR::read(core)?;
+    visssctlr.set_start(0);
+    visssctlr.set_stop(0);
+    visssctlr.write(core)?;
+
+    //
+    // Finally, enable the trace unit and wait for it to leave idle.
+    //
+    prgctlr.set_enable(true);
+    prgctlr.write(core)?;
+
+    while TRCSTATR::read(core)?.idle() {
+        continue;
+    }
+
+    humility::msg!("ETM enabled");
+
+    Ok(())
+}
+
 fn etmcmd_enable(
     core: &mut dyn Core,
     clockscaler: Option<u16>,
     traceid: u8,
+    cycle_accurate: bool,
+    data: bool,
 ) -> Result<()> {
+    if etmcmd_is_etmv4(core)? {
+        if cycle_accurate {
+            warn!("cycle-accurate tracing only supported for ETMv3.5");
+        }
+
+        if data {
+            warn!("data tracing only supported for ETMv3.5");
+        }
+
+        return etmcmd_enable_etmv4(core, clockscaler, traceid);
+    }
+
     let etmccr = ETMCCR::read(core)?;
 
     if !etmccr.has_etmidr() {
@@ -205,11 +811,38 @@ fn etmcmd_enable(
     etmcr.set_stall_processor(true);
     etmcr.set_port_size(1);
     etmcr.set_port_select(true);
+    etmcr.set_cycle_accurate_tracing(cycle_accurate);
+    etmcr.set_data_access(if data { 0b11 } else { 0 });
     etmcr.set_programming(true);
     etmcr.set_power_down(false);
     log::trace!("will write {:#x?}", etmcr);
     etmcr.write(core)?;
 
+    //
+    // Data trace is optional -- and on ETMs that don't implement it, the
+    // data access field of the ETMCR reads as zero.  If we asked for data
+    // trace and didn't get it, we press on with instruction trace.
+    //
+    if data && ETMCR::read(core)?.data_access() != 0b11 {
+        warn!("ETM does not support data tracing; tracing instructions only");
+        etmcr.set_data_access(0);
+    }
+
+    if etmcr.data_access() != 0 {
+        //
+        // Trace all data accesses:  ViewData is enabled by the always-true
+        // event, and excludes nothing.
+        //
+        let mut vdevr = ETMVDEVR::read(core)?;
+        vdevr.set_resource_a(HUMILITY_ETM_ALWAYSTRUE);
+        vdevr.write(core)?;
+
+        let mut vdcr3 = ETMVDCR3::read(core)?;
+        vdcr3.set_comparator_select(0);
+        vdcr3.set_exclude_only(true);
+        vdcr3.write(core)?;
+    }
+
     //
     // Set to the hard-wired always-true event
     //
@@ -254,6 +887,22 @@ fn etmcmd_enable(
 }
 
 fn etmcmd_disable(core: &mut dyn Core) -> Result<()> {
+    if etmcmd_is_etmv4(core)? {
+        let mut prgctlr = TRCPRGCTLR::read(core)?;
+
+        if !prgctlr.enable() {
+            humility::msg!("ETM not enabled");
+            return Ok(());
+        }
+
+        prgctlr.set_enable(false);
+        prgctlr.write(core)?;
+
+        humility::msg!("ETM disabled");
+
+        return Ok(());
+    }
+
     let mut etmcr = ETMCR::read(core)?;
 
     if etmcr.power_down() {
@@ -289,35 +938,83 @@ fn etmcmd_trace(
     let sym = hubris.instr_sym(addr).unwrap_or(("<unknown>", addr));
     let sigil = 2;
 
-    if !config.flowindent {
-        println!("{:-10} {:08x} {} {}:{}+{:x} {:x?}",
-            instr.nsecs, addr, c, module, sym.0, addr - sym.1, instr.target);
+    state.record(&TraceRecord::Instruction {
+        nsecs: instr.nsecs,
+        cycles: instr.cycles,
+        addr,
+        module: module.to_string(),
+        symbol: sym.0.to_string(),
+        offset: addr - sym.1,
+        target: instr.target,
+        skipped: instr.skipped,
+    })?;
+
+    if !config.flowindent && !config.folded {
+        if !etmcmd_trace_elsewhere(config, instr, module, state) {
+            println!("{:-10} {:08x} {} {}:{}+{:x} {:x?}",
+                instr.nsecs, addr, c, module, sym.0, addr - sym.1,
+                instr.target);
+        }
+
+        //
+        // We aren't indenting, but we still maintain our stack to be able
+        // to track our call depth.
+        //
+        match instr.target {
+            Some(HubrisTarget::Call(_)) | Some(HubrisTarget::IndirectCall) => {
+                state.stack.push((0, vec![], addr, instr.cycles));
+                state.call(config, instr);
+            }
+            Some(HubrisTarget::Return) => {
+                state.stack.pop();
+            }
+            _ => {}
+        }
+
         return Ok(());
     }
 
     let inlined = hubris.instr_inlined(addr, sym.1);
 
-    match state.target {
-        Some(HubrisTarget::Call(_)) | Some(HubrisTarget::IndirectCall) => {
-            state.indent += 2;
-            println!("{:-10} {:width$}-> {}:{}", instr.nsecs, "", module, sym.0,
-                width = state.indent);
-        }
-        None => {
-            println!("{:-10} {:width$} ? {}:{}", instr.nsecs, "", module, sym.0,
-                width = state.indent);
-        }
-        _ => {}
+    if let Some(HubrisTarget::Call(_)) | Some(HubrisTarget::IndirectCall) =
+        state.target
+    {
+        state.indent += 2;
     }
 
-    for (i, element) in inlined.iter().enumerate() {
-        if i < state.inlined.len() && element.id == state.inlined[i] {
-            continue;
+    let elsewhere = etmcmd_trace_elsewhere(config, instr, module, state);
+
+    //
+    // If we know our cycles, we attribute them (rather than time) to each
+    // stack.
+    //
+    if config.folded {
+        let weight = instr.cycles.unwrap_or(instr.nsecs);
+        state.fold(hubris, weight, (!elsewhere).then_some(addr));
+    }
+
+    if !elsewhere && !config.folded {
+        match state.target {
+            Some(HubrisTarget::Call(_)) | Some(HubrisTarget::IndirectCall) => {
+                println!("{:-10} {:width$}-> {}:{}", instr.nsecs, "", module,
+                    sym.0, width = state.indent);
+            }
+            None => {
+                println!("{:-10} {:width$} ? {}:{}", instr.nsecs, "", module,
+                    sym.0, width = state.indent);
+            }
+            _ => {}
         }
 
-        println!("{:-10} {:width$} | {}:{} {}", instr.nsecs, "", module,
-            element.name, element.id,
-            width = state.indent + (i * 2) + sigil);
+        for (i, element) in inlined.iter().enumerate() {
+            if i < state.inlined.len() && element.id == state.inlined[i] {
+                continue;
+            }
+
+            println!("{:-10} {:width$} | {}:{} {}", instr.nsecs, "", module,
+                element.name, element.id,
+                width = state.indent + (i * 2) + sigil);
+        }
     }
 
     state.inlined.clear();
@@ -336,16 +1033,31 @@ fn etmcmd_trace(
                 state.indent,
                 inlined.iter().map(|i| i.id).collect(),
                 instr.addr,
+                instr.cycles,
             ));
 
+            state.call(config, instr);
             state.indent = nindent;
 
             return Ok(());
         }
 
         Some(HubrisTarget::Return) => {
-            println!("{:-10} {:width$}<- {}:{}", instr.nsecs, "", module, sym.0,
-                width = state.indent);
+            //
+            // If we know our cycles, we indicate the cost of the function
+            // from which we are returning (including the cost of its call).
+            //
+            let cost = match (state.stack.last(), instr.cycles) {
+                (Some(&(_, _, _, Some(then))), Some(now)) => {
+                    format!(" ({} cycles)", now.saturating_sub(then))
+                }
+                _ => String::new(),
+            };
+
+            if !elsewhere && !config.folded {
+                println!("{:-10} {:width$}<- {}:{}{}", instr.nsecs, "", module,
+                    sym.0, cost, width = state.indent);
+            }
 
             if !state.stack.is_empty() {
                 let top = state.stack.pop().unwrap();
@@ -364,184 +1076,735 @@ fn etmcmd_trace(
     Ok(())
 }
 
+//
+// Determines if an instruction is outside of our filter (if any), in which
+// case it isn't displayed.  Rather than silently dropping instructions
+// outside of our filter, we display a single line upon leaving it.
+//
+#[rustfmt::skip::macros(println)]
+fn etmcmd_trace_elsewhere(
+    config: &TraceConfig,
+    instr: &TraceInstruction,
+    module: &str,
+    state: &mut TraceState,
+) -> bool {
+    if config.displayed(instr.addr, module) {
+        state.elsewhere = false;
+        return false;
+    }
+
+    if !state.elsewhere {
+        if !config.folded {
+            println!("{:-10} {:width$}-> elsewhere", instr.nsecs, "",
+                width = state.indent);
+        }
+
+        state.elsewhere = true;
+    }
+
+    true
+}
+
 fn etmcmd_trace_exception(
-    _config: &TraceConfig,
+    config: &TraceConfig,
     exception: &TraceException,
-    _state: &mut TraceState,
+    state: &mut TraceState,
 ) -> Result<()> {
-    println!("{:-10} {:8} X {:?}", exception.nsecs, "-", exception.exception);
+    state.record(&TraceRecord::Exception {
+        nsecs: exception.nsecs,
+        exception: exception.exception,
+    })?;
+
+    if !state.elsewhere && !config.folded {
+        println!(
+            "{:-10} {:8} X {:?}",
+            exception.nsecs, "-", exception.exception
+        );
+    }
 
     Ok(())
 }
 
-fn etmcmd_ingest(config: &TraceConfig, filename: &str) -> Result<()> {
-    let file = File::open(filename)?;
-    let mut rdr = csv::Reader::from_reader(file);
-    let mut curaddr: Option<u32> = None;
-    let mut lastaddr: Option<u32> = None;
-    let hubris = config.hubris;
+#[rustfmt::skip::macros(println)]
+fn etmcmd_trace_data(
+    config: &TraceConfig,
+    data: &TraceData,
+    state: &mut TraceState,
+) -> Result<()> {
+    state.record(&TraceRecord::Data {
+        nsecs: data.nsecs,
+        pc: data.pc,
+        addr: data.addr,
+        value: data.value,
+    })?;
+
+    if state.elsewhere || config.folded {
+        return Ok(());
+    }
 
-    let econfig = &ETM3Config {
-        alternative_encoding: true,
-        context_id: 0,
-        data_access: false,
-        traceid: config.traceid,
+    let target = match data.addr {
+        Some(addr) => match config.data_sym(addr) {
+            Some(sym) => format!("[0x{:x} {}]", addr, sym),
+            None => format!("[0x{:x}]", addr),
+        },
+        None => "[-]".to_string(),
     };
 
-    type SaleaeTraceRecord = (f64, u8, Option<String>, Option<String>);
+    let value = match data.value {
+        Some(value) => format!("= 0x{:x}", value),
+        None => "(value not traced)".to_string(),
+    };
 
-    let mut iter = rdr.deserialize();
-    let mut broken = false;
-    let mut target: (Option<u32>, Option<HubrisTarget>) = (None, None);
+    if !config.flowindent {
+        let pc = data.pc.map_or("-".to_string(), |pc| format!("{:08x}", pc));
+        println!("{:-10} {:8} D {} {}", data.nsecs, pc, target, value);
+        return Ok(());
+    }
 
-    let mut state = TraceState::default();
+    //
+    // With flowindent, we don't display the instruction that issued the
+    // access, so we indicate it with the access itself.
+    //
+    let from = match data.pc.and_then(|pc| config.hubris.instr_sym(pc)) {
+        Some((sym, base)) => {
+            format!(" at {}+{:x}", sym, data.pc.unwrap() - base)
+        }
+        None => String::new(),
+    };
 
-    etm_ingest(
-        econfig,
-        || {
-            if let Some(line) = iter.next() {
-                let record: SaleaeTraceRecord = line?;
-                Ok(Some((record.1, record.0)))
-            } else {
-                Ok(None)
-            }
-        },
-        |packet| {
-            let nsecs = (packet.time * 1_000_000_000_f64) as u64;
-
-            match (lastaddr, packet.header) {
-                (None, ETM3Header::ISync) | (Some(_), _) => {}
-                (None, _) => {
-                    if broken {
-                        return Ok(());
-                    }
+    println!("{:-10} {:width$}   D {} {}{}", data.nsecs, "", target, value,
+        from, width = state.indent);
 
-                    bail!("non-ISync packet at time {}", nsecs);
-                }
-            }
+    Ok(())
+}
 
-            let mut instr = |skipped| {
-                if broken {
-                    return Ok(());
-                }
+//
+// The state of ingesting ETM packets, shared between ingesting from a file
+// and ingesting from an attached device.
+//
+struct EtmIngestor<'a> {
+    config: &'a TraceConfig<'a>,
+    curaddr: Option<u32>,
+    lastaddr: Option<u32>,
+    broken: bool,
+    target: (Option<u32>, Option<HubrisTarget>),
+    exception: Option<ETM3Exception>,
+    state: TraceState,
 
-                let addr = curaddr.unwrap();
-                let mut l = 0;
+    /// Number of instructions decoded
+    decoded: u64,
 
-                curaddr = match hubris.instr_len(addr) {
-                    Some(len) => {
-                        l = len;
-                        Some(addr + len)
-                    }
-                    None => {
-                        warn!("unknown instruction length at {:x}!", addr);
-                        broken = true;
-                        None
-                    }
-                };
+    /// Number of instructions (or, for ETMv4, waypoints) that could not be
+    /// decoded because we had lost sync
+    lost: u64,
 
-                target = (Some(addr), hubris.instr_target(addr));
-                etmcmd_trace(
-                    config,
-                    &TraceInstruction {
-                        nsecs,
-                        addr,
-                        target: target.1,
-                        _len: l,
-                        skipped,
-                    },
-                    &mut state,
-                )
-            };
+    /// Addresses at which we lost sync
+    breaks: Vec<u32>,
 
-            println!("{:#x?}", packet);
+    /// Trace is cycle-accurate
+    cycle_accurate: bool,
 
-            match packet.header {
-                ETM3Header::PHeaderFormat1 { e, n } => {
-                    for _i in 0..e {
-                        instr(false)?;
-                    }
+    /// Cycles elapsed, if cycle-accurate
+    cycles: u64,
 
-                    for _i in 0..n {
-                        instr(true)?;
-                    }
+    /// Trace includes data addresses
+    data: bool,
+
+    /// Last data address, against which data addresses are compressed
+    lastdata: u32,
+}
+
+impl<'a> EtmIngestor<'a> {
+    fn new(config: &'a TraceConfig<'a>) -> Result<Self> {
+        let save = match &config.save {
+            Some(filename) => Some(File::create(filename)?),
+            None => None,
+        };
+
+        Ok(Self {
+            config,
+            curaddr: None,
+            lastaddr: None,
+            broken: false,
+            target: (None, None),
+            exception: None,
+            state: TraceState { save, ..Default::default() },
+            decoded: 0,
+            lost: 0,
+            breaks: vec![],
+            cycle_accurate: config.cycle_accurate,
+            cycles: 0,
+            data: config.data,
+            lastdata: 0,
+        })
+    }
+
+    //
+    // Summarizes our decoding coverage, indicating where we lost sync --
+    // and whether each such address is in the archive at all.
+    //
+    fn summarize(&self) {
+        let total = self.decoded + self.lost;
+
+        if total == 0 {
+            humility::msg!("no instructions decoded");
+            return;
+        }
+
+        humility::msg!(
+            "decoded {:.2}% of instructions ({} of {})",
+            (self.decoded as f64 / total as f64) * 100.0,
+            self.decoded,
+            total
+        );
+
+        if self.breaks.is_empty() {
+            return;
+        }
+
+        let hubris = self.config.hubris;
+
+        let breaks = self
+            .breaks
+            .iter()
+            .map(|&addr| match hubris.instr_mod(addr) {
+                Some(module) => format!("{:#x} (in {})", addr, module),
+                None => format!("{:#x} (not in archive)", addr),
+            })
+            .collect::<Vec<_>>();
+
+        humility::msg!(
+            "lost sync {} time{}: at {}",
+            breaks.len(),
+            if breaks.len() == 1 { "" } else { "s" },
+            breaks.join(", ")
+        );
+    }
+
+    fn econfig(&self) -> ETM3Config {
+        ETM3Config {
+            alternative_encoding: true,
+            cycle_accurate: self.cycle_accurate,
+            context_id: 0,
+            data_access: self.data,
+            traceid: self.config.traceid,
+        }
+    }
+
+    fn instr(&mut self, nsecs: u64, skipped: bool) -> Result<()> {
+        if self.broken {
+            self.lost += 1;
+            return Ok(());
+        }
+
+        let hubris = self.config.hubris;
+        let addr = self.curaddr.unwrap();
+        let mut l = 0;
+
+        self.curaddr = match hubris.instr_len(addr) {
+            Some(len) => {
+                l = len;
+                self.decoded += 1;
+                Some(addr + len)
+            }
+            None => {
+                warn!("unknown instruction length at {:x}!", addr);
+                self.broken = true;
+                self.lost += 1;
+                self.breaks.push(addr);
+                None
+            }
+        };
+
+        self.target = (Some(addr), hubris.instr_target(addr));
+        etmcmd_trace(
+            self.config,
+            &TraceInstruction {
+                nsecs,
+                cycles: self.cycle_accurate.then_some(self.cycles),
+                addr,
+                target: self.target.1,
+                _len: l,
+                skipped,
+            },
+            &mut self.state,
+        )
+    }
+
+    fn packet(&mut self, packet: &ETM3Packet) -> Result<()> {
+        let nsecs = (packet.time * 1_000_000_000_f64) as u64;
+
+        match (self.lastaddr, packet.header) {
+            (None, ETM3Header::ISync | ETM3Header::ISyncCycleCount)
+            | (Some(_), _) => {}
+            (None, _) => {
+                if self.broken {
+                    return Ok(());
                 }
-                ETM3Header::PHeaderFormat2 { e0, e1 } => {
-                    instr(e0)?;
-                    instr(e1)?;
+
+                bail!("non-ISync packet at time {}", nsecs);
+            }
+        }
+
+        log::trace!("{:#x?}", packet);
+
+        match packet.header {
+            //
+            // In cycle-accurate mode, each atom of a Format 1 P-header takes
+            // a cycle; the atoms of a Format 2 P-header share one.
+            //
+            ETM3Header::PHeaderFormat1 { e, n } => {
+                for _i in 0..e {
+                    self.wait(1);
+                    self.instr(nsecs, false)?;
                 }
-                ETM3Header::ExceptionExit
-                | ETM3Header::ASync
-                | ETM3Header::ISync
-                | ETM3Header::BranchAddress { .. } => {}
-                _ => {
-                    bail!("unhandled packet: {:#x?}", packet);
+
+                for _i in 0..n {
+                    self.wait(1);
+                    self.instr(nsecs, true)?;
                 }
             }
+            ETM3Header::PHeaderFormat2 { e0, e1 } => {
+                self.wait(1);
+                self.instr(nsecs, e0)?;
+                self.instr(nsecs, e1)?;
+            }
+            ETM3Header::PHeaderFormat0 => {
+                self.wait(1);
+            }
+            ETM3Header::PHeaderFormat3 { e, w } => {
+                self.wait(w as u64 + 1);
 
-            match packet.payload {
-                ETM3Payload::ISync { address, .. } => {
-                    if broken {
-                        warn!("re-railing at offset {}", packet.offset);
-                        broken = false;
-                        target = (None, None);
-                    }
+                if e {
+                    self.instr(nsecs, false)?;
+                }
+            }
+            ETM3Header::PHeaderFormat4 { n } => {
+                self.instr(nsecs, n)?;
+            }
+            ETM3Header::ExceptionExit
+            | ETM3Header::ASync
+            | ETM3Header::ISync
+            | ETM3Header::ISyncCycleCount
+            | ETM3Header::CycleCount
+            | ETM3Header::BranchAddress { .. }
+            | ETM3Header::NormalData { .. }
+            | ETM3Header::ValueNotTraced { .. } => {}
+            ETM3Header::DataSuppressed => {
+                warn!("data trace suppressed at offset {}", packet.offset);
+            }
+            _ => {
+                bail!("unhandled packet: {:#x?}", packet);
+            }
+        }
+
+        match packet.payload {
+            ETM3Payload::ISync { address, cycles, .. } => {
+                if let Some(cycles) = cycles {
+                    self.wait(cycles as u64);
+                }
 
-                    curaddr = Some(address);
-                    lastaddr = curaddr;
+                if self.broken {
+                    warn!("re-railing at offset {}", packet.offset);
+                    self.broken = false;
+                    self.target = (None, None);
                 }
-                ETM3Payload::BranchAddress { addr, mask, exception } => {
-                    curaddr = Some((lastaddr.unwrap() & mask) | addr);
-                    lastaddr = curaddr;
-
-                    match target {
-                        (
-                            Some(origin),
-                            Some(HubrisTarget::Direct(expected)),
-                        )
-                        | (Some(origin), Some(HubrisTarget::Call(expected))) => {
-                            if curaddr.unwrap() != expected {
-                                warn!(
-                                    "detected bad branch: at 0x{:x} expected \
+
+                self.curaddr = Some(address);
+                self.lastaddr = self.curaddr;
+            }
+            ETM3Payload::BranchAddress { addr, mask, exception } => {
+                self.curaddr = Some((self.lastaddr.unwrap() & mask) | addr);
+                self.lastaddr = self.curaddr;
+
+                match self.target {
+                    (Some(origin), Some(HubrisTarget::Direct(expected)))
+                    | (Some(origin), Some(HubrisTarget::Call(expected))) => {
+                        if self.curaddr.unwrap() != expected {
+                            warn!(
+                                "detected bad branch: at 0x{:x} expected \
                                 branch to 0x{:x}, found 0x{:x}; packet: {:x?}",
-                                    origin,
-                                    expected,
-                                    curaddr.unwrap(),
-                                    packet
-                                );
-                            }
+                                origin,
+                                expected,
+                                self.curaddr.unwrap(),
+                                packet
+                            );
                         }
+                    }
 
-                        (Some(origin), None) => {
-                            if exception.is_none() {
-                                warn!(
-                                    "detected bad branch: did not expect any \
+                    (Some(origin), None) => {
+                        if exception.is_none() {
+                            warn!(
+                                "detected bad branch: did not expect any \
                                 branch from 0x{:x}, but control transferred \
                                 to 0x{:x}; packet: {:x?}",
-                                    origin,
-                                    curaddr.unwrap(),
-                                    packet
-                                );
-                            }
+                                origin,
+                                self.curaddr.unwrap(),
+                                packet
+                            );
                         }
-
-                        (_, _) => {}
                     }
 
-                    if let Some(exception) = exception {
+                    (_, _) => {}
+                }
+
+                if let Some(exception) = exception {
+                    etmcmd_trace_exception(
+                        self.config,
+                        &TraceException { nsecs, exception },
+                        &mut self.state,
+                    )?;
+                }
+            }
+            ETM3Payload::CycleCount { cycles } => {
+                self.wait(cycles as u64);
+            }
+            ETM3Payload::Data { addr, mask, value } => {
+                let addr = addr.map(|addr| {
+                    self.lastdata = (self.lastdata & mask) | addr;
+                    self.lastdata
+                });
+
+                //
+                // A data access is associated with the last instruction we
+                // decoded -- unless we have lost sync.
+                //
+                let pc = if self.broken { None } else { self.target.0 };
+
+                etmcmd_trace_data(
+                    self.config,
+                    &TraceData { nsecs, pc, addr, value },
+                    &mut self.state,
+                )?;
+            }
+            ETM3Payload::None => {}
+        }
+
+        Ok(())
+    }
+
+    //
+    // Accounts for cycles (W atoms) in cycle-accurate trace.
+    //
+    fn wait(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    //
+    // In ETMv4, each atom denotes not an instruction but rather a waypoint
+    // (that is, a branch):  we execute instructions up to and including
+    // the next waypoint, which is taken for an E atom and not taken for an
+    // N atom.  A taken indirect branch is followed by an address packet.
+    //
+    fn atom(&mut self, nsecs: u64, taken: bool) -> Result<()> {
+        let hubris = self.config.hubris;
+
+        if self.broken {
+            self.lost += 1;
+            return Ok(());
+        }
+
+        while let (Some(addr), false) = (self.curaddr, self.broken) {
+            let target = hubris.instr_target(addr);
+            self.instr(nsecs, target.is_some() && !taken)?;
+
+            match (target, taken) {
+                (None, _) => continue,
+                (Some(_), false) => {}
+                (Some(HubrisTarget::Direct(dest)), true)
+                | (Some(HubrisTarget::Call(dest)), true) => {
+                    self.curaddr = Some(dest);
+                }
+                (Some(_), true) => {
+                    self.curaddr = None;
+                }
+            }
+
+            break;
+        }
+
+        Ok(())
+    }
+
+    fn packet_etmv4(&mut self, packet: &ETM4Packet) -> Result<()> {
+        let nsecs = (packet.time * 1_000_000_000_f64) as u64;
+
+        log::trace!("{:#x?}", packet);
+
+        match &packet.payload {
+            ETM4Payload::Atoms { atoms } => {
+                for &taken in atoms {
+                    self.atom(nsecs, taken)?;
+                }
+            }
+
+            ETM4Payload::Address { addr } => {
+                let addr = *addr;
+
+                match self.exception.take() {
+                    Some(exception) => {
+                        //
+                        // The address following an exception is the
+                        // preferred return address:  everything up to it
+                        // has been executed.  The address of the handler
+                        // will follow in its own address packet.
+                        //
+                        while let (Some(cur), false) =
+                            (self.curaddr, self.broken)
+                        {
+                            if cur >= addr {
+                                break;
+                            }
+
+                            self.instr(nsecs, false)?;
+                        }
+
                         etmcmd_trace_exception(
-                            config,
+                            self.config,
                             &TraceException { nsecs, exception },
-                            &mut state,
+                            &mut self.state,
                         )?;
+
+                        self.curaddr = None;
+                    }
+
+                    None => {
+                        if self.broken {
+                            warn!("re-railing at offset {}", packet.offset);
+                            self.broken = false;
+                            self.target = (None, None);
+                        }
+
+                        self.curaddr = Some(addr);
                     }
                 }
-                ETM3Payload::None => {}
             }
 
-            Ok(())
-        },
-    )?;
+            ETM4Payload::Exception { exception } => {
+                self.exception = Some(*exception);
+            }
+
+            ETM4Payload::Overflow => {
+                warn!("trace overflow at offset {}", packet.offset);
+                self.curaddr = None;
+            }
+
+            ETM4Payload::ExceptionReturn
+            | ETM4Payload::TraceInfo
+            | ETM4Payload::TraceOn
+            | ETM4Payload::Discard => {
+                self.curaddr = None;
+            }
+
+            ETM4Payload::Timestamp { .. } | ETM4Payload::None => {}
+        }
+
+        Ok(())
+    }
+}
+
+//
+// Returns a sink for ITM trace that is interleaved with our ETM trace, which
+// writes the output of each stimulus port as it is decoded.
+//
+fn etmcmd_itm_sink(
+    config: &TraceConfig,
+) -> Result<impl FnMut(&TPIUPacket) -> Result<()>> {
+    let mut out: Box<dyn Write> = match &config.itm_output {
+        Some(filename) => Box::new(File::create(filename)?),
+        None => Box::new(std::io::stderr()),
+    };
+
+    Ok(itm_decoder(move |packet| {
+        if let ITMPayload::Instrumentation { payload, .. } = &packet.payload {
+            out.write_all(payload)?;
+        }
+
+        Ok(())
+    }))
+}
+
+//
+// Ingests ETMv3.5 trace, demultiplexing any ITM trace interleaved with it.
+//
+fn etmcmd_ingest_etm3(
+    ingestor: &mut EtmIngestor,
+    readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+) -> Result<()> {
+    let config = ingestor.config;
+    let econfig = ingestor.econfig();
+
+    match config.itm {
+        Some(itm) => etm_ingest_demux(
+            &econfig,
+            &[itm],
+            readnext,
+            |packet| ingestor.packet(packet),
+            etmcmd_itm_sink(config)?,
+        ),
+        None => {
+            etm_ingest(&econfig, readnext, |packet| ingestor.packet(packet))
+        }
+    }
+}
+
+fn etmcmd_ingest(
+    config: &TraceConfig,
+    filename: &str,
+    etmv4: bool,
+) -> Result<()> {
+    let file = File::open(filename)?;
+    let mut rdr = csv::Reader::from_reader(file);
+    let mut ingestor = EtmIngestor::new(config)?;
+
+    type SaleaeTraceRecord = (f64, u8, Option<String>, Option<String>);
+
+    let mut iter = rdr.deserialize();
+
+    let readnext = || {
+        if let Some(line) = iter.next() {
+            let record: SaleaeTraceRecord = line?;
+            Ok(Some((record.1, record.0)))
+        } else {
+            Ok(None)
+        }
+    };
+
+    if etmv4 {
+        etmv4_ingest(
+            &ETM4Config { traceid: Some(config.traceid) },
+            readnext,
+            |packet| ingestor.packet_etmv4(packet),
+        )?;
+    } else {
+        etmcmd_ingest_etm3(&mut ingestor, readnext)?;
+    }
+
+    if config.folded {
+        ingestor.state.print_folded();
+    }
+
+    ingestor.summarize();
+    ingestor.state.print_deepest();
+
+    Ok(())
+}
+
+//
+// Scans a capture for the trace identifiers that it contains, suggesting
+// one with which to decode it if it doesn't contain our own.
+//
+fn etmcmd_detect_traceid(traceid: u8, filename: &str) -> Result<()> {
+    let file = File::open(filename)?;
+    let mut rdr = csv::Reader::from_reader(file);
+
+    type SaleaeTraceRecord = (f64, u8, Option<String>, Option<String>);
+
+    let mut iter = rdr.deserialize();
+
+    let ids = tpiu_detect_ids(|| {
+        if let Some(line) = iter.next() {
+            let record: SaleaeTraceRecord = line?;
+            Ok(Some((record.1, record.0)))
+        } else {
+            Ok(None)
+        }
+    })?;
+
+    if ids.is_empty() {
+        bail!("no trace found in {filename}");
+    }
+
+    humility::msg!("trace identifiers present in {filename}:");
+
+    for (id, nbytes) in &ids {
+        humility::msg!("  {:<6} {:>12} bytes", format!("{id:#x}"), nbytes);
+    }
+
+    if ids.contains_key(&traceid) {
+        humility::msg!("trace with identifier {traceid:#x} is present");
+    } else {
+        let (likely, _) = ids.iter().max_by_key(|(_, &n)| n).unwrap();
+
+        humility::msg!(
+            "no trace with identifier {traceid:#x}; \
+            use \"--traceid {likely:#x}\" to decode"
+        );
+    }
+
+    Ok(())
+}
+
+fn etmcmd_ingest_attached(
+    core: &mut dyn Core,
+    config: &TraceConfig,
+) -> Result<()> {
+    let mut ingestor = EtmIngestor::new(config)?;
+    let mut bytes: Vec<u8> = vec![];
+    let mut ndx = 0;
+
+    let etmv4 = etmcmd_is_etmv4(core)?;
+
+    if etmv4 && config.itm.is_some() {
+        bail!("--itm is only supported for ETMv3.5");
+    }
+
+    //
+    // For ETMv3.5, whether our trace is cycle-accurate (and whether it
+    // includes data addresses) is determined by how the ETM has been
+    // configured (regardless of how we were invoked).
+    //
+    if !etmv4 {
+        let etmcr = ETMCR::read(core)?;
+        ingestor.cycle_accurate = etmcr.cycle_accurate_tracing();
+        ingestor.data = (etmcr.data_access() & 0b10) != 0;
+    }
+
+    //
+    // If we have a SWO unit, our trace data isn't formatted.
+    //
+    let swo = CoreInfo::read(core)?.address(CoreSightComponent::SWO);
+    let start = Instant::now();
+
+    //
+    // We stop ingesting on Ctrl-C to be able to emit any folded stacks and
+    // our summary.
+    //
+    static DONE: AtomicBool = AtomicBool::new(false);
+    ctrlc::set_handler(|| DONE.store(true, Ordering::SeqCst))?;
+
+    let readnext = || {
+        while ndx == bytes.len() {
+            if DONE.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+
+            bytes = core.read_swv()?;
+            ndx = 0;
+        }
+        ndx += 1;
+        Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
+    };
+
+    if etmv4 {
+        let traceid = match swo {
+            Some(_) => None,
+            None => Some(config.traceid),
+        };
+
+        etmv4_ingest(&ETM4Config { traceid }, readnext, |packet| {
+            ingestor.packet_etmv4(packet)
+        })?;
+    } else {
+        etmcmd_ingest_etm3(&mut ingestor, readnext)?;
+    }
+
+    if config.folded {
+        ingestor.state.print_folded();
+    }
+
+    ingestor.summarize();
+    ingestor.state.print_deepest();
 
     Ok(())
 }
@@ -560,6 +1823,89 @@ fn etmcmd_output(core: &mut dyn Core) -> Result<()> {
     }
 }
 
+fn etmcmd_replay(config: &TraceConfig, filename: &str) -> Result<()> {
+    let file = BufReader::new(File::open(filename)?);
+    let mut state = TraceState::default();
+
+    for (lineno, line) in file.lines().enumerate() {
+        let line = line?;
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let record: TraceRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                bail!("bad record at line {}: {}", lineno + 1, e);
+            }
+        };
+
+        match record {
+            TraceRecord::Instruction {
+                nsecs,
+                cycles,
+                addr,
+                target,
+                skipped,
+                ..
+            } => {
+                etmcmd_trace(
+                    config,
+                    &TraceInstruction {
+                        nsecs,
+                        cycles,
+                        addr,
+                        _len: 0,
+                        target,
+                        skipped,
+                    },
+                    &mut state,
+                )?;
+            }
+            TraceRecord::Exception { nsecs, exception } => {
+                etmcmd_trace_exception(
+                    config,
+                    &TraceException { nsecs, exception },
+                    &mut state,
+                )?;
+            }
+            TraceRecord::Data { nsecs, pc, addr, value } => {
+                etmcmd_trace_data(
+                    config,
+                    &TraceData { nsecs, pc, addr, value },
+                    &mut state,
+                )?;
+            }
+        }
+    }
+
+    if config.folded {
+        state.print_folded();
+    }
+
+    state.print_deepest();
+
+    Ok(())
+}
+
+//
+// Builds a map of the archive's variables by address, against which the
+// targets of data accesses are resolved.
+//
+fn etmcmd_variables(hubris: &HubrisArchive) -> BTreeMap<u32, (&str, usize)> {
+    let mut variables = BTreeMap::new();
+
+    for (name, v) in hubris.qualified_variables() {
+        if v.size != 0 {
+            let name = name.rsplit("::").next().unwrap_or(name);
+            variables.entry(v.addr).or_insert((name, v.size));
+        }
+    }
+
+    variables
+}
+
 fn etmcmd(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
     let hubris = context.archive.as_ref().unwrap();
@@ -573,14 +1919,51 @@ fn etmcmd(context: &mut ExecutionContext) -> Result<()> {
         bail!("traceid has a maximum value of {:x}", HUMILITY_ETM_TRACEID_MAX);
     }
 
+    let config = TraceConfig {
+        hubris,
+        flowindent: subargs.flowindent,
+        folded: subargs.folded,
+        traceid: subargs.traceid,
+        cycle_accurate: subargs.cycle_accurate,
+        data: subargs.data,
+        save: subargs.save.clone(),
+        task: subargs.task.clone(),
+        range: subargs.range,
+        variables: etmcmd_variables(hubris),
+        itm: subargs.itm,
+        itm_output: subargs.itm_output.clone(),
+        max_depth: subargs.max_depth,
+    };
+
+    if subargs.itm == Some(traceid) {
+        bail!("ITM and ETM must have different trace identifiers");
+    }
+
+    if let Some(task) = &subargs.task {
+        if hubris.lookup_task(task).is_none() && task != "kernel" {
+            bail!("no such task: {}", task);
+        }
+    }
+
+    if let Some(replay) = &subargs.replay {
+        match etmcmd_replay(&config, replay) {
+            Err(e) => {
+                bail!("failed to replay {}: {}", replay, e);
+            }
+            _ => {
+                return Ok(());
+            }
+        }
+    }
+
     if let Some(ingest) = &subargs.ingest {
-        let config = TraceConfig {
-            hubris,
-            flowindent: subargs.flowindent,
-            traceid: subargs.traceid,
-        };
+        if subargs.detect_traceid {
+            return etmcmd_detect_traceid(traceid, ingest).with_context(|| {
+                format!("failed to detect trace identifiers in {ingest}")
+            });
+        }
 
-        match etmcmd_ingest(&config, ingest) {
+        match etmcmd_ingest(&config, ingest, subargs.etmv4) {
             Err(e) => {
                 bail!("failed to ingest {}: {}", ingest, e);
             }
@@ -603,7 +1986,17 @@ fn etmcmd(context: &mut ExecutionContext) -> Result<()> {
     }
 
     if subargs.enable {
-        rval = etmcmd_enable(core.as_mut(), subargs.clockscaler, traceid);
+        if subargs.attach {
+            core.init_swv()?;
+        }
+
+        rval = etmcmd_enable(
+            core.as_mut(),
+            subargs.clockscaler,
+            traceid,
+            subargs.cycle_accurate,
+            subargs.data,
+        );
     }
 
     if subargs.disable {
@@ -613,6 +2006,17 @@ fn etmcmd(context: &mut ExecutionContext) -> Result<()> {
     core.run()?;
     humility::msg!("core resumed");
 
+    if rval.is_ok() && subargs.attach {
+        match etmcmd_ingest_attached(core.as_mut(), &config) {
+            Err(e) => {
+                bail!("failed to ingest from attached device: {}", e);
+            }
+            _ => {
+                return Ok(());
+            }
+        }
+    }
+
     if subargs.output {
         match etmcmd_output(core.as_mut()) {
             Err(e) => {
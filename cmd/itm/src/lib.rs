@@ -31,9 +31,131 @@
 //! Task #7 Divide-by-zero
 //! ```
 //!
+//! ITM can additionally carry hardware packets from the Data Watchpoint and
+//! Trace (DWT) unit.  To enable periodic PC sampling (every specified number
+//! of cycles, rounded to what the DWT can represent), use `--sample`; to
+//! enable exception trace, use `--exceptions`.  PC samples and exception
+//! entry and exit are then displayed as they are ingested:
+//!
+//! ```console
+//! $ humility itm -ea --sample 1024 --exceptions
+//! humility: attached via ST-Link
+//! humility: core halted
+//! humility: core resumed
+//! humility: ITM synchronization packet found at offset 6
+//! PC 0x08001a24 idle:main+0x14
+//! exception entered SysTick
+//! exception exited SysTick
+//! exception returned Thread
+//! PC 0x08001a24 idle:main+0x14
+//! PC sleeping
+//! ```
+//!
+//! Which of these the DWT supports varies by part.  `--probe` concludes
+//! with a summary of the DWT's capabilities, including each of its
+//! comparators and whether it is already in use:
+//!
+//! ```console
+//! $ humility itm --probe
+//! ...
+//! humility: DWT capabilities:
+//! humility:   comparators        4
+//! humility:   cycle counter      present
+//! humility:   profiling counters present
+//! humility:   PC sampling        present
+//! humility:   exception trace    present
+//! humility:   external trigger   present
+//! humility:   comparator 0       available
+//! humility:   comparator 1       available; linked data value match
+//! humility:   comparator 2       available
+//! humility:   comparator 3       available; linked data value match
+//! ```
+//!
+//! To correlate output with time, use `--timestamps` when enabling ITM to
+//! enable ITM local timestamps, and again when ingesting to prefix each line
+//! of instrumentation output with the time (in seconds) at which it was
+//! emitted.  Time is reconstructed from the CPU clock as implied by the
+//! clock scaler:  when ingesting from a file, this must be specified with
+//! `--clockscaler` (otherwise timestamps are displayed in cycles); when
+//! attached, it is read from the device.
+//!
+//! When enabling ITM, the clock scaler is derived from the CPU clock as
+//! found in the archive.  If the clock can't be determined (or if the
+//! derived scaler yields garbage), `--autoscale` can be used with `--enable`
+//! and `--attach` to instead sweep a series of candidate clock scalers,
+//! capturing SWV data briefly for each and selecting the one that yields
+//! valid ITM synchronization packets.  The selected clock scaler is
+//! reported so that it can be specified with `--clockscaler` thereafter:
+//!
+//! ```console
+//! $ humility itm -ea --autoscale
+//! humility: core halted
+//! humility: detected clock scaler of 199; use "-c 199" to skip detection
+//! humility: core resumed
+//! ...
+//! ```
+//!
+//! Instrumentation output can be restricted to a single stimulus port with
+//! `--port`.  Alternatively, `--split` writes the output of each stimulus
+//! port to its own file (named `port0`, `port1`, etc.) in the specified
+//! directory:
+//!
+//! ```console
+//! $ humility itm -a --split ./itm-out
+//! ```
+//!
+//! By default, instrumentation output is displayed as it arrives, which
+//! can result in output from different stimulus ports being interleaved.
+//! To instead buffer the output of each port until a complete line has been
+//! received and then display the line (prefixed with its port), use
+//! `--lines`; the delimiter can be changed from newline with `--delimiter`.
+//! Any partial lines are displayed when ingesting ends (including on
+//! Ctrl-C when attached).
+//!
+//! Some stimulus ports carry binary data rather than text.  To interpret
+//! the output of such a port as a sequence of fixed-size integers, use
+//! `--decode` to specify the port and the format of its values (one of
+//! `u8`, `i8`, `u16le`, `u16be`, `i16le`, `i16be`, `u32le`, `u32be`,
+//! `i32le` or `i32be`); it may be specified once for each port to be
+//! decoded.  Values are reassembled across packet boundaries, and each is
+//! displayed as a record, while the output of other ports remains text:
+//!
+//! ```console
+//! $ humility itm --ingest ./trace.csv --decode port3=u32le
+//! humility: ITM synchronization packet found at offset 6
+//! Task #7 Divide-by-zero
+//! port 3: u32le 0x0000002a (42)
+//! port 3: u32le 0x0001e240 (123456)
+//! ```
+//!
+//! To be able to decode the output of an attached device again later (e.g.,
+//! with different options), use `--capture` to also write the raw SWV data
+//! to a file as it arrives.  Such a file can then be specified with
+//! `--ingest`; raw SWV data is assumed if the file isn't a Saleae CSV
+//! export, or if `--raw` is specified (as is implied by a `.bin`, `.raw` or
+//! `.swv` extension).  Note that if the device's trace port bypasses the
+//! TPIU formatter (as is the case for parts with a SWO), `--bypass` must
+//! also be specified when ingesting:
+//!
+//! ```console
+//! $ humility itm -a --capture itm.swv
+//! humility: capturing raw SWV data to itm.swv
+//! ...
+//! $ humility itm --ingest itm.swv
+//! ```
+//!
+//! When attached, ingesting normally continues until Ctrl-C.  To instead
+//! stop once the target has gone quiet (e.g., to capture the output of a
+//! test from a script), use `--idle-timeout` to specify the number of
+//! milliseconds without SWV data after which ingesting should end:
+//!
+//! ```console
+//! $ humility itm -a --lines --idle-timeout 2000
+//! ```
+//!
 
 use anyhow::{bail, Context, Result};
-use clap::{CommandFactory, Parser};
+use clap::{ArgGroup, CommandFactory, Parser};
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
@@ -43,15 +165,23 @@ use humility_cortex::debug::*;
 use humility_cortex::dwt::*;
 use humility_cortex::itm::*;
 use humility_cortex::scs::*;
+use humility_cortex::swo::*;
 use humility_cortex::tpiu::*;
-use std::fs::File;
-use std::io::Read;
-use std::time::Instant;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 const ITM_TRACEID_MAX: u8 = 0x7f;
 
 #[derive(Parser, Debug)]
-#[clap(name = "itm", about = env!("CARGO_PKG_DESCRIPTION"))]
+#[clap(
+    name = "itm", about = env!("CARGO_PKG_DESCRIPTION"),
+    group = ArgGroup::new("clocked").multiple(true)
+)]
 struct ItmArgs {
     /// probe for ITM capability on attached device
     #[clap(
@@ -60,7 +190,10 @@ struct ItmArgs {
     probe: bool,
 
     /// enable ITM on attached device
-    #[clap(long, short, conflicts_with_all = &["disable", "ingest"])]
+    #[clap(
+        long, short, group = "clocked",
+        conflicts_with_all = &["disable", "ingest"]
+    )]
     enable: bool,
 
     /// disable ITM on attached device
@@ -75,7 +208,7 @@ struct ItmArgs {
     traceid: u8,
 
     /// ingest ITM data as CSV
-    #[clap(long, short, value_name = "filename")]
+    #[clap(long, short, value_name = "filename", group = "clocked")]
     ingest: Option<String>,
 
     /// ingest directly from attached device
@@ -87,14 +220,526 @@ struct ItmArgs {
     bypass: bool,
 
     /// sets the value of SWOSCALER
-    #[clap(long, short, value_name = "scaler", requires = "enable",
+    #[clap(long, short, value_name = "scaler", requires = "clocked",
         parse(try_from_str = parse_int::parse),
     )]
     clockscaler: Option<u16>,
 
+    /// determine SWOSCALER by sweeping candidate values
+    #[clap(
+        long, requires_all = &["enable", "attach"],
+        conflicts_with = "clockscaler"
+    )]
+    autoscale: bool,
+
     /// reset target
     #[clap(long, short, requires = "attach")]
     reset: bool,
+
+    /// stop ingesting once no data has arrived for the specified duration
+    #[clap(long, value_name = "ms", requires = "attach",
+        parse(try_from_str = parse_int::parse),
+    )]
+    idle_timeout: Option<u64>,
+
+    /// enable DWT PC sampling every specified number of cycles
+    #[clap(long, value_name = "cycles", requires = "enable",
+        parse(try_from_str = parse_int::parse),
+    )]
+    sample: Option<u32>,
+
+    /// enable DWT exception trace
+    #[clap(long, requires = "enable")]
+    exceptions: bool,
+
+    /// enable and display local timestamps
+    #[clap(long, conflicts_with = "disable")]
+    timestamps: bool,
+
+    /// only display output from the specified stimulus port
+    #[clap(long, value_name = "port",
+        parse(try_from_str = parse_int::parse),
+    )]
+    port: Option<u32>,
+
+    /// write the output of each stimulus port to its own file
+    #[clap(long, value_name = "directory")]
+    split: Option<PathBuf>,
+
+    /// display output from each stimulus port a line at a time
+    #[clap(long, conflicts_with = "split")]
+    lines: bool,
+
+    /// sets the line delimiter (as a byte value) for --lines
+    #[clap(long, value_name = "byte", requires = "lines",
+        default_value_t = b'\n', parse(try_from_str = parse_int::parse),
+    )]
+    delimiter: u8,
+
+    /// decode a stimulus port's output as integers of the given format
+    #[clap(long, value_name = "port=format", multiple_occurrences = true,
+        conflicts_with = "split", parse(try_from_str = parse_decode),
+    )]
+    decode: Vec<(u32, ItmDecoder)>,
+
+    /// write the raw SWV data ingested from the attached device to a file
+    #[clap(long, value_name = "filename", requires = "attach")]
+    capture: Option<PathBuf>,
+
+    /// ingested file is raw SWV data rather than CSV (implied by a .bin,
+    /// .raw or .swv extension)
+    #[clap(long, requires = "ingest")]
+    raw: bool,
+}
+
+//
+// A decoder for a stimulus port that carries binary data:  the port's
+// output is interpreted as a sequence of integers of a fixed size.
+//
+#[derive(Copy, Clone, Debug)]
+struct ItmDecoder {
+    size: usize,
+    signed: bool,
+    big_endian: bool,
+}
+
+impl ItmDecoder {
+    fn name(&self) -> String {
+        format!(
+            "{}{}{}",
+            if self.signed { "i" } else { "u" },
+            self.size * 8,
+            match (self.size, self.big_endian) {
+                (1, _) => "",
+                (_, true) => "be",
+                (_, false) => "le",
+            }
+        )
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let mut buf = [0u8; 4];
+
+        let value = if self.big_endian {
+            buf[4 - self.size..].copy_from_slice(bytes);
+            u32::from_be_bytes(buf)
+        } else {
+            buf[..self.size].copy_from_slice(bytes);
+            u32::from_le_bytes(buf)
+        };
+
+        let width = self.size * 2;
+
+        if self.signed {
+            let shift = 32 - self.size * 8;
+            let signed = ((value << shift) as i32) >> shift;
+            format!("{} 0x{:0width$x} ({})", self.name(), value, signed)
+        } else {
+            format!("{} 0x{:0width$x} ({})", self.name(), value, value)
+        }
+    }
+}
+
+fn parse_decode(s: &str) -> Result<(u32, ItmDecoder)> {
+    let Some((port, format)) = s.split_once('=') else {
+        bail!("expected port=format (e.g., \"port3=u32le\")");
+    };
+
+    let port = port.strip_prefix("port").unwrap_or(port);
+    let port: u32 = parse_int::parse(port)
+        .with_context(|| format!("invalid stimulus port \"{port}\""))?;
+
+    if port > 31 {
+        bail!("stimulus port must be between 0 and 31");
+    }
+
+    let (size, signed, big_endian) = match format {
+        "u8" => (1, false, false),
+        "i8" => (1, true, false),
+        "u16le" => (2, false, false),
+        "u16be" => (2, false, true),
+        "i16le" => (2, true, false),
+        "i16be" => (2, true, true),
+        "u32le" => (4, false, false),
+        "u32be" => (4, false, true),
+        "i32le" => (4, true, false),
+        "i32be" => (4, true, true),
+        _ => bail!(
+            "unknown format \"{format}\"; expected one of u8, i8, \
+            u16le, u16be, i16le, i16be, u32le, u32be, i32le or i32be"
+        ),
+    };
+
+    Ok((port, ItmDecoder { size, signed, big_endian }))
+}
+
+//
+// The display of ingested ITM packets.  When displaying timestamps, as a
+// local timestamp denotes the time of the packets that precede it,
+// instrumentation output is held until the timestamp following it arrives.
+// When displaying lines, each port's output is held until its delimiter.
+// When decoding a port, its output is held until a complete value has been
+// received.
+//
+struct ItmOutput<'a> {
+    hubris: &'a HubrisArchive,
+    raw_ports: bool,
+    timestamps: bool,
+    hz: Option<u64>,
+    cycles: u64,
+    pending: Vec<(u32, u8)>,
+    linestart: bool,
+    port: Option<u32>,
+    split: Option<PathBuf>,
+    files: HashMap<u32, File>,
+    delimiter: Option<u8>,
+    lines: BTreeMap<u32, Vec<u8>>,
+    decoders: HashMap<u32, ItmDecoder>,
+    partial: BTreeMap<u32, Vec<u8>>,
+}
+
+impl<'a> ItmOutput<'a> {
+    fn new(hubris: &'a HubrisArchive, subargs: &ItmArgs) -> Result<Self> {
+        if let Some(dir) = &subargs.split {
+            fs::create_dir_all(dir).with_context(|| {
+                format!("failed to create {}", dir.display())
+            })?;
+        }
+
+        Ok(Self {
+            hubris,
+            raw_ports: false,
+            timestamps: subargs.timestamps,
+            hz: subargs.clockscaler.map(swoscaler_clock),
+            cycles: 0,
+            pending: vec![],
+            linestart: true,
+            port: subargs.port,
+            split: subargs.split.clone(),
+            files: HashMap::new(),
+            delimiter: if subargs.lines {
+                Some(subargs.delimiter)
+            } else {
+                None
+            },
+            lines: BTreeMap::new(),
+            decoders: subargs.decode.iter().copied().collect(),
+            partial: BTreeMap::new(),
+        })
+    }
+
+    //
+    // Writes the output of a stimulus port to its own file, creating the
+    // file upon first output.
+    //
+    fn write_port(
+        &mut self,
+        dir: &Path,
+        port: u32,
+        payload: &[u8],
+    ) -> Result<()> {
+        let file = match self.files.entry(port) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = dir.join(format!("port{}", port));
+                let file = File::create(&path).with_context(|| {
+                    format!("failed to create {}", path.display())
+                })?;
+
+                entry.insert(file)
+            }
+        };
+
+        file.write_all(payload)?;
+
+        Ok(())
+    }
+
+    fn timestamp(&self) -> String {
+        if !self.timestamps {
+            return String::new();
+        }
+
+        match self.hz {
+            Some(hz) => format!("[{:12.6}] ", self.cycles as f64 / hz as f64),
+            None => format!("[{:12}] ", self.cycles),
+        }
+    }
+
+    fn display_line(&self, port: u32, line: &[u8]) {
+        let line = line.iter().map(|&b| b as char).collect::<String>();
+        println!("{}port {}: {}", self.timestamp(), port, line);
+    }
+
+    fn display_decoded(&mut self, port: u32, decoder: ItmDecoder, b: u8) {
+        let partial = self.partial.entry(port).or_default();
+        partial.push(b);
+
+        if partial.len() < decoder.size {
+            return;
+        }
+
+        let value = decoder.decode(partial);
+        partial.clear();
+
+        //
+        // If we are in the middle of a line of text from another port,
+        // terminate it so our record stands on its own.
+        //
+        if self.delimiter.is_none() && !self.linestart {
+            println!();
+            self.linestart = true;
+        }
+
+        println!("{}port {}: {}", self.timestamp(), port, value);
+    }
+
+    fn display(&mut self, port: u32, b: u8) {
+        if let Some(&decoder) = self.decoders.get(&port) {
+            self.display_decoded(port, decoder, b);
+            return;
+        }
+
+        match self.delimiter {
+            Some(delimiter) => {
+                let line = self.lines.entry(port).or_default();
+
+                if b != delimiter {
+                    line.push(b);
+                    return;
+                }
+
+                let line = std::mem::take(line);
+                self.display_line(port, &line);
+            }
+            None => {
+                if self.linestart {
+                    print!("{}", self.timestamp());
+                }
+
+                print!("{}", b as char);
+                self.linestart = b == b'\n';
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        for (port, b) in std::mem::take(&mut self.pending) {
+            self.display(port, b);
+        }
+    }
+
+    //
+    // Called when ingesting has ended to display anything that we are
+    // holding.
+    //
+    fn finish(&mut self) {
+        self.flush();
+
+        for (port, line) in std::mem::take(&mut self.lines) {
+            if !line.is_empty() {
+                self.display_line(port, &line);
+            }
+        }
+
+        for (port, partial) in std::mem::take(&mut self.partial) {
+            if !partial.is_empty() {
+                humility::warn!(
+                    "port {}: discarding {} trailing byte(s) of an \
+                    incomplete value: {:x?}",
+                    port,
+                    partial.len(),
+                    partial
+                );
+            }
+        }
+    }
+
+    fn packet(&mut self, packet: &ITMPacket) -> Result<()> {
+        match &packet.payload {
+            ITMPayload::Instrumentation { payload, port } => {
+                if self.port.map_or(false, |p| p != *port) {
+                    return Ok(());
+                }
+
+                if let Some(dir) = self.split.clone() {
+                    self.write_port(&dir, *port, payload)?;
+                } else if self.raw_ports
+                    && *port > 1
+                    && !self.decoders.contains_key(port)
+                {
+                    println!("{:x?}", payload);
+                } else if self.timestamps {
+                    self.pending.extend(payload.iter().map(|&b| (*port, b)));
+                } else {
+                    for &b in payload {
+                        self.display(*port, b);
+                    }
+                }
+            }
+            ITMPayload::LocalTimestamp { timedelta, .. } => {
+                self.cycles += *timedelta as u64;
+                self.flush();
+            }
+            _ => {
+                itmcmd_hardware(self.hubris, packet);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn itmcmd_exception_name(exception: u16) -> String {
+    match exception {
+        0 => "Thread".to_string(),
+        1 => "Reset".to_string(),
+        2 => "NMI".to_string(),
+        3 => "HardFault".to_string(),
+        4 => "MemManage".to_string(),
+        5 => "BusFault".to_string(),
+        6 => "UsageFault".to_string(),
+        11 => "SVCall".to_string(),
+        12 => "DebugMonitor".to_string(),
+        14 => "PendSV".to_string(),
+        15 => "SysTick".to_string(),
+        16.. => format!("IRQ{}", exception - 16),
+        _ => format!("exception {}", exception),
+    }
+}
+
+//
+// Displays the DWT hardware packets that we understand, resolving PC
+// samples to symbols if we have an archive.
+//
+fn itmcmd_hardware(hubris: &HubrisArchive, packet: &ITMPacket) {
+    match packet.payload {
+        ITMPayload::PCSample { pc: Some(pc) } => {
+            let module = hubris.instr_mod(pc).unwrap_or("<unknown>");
+
+            match hubris.instr_sym(pc) {
+                Some((sym, base)) => {
+                    println!(
+                        "PC 0x{:08x} {}:{}+0x{:x}",
+                        pc,
+                        module,
+                        sym,
+                        pc - base
+                    );
+                }
+                None => {
+                    println!("PC 0x{:08x} {}", pc, module);
+                }
+            }
+        }
+        ITMPayload::PCSample { pc: None } => {
+            println!("PC sleeping");
+        }
+        ITMPayload::ExceptionTrace { exception, function } => {
+            let function = match function {
+                ITMExceptionFunction::Entered => "entered",
+                ITMExceptionFunction::Exited => "exited",
+                ITMExceptionFunction::Returned => "returned",
+            };
+
+            println!(
+                "exception {} {}",
+                function,
+                itmcmd_exception_name(exception)
+            );
+        }
+        ITMPayload::EventCounter { wrapped } => {
+            log::trace!("DWT event counter wrapped: {:#x}", wrapped);
+        }
+        ITMPayload::Hardware { source, payload, len } => {
+            log::trace!("DWT source {} packet: {:x?}", source, &payload[..len]);
+        }
+        _ => {}
+    }
+}
+
+//
+// Summarize what the DWT can do:  how many comparators it has (and whether
+// they are in use), and whether it supports the cycle counter, the profiling
+// counters, PC sampling and exception trace.  Note that PC sampling and
+// exception trace are emitted as trace packets, and are therefore only
+// available if the DWT supports them; PC sampling additionally requires the
+// cycle counter.
+//
+fn itmcmd_probe_dwt(core: &mut dyn Core) -> Result<()> {
+    let ctrl = DWT_CTRL::read(core)?;
+    let supported = |absent: bool| if absent { "absent" } else { "present" };
+
+    if !DEMCR::read(core)?.trcena() {
+        humility::warn!(
+            "TRCENA is not set in the DEMCR; DWT capabilities may be \
+            misreported"
+        );
+    }
+
+    humility::msg!("DWT capabilities:");
+    humility::msg!("  {:<18} {}", "comparators", ctrl.num_comparators());
+    humility::msg!(
+        "  {:<18} {}",
+        "cycle counter",
+        supported(ctrl.no_cycle_counter())
+    );
+    humility::msg!(
+        "  {:<18} {}",
+        "profiling counters",
+        supported(ctrl.no_profiling_counter())
+    );
+    humility::msg!(
+        "  {:<18} {}",
+        "PC sampling",
+        supported(ctrl.no_trace_sampling() || ctrl.no_cycle_counter())
+    );
+    humility::msg!(
+        "  {:<18} {}",
+        "exception trace",
+        supported(ctrl.no_trace_sampling())
+    );
+    humility::msg!(
+        "  {:<18} {}",
+        "external trigger",
+        supported(ctrl.no_external_trigger())
+    );
+
+    for comparator in 0..ctrl.num_comparators() {
+        let base = dwt_comparator_base(comparator);
+        let comp = DWT_COMP::read(core, base)?.register;
+        let mask = DWT_MASK::read(core, base)?.register;
+        let function = DWT_FUNCTION::read(core, base)?.register;
+
+        let mut notes = vec![];
+
+        if function.function() == 0 {
+            notes.push("available".to_string());
+        } else {
+            notes.push(format!(
+                "in use (comp {:#x}, mask {:#x}, function {:#x})",
+                comp.comp(),
+                mask.mask(),
+                function.function()
+            ));
+        }
+
+        if function.lnk1ena() {
+            notes.push("linked data value match".to_string());
+        }
+
+        if function.id() != 0 {
+            notes.push(format!("match types {:#07b}", function.id()));
+        }
+
+        humility::msg!(
+            "  {:<18} {}",
+            format!("comparator {comparator}"),
+            notes.join("; ")
+        );
+    }
+
+    Ok(())
 }
 
 fn itmcmd_probe(core: &mut dyn Core, coreinfo: &CoreInfo) -> Result<()> {
@@ -121,7 +766,7 @@ fn itmcmd_probe(core: &mut dyn Core, coreinfo: &CoreInfo) -> Result<()> {
         _ => {}
     }
 
-    Ok(())
+    itmcmd_probe_dwt(core)
 }
 
 fn itmcmd_disable(core: &mut dyn Core) -> Result<()> {
@@ -149,24 +794,27 @@ fn itmcmd_disable(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
-fn itmcmd_ingest(subargs: &ItmArgs, filename: &str) -> Result<()> {
+fn itmcmd_ingest(
+    hubris: &HubrisArchive,
+    subargs: &ItmArgs,
+    filename: &str,
+) -> Result<()> {
     let file = File::open(filename)?;
     let traceid = if subargs.bypass { None } else { Some(subargs.traceid) };
 
-    let process = |packet: &ITMPacket| -> Result<()> {
-        if let ITMPayload::Instrumentation { payload, .. } = &packet.payload {
-            for p in payload {
-                print!("{}", *p as char);
-            }
-        }
-
-        Ok(())
-    };
+    let mut output = ItmOutput::new(hubris, subargs)?;
+    let process = |packet: &ITMPacket| output.packet(packet);
 
     let mut rdr = csv::Reader::from_reader(file);
 
-    match rdr.headers() {
-        Ok(_hdr) => {
+    let raw = subargs.raw
+        || matches!(
+            Path::new(filename).extension().and_then(|e| e.to_str()),
+            Some("bin" | "raw" | "swv")
+        );
+
+    let rval = match rdr.headers() {
+        Ok(_hdr) if !raw => {
             type SaleaeTraceRecord = (f64, u8, Option<String>, Option<String>);
             let mut iter = rdr.deserialize();
 
@@ -183,8 +831,10 @@ fn itmcmd_ingest(subargs: &ItmArgs, filename: &str) -> Result<()> {
                 process,
             )
         }
-        Err(_) => {
-            humility::msg!("not a Saleae trace file; assuming raw input");
+        _ => {
+            if !raw {
+                humility::msg!("not a Saleae trace file; assuming raw input");
+            }
 
             let mut file = File::open(filename)?;
             let mut buffer = [0; 1];
@@ -205,10 +855,14 @@ fn itmcmd_ingest(subargs: &ItmArgs, filename: &str) -> Result<()> {
                 process,
             )
         }
-    }
+    };
+
+    output.finish();
+    rval
 }
 
 fn itmcmd_ingest_attached(
+    hubris: &HubrisArchive,
     core: &mut dyn Core,
     coreinfo: &CoreInfo,
     subargs: &ItmArgs,
@@ -222,35 +876,89 @@ fn itmcmd_ingest_attached(
         Some(subargs.traceid)
     };
 
+    if subargs.timestamps && !ITM_TCR::read(core)?.timestamp_enable() {
+        bail!("local timestamps not enabled; enable ITM with --timestamps");
+    }
+
+    //
+    // When attached, we display output from ports other than 0 and 1 as
+    // raw bytes -- unless we have been asked for a particular port or for
+    // lines.
+    //
+    let mut output = ItmOutput::new(hubris, subargs)?;
+    output.raw_ports = subargs.port.is_none() && !subargs.lines;
+
+    //
+    // If we weren't told our clock scaler, determine it from the device.
+    //
+    if output.hz.is_none() {
+        let scaler = match coreinfo.address(CoreSightComponent::SWO) {
+            Some(swo) => SWO_CODR::read(core, swo)?.register.prescaler(),
+            None => TPIU_ACPR::read(core)?.swoscaler(),
+        };
+
+        output.hz = Some(swoscaler_clock(scaler as u16));
+    }
+
+    //
+    // Upon Ctrl-C, we stop ingesting so we can display anything we're
+    // holding.
+    //
+    static DONE: AtomicBool = AtomicBool::new(false);
+    ctrlc::set_handler(|| DONE.store(true, Ordering::SeqCst))?;
+
+    let mut capture = match &subargs.capture {
+        Some(path) => {
+            let file = File::create(path).with_context(|| {
+                format!("failed to create {}", path.display())
+            })?;
+            humility::msg!("capturing raw SWV data to {}", path.display());
+            Some(file)
+        }
+        None => None,
+    };
+
     let start = Instant::now();
+    let idle_timeout = subargs.idle_timeout.map(Duration::from_millis);
+    let mut last = start;
 
-    itm_ingest(
+    let rval = itm_ingest(
         traceid,
         || {
             while ndx == bytes.len() {
+                if DONE.load(Ordering::SeqCst) {
+                    return Ok(None);
+                }
+
+                if let Some(timeout) = idle_timeout {
+                    if last.elapsed() >= timeout {
+                        humility::msg!(
+                            "no data for {} ms; stopping",
+                            timeout.as_millis()
+                        );
+                        return Ok(None);
+                    }
+                }
+
                 bytes = core.read_swv()?;
                 ndx = 0;
-            }
-            ndx += 1;
-            Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
-        },
-        |packet| {
-            if let ITMPayload::Instrumentation { payload, port } =
-                &packet.payload
-            {
-                if *port > 1 {
-                    println!("{:x?}", payload);
-                    return Ok(());
+
+                if let Some(file) = &mut capture {
+                    file.write_all(&bytes)?;
                 }
 
-                for p in payload {
-                    print!("{}", *p as char);
+                if !bytes.is_empty() {
+                    last = Instant::now();
                 }
             }
-
-            Ok(())
+            ndx += 1;
+            Ok(Some((bytes[ndx - 1], start.elapsed().as_secs_f64())))
         },
-    )
+        |packet| output.packet(packet),
+    );
+
+    output.finish();
+    rval
 }
 
 fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
@@ -267,7 +975,7 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
     }
 
     if let Some(ingest) = &subargs.ingest {
-        match itmcmd_ingest(subargs, ingest) {
+        match itmcmd_ingest(hubris, subargs, ingest) {
             Err(e) => {
                 bail!("failed to ingest {}: {}", ingest, e);
             }
@@ -308,6 +1016,31 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
         let stim = 0x0000_000f;
         let clockscaler = match subargs.clockscaler {
             Some(value) => value,
+            None if subargs.autoscale => {
+                let detected =
+                    itm_detect_swoscaler(core, &coreinfo, traceid, stim);
+
+                match detected {
+                    Ok(Some(scaler)) => {
+                        humility::msg!(
+                            "detected clock scaler of {scaler}; \
+                            use \"-c {scaler}\" to skip detection"
+                        );
+                        scaler
+                    }
+                    Ok(None) => {
+                        core.run()?;
+                        bail!(
+                            "could not detect clock scaler; is the target \
+                            running with SWO connected?"
+                        );
+                    }
+                    Err(e) => {
+                        core.run()?;
+                        return Err(e);
+                    }
+                }
+            }
             None => {
                 if !hubris.loaded() {
                     core.run()?;
@@ -325,6 +1058,14 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
         };
 
         rval = itm_enable_explicit(core, &coreinfo, clockscaler, traceid, stim);
+
+        if rval.is_ok() && (subargs.sample.is_some() || subargs.exceptions) {
+            rval = itm_enable_dwt(core, subargs.sample, subargs.exceptions);
+        }
+
+        if rval.is_ok() && subargs.timestamps {
+            rval = itm_enable_timestamps(core);
+        }
     }
 
     core.run()?;
@@ -336,7 +1077,7 @@ fn itmcmd(context: &mut ExecutionContext) -> Result<()> {
     }
 
     if rval.is_ok() && subargs.attach {
-        match itmcmd_ingest_attached(core, &coreinfo, subargs) {
+        match itmcmd_ingest_attached(hubris, core, &coreinfo, subargs) {
             Err(e) => {
                 bail!("failed to ingest from attached device: {}", e);
             }
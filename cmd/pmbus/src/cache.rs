@@ -0,0 +1,135 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//
+// An on-disk cache of the results of `--scan`, allowing the PMBus devices
+// found on a bus (and the drivers that they were matched against) to be
+// known without scanning the bus again.  There is a cache file for each
+// archive, named by its image ID (or, failing that, its board and git
+// revision) and kept in the humility cache directory -- which is either
+// `$XDG_CACHE_HOME/humility` or `$HOME/.cache/humility`.  If neither of
+// these can be determined, nothing is cached.
+//
+
+use anyhow::{Context, Result};
+use humility::hubris::HubrisArchive;
+use humility_i2c::I2cArgs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A device found at an address when scanning
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanEntry {
+    pub address: u8,
+    pub rev: Option<u8>,
+    pub status: String,
+    pub mfr_id: Option<String>,
+    pub mfr_model: Option<String>,
+    pub driver: Option<String>,
+}
+
+/// The scanned buses of an archive, keyed by controller, port and segment
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanCache {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    buses: BTreeMap<String, Vec<ScanEntry>>,
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+
+    Some(dir.join("humility"))
+}
+
+fn cache_key(hubris: &HubrisArchive) -> Option<String> {
+    if let Some((_, imageid)) = &hubris.imageid {
+        return Some(imageid.iter().map(|b| format!("{b:02x}")).collect());
+    }
+
+    let board = hubris.manifest.board.as_ref()?;
+    let gitrev = hubris.manifest.gitrev.as_ref()?;
+
+    Some(format!("{board}-{gitrev}"))
+}
+
+fn bus_key(hargs: &I2cArgs) -> String {
+    match hargs.mux {
+        Some((mux, segment)) => {
+            format!("{}:{}:{mux}:{segment}", hargs.controller, hargs.port.name)
+        }
+        None => format!("{}:{}", hargs.controller, hargs.port.name),
+    }
+}
+
+impl ScanCache {
+    ///
+    /// Loads the cache for the specified archive.  A cache that is absent
+    /// (or that can't be read) is treated as empty.
+    ///
+    pub fn load(hubris: &HubrisArchive) -> Self {
+        let path = match (cache_dir(), cache_key(hubris)) {
+            (Some(dir), Some(key)) => dir.join(format!("pmbus-{key}.json")),
+            _ => return Self::default(),
+        };
+
+        let mut cache = match std::fs::read(&path) {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(cache) => cache,
+                Err(e) => {
+                    humility::warn!(
+                        "ignoring malformed scan cache {}: {e}",
+                        path.display()
+                    );
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        };
+
+        cache.path = Some(path);
+        cache
+    }
+
+    /// Returns the cached scan of the specified bus, if any
+    pub fn bus(&self, hargs: &I2cArgs) -> Option<&Vec<ScanEntry>> {
+        self.buses.get(&bus_key(hargs))
+    }
+
+    /// Returns the driver found at the specified device when scanned, if any
+    pub fn driver(&self, hargs: &I2cArgs) -> Option<&str> {
+        let address = hargs.address?;
+
+        self.bus(hargs)?
+            .iter()
+            .find(|entry| entry.address == address)
+            .and_then(|entry| entry.driver.as_deref())
+    }
+
+    /// Records the scan of the specified bus, writing out the cache
+    pub fn store(
+        &mut self,
+        hargs: &I2cArgs,
+        entries: Vec<ScanEntry>,
+    ) -> Result<()> {
+        self.buses.insert(bus_key(hargs), entries);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| {
+                format!("failed to create {}", dir.display())
+            })?;
+        }
+
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
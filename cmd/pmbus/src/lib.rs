@@ -51,7 +51,8 @@
 //! For the common case of devices known to the system, you can specify a device
 //! by name if it matches a single device in the system (e.g., `humility pmbus
 //! -d bmr491`).  In lieu of specifying a device, you can specify a rail via
-//! `--rail` (`-r`), e.g.:
+//! `--rail` (`-r`), in which case the I2C topology of the device (and the
+//! PMBus driver) is determined from the archive, e.g.:
 //!
 //! ```console
 //! $ humility pmbus --rail VDD_MEM_EFGH
@@ -104,12 +105,130 @@
 //!      +-----------------------------------------------------------------------
 //! ```
 //!
-//! You can also write a PMBus command with `--write` (`-w`), which allows for
-//! for particular fields to be written, e.g.:
+//! For the status commands (`STATUS_WORD`, `STATUS_VOUT`, `STATUS_IOUT`,
+//! etc.), any set bits (that is, any faults or warnings) are expanded even
+//! without `--verbose`:
+//!
+//! ```console
+//! $ humility pmbus -r VDD_MEM_ABCD --command STATUS_WORD
+//! humility: attached via ST-Link V3
+//! 0x79 STATUS_WORD               0x4800
+//!      |
+//!      | b14    0b1 = fault                    <= OutputCurrentFault
+//!      | b11    0b1 = POWER_GOOD negated       <= PowerGoodStatus
+//!      +-----------------------------------------------------------------------
+//! ```
+//!
+//! For devices with multiple rails, the rail is selected by writing the PMBus
+//! `PAGE` command.  To select a page explicitly, use `--page`; to run the
+//! specified command(s) on every page of the device, use `--page all`:
+//!
+//! ```console
+//! $ humility pmbus -d isl68224 --command READ_VOUT --page all
+//! humility: attached via ST-Link V3
+//! page 0:
+//! 0x8b READ_VOUT                 0x09c4 = 2.500V
+//! page 1:
+//! 0x8b READ_VOUT                 0x09c3 = 2.499V
+//! page 2:
+//! 0x8b READ_VOUT                 0x0704 = 1.796V
+//! ```
+//!
+//! To repeatedly run the specified command(s), specify an interval (in
+//! milliseconds) with `--interval` (`-i`).  Each result is prefixed with the
+//! time of its sample (in seconds since the epoch); use Ctrl-C to exit:
+//!
+//! ```console
+//! $ humility pmbus -r VDD_VCORE --command READ_TEMPERATURE_1 --interval 1000
+//! humility: attached via ST-Link V3
+//! 1697472000.125 0x8d READ_TEMPERATURE_1        0x0028 = 40.000°C
+//! 1697472001.231 0x8d READ_TEMPERATURE_1        0x0028 = 40.000°C
+//! 1697472002.338 0x8d READ_TEMPERATURE_1        0x0029 = 41.000°C
+//! ^C
+//! ```
+//!
+//! To log results for consumption by another program, use `--format` to
+//! emit them as `json` or `csv` rather than as a table.  Each result
+//! includes the command code and name, the raw bytes (as a hex string), the
+//! decoded value and any interpreted fields; results that are in error
+//! include an `error` field:
+//!
+//! ```console
+//! $ humility pmbus -r VDD_VCORE --command READ_VOUT,READ_IOUT --format csv
+//! humility: attached via ST-Link V3
+//! time,page,code,name,raw,value,interpreted,fields,error
+//! ,,0x8b,READ_VOUT,9d04,1181,1.181V,,
+//! ,,0x8c,READ_IOUT,1402,532,53.200A,,
+//! ```
+//!
+//! When one device is misbehaving and an identical one isn't, it can be
+//! useful to compare them:  `--compare` reads every command from the
+//! specified device and from the device at the specified address on the
+//! same bus, displaying only those commands whose results differ.  A device
+//! can also be compared against results previously saved with `--format
+//! json` (e.g., a known-good capture), by specifying the file instead of an
+//! address.  (As with reading a device, `--command` and `--page` can be
+//! used to limit or extend the commands compared.)
+//!
+//! ```console
+//! $ humility pmbus -d 0x24 -c 3 -p h --compare 0x27
+//! humility: attached via ST-Link V3
+//! CODE COMMAND                   0x24                       0x27
+//! 0x21 VOUT_COMMAND              0x0d33 = 3.300V            0x1400 = 5.000V
+//! 0x8b READ_VOUT                 0x0d3f = 3.311V            0x13ef = 4.982V
+//! 0x8c READ_IOUT                 0xd033 = 0.404A            0xd042 = 0.518A
+//! humility: 3 of 42 commands differ
+//! $ humility pmbus -r V3P3_SP_A2 --format json > golden.json
+//! $ humility pmbus -r V3P3_SP_A2 --compare golden.json
+//! ```
+//!
+//! Some commands (e.g., manufacturer-specific commands) are issued as a
+//! block write followed by a block read of the device's response.  To issue
+//! such a command, specify it (by name or by code) with `--block-command`,
+//! and the payload of the block write as comma-separated bytes with
+//! `--payload`.  The response is displayed as raw bytes, followed by its
+//! ASCII decoding (with any non-printable bytes displayed as `.`):
+//!
+//! ```console
+//! $ humility pmbus -r VDD_VCORE --block-command 0xd0 --payload 0x2,0x0
+//! humility: attached via ST-Link V3
+//! humility: I2C3, port H, dev 0x5a, rail 0: wrote 2 bytes to 0xd0
+//! 0xd0 <unknown>                 0x52 0x41 0x41 0x32 0x32 0x39 0x36 0x31 0x38
+//!                                "RAA229618"
+//! ```
+//!
+//! (This requires the `i2c` agent.)
+//!
+//! To probe a command that no driver knows about (e.g., when bringing up a
+//! new regulator), use `--raw` to read a specified number of bytes from an
+//! arbitrary command code, specified as `code:length`.  The bytes read are
+//! displayed without any interpretation:
+//!
+//! ```console
+//! $ humility pmbus -r VDD_VCORE --raw 0xd4:2
+//! humility: attached via ST-Link V3
+//! 0xd4 0x18 0x00
+//! ```
+//!
+//! (This also requires the `i2c` agent.)
+//!
+//! To check the integrity of reads, use `--pec` to request (and validate)
+//! the PMBus packet error code for each read; any read that fails validation
+//! is reported as a `PEC error`.  (This requires the `i2c` agent, and is not
+//! performed on block reads.)
+//!
+//! You can also write a PMBus command with `--write` (`-w`, or `--set`),
+//! which allows for for particular fields to be written.  Because a bad write
+//! can damage hardware, writes are only validated and displayed unless
+//! `--doit` is also specified:
 //!
 //! ```console
 //! $ humility pmbus -r VDD_VCORE -w OPERATION.MarginFaultResponse=ActUpon
 //! humility: attached via ST-Link V3
+//! humility: I2C3, port H, dev 0x5a, rail 0: would write OPERATION.MarginFaultResponse=ActUpon
+//! humility: not committing anything; use --doit to write
+//! $ humility pmbus -r VDD_VCORE -w OPERATION.MarginFaultResponse=ActUpon --doit
+//! humility: attached via ST-Link V3
 //! humility: I2C3, port H, dev 0x5a, rail 0: successfully wrote OPERATION
 //! ```
 //!
@@ -164,13 +283,50 @@
 //! bmr491      V12_SYS_A2           Y    1   53.625V   11.995V   19.250A  35.750°C
 //! ```
 //!
+//! To discover which PMBus devices are present on a controller (e.g., during
+//! bring-up), use `--scan` (`-S`).  Each address on the specified bus is
+//! probed; for any address that responds, `PMBUS_REVISION`, `MFR_ID` and
+//! `MFR_MODEL` are read, and the device is matched (where possible) against
+//! the PMBus drivers known to the archive.  A device that acknowledges its
+//! address but fails `PMBUS_REVISION` is reported as `unsupported`:
+//!
+//! ```console
+//! $ humility pmbus --scan -c 4 -p f
+//! humility: attached via ST-Link V3
+//! ADDR REV  STATUS      MFR_ID     MFR_MODEL        DRIVER
+//! 0x10 0x22 pmbus       ADI        ADM1272-2A       adm1272
+//! 0x14 0x22 pmbus       ADI        ADM1272-2A       adm1272
+//! 0x25 0x33 pmbus       TI         TPS546B24A       tps546b24a
+//! 0x48 -    unsupported -          -                -
+//! 0x67 0x22 pmbus       Flex       BMR491           bmr491
+//! humility: 5 devices found on I2C4, port F
+//! ```
+//!
+//! The results of a scan are cached on disk (in `$XDG_CACHE_HOME/humility`
+//! or `$HOME/.cache/humility`) for the archive, and a subsequent scan of the
+//! same bus displays the cached results rather than scanning again; use
+//! `--rescan` to force a fresh scan.  When operating on a device (or rail)
+//! for which the archive does not name a known driver, the driver found for
+//! the device by a cached scan (if any) is used.
+//!
 //! Note that for some devices, it is not possible to get accurate voltage and
 //! current readings from `pmbus` alone, as knowledge of how the device is
 //! integrated into a larger system is required to interpret raw values.  For
 //! these devices, the raw value is provided (as in the `adm1272` output,
 //! above); to get the interpreted value, use `humility power` instead (which
 //! has the added advantage of displaying all power rails in the systemn, not
-//! just PMBus devices.)
+//! just PMBus devices.)  Alternatively, if the DIRECT-format coefficients
+//! for a quantity are known for the device (as integrated), they can be
+//! specified as `m,b,R` with `--coefficients`, and raw telemetry that the
+//! driver can't convert will be converted with them.  As coefficients
+//! generally differ by quantity, this should be used with `--command` to
+//! specify only the commands to which the coefficients pertain:
+//!
+//! ```console
+//! $ humility pmbus -r V54_FAN --command READ_VIN --coefficients 4062,0,-2
+//! humility: attached via ST-Link V3
+//! 0x88 READ_VIN                  0x088c = 53.865V
+//! ```
 //!
 //! `humility pmbus` can use two different mechanisms to perform PMBus actions,
 //! selected by the `--agent` command-line argument.
@@ -196,14 +352,20 @@ use humility_hiffy::*;
 use humility_i2c::I2cArgs;
 use humility_idol::{HubrisIdol, IdolArgument, IdolOperation};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use hif::*;
 use indexmap::IndexMap;
 use pmbus::commands::*;
 use pmbus::*;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod cache;
+use cache::{ScanCache, ScanEntry};
 
 #[derive(Parser, Debug)]
 #[clap(name = "pmbus", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -266,9 +428,13 @@ struct PmbusArgs {
     commands: Option<Vec<String>>,
 
     /// specifies writes to perform
-    #[clap(long, short = 'w', use_value_delimiter = false)]
+    #[clap(long, short = 'w', alias = "set", use_value_delimiter = false)]
     writes: Option<Vec<String>>,
 
+    /// actually perform any specified writes
+    #[clap(long, requires = "writes")]
+    doit: bool,
+
     /// specifies an I2C controller
     #[clap(long, short, value_name = "controller",
         parse(try_from_str = parse_int::parse),
@@ -297,11 +463,107 @@ struct PmbusArgs {
     #[clap(long, short = 'r', value_name = "rail", use_value_delimiter = true)]
     rail: Option<Vec<String>>,
 
+    /// repeatedly run the specified command(s) at the given interval
+    #[clap(
+        long, short = 'i', value_name = "ms", requires = "commands",
+        conflicts_with_all = &["writes", "summarize", "dryrun"],
+        parse(try_from_str = parse_int::parse)
+    )]
+    interval: Option<u64>,
+
+    /// select the specified page (or "all" pages) before reading
+    #[clap(
+        long, value_name = "page",
+        conflicts_with_all = &["rail", "writes", "summarize"]
+    )]
+    page: Option<String>,
+
+    /// convert raw telemetry with the specified DIRECT-format coefficients
+    #[clap(
+        long, value_name = "m,b,R",
+        conflicts_with_all = &["writes", "summarize", "list"],
+        parse(try_from_str = parse_coefficients)
+    )]
+    coefficients: Option<DirectCoefficients>,
+
+    /// request and validate packet error checking (PEC) on reads
+    #[clap(long, conflicts_with_all = &["writes", "summarize"])]
+    pec: bool,
+
+    /// emit results in the specified format instead of as a table
+    #[clap(
+        long, arg_enum, value_name = "format",
+        conflicts_with_all = &["writes", "summarize", "commandhelp"]
+    )]
+    format: Option<OutputFormat>,
+
+    /// issue a block write of --payload to the specified command, followed
+    /// by a block read of its response
+    #[clap(
+        long, value_name = "command", requires = "payload",
+        conflicts_with_all = &[
+            "list", "summarize", "commands", "writes", "commandhelp",
+            "interval", "page", "pec", "format", "scan",
+        ]
+    )]
+    block_command: Option<String>,
+
+    /// payload of the block write for --block-command, as comma-separated
+    /// bytes
+    #[clap(
+        long, value_name = "bytes", requires = "block-command",
+        use_value_delimiter = true, parse(try_from_str = parse_int::parse)
+    )]
+    payload: Option<Vec<u8>>,
+
+    /// read the specified number of bytes from an arbitrary command code,
+    /// specified as code:length
+    #[clap(
+        long, value_name = "code:length", parse(try_from_str = parse_raw),
+        conflicts_with_all = &[
+            "list", "summarize", "commands", "writes", "commandhelp",
+            "interval", "page", "pec", "format", "scan", "block-command",
+        ]
+    )]
+    raw: Option<(u8, u8)>,
+
+    /// scan the specified bus for PMBus devices
+    #[clap(
+        long, short = 'S',
+        conflicts_with_all = &[
+            "list", "summarize", "device", "rail", "driver", "commands",
+            "writes", "commandhelp", "interval", "page", "pec", "format",
+        ]
+    )]
+    scan: bool,
+
+    /// scan the bus even if the results of a previous scan are cached
+    #[clap(long, requires = "scan")]
+    rescan: bool,
+
+    /// compare the device against the device at the specified address (on
+    /// the same bus) or against results saved with --format json,
+    /// displaying only the commands that differ
+    #[clap(
+        long, value_name = "address|file",
+        conflicts_with_all = &[
+            "list", "summarize", "writes", "commandhelp", "interval",
+            "dryrun", "format", "scan", "block-command", "raw",
+        ]
+    )]
+    compare: Option<String>,
+
     /// agent to use when executing PMBus operations
     #[clap(long, arg_enum, default_value_t=Agent::Auto)]
     agent: Agent,
 }
 
+#[derive(clap::ArgEnum, Clone, Debug)]
+enum OutputFormat {
+    Json,
+    Csv,
+}
+
 #[derive(clap::ArgEnum, Clone, Debug)]
 enum Agent {
     Auto,
@@ -309,6 +571,88 @@ enum Agent {
     I2c,
 }
 
+//
+// PMBus DIRECT-format coefficients, as specified with --coefficients.  For
+// devices that use the DIRECT format, the coefficients depend on how the
+// device is integrated into the system (e.g., the value of a current sense
+// resistor), so the driver alone cannot convert their telemetry.
+//
+#[derive(Copy, Clone, Debug)]
+struct DirectCoefficients {
+    m: i32,
+    b: i32,
+    r: i32,
+}
+
+fn parse_raw(raw: &str) -> Result<(u8, u8)> {
+    let (code, len) = raw.split_once(':').ok_or_else(|| {
+        anyhow!("raw read \"{raw}\" must be of the form code:length")
+    })?;
+
+    let code = parse_int::parse::<u8>(code)?;
+    let len = parse_int::parse::<u8>(len)?;
+
+    if len == 0 {
+        bail!("raw read length must be non-zero");
+    }
+
+    Ok((code, len))
+}
+
+fn parse_coefficients(str: &str) -> Result<DirectCoefficients> {
+    let vals = str
+        .split(',')
+        .map(|v| {
+            v.trim().parse::<i32>().map_err(|_| {
+                anyhow!("invalid coefficient \"{}\" in \"{}\"", v, str)
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let [m, b, r] = vals[..] else {
+        bail!("coefficients must be of the form m,b,R");
+    };
+
+    if m == 0 {
+        bail!("coefficient m cannot be zero");
+    }
+
+    Ok(DirectCoefficients { m, b, r })
+}
+
+impl DirectCoefficients {
+    //
+    // Converts the raw value of a telemetry command to its real-world value
+    // (with units), returning None if the command isn't known telemetry.
+    // Per the PMBus specification, a raw value Y is converted to a value X
+    // as X = (Y * 10^-R - b) / m.
+    //
+    fn convert(&self, code: u8, val: &[u8]) -> Option<String> {
+        use pmbus::commands::CommandCode;
+
+        let units = [
+            (CommandCode::READ_VIN as u8, "V"),
+            (CommandCode::READ_IIN as u8, "A"),
+            (CommandCode::READ_VCAP as u8, "V"),
+            (CommandCode::READ_VOUT as u8, "V"),
+            (CommandCode::READ_IOUT as u8, "A"),
+            (CommandCode::READ_TEMPERATURE_1 as u8, "°C"),
+            (CommandCode::READ_TEMPERATURE_2 as u8, "°C"),
+            (CommandCode::READ_TEMPERATURE_3 as u8, "°C"),
+            (CommandCode::READ_POUT as u8, "W"),
+            (CommandCode::READ_PIN as u8, "W"),
+        ]
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, units)| *units)?;
+
+        let y = i16::from_le_bytes(val.try_into().ok()?) as f64;
+        let x = (y * 10f64.powi(-self.r) - self.b as f64) / self.m as f64;
+
+        Some(format!("{:.3}{}", x, units))
+    }
+}
+
 fn all_commands(
     device: pmbus::Device,
 ) -> (HashMap<String, u8>, HashMap<u8, String>) {
@@ -382,7 +726,31 @@ fn print_command(
     }
 }
 
+//
+// Returns true if the specified command reports status, in which case any
+// set bits denote faults or warnings.
+//
+fn is_status(code: u8) -> bool {
+    use pmbus::commands::CommandCode;
+
+    [
+        CommandCode::STATUS_BYTE as u8,
+        CommandCode::STATUS_WORD as u8,
+        CommandCode::STATUS_VOUT as u8,
+        CommandCode::STATUS_IOUT as u8,
+        CommandCode::STATUS_INPUT as u8,
+        CommandCode::STATUS_TEMPERATURE as u8,
+        CommandCode::STATUS_CML as u8,
+        CommandCode::STATUS_OTHER as u8,
+        CommandCode::STATUS_MFR_SPECIFIC as u8,
+        CommandCode::STATUS_FANS_1_2 as u8,
+        CommandCode::STATUS_FANS_3_4 as u8,
+    ]
+    .contains(&code)
+}
+
 #[rustfmt::skip::macros(println)]
+#[allow(clippy::too_many_arguments)]
 fn print_result(
     subargs: &PmbusArgs,
     device: pmbus::Device,
@@ -391,6 +759,7 @@ fn print_result(
     command: &dyn pmbus::Command,
     result: &Result<Vec<u8>, u32>,
     worker: &dyn PmbusWorker,
+    prefix: &str,
 ) -> Result<()> {
     let nbytes = match command.read_op() {
         pmbus::Operation::ReadByte => Some(1),
@@ -403,7 +772,7 @@ fn print_result(
     };
 
     let name = command.name();
-    let cmdstr = format!("0x{:02x} {:<25}", code, name);
+    let cmdstr = format!("{}0x{:02x} {:<25}", prefix, code, name);
 
     fn printchar(val: u8) {
         let c = val as char;
@@ -416,6 +785,10 @@ fn print_result(
     }
 
     match result {
+        Err(PEC_ERROR) => {
+            println!("{} PEC error", cmdstr);
+        }
+
         Err(err) => {
             if subargs.errors {
                 println!("{} Err({})", cmdstr, worker.decode_read_err(*err));
@@ -495,7 +868,11 @@ fn print_result(
                     return;
                 }
 
-                if !subargs.verbose {
+                //
+                // Unless we are being verbose, we only display the fields of
+                // a status command -- and then only those that are set.
+                //
+                if !subargs.verbose && !(is_status(code) && value.raw() != 0) {
                     return;
                 }
 
@@ -524,7 +901,20 @@ fn print_result(
             }
 
             if !interpreted {
-                printraw(false);
+                let converted =
+                    subargs.coefficients.and_then(|c| c.convert(code, val));
+
+                match converted {
+                    Some(converted) => {
+                        println!(
+                            "{} 0x{:04x} = {}",
+                            cmdstr,
+                            u16::from_le_bytes([val[0], val[1]]),
+                            converted
+                        );
+                    }
+                    None => printraw(false),
+                }
             }
 
             if printed {
@@ -539,6 +929,161 @@ fn print_result(
     Ok(())
 }
 
+//
+// A single command result, as emitted by `--format` (and, as saved with
+// `--format json`, read by `--compare`)
+//
+#[derive(Serialize, Deserialize)]
+struct PmbusRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u8>,
+    code: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interpreted: Option<String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    fields: IndexMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl PmbusRecord {
+    const CSV_HEADER: [&'static str; 9] = [
+        "time",
+        "page",
+        "code",
+        "name",
+        "raw",
+        "value",
+        "interpreted",
+        "fields",
+        "error",
+    ];
+
+    fn to_csv(&self) -> [String; 9] {
+        [
+            self.time.clone().unwrap_or_default(),
+            self.page.map(|p| p.to_string()).unwrap_or_default(),
+            self.code.clone(),
+            self.name.clone(),
+            self.raw.clone().unwrap_or_default(),
+            self.value.map(|v| v.to_string()).unwrap_or_default(),
+            self.interpreted.clone().unwrap_or_default(),
+            self.fields
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join(";"),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+
+    //
+    // Describes the result for comparison:  its raw value (along with its
+    // interpretation, if any), or its error.
+    //
+    fn describe(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("<{error}>");
+        }
+
+        let raw = match (&self.raw, self.value) {
+            (Some(raw), Some(value)) => {
+                format!("0x{:0width$x}", value, width = raw.len())
+            }
+            (Some(raw), None) => format!("0x{raw}"),
+            (None, _) => "-".to_string(),
+        };
+
+        match &self.interpreted {
+            Some(interpreted) => format!("{raw} = {interpreted}"),
+            None => raw,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pmbus_record(
+    device: pmbus::Device,
+    code: u8,
+    mode: impl Fn() -> VOutModeCommandData,
+    command: &dyn pmbus::Command,
+    result: &Result<Vec<u8>, u32>,
+    worker: &dyn PmbusWorker,
+    time: Option<String>,
+    coefficients: Option<DirectCoefficients>,
+) -> PmbusRecord {
+    let nbytes = match command.read_op() {
+        pmbus::Operation::ReadByte => Some(1),
+        pmbus::Operation::ReadWord => Some(2),
+        pmbus::Operation::ReadWord32 => Some(4),
+        _ => None,
+    };
+
+    let mut record = PmbusRecord {
+        time,
+        page: None,
+        code: format!("0x{:02x}", code),
+        name: command.name().to_string(),
+        raw: None,
+        value: None,
+        interpreted: None,
+        fields: IndexMap::new(),
+        error: None,
+    };
+
+    let val = match result {
+        Err(err) => {
+            record.error = Some(worker.decode_read_err(*err));
+            return record;
+        }
+        Ok(val) if val.is_empty() => {
+            record.error = Some("timed out".to_string());
+            return record;
+        }
+        Ok(val) => val,
+    };
+
+    record.raw = Some(val.iter().map(|b| format!("{:02x}", b)).collect());
+
+    match nbytes {
+        Some(nbytes) if val.len() != nbytes => {
+            record.error = Some("short read".to_string());
+            return record;
+        }
+        Some(_) => {
+            let mut bytes = [0u8; 4];
+            bytes[..val.len()].copy_from_slice(val);
+            record.value = Some(u32::from_le_bytes(bytes));
+        }
+        None => {}
+    }
+
+    let err = device.interpret(code, val, mode, |field, value| {
+        if field.bitfield() {
+            record.fields.insert(field.name().to_string(), value.to_string());
+        } else {
+            record.interpreted = Some(value.to_string());
+        }
+    });
+
+    if let Err(err) = err {
+        record.error = Some(format!("{:?}", err));
+    }
+
+    if record.interpreted.is_none() {
+        record.interpreted = coefficients.and_then(|c| c.convert(code, val));
+    }
+
+    record
+}
+
 fn prepare_write(
     device: pmbus::Device,
     code: u8,
@@ -1002,12 +1547,50 @@ fn find_rail<'a>(
 
     match found {
         None => {
-            bail!("rail {} not found", rail);
+            let all = hubris
+                .manifest
+                .i2c_devices
+                .iter()
+                .filter_map(|device| match &device.class {
+                    HubrisI2cDeviceClass::Pmbus { rails } => Some(rails),
+                    _ => None,
+                })
+                .flatten()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>();
+
+            bail!(
+                "rail {} not found; expected one of: {}",
+                rail,
+                all.join(", ")
+            );
         }
         Some((device, rail)) => Ok((I2cArgs::from_device(device), rail)),
     }
 }
 
+//
+// When a rail is specified without a device, the rail alone determines the
+// device's topology; any explicitly specified topology is an error rather
+// than something we would silently ignore.
+//
+fn check_rail_topology(subargs: &PmbusArgs) -> Result<()> {
+    if subargs.rail.is_some()
+        && subargs.device.is_none()
+        && (subargs.controller.is_some()
+            || subargs.port.is_some()
+            || subargs.bus.is_some()
+            || subargs.mux.is_some())
+    {
+        bail!(
+            "--rail determines the controller, port, bus and mux; \
+            they cannot also be specified unless --device is specified"
+        );
+    }
+
+    Ok(())
+}
+
 #[derive(Debug)]
 enum WriteOp {
     Modify(usize, Vec<(Bitpos, Replacement)>),
@@ -1143,6 +1726,8 @@ fn writes(
     hubris: &HubrisArchive,
     worker: &mut dyn PmbusWorker,
 ) -> Result<()> {
+    check_rail_topology(subargs)?;
+
     let hargs = match (&subargs.rail, &subargs.device) {
         (Some(rails), None) => rails
             .iter()
@@ -1194,6 +1779,25 @@ fn writes(
     let writecmds = subargs.writes.as_ref().unwrap();
     let writes = validate_writes(writecmds, device)?;
 
+    //
+    // A bad write can damage hardware, so unless we have been explicitly
+    // told to proceed, we just indicate what we would have done.
+    //
+    if !subargs.doit {
+        for (harg, rail) in &hargs {
+            for write in writecmds {
+                if let Some(rnum) = rail {
+                    humility::msg!("{harg}, rail {rnum}: would write {write}");
+                } else {
+                    humility::msg!("{harg}: would write {write}");
+                }
+            }
+        }
+
+        humility::msg!("not committing anything; use --doit to write");
+        return Ok(());
+    }
+
     //
     // First up, we are going to do any reads that we need to perform, along
     // with any operations to set a command (SendByte) as well as set an
@@ -1425,6 +2029,74 @@ trait PmbusWorker {
 
     fn decode_read_err(&self, code: u32) -> String;
     fn decode_write_err(&self, code: u32) -> String;
+
+    /// Enables packet error checking on subsequent reads
+    fn enable_pec(&mut self) -> Result<()>;
+}
+
+//
+// Error indicating that a HIF program failed to complete before our
+// deadline.  This is distinct from the timeout enforced by the HIF context
+// itself, which is measured from when the program was kicked rather than
+// from when we began waiting on it.
+//
+#[derive(Debug)]
+struct PmbusTimeout(u32);
+
+impl std::fmt::Display for PmbusTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out after {} ms", self.0)
+    }
+}
+
+impl std::error::Error for PmbusTimeout {}
+
+//
+// Runs the given HIF program, polling for its completion until `timeout`
+// milliseconds of wall-clock time have elapsed.
+//
+fn run_with_deadline(
+    context: &mut HiffyContext,
+    core: &mut dyn Core,
+    ops: &[Op],
+    timeout: u32,
+) -> Result<Vec<Result<Vec<u8>, u32>>> {
+    let deadline = Instant::now() + Duration::from_millis(timeout.into());
+
+    context.start(core, ops, None)?;
+
+    while !context.done(core)? {
+        let now = Instant::now();
+
+        if now >= deadline {
+            return Err(PmbusTimeout(timeout).into());
+        }
+
+        std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+
+    context.results(core)
+}
+
+//
+// Error code used to indicate that a read failed packet error checking
+//
+const PEC_ERROR: u32 = u32::MAX;
+
+//
+// Computes the SMBus packet error code (a CRC-8 with a polynomial of x^8 +
+// x^2 + x + 1) over the specified bytes.
+//
+fn smbus_pec(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
 }
 
 struct I2cWorker<'a> {
@@ -1433,6 +2105,17 @@ struct I2cWorker<'a> {
     write_func: HiffyFunction,
     context: HiffyContext<'a>,
     ops: Vec<Op>,
+    timeout: u32,
+
+    /// Whether packet error checking is enabled
+    pec: bool,
+
+    /// Address of the current device
+    address: Option<u8>,
+
+    /// For each queued call, the address and command code of a read that
+    /// is to have its PEC validated
+    checks: Vec<Option<(u8, u8)>>,
 }
 
 impl<'a> I2cWorker<'a> {
@@ -1444,7 +2127,38 @@ impl<'a> I2cWorker<'a> {
         let context = HiffyContext::new(hubris, core, timeout)?;
         let read_func = context.get_function("I2cRead", 7)?;
         let write_func = context.get_function("I2cWrite", 8)?;
-        Ok(Self { core, context, read_func, write_func, ops: vec![] })
+        Ok(Self {
+            core,
+            context,
+            read_func,
+            write_func,
+            ops: vec![],
+            timeout,
+            pec: false,
+            address: None,
+            checks: vec![],
+        })
+    }
+}
+
+impl I2cWorker<'_> {
+    /// Performs a raw, single-byte read to determine if a device is present
+    fn probe(&mut self) {
+        self.checks.push(None);
+        self.ops.push(Op::PushNone);
+        self.ops.push(Op::Push(1));
+        self.ops.push(Op::Call(self.read_func.id));
+        self.ops.push(Op::DropN(2));
+    }
+
+    /// Reads the specified number of bytes from the specified command code,
+    /// regardless of what the command code denotes
+    fn read_raw(&mut self, code: u8, nbytes: u8) {
+        self.checks.push(None);
+        self.ops.push(Op::Push(code));
+        self.ops.push(Op::Push(nbytes));
+        self.ops.push(Op::Call(self.read_func.id));
+        self.ops.push(Op::DropN(2));
     }
 }
 
@@ -1466,6 +2180,8 @@ impl PmbusWorker for I2cWorker<'_> {
         } else {
             bail!("no device specified");
         }
+
+        self.address = harg.address;
         Ok(())
     }
 
@@ -1476,17 +2192,37 @@ impl PmbusWorker for I2cWorker<'_> {
         self.ops.push(Op::Push(1));
         self.ops.push(Op::Call(self.write_func.id));
         self.ops.push(Op::DropN(3));
+        self.checks.push(None);
     }
 
     fn read(&mut self, code: u8, op: pmbus::Operation) {
-        let op = match op {
-            pmbus::Operation::ReadByte => Op::Push(1),
-            pmbus::Operation::ReadWord => Op::Push(2),
-            pmbus::Operation::ReadWord32 => Op::Push(4),
-            pmbus::Operation::ReadBlock => Op::PushNone,
+        let nbytes = match op {
+            pmbus::Operation::ReadByte => Some(1),
+            pmbus::Operation::ReadWord => Some(2),
+            pmbus::Operation::ReadWord32 => Some(4),
+            pmbus::Operation::ReadBlock => None,
             _ => panic!("not a read operation"),
         };
 
+        //
+        // If we are checking PEC, we read one additional byte.  (We don't
+        // check PEC on block reads, as the length isn't known a priori.)
+        //
+        let op = match nbytes {
+            Some(nbytes) if self.pec => {
+                self.checks.push(self.address.map(|addr| (addr, code)));
+                Op::Push(nbytes + 1)
+            }
+            Some(nbytes) => {
+                self.checks.push(None);
+                Op::Push(nbytes)
+            }
+            None => {
+                self.checks.push(None);
+                Op::PushNone
+            }
+        };
+
         self.ops.push(Op::Push(code));
         self.ops.push(op);
         self.ops.push(Op::Call(self.read_func.id));
@@ -1500,18 +2236,53 @@ impl PmbusWorker for I2cWorker<'_> {
     fn run(&mut self) -> Result<Vec<Result<Vec<u8>, u32>>> {
         self.ops.push(Op::Done);
         let ops = std::mem::take(&mut self.ops);
-        self.context.run(self.core, &ops, None)
-    }
+        let mut results = run_with_deadline(
+            &mut self.context,
+            self.core,
+            &ops,
+            self.timeout,
+        )?;
+        let checks = std::mem::take(&mut self.checks);
+
+        for (result, check) in results.iter_mut().zip(checks) {
+            let (addr, code) = match (&result, check) {
+                (Ok(val), Some(check)) if !val.is_empty() => check,
+                _ => continue,
+            };
 
-    fn decode_read_err(&self, code: u32) -> String {
-        self.read_func.strerror(code)
-    }
+            let val = result.as_mut().unwrap();
+            let pec = val.pop().unwrap();
 
-    fn decode_write_err(&self, code: u32) -> String {
+            let mut bytes = vec![addr << 1, code, (addr << 1) | 1];
+            bytes.extend_from_slice(val);
+
+            if smbus_pec(&bytes) != pec {
+                *result = Err(PEC_ERROR);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn decode_read_err(&self, code: u32) -> String {
+        match code {
+            PEC_ERROR => "PEC error".to_string(),
+            _ => self.read_func.strerror(code),
+        }
+    }
+
+    fn decode_write_err(&self, code: u32) -> String {
         self.read_func.strerror(code)
     }
 
+    fn enable_pec(&mut self) -> Result<()> {
+        self.pec = true;
+        Ok(())
+    }
+
     fn write(&mut self, code: u8, op: &WriteOp) {
+        self.checks.push(None);
+
         match op {
             WriteOp::SetBlock(payload) => {
                 self.ops.push(Op::Push(code));
@@ -1578,6 +2349,7 @@ struct IdolWorker<'a> {
     core: &'a mut dyn Core,
     context: HiffyContext<'a>,
     ops: Vec<Op>,
+    timeout: u32,
 
     write_set: IdolOperation<'a>,
     write_byte: IdolOperation<'a>,
@@ -1640,6 +2412,7 @@ impl<'a> IdolWorker<'a> {
             core,
             context,
             ops: vec![],
+            timeout,
             write_set,
             write_byte,
             write_word,
@@ -1835,10 +2608,19 @@ impl PmbusWorker for IdolWorker<'_> {
         self.write_byte.strerror(code)
     }
 
+    fn enable_pec(&mut self) -> Result<()> {
+        bail!("packet error checking requires the i2c agent");
+    }
+
     fn run(&mut self) -> Result<Vec<Result<Vec<u8>, u32>>> {
         self.ops.push(Op::Done);
         let ops = std::mem::take(&mut self.ops);
-        let mut out = self.context.run(self.core, &ops, None)?;
+        let mut out = run_with_deadline(
+            &mut self.context,
+            self.core,
+            &ops,
+            self.timeout,
+        )?;
 
         // Block reads return a RawPmbusBlock, which is an active length
         // followed by a max-length array.  We convert from that type to a raw
@@ -1874,6 +2656,402 @@ impl PmbusWorker for IdolWorker<'_> {
     }
 }
 
+//
+// The largest payload of an SMBus block write
+//
+const BLOCK_MAX: usize = 32;
+
+//
+// Determines the single device (and rail, if any) that a block command or a
+// raw read is to be issued to.
+//
+fn single_target(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    flag: &str,
+) -> Result<(I2cArgs, Option<u8>)> {
+    check_rail_topology(subargs)?;
+
+    Ok(match (&subargs.rail, &subargs.device) {
+        (Some(rails), None) => {
+            if rails.len() > 1 {
+                bail!("cannot specify more than one rail");
+            }
+
+            find_rail(hubris, &rails[0])?
+        }
+
+        (Some(_), Some(_)) => {
+            bail!("--rail cannot be used with --device for {flag}");
+        }
+
+        (None, _) => (
+            I2cArgs::parse(
+                hubris,
+                &subargs.bus,
+                subargs.controller,
+                &subargs.port,
+                &subargs.mux,
+                &subargs.device,
+            )?,
+            None,
+        ),
+    })
+}
+
+fn block_command(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut dyn PmbusWorker,
+    cmd: &str,
+) -> Result<()> {
+    let (hargs, rail) = single_target(subargs, hubris, "--block-command")?;
+
+    let device = match (&subargs.driver, &hargs.device) {
+        (Some(driver), _) => match pmbus::Device::from_str(driver) {
+            Some(device) => device,
+            None => bail!("unknown device \"{}\"", driver),
+        },
+        (None, Some(driver)) => {
+            pmbus::Device::from_str(driver).unwrap_or(pmbus::Device::Common)
+        }
+        (None, None) => pmbus::Device::Common,
+    };
+
+    let (all, bycode) = all_commands(device);
+
+    let code = match all.get(cmd) {
+        Some(code) => *code,
+        None => match parse_int::parse::<u8>(cmd) {
+            Ok(code) => code,
+            Err(_) => {
+                bail!(
+                    "unrecognized PMBus command {}; \
+                     use -H for command help",
+                    cmd
+                );
+            }
+        },
+    };
+
+    //
+    // Commands that the driver knows about should be read as a block; for
+    // anything else (e.g., manufacturer-specific commands that the driver
+    // doesn't know), we take the user at their word.
+    //
+    device.command(code, |c| {
+        if c.read_op() != pmbus::Operation::ReadBlock {
+            warn!("{} is not a block read; issuing as one anyway", c.name());
+        }
+    });
+
+    let payload = subargs.payload.clone().unwrap_or_default();
+
+    if payload.len() > BLOCK_MAX {
+        bail!("payload cannot exceed {} bytes", BLOCK_MAX);
+    }
+
+    worker.begin_device(&hargs)?;
+
+    if let Some(rnum) = rail {
+        worker.select_rail(rnum);
+    }
+
+    worker.write(code, &WriteOp::SetBlock(payload.clone()));
+    worker.read(code, pmbus::Operation::ReadBlock);
+    worker.end_device();
+
+    let results = worker.run()?;
+    let mut ndx = 0;
+
+    let target = match rail {
+        Some(rnum) => format!("{hargs}, rail {rnum}"),
+        None => format!("{hargs}"),
+    };
+
+    if rail.is_some() {
+        if let Err(code) = results[ndx] {
+            bail!("{target}: failed to set rail: {code}");
+        }
+
+        ndx += 1;
+    }
+
+    if let Err(err) = results[ndx] {
+        bail!(
+            "{target}: failed to write {cmd}: {}",
+            worker.decode_write_err(err)
+        );
+    }
+
+    humility::msg!("{target}: wrote {} bytes to {cmd}", payload.len());
+
+    let name = bycode.get(&code).map_or("<unknown>", |name| name.as_str());
+    let cmdstr = format!("0x{:02x} {:<25}", code, name);
+
+    let val = match &results[ndx + 1] {
+        Err(err) => {
+            bail!(
+                "{target}: failed to read {cmd}: {}",
+                worker.decode_read_err(*err)
+            );
+        }
+        Ok(val) if val.is_empty() => {
+            println!("{} (empty response)", cmdstr);
+            return Ok(());
+        }
+        Ok(val) => val,
+    };
+
+    let bytes = val
+        .iter()
+        .map(|b| format!("0x{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let ascii = val
+        .iter()
+        .map(|&b| {
+            let c = b as char;
+
+            if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '.'
+            }
+        })
+        .collect::<String>();
+
+    println!("{} {}", cmdstr, bytes);
+    println!("{:30} \"{}\"", "", ascii);
+
+    Ok(())
+}
+
+fn raw_read(
+    subargs: &PmbusArgs,
+    hubris: &HubrisArchive,
+    worker: &mut I2cWorker,
+    code: u8,
+    nbytes: u8,
+) -> Result<()> {
+    let (hargs, rail) = single_target(subargs, hubris, "--raw")?;
+
+    worker.begin_device(&hargs)?;
+
+    if let Some(rnum) = rail {
+        worker.select_rail(rnum);
+    }
+
+    worker.read_raw(code, nbytes);
+    worker.end_device();
+
+    let results = worker.run()?;
+    let mut ndx = 0;
+
+    let target = match rail {
+        Some(rnum) => format!("{hargs}, rail {rnum}"),
+        None => format!("{hargs}"),
+    };
+
+    if rail.is_some() {
+        if let Err(code) = results[ndx] {
+            bail!("{target}: failed to set rail: {code}");
+        }
+
+        ndx += 1;
+    }
+
+    match &results[ndx] {
+        Err(err) => {
+            bail!(
+                "{target}: failed to read 0x{:02x}: {}",
+                code,
+                worker.decode_read_err(*err)
+            );
+        }
+        Ok(val) => {
+            let bytes = val
+                .iter()
+                .map(|b| format!("0x{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            println!("0x{:02x} {}", code, bytes);
+        }
+    }
+
+    Ok(())
+}
+
+//
+// Scans the specified bus, returning each device found on it.
+//
+fn scan(
+    hubris: &HubrisArchive,
+    hargs: &I2cArgs,
+    worker: &mut I2cWorker,
+) -> Result<Vec<ScanEntry>> {
+    //
+    // The drivers that we can match against are those named by PMBus devices
+    // in the archive.
+    //
+    let mut drivers = vec![];
+
+    for device in &hubris.manifest.i2c_devices {
+        if let HubrisI2cDeviceClass::Pmbus { .. } = &device.class {
+            if pmbus::Device::from_str(&device.device).is_some()
+                && !drivers.contains(&device.device)
+            {
+                drivers.push(device.device.clone());
+            }
+        }
+    }
+
+    let tostr = |val: &[u8]| {
+        String::from_utf8_lossy(val)
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string()
+    };
+
+    let normalize = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase()
+    };
+
+    let identify = |model: &str, expected: Option<&String>| {
+        let model = normalize(model);
+
+        if model.is_empty() {
+            return None;
+        }
+
+        //
+        // If the archive expects a particular device at this address and
+        // it matches, prefer it; otherwise take the first driver that does.
+        //
+        expected
+            .into_iter()
+            .chain(drivers.iter())
+            .find(|d| {
+                let d = normalize(d);
+                model.starts_with(&d) || d.starts_with(&model)
+            })
+            .cloned()
+    };
+
+    let revision = CommandCode::PMBUS_REVISION as u8;
+    let mfr_id = CommandCode::MFR_ID as u8;
+    let mfr_model = CommandCode::MFR_MODEL as u8;
+
+    //
+    // Each address results in one probe and three reads.  We execute a batch
+    // of addresses at a time to keep our HIF program small.
+    //
+    const NCALLS: usize = 4;
+    const BATCH: u8 = 16;
+
+    let mut entries = vec![];
+
+    for base in (0..0x80u8).step_by(BATCH as usize) {
+        let addrs = base..base + BATCH;
+
+        for address in addrs.clone() {
+            let dargs =
+                I2cArgs { address: Some(address), device: None, ..*hargs };
+            worker.begin_device(&dargs)?;
+            worker.probe();
+            worker.read(revision, pmbus::Operation::ReadByte);
+            worker.read(mfr_id, pmbus::Operation::ReadBlock);
+            worker.read(mfr_model, pmbus::Operation::ReadBlock);
+            worker.end_device();
+        }
+
+        let results = worker.run()?;
+
+        for (address, results) in addrs.zip(results.chunks(NCALLS)) {
+            let status = match &results[0] {
+                Ok(val) if val.is_empty() => "timed out".to_string(),
+                Ok(_) => match &results[1] {
+                    Ok(_) => "pmbus".to_string(),
+                    Err(_) => "unsupported".to_string(),
+                },
+                Err(code) => match worker.decode_read_err(*code).as_str() {
+                    "NoDevice" | "ReservedAddress" => continue,
+                    err => err.to_string(),
+                },
+            };
+
+            let rev = match &results[1] {
+                Ok(val) if val.len() == 1 => Some(val[0]),
+                _ => None,
+            };
+
+            let read = |ndx: usize| match &results[ndx] {
+                Ok(val) if !val.is_empty() => Some(tostr(val)),
+                _ => None,
+            };
+
+            let dargs =
+                I2cArgs { address: Some(address), device: None, ..*hargs };
+
+            let expected = hubris
+                .manifest
+                .i2c_devices
+                .iter()
+                .find(|d| dargs.matches_device(d))
+                .map(|d| &d.device);
+
+            let mfr_model = read(3);
+            let driver = mfr_model.as_ref().and_then(|m| identify(m, expected));
+
+            entries.push(ScanEntry {
+                address,
+                rev,
+                status,
+                mfr_id: read(2),
+                mfr_model,
+                driver,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[allow(clippy::print_literal)]
+fn print_scan(hargs: &I2cArgs, entries: &[ScanEntry]) {
+    println!(
+        "{:4} {:4} {:11} {:10} {:16} DRIVER",
+        "ADDR", "REV", "STATUS", "MFR_ID", "MFR_MODEL"
+    );
+
+    let dash = || "-".to_string();
+
+    for entry in entries {
+        println!(
+            "0x{:02x} {:4} {:11} {:10} {:16} {}",
+            entry.address,
+            entry.rev.map_or_else(dash, |rev| format!("0x{:02x}", rev)),
+            entry.status,
+            entry.mfr_id.clone().unwrap_or_else(dash),
+            entry.mfr_model.clone().unwrap_or_else(dash),
+            entry.driver.clone().unwrap_or_else(dash),
+        );
+    }
+
+    let found = entries.len();
+
+    humility::msg!(
+        "{} device{} found on {}",
+        found,
+        if found == 1 { "" } else { "s" },
+        hargs
+    );
+}
+
 #[allow(clippy::print_literal)]
 fn pmbus(context: &mut ExecutionContext) -> Result<()> {
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -1921,6 +3099,63 @@ fn pmbus(context: &mut ExecutionContext) -> Result<()> {
 
     let timeout = subargs.timeout;
 
+    if subargs.scan {
+        if core.is_net() || matches!(subargs.agent, Agent::Idol) {
+            bail!("scanning requires the i2c agent");
+        }
+
+        let hargs = I2cArgs::parse(
+            hubris,
+            &subargs.bus,
+            subargs.controller,
+            &subargs.port,
+            &subargs.mux,
+            &None,
+        )?;
+
+        let mut cache = ScanCache::load(hubris);
+
+        let entries = match cache.bus(&hargs) {
+            Some(entries) if !subargs.rescan => {
+                humility::msg!(
+                    "using cached scan of {hargs}; use --rescan to rescan"
+                );
+                entries.clone()
+            }
+            _ => {
+                let mut worker = I2cWorker::new(hubris, core, timeout)?;
+                let entries = scan(hubris, &hargs, &mut worker)?;
+
+                if let Err(e) = cache.store(&hargs, entries.clone()) {
+                    warn!("failed to cache scan results: {e:?}");
+                }
+
+                entries
+            }
+        };
+
+        print_scan(&hargs, &entries);
+        return Ok(());
+    }
+
+    if let Some(cmd) = &subargs.block_command {
+        if core.is_net() || matches!(subargs.agent, Agent::Idol) {
+            bail!("block commands require the i2c agent");
+        }
+
+        let mut worker = I2cWorker::new(hubris, core, timeout)?;
+        return block_command(&subargs, hubris, &mut worker, cmd);
+    }
+
+    if let Some((code, nbytes)) = subargs.raw {
+        if core.is_net() || matches!(subargs.agent, Agent::Idol) {
+            bail!("raw reads require the i2c agent");
+        }
+
+        let mut worker = I2cWorker::new(hubris, core, timeout)?;
+        return raw_read(&subargs, hubris, &mut worker, code, nbytes);
+    }
+
     // Pick an implementation based on our flags and core state
     let mut worker: Box<dyn PmbusWorker> = match subargs.agent {
         Agent::Auto => {
@@ -1962,11 +3197,243 @@ fn pmbus(context: &mut ExecutionContext) -> Result<()> {
     pmbus_main(&subargs, hubris, worker.as_mut())
 }
 
+//
+// Reads every specified command (on every specified page) from a device,
+// returning the results as records.
+//
+fn read_records(
+    subargs: &PmbusArgs,
+    device: pmbus::Device,
+    worker: &mut dyn PmbusWorker,
+    hargs: &I2cArgs,
+    pages: &[Option<u8>],
+    run: &[bool; 256],
+) -> Result<Vec<PmbusRecord>> {
+    let page = pmbus::commands::CommandCode::PAGE as u8;
+    let vout = pmbus::commands::CommandCode::VOUT_MODE as u8;
+    let mut records = vec![];
+
+    for &select in pages {
+        let mut cmds = vec![];
+
+        worker.begin_device(hargs)?;
+
+        if let Some(rnum) = select {
+            worker.select_rail(rnum);
+            cmds.push(page);
+        }
+
+        let mut addcmd = |cmd: &dyn pmbus::Command, code| {
+            let op = cmd.read_op();
+            if matches!(
+                op,
+                pmbus::Operation::ReadByte
+                    | pmbus::Operation::ReadWord
+                    | pmbus::Operation::ReadWord32
+                    | pmbus::Operation::ReadBlock
+            ) {
+                worker.read(code, op);
+                cmds.push(code);
+            }
+        };
+
+        device.command(vout, |cmd| addcmd(cmd, vout));
+
+        for i in 0..=255u8 {
+            if run[i as usize] {
+                device.command(i, |cmd| addcmd(cmd, i));
+            }
+        }
+
+        worker.end_device();
+
+        if cmds.is_empty() {
+            bail!("no command to run");
+        }
+
+        let results = worker.run()?;
+
+        let base = match (select, results.first()) {
+            (Some(_), Some(Err(code))) => {
+                bail!(
+                    "couldn't select page: {}",
+                    worker.decode_write_err(*code)
+                );
+            }
+            (Some(_), _) => 1,
+            (None, _) => 0,
+        };
+
+        let mode = match (cmds.get(base), results.get(base)) {
+            (Some(&code), Some(Err(err))) if code == vout => {
+                bail!("can't read VOUT_MODE: {}", worker.decode_read_err(*err));
+            }
+            (Some(&code), Some(Ok(val))) if code == vout => {
+                Some(VOUT_MODE::CommandData::from_slice(val).unwrap())
+            }
+            _ => None,
+        };
+
+        let ndx = if mode.is_some() { base + 1 } else { base };
+
+        let getmode = || match mode {
+            Some(mode) => mode,
+            None => {
+                panic!("unexpected call to get VOutMode");
+            }
+        };
+
+        for i in ndx..results.len() {
+            device.command(cmds[i], |cmd| {
+                let mut record = pmbus_record(
+                    device,
+                    cmds[i],
+                    getmode,
+                    cmd,
+                    &results[i],
+                    worker,
+                    None,
+                    subargs.coefficients,
+                );
+
+                record.page = subargs.page.as_ref().and(select);
+                records.push(record);
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+//
+// Compares a device against either another device on the same bus or
+// results previously saved with `--format json`, displaying only those
+// commands whose results differ.
+//
+fn compare(
+    subargs: &PmbusArgs,
+    device: pmbus::Device,
+    worker: &mut dyn PmbusWorker,
+    hargs: &I2cArgs,
+    pages: &[Option<u8>],
+    run: &[bool; 256],
+    other: &str,
+) -> Result<()> {
+    let records = read_records(subargs, device, worker, hargs, pages, run)?;
+
+    let label = match hargs.address {
+        Some(address) => format!("0x{address:02x}"),
+        None => "device".to_string(),
+    };
+
+    let (olabel, orecords) = match parse_int::parse::<u8>(other) {
+        Ok(address) => {
+            let oargs = I2cArgs {
+                controller: hargs.controller,
+                port: hargs.port,
+                mux: hargs.mux,
+                device: hargs.device.clone(),
+                address: Some(address),
+                class: hargs.class,
+            };
+
+            let orecords =
+                read_records(subargs, device, worker, &oargs, pages, run)?;
+
+            (format!("0x{address:02x}"), orecords)
+        }
+        Err(_) => {
+            let contents = std::fs::read_to_string(other)
+                .with_context(|| format!("failed to read {other}"))?;
+
+            let orecords: Vec<PmbusRecord> = serde_json::from_str(&contents)
+                .with_context(|| {
+                    format!(
+                        "{other} is neither an address nor results saved \
+                        with --format json"
+                    )
+                })?;
+
+            (other.to_string(), orecords)
+        }
+    };
+
+    let mut results: BTreeMap<_, (Option<&PmbusRecord>, Option<&PmbusRecord>)> =
+        BTreeMap::new();
+
+    for record in &records {
+        results.entry((record.page, &record.code)).or_default().0 =
+            Some(record);
+    }
+
+    for record in &orecords {
+        results.entry((record.page, &record.code)).or_default().1 =
+            Some(record);
+    }
+
+    let describe =
+        |r: Option<&PmbusRecord>| r.map_or("-".to_string(), |r| r.describe());
+
+    let width = records
+        .iter()
+        .map(|r| r.describe().len())
+        .chain(std::iter::once(label.len()))
+        .max()
+        .unwrap_or(0);
+
+    let paged = results.keys().any(|(page, _)| page.is_some());
+    let mut differ = 0;
+
+    println!(
+        "{}{:4} {:<25} {:<width$} {}",
+        if paged { "PAGE " } else { "" },
+        "CODE",
+        "COMMAND",
+        label,
+        olabel,
+    );
+
+    for ((page, code), &(left, right)) in &results {
+        let name = left.or(right).map_or("", |r| r.name.as_str());
+        let (left, right) = (describe(left), describe(right));
+
+        if left == right {
+            continue;
+        }
+
+        println!(
+            "{}{} {:<25} {:<width$} {}",
+            match (paged, page) {
+                (true, Some(page)) => format!("{page:<4} "),
+                (true, None) => format!("{:<4} ", "-"),
+                (false, _) => String::new(),
+            },
+            code,
+            name,
+            left,
+            right,
+        );
+
+        differ += 1;
+    }
+
+    humility::msg!(
+        "{differ} of {} command{} differ{}",
+        results.len(),
+        if results.len() == 1 { "" } else { "s" },
+        if differ == 1 { "s" } else { "" },
+    );
+
+    Ok(())
+}
+
 fn pmbus_main(
     subargs: &PmbusArgs,
     hubris: &HubrisArchive,
     worker: &mut dyn PmbusWorker,
 ) -> Result<()> {
+    check_rail_topology(subargs)?;
+
     let hargs = match (&subargs.rail, &subargs.device) {
         (Some(rails), None) => {
             if rails.len() > 1 {
@@ -1993,13 +3460,25 @@ fn pmbus_main(
                 bail!("unknown device \"{}\"", driver);
             }
         }
-    } else if let Some(driver) = &hargs.device {
-        match pmbus::Device::from_str(driver) {
-            Some(device) => device,
+    } else if let Some(device) =
+        hargs.device.as_deref().and_then(pmbus::Device::from_str)
+    {
+        device
+    } else {
+        //
+        // If the archive doesn't know the driver for this device, a prior
+        // scan of its bus might.
+        //
+        match ScanCache::load(hubris).driver(&hargs) {
+            Some(driver) => match pmbus::Device::from_str(driver) {
+                Some(device) => {
+                    humility::msg!("using driver {driver} from cached scan");
+                    device
+                }
+                None => pmbus::Device::Common,
+            },
             None => pmbus::Device::Common,
         }
-    } else {
-        pmbus::Device::Common
     };
 
     let (all, _) = all_commands(device);
@@ -2038,9 +3517,9 @@ fn pmbus_main(
         return Ok(());
     }
 
-    let mut cmds = vec![];
-
-    worker.begin_device(&hargs)?;
+    if subargs.pec {
+        worker.enable_pec()?;
+    }
 
     let rails = match hargs.class {
         HubrisI2cDeviceClass::Pmbus { rails } => Some(rails),
@@ -2062,22 +3541,55 @@ fn pmbus_main(
 
         run.fill(false);
 
+        let driver = match (&subargs.driver, &hargs.device) {
+            (Some(driver), _) | (None, Some(driver)) => driver.as_str(),
+            (None, None) => "PMBus device",
+        };
+
+        let (common, _) = all_commands(pmbus::Device::Common);
+
         for cmd in commands {
-            if let Some(code) = all.get(cmd) {
-                run[*code as usize] = true;
+            let code = if let Some(code) = all.get(cmd) {
+                *code
             } else if let Ok(code) = parse_int::parse::<u8>(cmd) {
-                run[code as usize] = true;
+                code
+            } else if common.contains_key(cmd) {
+                bail!("{} is not supported by {}", cmd, driver);
             } else {
                 bail!(
                     "unrecognized PMBus command {}; \
                      use -H for command help",
                     cmd
                 );
+            };
+
+            //
+            // Make sure that this command exists for our device and can
+            // actually be read; otherwise we would silently skip it.
+            //
+            let mut op = None;
+            device.command(code, |c| op = Some(c.read_op()));
+
+            match op {
+                None => {
+                    bail!("command {} is not supported by {}", cmd, driver);
+                }
+                Some(
+                    pmbus::Operation::ReadByte
+                    | pmbus::Operation::ReadWord
+                    | pmbus::Operation::ReadWord32
+                    | pmbus::Operation::ReadBlock,
+                ) => {
+                    run[code as usize] = true;
+                }
+                Some(_) => {
+                    bail!("command {} cannot be read", cmd);
+                }
             }
         }
     }
 
-    let mut setrail = false;
+    let mut select = None;
 
     //
     // If we have a rail specified, we want to set that first.
@@ -2127,99 +3639,262 @@ fn pmbus_main(
             },
         };
 
-        let page = pmbus::commands::CommandCode::PAGE as u8;
-
         if rails.len() > 1 {
-            worker.select_rail(rnum as u8);
-            cmds.push(page);
-            setrail = true;
+            select = Some(rnum as u8);
         }
     }
 
-    let mut addcmd = |cmd: &dyn pmbus::Command, code| {
-        let op = cmd.read_op();
-        if matches!(
-            op,
-            pmbus::Operation::ReadByte
-                | pmbus::Operation::ReadWord
-                | pmbus::Operation::ReadWord32
-                | pmbus::Operation::ReadBlock
-        ) {
-            if subargs.dryrun {
-                println!("0x{:02x} {:?}", code, cmd);
-            }
-
-            worker.read(code, op);
-            cmds.push(code);
+    //
+    // If we have been asked for a particular page (or all of them), we
+    // select each in turn, with the number of pages determined by the
+    // number of rails.
+    //
+    let pages = match (&subargs.page, rails) {
+        (None, _) => vec![select],
+        (Some(_), None) => {
+            bail!("device has unknown rails; cannot select page");
+        }
+        (Some(_), Some(rails)) if rails.is_empty() => {
+            bail!("device has no defined rails; cannot select page");
+        }
+        (Some(page), Some(rails)) if page == "all" => {
+            (0..rails.len()).map(|p| Some(p as u8)).collect()
         }
+        (Some(page), Some(rails)) => match parse_int::parse::<u8>(page) {
+            Ok(p) if (p as usize) < rails.len() => vec![Some(p)],
+            Ok(p) => {
+                bail!("invalid page {}; device has {} page(s)", p, rails.len())
+            }
+            Err(_) => bail!("page must be a number or \"all\""),
+        },
     };
 
-    let vout = pmbus::commands::CommandCode::VOUT_MODE as u8;
-    device.command(vout, |cmd| addcmd(cmd, vout));
-
-    for i in 0..=255u8 {
-        if run[i as usize] {
-            device.command(i, |cmd| addcmd(cmd, i));
-        }
+    if let Some(other) = &subargs.compare {
+        return compare(subargs, device, worker, &hargs, &pages, &run, other);
     }
 
-    if subargs.dryrun {
-        return Ok(());
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    if subargs.interval.is_some() {
+        ctrlc::set_handler(|| DONE.store(true, Ordering::SeqCst))?;
     }
 
-    if cmds.is_empty() {
-        bail!("no command to run");
+    let page = pmbus::commands::CommandCode::PAGE as u8;
+    let vout = pmbus::commands::CommandCode::VOUT_MODE as u8;
+    let mut csvout = csv::Writer::from_writer(std::io::stdout());
+
+    if let Some(OutputFormat::Csv) = subargs.format {
+        csvout.write_record(PmbusRecord::CSV_HEADER)?;
     }
 
-    let results = worker.run()?;
+    //
+    // If we are monitoring, we construct and run our program once per
+    // interval; otherwise, we run it exactly once.
+    //
+    loop {
+        //
+        // When monitoring, each sample is prefixed with the time at which
+        // it was taken (in seconds since the epoch).
+        //
+        let time = match subargs.interval {
+            Some(_) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                Some(format!("{}.{:03}", now.as_secs(), now.subsec_millis()))
+            }
+            None => None,
+        };
 
-    let base = if setrail {
-        match results[0] {
-            Err(code) => {
-                bail!("couldn't set rail: {}", worker.decode_write_err(code));
+        let mut records = vec![];
+
+        //
+        // Track how many commands have completed in this scan, so that we
+        // can report our progress should a subsequent page time out.
+        //
+        let mut completed = 0;
+
+        for &select in &pages {
+            let mut cmds = vec![];
+
+            worker.begin_device(&hargs)?;
+
+            if let Some(rnum) = select {
+                worker.select_rail(rnum);
+                cmds.push(page);
             }
-            Ok(_) => 1,
-        }
-    } else {
-        0
-    };
 
-    let (mode, ndx) = if cmds[base] == vout {
-        let mode = match results[base] {
-            Err(code) => {
-                bail!("can't read VOUT_MODE: {}", worker.decode_read_err(code));
+            let mut addcmd = |cmd: &dyn pmbus::Command, code| {
+                let op = cmd.read_op();
+                if matches!(
+                    op,
+                    pmbus::Operation::ReadByte
+                        | pmbus::Operation::ReadWord
+                        | pmbus::Operation::ReadWord32
+                        | pmbus::Operation::ReadBlock
+                ) {
+                    if subargs.dryrun {
+                        println!("0x{:02x} {:?}", code, cmd);
+                    }
+
+                    worker.read(code, op);
+                    cmds.push(code);
+                }
+            };
+
+            device.command(vout, |cmd| addcmd(cmd, vout));
+
+            for i in 0..=255u8 {
+                if run[i as usize] {
+                    device.command(i, |cmd| addcmd(cmd, i));
+                }
             }
-            Ok(ref val) => VOUT_MODE::CommandData::from_slice(val).unwrap(),
-        };
 
-        (Some(mode), base + 1)
-    } else {
-        (None, base)
-    };
+            worker.end_device();
 
-    let getmode = || match mode {
-        Some(mode) => mode,
-        None => {
-            panic!("unexpected call to get VOutMode");
+            if subargs.dryrun {
+                continue;
+            }
+
+            if cmds.is_empty() {
+                bail!("no command to run");
+            }
+
+            let results = match worker.run() {
+                Ok(results) => results,
+                Err(err) if err.is::<PmbusTimeout>() => {
+                    bail!(
+                        "pmbus scan timed out after {} ms \
+                        ({} of {} commands completed)",
+                        subargs.timeout,
+                        completed,
+                        completed + cmds.len()
+                    );
+                }
+                Err(err) => return Err(err),
+            };
+
+            completed += cmds.len();
+
+            let base = if select.is_some() {
+                match results[0] {
+                    Err(code) => {
+                        bail!(
+                            "couldn't select page: {}",
+                            worker.decode_write_err(code)
+                        );
+                    }
+                    Ok(_) => 1,
+                }
+            } else {
+                0
+            };
+
+            let (mode, ndx) = if cmds[base] == vout {
+                let mode = match results[base] {
+                    Err(code) => {
+                        bail!(
+                            "can't read VOUT_MODE: {}",
+                            worker.decode_read_err(code)
+                        );
+                    }
+                    Ok(ref val) => {
+                        VOUT_MODE::CommandData::from_slice(val).unwrap()
+                    }
+                };
+
+                (Some(mode), base + 1)
+            } else {
+                (None, base)
+            };
+
+            let getmode = || match mode {
+                Some(mode) => mode,
+                None => {
+                    panic!("unexpected call to get VOutMode");
+                }
+            };
+
+            if subargs.format.is_some() {
+                for i in ndx..results.len() {
+                    device.command(cmds[i], |cmd| {
+                        let mut record = pmbus_record(
+                            device,
+                            cmds[i],
+                            getmode,
+                            cmd,
+                            &results[i],
+                            worker,
+                            time.clone(),
+                            subargs.coefficients,
+                        );
+
+                        record.page = subargs.page.as_ref().and(select);
+                        records.push(record);
+                    });
+                }
+            } else {
+                if subargs.page.is_some() {
+                    if let Some(page) = select {
+                        println!("page {page}:");
+                    }
+                }
+
+                let prefix = match &time {
+                    Some(time) => format!("{time} "),
+                    None => String::new(),
+                };
+
+                for i in ndx..results.len() {
+                    let mut r = Ok(());
+
+                    device.command(cmds[i], |cmd| {
+                        r = print_result(
+                            subargs,
+                            device,
+                            cmds[i],
+                            getmode,
+                            cmd,
+                            &results[i],
+                            worker,
+                            &prefix,
+                        );
+                    });
+
+                    r?;
+                }
+            }
         }
-    };
 
-    for i in ndx..results.len() {
-        let mut r = Ok(());
-
-        device.command(cmds[i], |cmd| {
-            r = print_result(
-                subargs,
-                device,
-                cmds[i],
-                getmode,
-                cmd,
-                &results[i],
-                worker,
-            );
-        });
+        if subargs.dryrun {
+            return Ok(());
+        }
+
+        match subargs.format {
+            Some(OutputFormat::Json) if time.is_some() => {
+                println!("{}", serde_json::to_string(&records)?);
+            }
+            Some(OutputFormat::Json) => {
+                println!("{}", serde_json::to_string_pretty(&records)?);
+            }
+            Some(OutputFormat::Csv) => {
+                for record in &records {
+                    csvout.write_record(record.to_csv())?;
+                }
 
-        r?;
+                csvout.flush()?;
+            }
+            None => {}
+        }
+
+        match subargs.interval {
+            Some(interval) if !DONE.load(Ordering::SeqCst) => {
+                std::thread::sleep(Duration::from_millis(interval));
+            }
+            _ => break,
+        }
+
+        if DONE.load(Ordering::SeqCst) {
+            break;
+        }
     }
 
     Ok(())
@@ -56,6 +56,23 @@
 //! 0x00011d00 |    62 6f 75 6e 64 73                            |  bounds
 //! ```
 //!
+//! Reads that are larger than the maximum size of a single read from the
+//! target are performed in chunks, with the target halted for the duration.
+//!
+//! If some of the specified memory cannot be read (e.g., over an unreliable
+//! link, or from a dump that does not contain it), the memory that can be
+//! read is displayed, with the memory that cannot be read marked as `??`:
+//!
+//! ```console
+//! $ humility -d hubris.core.0 readmem 0x2400fff8 16
+//! humility: attached to dump
+//! humility: reading at 0x2400fff8 for 16 bytes
+//! humility: WARNING: could not read 8 bytes at 0x24010000
+//!              0  1  2  3  4  5  6  7 \/  9  a  b  c  d  e  f
+//! 0x2400fff0 |                         00 00 00 00 01 00 00 00 |         ........
+//! 0x24010000 | ?? ?? ?? ?? ?? ?? ?? ??                         | ????????
+//! ```
+//!
 //! The length argument can have an optional size suffix.  Note that "k" is
 //! used to to denote the SI kilobytes (that is, 1000 bytes); if one wishes to
 //! have a multiples of 1024 bytes (a kibibyte), "KiB" should be used instead.
@@ -74,6 +91,45 @@
 //! 0x20000030 | 00004d28 00004d28 00004d28 00004d28 | (M..(M..(M..(M..
 //! ```
 //!
+//! By default, halfwords and words are interpreted as little-endian; to
+//! interpret them as big-endian (e.g., when examining network structures),
+//! use `--big-endian` (`-B`).  Note that this only affects the interpretation
+//! of halfwords and words, not the order in which bytes are displayed in the
+//! ASCII column.
+//!
+//! To interpret memory as IEEE 754 floating point values, use `--float` (for
+//! single-precision) or `--double` (for double-precision); each value is
+//! displayed alongside its raw bits:
+//!
+//! ```console
+//! $ humility readmem --float 0x24000c10 16
+//! humility: attached via ST-Link V3
+//! 0x24000c10 | 0x42c80000 | 100
+//! 0x24000c14 | 0x3fc00000 | 1.5
+//! 0x24000c18 | 0xbf800000 | -1
+//! 0x24000c1c | 0x00000000 | 0
+//! ```
+//!
+//! To display a region of code as instructions, provide an archive and
+//! specify `--disassemble` (`-D`).  Each instruction is shown with its
+//! symbolic location and (for instructions that affect control flow) its
+//! target:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem -D 0x0803c2e0 16
+//! humility: attached via ST-Link V3
+//! 0x0803c2e0 | 4620      | spi:main+0x58
+//! 0x0803c2e2 | f000 fdf3 | spi:main+0x5a -> 0x0803cecc <spi:sys_send_stub+0x0>
+//! 0x0803c2e6 | 2800      | spi:main+0x5e
+//! 0x0803c2e8 | d1f6      | spi:main+0x60 -> 0x0803c2d8 <spi:main+0x50>
+//! 0x0803c2ea | e7fe      | spi:main+0x62 -> 0x0803c2ea <spi:main+0x62>
+//! 0x0803c2ec | bd80      | spi:main+0x64 -> (return)
+//! 0x0803c2ee | bf00      | spi:main+0x66
+//! ```
+//!
+//! If the region contains anything other than code known to the archive,
+//! disassembly will stop with a warning.
+//!
 //! A frequent use of `readmem` is to read peripheral memory; as a
 //! convenience, a peripheral name can be used in lieu of an address, provided
 //! that an archive or dump is also specified:
@@ -90,6 +146,45 @@
 //!
 //! **Note that reading some peripheral memory may have side effects!**
 //!
+//! Similarly, the name of a variable can be used as the address.  If no
+//! length is specified, the size of the variable will be used:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem -w CURRENT_TASK_PTR
+//! humility: attached via ST-Link V3
+//!                    \/        4        8        c
+//! 0x20000658 | 20000898                            | ...
+//! ```
+//!
+//! If a variable has the same name in more than one task, it must be
+//! specified by its qualified name (as listed by `humility readvar -l`).
+//!
+//! To read memory within a task's RAM region without having to determine
+//! its address (which can change whenever the image is relinked), the
+//! address can be specified as the name of a task and an offset into its RAM
+//! region, separated by a colon.  It is an error for the resulting address
+//! to fall outside of the region; if no length is specified, the read will
+//! not extend past the end of the region:
+//!
+//! ```console
+//! $ humility readmem -w net:0x40 16
+//! humility: attached via ST-Link V3
+//!                    \/        4        8        c
+//! 0x24000040 | 00000000 24003c10 00000001 0000ffff | .....<.$........
+//! ```
+//!
+//! To search a region for a pattern rather than display it, use `--find`
+//! with the pattern as a sequence of hex bytes (or, with `--string`, as an
+//! ASCII string).  Every address at which the pattern is found is printed:
+//!
+//! ```console
+//! $ humility readmem --find deadbeef 0x24000000 256KiB
+//! humility: attached via ST-Link V3
+//! 0x24001a40 (+0x1a40)
+//! 0x2401c3f8 (+0x1c3f8)
+//! humility: found 2 matches
+//! ```
+//!
 //! It can also be useful to interpret memory contents symbolically; to do this,
 //! provide a dump or achive and specify the `-s` option, e.g.:
 //!
@@ -114,15 +209,134 @@
 //! 0x20004b6c | 0x00000000
 //! ```
 //!
+//! Where `-s` interprets the *contents* of memory symbolically, `--annotate`
+//! instead describes the memory being displayed:  each line of the hex dump
+//! is annotated in the right margin with the variables that it overlaps (or,
+//! if there are none, with the region that contains it):
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem -w --annotate 0x24000e04 0x20
+//! humility: attached via ST-Link V3
+//!                     0       \/        8        c
+//! 0x24000e00 |          00000001 24000f10 00000003 |     .......$.... <- NET_STATE+0x4
+//! 0x24000e10 | 00000000 0000002a 00000000 00000000 | ....*........... <- NET_STATE+0x10, RX_COUNT
+//! 0x24000e20 | 00000000                            | ....             <- net: 0x24000000+0xe20
+//! ```
+//!
+//! To make a region easier to scan visually, `--color` colors the hex dump:
+//! zero bytes are dimmed, printable ASCII is highlighted in green, and words
+//! that look like pointers are shown in cyan.  A word looks like a pointer
+//! if it is the address of an instruction known to the archive or if it
+//! falls within one of the (non-device) memory regions; without an archive,
+//! only zeroes and ASCII are distinguished.  Color is only used when the
+//! output is a terminal and `NO_COLOR` is not set; otherwise, the output is
+//! the same as without `--color`.
+//!
+//! `readmem` can also be used to modify memory by specifying `--write` with
+//! a comma-delimited list of values.  The values are written starting at the
+//! specified address, each sized as a byte, a halfword (`-H`) or a word
+//! (`-w`), with the same alignment constraints as reading.  The target is
+//! halted for the duration of the writes and then resumed:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem -w 0x24000100 --write 0x1,0xdeadbeef
+//! humility: attached via ST-Link V3
+//! humility: writing 0x1 to 0x24000100
+//! humility: writing 0xdeadbeef to 0x24000104
+//! ```
+//!
+//! Because it is used to determine the extent of flash (to which writes are
+//! refused), an archive must be specified when writing.
+//!
+//! To decode memory as a structure, provide an archive and specify the name
+//! of the structure with `--struct`; each member is displayed with its
+//! address and offset, decoded per its type:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem --struct TaskDesc 0x08000224
+//! humility: attached via ST-Link V3
+//! TaskDesc (0x08000224, 28 bytes) {
+//!     0x08000224 +0x0    regions = [ 0x3, 0x8, 0x9, 0xd, 0x0, 0x0, 0x0, 0x0 ]
+//!     0x0800022c +0x8    entry_point = 0x8013001
+//!     0x08000230 +0xc    initial_stack = 0x24000800
+//!     0x08000234 +0x10   priority = 0x0
+//!     0x08000238 +0x14   flags = TaskFlags {
+//!         bits: 0x1
+//!     }
+//!     0x0800023c +0x18   index = 0x0
+//! }
+//! ```
+//!
+//! To display an array of consecutive items (e.g., the entries of a ring
+//! buffer), use `--count` to specify the number of items.  With `--struct`,
+//! each item is a structure of the specified type; otherwise, each item is
+//! of the specified length.  Each item is displayed with its index:
+//!
+//! ```console
+//! $ humility -a ~/hubris/target/gemini-bu/dist/build-gemini-bu.zip readmem --struct TaskDesc --count 2 0x08000224
+//! humility: attached via ST-Link V3
+//! [0] TaskDesc (0x08000224, 28 bytes) {
+//!     0x08000224 +0x0    regions = [ 0x3, 0x8, 0x9, 0xd, 0x0, 0x0, 0x0, 0x0 ]
+//!     ...
+//! }
+//! [1] TaskDesc (0x08000240, 28 bytes) {
+//!     0x08000240 +0x0    regions = [ 0x4, 0x8, 0xa, 0xd, 0x0, 0x0, 0x0, 0x0 ]
+//!     ...
+//! }
+//! $ humility readmem -w --count 2 0x20000658 8
+//! humility: attached via ST-Link V3
+//! [0]
+//!                    \/        4
+//! 0x20000658 | 20000898 00000000                   | ........
+//! [1]
+//!                    \/        4
+//! 0x20000660 | 00000001 0800a1c5                   | ........
+//! ```
+//!
+//! To extract memory for consumption by another tool, use `--output` to
+//! write the raw contents to the specified file (or to stdout if `-` is
+//! specified).  This allows for larger regions (e.g., a flash image) to be
+//! extracted:
+//!
+//! ```console
+//! $ humility readmem --output flash.bin 0x08000000 1MiB
+//! humility: attached via ST-Link V3
+//! humility: Wrote 1048576 bytes to "flash.bin"
+//! ```
+//!
+//! To display a NUL-terminated string (e.g., a panic message or the contents
+//! of a log buffer), use `--string`.  Memory is read up to the first NUL
+//! byte or up to the specified length (defaulting to 256 bytes), and the
+//! string is printed with any non-printable characters escaped:
+//!
+//! ```console
+//! $ humility readmem --string 0x24001a40
+//! humility: attached via ST-Link V3
+//! 0x24001a40 | "panicked at 'attempt to add with overflow'\n"
+//! ```
+//!
+//! To watch a region of memory change over time, use `--watch`: the region
+//! will be re-read every `--interval` milliseconds (defaulting to 1000),
+//! with the display redrawn in place and any values that changed since the
+//! previous read highlighted.  Hit Ctrl-C to exit; the target is left
+//! running.
+//!
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use clap::{CommandFactory, Parser};
+use crossterm::cursor::MoveTo;
+use crossterm::execute;
+use crossterm::terminal::{Clear, ClearType};
 use humility::hubris::*;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Dumper, Validate};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::io::Write;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 //
 // We allow the size to be specified as a number (e.g., with an optional `0x`
@@ -145,20 +359,140 @@ fn parse_size<T: AsRef<[u8]>>(src: T) -> Result<u64, parse_size::Error> {
 #[clap(name = "readmem", about = env!("CARGO_PKG_DESCRIPTION"))]
 struct ReadmemArgs {
     /// print out as halfwords instead of as bytes
-    #[clap(long, short = 'H', conflicts_with_all = &["word", "symbol", "file"])]
+    #[clap(long, short = 'H', conflicts_with_all = &["word", "symbol"])]
     halfword: bool,
 
     /// print out as words instead of as bytes
-    #[clap(long, short, conflicts_with_all = &["symbol" , "file"])]
+    #[clap(long, short, conflicts_with_all = &["symbol"])]
     word: bool,
 
     /// print out as symbols
     #[clap(long, short)]
     symbol: bool,
 
-    /// Save to a file instead of printing out
-    #[clap(long, conflicts_with_all = &["word", "halfword", "symbol"])]
-    file: Option<PathBuf>,
+    /// print out as single-precision floating point values
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "halfword", "word", "symbol", "double", "output", "write",
+            "structure", "watch",
+        ]
+    )]
+    float: bool,
+
+    /// print out as double-precision floating point values
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "halfword", "word", "symbol", "output", "write", "structure",
+            "watch",
+        ]
+    )]
+    double: bool,
+
+    /// print out as instructions
+    #[clap(
+        long, short = 'D',
+        conflicts_with_all = &[
+            "halfword", "word", "symbol", "float", "double", "output",
+            "write", "structure", "find", "watch",
+        ]
+    )]
+    disassemble: bool,
+
+    /// interpret halfwords and words as big-endian
+    #[clap(
+        long, short = 'B',
+        conflicts_with_all = &["output", "write", "structure"]
+    )]
+    big_endian: bool,
+
+    /// save raw memory to a file (or "-" for stdout) instead of printing out
+    #[clap(
+        long, short, alias = "file", value_name = "file",
+        conflicts_with_all = &["symbol", "write", "watch"]
+    )]
+    output: Option<String>,
+
+    /// write the specified value(s) instead of reading
+    #[clap(
+        long, value_name = "values", use_value_delimiter = true,
+        parse(try_from_str = parse_int::parse),
+        conflicts_with_all = &["symbol", "length"]
+    )]
+    write: Option<Vec<u32>>,
+
+    /// decode memory as the specified structure
+    #[clap(
+        long = "struct", value_name = "type",
+        conflicts_with_all = &[
+            "halfword", "word", "symbol", "output", "write", "watch", "length"
+        ]
+    )]
+    structure: Option<String>,
+
+    /// search for the specified pattern (in hex) instead of printing out
+    #[clap(
+        long, value_name = "pattern",
+        conflicts_with_all = &[
+            "symbol", "output", "write", "structure", "watch"
+        ]
+    )]
+    find: Option<String>,
+
+    /// print out as a NUL-terminated string (or, with --find, interpret
+    /// the pattern as an ASCII string rather than hex)
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "halfword", "word", "symbol", "float", "double", "disassemble",
+            "output", "write", "structure", "watch",
+        ]
+    )]
+    string: bool,
+
+    /// read the specified number of consecutive items (structures or
+    /// regions of the specified length), displaying each with its index
+    #[clap(
+        long, value_name = "items",
+        parse(try_from_str = parse_int::parse),
+        conflicts_with_all = &[
+            "symbol", "float", "double", "disassemble", "output", "write",
+            "find", "string", "watch",
+        ]
+    )]
+    count: Option<usize>,
+
+    /// annotate each line with the variable or region that contains it
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "symbol", "float", "double", "disassemble", "output", "write",
+            "structure", "find", "string", "watch",
+        ]
+    )]
+    annotate: bool,
+
+    /// color output to distinguish zeroes, ASCII, and likely pointers
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "symbol", "float", "double", "disassemble", "output", "write",
+            "structure", "find", "string", "watch",
+        ]
+    )]
+    color: bool,
+
+    /// repeatedly read memory, highlighting changes
+    #[clap(long, conflicts_with_all = &["symbol", "write"])]
+    watch: bool,
+
+    /// interval between reads when watching
+    #[clap(
+        long, value_name = "ms", default_value_t = 1000,
+        parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
 
     /// address to read
     address: String,
@@ -168,6 +502,632 @@ struct ReadmemArgs {
     length: Option<u64>,
 }
 
+fn writemem(
+    hubris: &HubrisArchive,
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    size: usize,
+    values: &[u32],
+) -> Result<()> {
+    if core.is_dump() || core.is_archive() {
+        bail!("can only write to a live target");
+    }
+
+    if hubris.archive().is_empty() {
+        bail!("an archive is required to write memory");
+    }
+
+    for v in values {
+        if size < 4 && *v >> (size * 8) != 0 {
+            bail!("value {:#x} does not fit in {} byte(s)", v, size);
+        }
+    }
+
+    //
+    // We don't want to allow anyone to believe that they are modifying flash
+    // by writing to it (it will at best fault, and at worst do something
+    // surprising), so we refuse any write that overlaps any part of flash.
+    //
+    let len = (values.len() * size) as u32;
+    let flash = HubrisFlashMap::new(hubris)?;
+
+    for (&base, &(fsize, _)) in &flash.regions {
+        if addr < base + fsize && base < addr + len {
+            bail!(
+                "cannot write to flash ({:#x}-{:#x} overlaps {:#x}-{:#x})",
+                addr,
+                addr + len - 1,
+                base,
+                base + fsize - 1
+            );
+        }
+    }
+
+    core.halt()?;
+
+    let rval = values.iter().enumerate().try_for_each(|(i, v)| {
+        let addr = addr + (i * size) as u32;
+        humility_log::msg!("writing {v:#x} to {addr:#x}");
+
+        match size {
+            4 => core.write_word_32(addr, *v),
+            _ => core.write_8(addr, &v.to_le_bytes()[..size]),
+        }
+    });
+
+    core.run()?;
+    rval
+}
+
+fn watchmem(
+    core: &mut dyn humility::core::Core,
+    dumper: &Dumper,
+    addr: u32,
+    length: usize,
+    interval: u64,
+) -> Result<()> {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    if core.is_dump() || core.is_archive() {
+        bail!("can only watch a live target");
+    }
+
+    ctrlc::set_handler(|| DONE.store(true, Ordering::SeqCst))?;
+
+    let mut stdout = std::io::stdout();
+    let mut previous: Option<Vec<u8>> = None;
+
+    execute!(stdout, Clear(ClearType::All))?;
+
+    while !DONE.load(Ordering::SeqCst) {
+        let mut bytes = vec![0u8; length];
+        core.read_8(addr, &mut bytes)?;
+
+        execute!(stdout, MoveTo(0, 0))?;
+        dumper.dump_diff(&bytes, previous.as_deref(), addr);
+        previous = Some(bytes);
+
+        std::thread::sleep(Duration::from_millis(interval));
+    }
+
+    Ok(())
+}
+
+fn warn_unreadable(addr: u32, unreadable: &[Range<usize>]) {
+    for r in unreadable {
+        humility_log::warn!(
+            "could not read {} bytes at {:#x}",
+            r.len(),
+            addr + r.start as u32
+        );
+    }
+}
+
+//
+// Reads `length` bytes at `addr`, handing them to `f` in chunks of no more
+// than the maximum read size.  If more than one chunk is required, we halt
+// the target for the duration (so the contents are a consistent snapshot)
+// and indicate our progress as we go.
+//
+fn read_chunked(
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    length: usize,
+    mut f: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    read_chunked_with(core, addr, length, false, |buf, _| f(buf))
+}
+
+fn read_chunk(
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    buf: &mut [u8],
+    partial: bool,
+) -> Result<Vec<Range<usize>>> {
+    if partial {
+        core.try_read_8(addr, buf)
+    } else {
+        core.read_8(addr, buf)?;
+        Ok(vec![])
+    }
+}
+
+//
+// Like [`read_chunked`], but if `partial` is set, tolerates memory that
+// cannot be read:  such memory is handed to `f` as zeroes, along with the
+// ranges (as offsets from `addr`) that could not be read.
+//
+fn read_chunked_with(
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    length: usize,
+    partial: bool,
+    mut f: impl FnMut(&[u8], &[Range<usize>]) -> Result<()>,
+) -> Result<()> {
+    let max = humility::core::CORE_MAX_READSIZE;
+    let mut bytes = vec![0u8; std::cmp::min(max, length)];
+
+    if length <= max {
+        let failed = read_chunk(core, addr, &mut bytes, partial)?;
+        return f(&bytes, &failed);
+    }
+
+    let live = !core.is_dump() && !core.is_archive();
+
+    let bar = ProgressBar::new(length as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("humility: reading [{bar:30}] {bytes}/{total_bytes}"),
+    );
+
+    if live {
+        core.halt()?;
+    }
+
+    let mut rval = Ok(());
+
+    for offs in (0..length).step_by(max) {
+        let buf = &mut bytes[..std::cmp::min(max, length - offs)];
+
+        rval = read_chunk(core, addr + offs as u32, buf, partial).and_then(
+            |failed| {
+                let failed = failed
+                    .into_iter()
+                    .map(|r| r.start + offs..r.end + offs)
+                    .collect::<Vec<_>>();
+
+                f(buf, &failed)
+            },
+        );
+
+        if rval.is_err() {
+            break;
+        }
+
+        bar.set_position((offs + buf.len()) as u64);
+    }
+
+    bar.finish_and_clear();
+
+    if live {
+        core.run()?;
+    }
+
+    rval
+}
+
+//
+// Reads a NUL-terminated string of no more than `length` bytes at `addr`,
+// printing it with any non-printable characters escaped.
+//
+fn readstring(
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    length: usize,
+) -> Result<()> {
+    let mut bytes = Vec::with_capacity(length);
+
+    read_chunked(core, addr, length, |buf| {
+        bytes.extend_from_slice(buf);
+        Ok(())
+    })?;
+
+    match bytes.iter().position(|&b| b == 0) {
+        Some(nul) => bytes.truncate(nul),
+        None => {
+            humility_log::warn!(
+                "no NUL terminator found in {} bytes at {:#x}",
+                length,
+                addr
+            );
+        }
+    }
+
+    //
+    // If the string isn't valid UTF-8, we escape it byte-by-byte as ASCII.
+    //
+    let escaped = match std::str::from_utf8(&bytes) {
+        Ok(s) => s.escape_debug().to_string(),
+        Err(_) => bytes.escape_ascii().to_string(),
+    };
+
+    println!("0x{:08x} | \"{}\"", addr, escaped);
+
+    Ok(())
+}
+
+//
+// Resolves a name to an address, returning the size of the named object if
+// it is known.  We first look for a variable (which may be qualified), and
+// then for a peripheral.
+//
+fn lookup_address(
+    hubris: &HubrisArchive,
+    name: &str,
+) -> Result<(u32, Option<usize>)> {
+    if let Ok(variables) = hubris.lookup_variables(name) {
+        if variables.len() > 1 {
+            let names = hubris
+                .qualified_variables()
+                .filter(|&(n, _)| n.ends_with(&format!("::{name}")))
+                .map(|(n, _)| n)
+                .collect::<Vec<_>>();
+
+            bail!("{name} is ambiguous; expected one of: {}", names.join(", "));
+        }
+
+        return Ok((variables[0].addr, Some(variables[0].size)));
+    }
+
+    if let Some((_, v)) = hubris.qualified_variables().find(|&(n, _)| n == name)
+    {
+        return Ok((v.addr, Some(v.size)));
+    }
+
+    if let Ok(addr) = hubris.lookup_peripheral(name) {
+        return Ok((addr, None));
+    }
+
+    let lower = name.to_lowercase();
+
+    let mut matches = hubris
+        .qualified_variables()
+        .map(|(n, _)| n)
+        .chain(hubris.manifest.peripherals.keys().map(String::as_str))
+        .filter(|n| n.to_lowercase().contains(&lower))
+        .collect::<Vec<_>>();
+
+    matches.sort_unstable();
+    matches.dedup();
+
+    if matches.is_empty() {
+        bail!("{name} does not correspond to a variable or peripheral");
+    }
+
+    bail!(
+        "{name} does not correspond to a variable or peripheral; \
+        did you mean: {}",
+        matches.join(", ")
+    );
+}
+
+//
+// Parses an address of the form `task:offset`, returning None if the address
+// isn't of that form.
+//
+fn parse_task_offset(address: &str) -> Option<(&str, u32)> {
+    let (task, offset) = address.split_once(':')?;
+
+    if task.is_empty() {
+        return None;
+    }
+
+    parse_int::parse::<u32>(offset).ok().map(|offset| (task, offset))
+}
+
+//
+// Resolves an offset into a task's RAM region (that is, the lowest-addressed
+// writable memory region that belongs to it), returning the resulting
+// address and the end of the region.
+//
+fn lookup_task_offset(
+    hubris: &HubrisArchive,
+    core: &mut dyn humility::core::Core,
+    task: &str,
+    offset: u32,
+) -> Result<(u32, u32)> {
+    let t = match hubris.lookup_task(task) {
+        Some(t @ HubrisTask::Task(_)) => *t,
+        _ => bail!("invalid task \"{task}\""),
+    };
+
+    let (base, size) = hubris
+        .regions(core)?
+        .into_values()
+        .filter(|r| !r.attr.device && !r.attr.external && r.attr.write)
+        .find(|r| r.tasks.contains(&t))
+        .map(|r| (r.base, r.size))
+        .ok_or_else(|| anyhow!("{task} has no RAM region"))?;
+
+    if offset >= size {
+        bail!(
+            "offset {offset:#x} is outside of {task}'s RAM region \
+            ({base:#x}-{:#x})",
+            base + size - 1
+        );
+    }
+
+    Ok((base + offset, base + size))
+}
+
+fn readstruct(
+    hubris: &HubrisArchive,
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    name: &str,
+    count: Option<usize>,
+) -> Result<()> {
+    let s = hubris.lookup_struct_byname(name)?;
+    let total = s.size * count.unwrap_or(1);
+    let mut bytes = Vec::with_capacity(total);
+
+    read_chunked(core, addr, total, |buf| {
+        bytes.extend_from_slice(buf);
+        Ok(())
+    })?;
+
+    let fmt = HubrisPrintFormat {
+        newline: true,
+        hex: true,
+        ..HubrisPrintFormat::default()
+    };
+
+    for (i, item) in bytes.chunks(s.size.max(1)).enumerate() {
+        let addr = addr + (i * s.size) as u32;
+        let index = match count {
+            Some(_) => format!("[{}] ", i),
+            None => "".to_string(),
+        };
+
+        println!("{}{} (0x{:08x}, {} bytes) {{", index, s.name, addr, s.size);
+
+        for m in &s.members {
+            let val = hubris.printfmt(&item[m.offset..], m.goff, fmt)?;
+
+            println!(
+                "    0x{:08x} +0x{:<4x} {} = {}",
+                addr + m.offset as u32,
+                m.offset,
+                m.name,
+                val.replace('\n', "\n    ")
+            );
+        }
+
+        println!("}}");
+    }
+
+    Ok(())
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<u8>> {
+    let hex = pattern.strip_prefix("0x").unwrap_or(pattern);
+
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.is_ascii() {
+        bail!("pattern must be a non-empty, even number of hex digits");
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| anyhow!("invalid hex pattern \"{}\"", pattern))
+        })
+        .collect()
+}
+
+//
+// Searches the region for the pattern, reporting every match (including
+// overlapping ones).  To catch matches that span chunks, we hold on to the
+// tail of each chunk until we have seen the next.
+//
+fn findmem(
+    core: &mut dyn humility::core::Core,
+    addr: u32,
+    length: usize,
+    pattern: &[u8],
+) -> Result<()> {
+    if pattern.is_empty() {
+        bail!("pattern cannot be empty");
+    }
+
+    if pattern.len() > length {
+        bail!(
+            "pattern ({} bytes) is longer than region ({} bytes)",
+            pattern.len(),
+            length
+        );
+    }
+
+    let mut window = vec![];
+    let mut base = 0;
+    let mut found = 0;
+
+    read_chunked(core, addr, length, |buf| {
+        window.extend_from_slice(buf);
+
+        for (i, w) in window.windows(pattern.len()).enumerate() {
+            if w == pattern {
+                let offs = base + i;
+                println!("0x{:08x} (+0x{:x})", addr + offs as u32, offs);
+                found += 1;
+            }
+        }
+
+        let drop = window.len() - (pattern.len() - 1).min(window.len());
+        window.drain(..drop);
+        base += drop;
+
+        Ok(())
+    })?;
+
+    humility_log::msg!(
+        "found {} match{}",
+        found,
+        if found == 1 { "" } else { "es" }
+    );
+
+    Ok(())
+}
+
+//
+// Returns a function that, given the address and length of a line of a hex
+// dump, describes the memory on the line:  the variables that it overlaps
+// (with the offset into the variable that the line starts in, if any), or --
+// if there are no such variables -- the region that contains it.
+//
+fn annotator<'a>(
+    hubris: &'a HubrisArchive,
+    core: &mut dyn humility::core::Core,
+) -> Result<impl Fn(u32, usize) -> Option<String> + 'a> {
+    let regions = hubris.regions(core)?;
+    let mut variables: BTreeMap<u32, Vec<(&str, usize)>> = BTreeMap::new();
+    let mut largest = 0;
+
+    for (name, v) in hubris.qualified_variables() {
+        if v.size == 0 {
+            continue;
+        }
+
+        let name = name.rsplit("::").next().unwrap_or(name);
+        let entry = variables.entry(v.addr).or_default();
+
+        if !entry.iter().any(|&(n, _)| n == name) {
+            entry.push((name, v.size));
+        }
+
+        largest = largest.max(v.size);
+    }
+
+    Ok(move |addr: u32, len: usize| {
+        let start = addr as u64;
+        let end = start + len as u64;
+        let from = start.saturating_sub(largest as u64) as u32;
+        let mut notes = vec![];
+
+        for (&base, vars) in variables.range(from..) {
+            if base as u64 >= end {
+                break;
+            }
+
+            for &(name, size) in vars {
+                if base as u64 + size as u64 <= start {
+                    continue;
+                }
+
+                notes.push(if base < addr {
+                    format!("{}+0x{:x}", name, addr - base)
+                } else {
+                    name.to_string()
+                });
+            }
+        }
+
+        if notes.is_empty() {
+            hubris.explain(&regions, addr)
+        } else {
+            Some(notes.join(", "))
+        }
+    })
+}
+
+//
+// Returns a function that determines if a word looks like a pointer:  that
+// is, if it is the address of an instruction known to the archive or if it
+// falls in one of the (non-device) memory regions.  Without an archive,
+// nothing looks like a pointer.
+//
+fn pointer_detector<'a>(
+    hubris: &'a HubrisArchive,
+    core: &mut dyn humility::core::Core,
+) -> Result<impl Fn(u32) -> bool + 'a> {
+    let regions = if hubris.archive().is_empty() {
+        BTreeMap::new()
+    } else {
+        hubris.regions(core)?
+    };
+
+    Ok(move |val: u32| {
+        if hubris.instr_sym(val).is_some() {
+            return true;
+        }
+
+        match regions.range(..=val).next_back() {
+            Some((_, region)) => {
+                !region.attr.device && val - region.base < region.size
+            }
+            None => false,
+        }
+    })
+}
+
+//
+// Returns the symbolic name of an instruction address (e.g., "spi:main+0x5b")
+//
+fn symbolize(hubris: &HubrisArchive, val: u32) -> Option<String> {
+    let sval = hubris.instr_sym(val)?;
+
+    Some(format!(
+        "{}{}+0x{:x}",
+        match hubris.instr_mod(val) {
+            Some(module) if module != "kernel" => format!("{}:", module),
+            _ => "".to_string(),
+        },
+        sval.0,
+        val - sval.1
+    ))
+}
+
+//
+// Walks the buffer instruction by instruction, using the instruction
+// boundaries determined when the archive was loaded.  If we hit an address
+// that isn't a known instruction boundary, we have no way of knowing how to
+// proceed, so we stop.
+//
+fn disassemble(hubris: &HubrisArchive, bytes: &[u8], addr: u32) {
+    let mut offs = 0;
+
+    while offs < bytes.len() {
+        let iaddr = addr + offs as u32;
+
+        let len = match hubris.instr_len(iaddr) {
+            Some(len) if offs + len as usize <= bytes.len() => len as usize,
+            Some(_) => break,
+            None => {
+                humility_log::warn!(
+                    "0x{:08x} is not a known instruction; \
+                    stopping disassembly",
+                    iaddr
+                );
+                break;
+            }
+        };
+
+        let halfwords = bytes[offs..offs + len]
+            .chunks(2)
+            .map(|c| match c {
+                [lo, hi] => format!("{:04x}", u16::from_le_bytes([*lo, *hi])),
+                _ => format!("{:02x}", c[0]),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let target = match hubris.instr_target(iaddr) {
+            Some(HubrisTarget::Direct(t)) | Some(HubrisTarget::Call(t)) => {
+                match symbolize(hubris, t) {
+                    Some(sym) => format!(" -> 0x{:08x} <{}>", t, sym),
+                    None => format!(" -> 0x{:08x}", t),
+                }
+            }
+            Some(HubrisTarget::Indirect) => " -> (indirect)".to_string(),
+            Some(HubrisTarget::IndirectCall) => {
+                " -> (indirect call)".to_string()
+            }
+            Some(HubrisTarget::Return) => " -> (return)".to_string(),
+            None => "".to_string(),
+        };
+
+        println!(
+            "0x{:08x} | {:<9} | {}{}",
+            iaddr,
+            halfwords,
+            symbolize(hubris, iaddr).unwrap_or_default(),
+            target
+        );
+
+        offs += len;
+    }
+}
+
 fn readmem(context: &mut ExecutionContext) -> Result<()> {
     let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
@@ -175,7 +1135,15 @@ fn readmem(context: &mut ExecutionContext) -> Result<()> {
 
     let subargs = ReadmemArgs::try_parse_from(subargs)?;
     let max = humility::core::CORE_MAX_READSIZE;
-    let size = if subargs.word || subargs.symbol {
+
+    //
+    // If we're writing raw memory out, any display size is moot.
+    //
+    let size = if subargs.output.is_some() {
+        1
+    } else if subargs.double {
+        8
+    } else if subargs.word || subargs.symbol || subargs.float {
         4
     } else if subargs.halfword {
         2
@@ -183,69 +1151,213 @@ fn readmem(context: &mut ExecutionContext) -> Result<()> {
         1
     };
 
-    let length = subargs.length.unwrap_or(256) as usize;
-
-    if length & (size - 1) != 0 {
-        bail!("length must be {}-byte aligned", size);
+    if subargs.big_endian && size == 1 {
+        bail!(
+            "--big-endian requires --halfword, --word, --symbol, \
+            --float, or --double"
+        );
     }
 
-    if subargs.symbol {
+    if subargs.symbol
+        || subargs.disassemble
+        || subargs.annotate
+        || subargs.structure.is_some()
+    {
         hubris.validate(core, HubrisValidate::ArchiveMatch)?;
     }
 
-    let addr = match parse_int::parse::<u32>(&subargs.address) {
-        Ok(addr) => addr,
+    let annotate = if subargs.annotate {
+        if hubris.archive().is_empty() {
+            bail!("an archive is required to annotate memory");
+        }
+
+        Some(annotator(hubris, core)?)
+    } else {
+        None
+    };
+
+    //
+    // We only color our output if it's going to a terminal and the user
+    // hasn't asked us not to; otherwise, we silently degrade to plain output.
+    //
+    let pointer = if subargs.color
+        && atty::is(atty::Stream::Stdout)
+        && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+    {
+        Some(pointer_detector(hubris, core)?)
+    } else {
+        None
+    };
+
+    let mut limit = None;
+
+    let (addr, symsize) = match parse_int::parse::<u32>(&subargs.address) {
+        Ok(addr) => (addr, None),
         _ => {
             hubris.validate(core, HubrisValidate::ArchiveMatch)?;
-            hubris.lookup_peripheral(&subargs.address)?
+
+            match parse_task_offset(&subargs.address) {
+                Some((task, offset)) => {
+                    let (addr, end) =
+                        lookup_task_offset(hubris, core, task, offset)?;
+                    limit = Some(end);
+                    (addr, None)
+                }
+                None => lookup_address(hubris, &subargs.address)?,
+            }
         }
     };
 
+    //
+    // If we were given a variable and no length, we default to the size of
+    // the variable (rounded up to our display size).
+    //
+    let length = match (subargs.length, symsize, limit) {
+        (Some(length), _, _) => length as usize,
+        (None, Some(symsize), _) => (symsize.max(1) + size - 1) & !(size - 1),
+        (None, None, Some(end)) => std::cmp::min(256, (end - addr) as usize),
+        (None, None, None) => 256,
+    };
+
+    if length & (size - 1) != 0 {
+        bail!("length must be {}-byte aligned", size);
+    }
+
     if addr & (size - 1) as u32 != 0 {
         bail!("address must be {}-byte aligned", size);
     }
 
-    if let Some(file) = subargs.file {
-        let mut f = std::fs::File::create(&file)?;
-        let mut bytes = vec![0u8; max];
-        for (i, addr) in (addr..addr + (length as u32)).step_by(max).enumerate()
-        {
-            let buf = &mut bytes[..std::cmp::min(max, length - (i * max))];
-            core.read_8(addr, buf)?;
-            f.write_all(buf)?;
+    if let Some(ref values) = subargs.write {
+        return writemem(hubris, core, addr, size, values);
+    }
+
+    if let Some(ref name) = subargs.structure {
+        return readstruct(hubris, core, addr, name, subargs.count);
+    }
+
+    if let Some(ref pattern) = subargs.find {
+        let pattern = if subargs.string {
+            pattern.as_bytes().to_vec()
+        } else {
+            parse_pattern(pattern)?
+        };
+
+        return findmem(core, addr, length, &pattern);
+    }
+
+    if subargs.string {
+        return readstring(core, addr, length);
+    }
+
+    if let Some(output) = subargs.output {
+        let mut f: Box<dyn Write> = if output == "-" {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(std::fs::File::create(&output)?)
+        };
+
+        read_chunked(core, addr, length, |buf| Ok(f.write_all(buf)?))?;
+        f.flush()?;
+
+        if output != "-" {
+            humility_log::msg!("Wrote {} bytes to {:?}", length, output);
         }
-        humility_log::msg!("Wrote {} bytes to {:?}", length, file);
+
         return Ok(());
     }
 
-    if length > max {
-        bail!("cannot read more than {} bytes", max);
+    let mut dumper = Dumper::new();
+    dumper.size = size;
+    dumper.big_endian = subargs.big_endian;
+
+    let display = |bytes: &[u8], unreadable: &[Range<usize>], addr: u32| {
+        let annotate = annotate
+            .as_ref()
+            .map(|f| f as &dyn Fn(u32, usize) -> Option<String>);
+
+        match (&pointer, annotate) {
+            (Some(p), annotate) => {
+                dumper.dump_colored(bytes, unreadable, addr, annotate, p)
+            }
+            (None, Some(f)) => {
+                dumper.dump_annotated(bytes, unreadable, addr, f)
+            }
+            (None, None) => dumper.dump_partial(bytes, unreadable, addr),
+        }
+    };
+
+    if subargs.watch {
+        if length > max {
+            bail!("cannot watch more than {} bytes", max);
+        }
+
+        return watchmem(core, &dumper, addr, length, subargs.interval);
+    }
+
+    if let Some(count) = subargs.count {
+        let mut bytes = Vec::with_capacity(length * count);
+        let mut unreadable = vec![];
+
+        read_chunked_with(core, addr, length * count, true, |buf, failed| {
+            bytes.extend_from_slice(buf);
+            unreadable.extend_from_slice(failed);
+            Ok(())
+        })?;
+
+        warn_unreadable(addr, &unreadable);
+
+        for (i, item) in bytes.chunks(length.max(1)).enumerate() {
+            let start = i * length;
+
+            let unreadable = unreadable
+                .iter()
+                .filter(|r| r.start < start + item.len() && start < r.end)
+                .map(|r| {
+                    r.start.saturating_sub(start)
+                        ..std::cmp::min(r.end - start, item.len())
+                })
+                .collect::<Vec<_>>();
+
+            let addr = addr + start as u32;
+            println!("[{}]", i);
+            display(item, &unreadable, addr);
+        }
+
+        return Ok(());
     }
 
-    let mut bytes = vec![0u8; length];
+    let mut bytes = Vec::with_capacity(length);
+    let mut unreadable = vec![];
 
-    core.read_8(addr, &mut bytes)?;
+    //
+    // If we are simply displaying memory, we tolerate memory that cannot be
+    // read, and mark it as such rather than failing outright.
+    //
+    let partial = !(subargs.symbol
+        || subargs.disassemble
+        || subargs.float
+        || subargs.double);
+
+    read_chunked_with(core, addr, length, partial, |buf, failed| {
+        bytes.extend_from_slice(buf);
+        unreadable.extend_from_slice(failed);
+        Ok(())
+    })?;
 
     if subargs.symbol {
         for offs in (0..length).step_by(size) {
             let slice = &bytes[offs..offs + size];
-            let val = u32::from_le_bytes(slice.try_into().unwrap());
+            let val = if subargs.big_endian {
+                u32::from_be_bytes(slice.try_into().unwrap())
+            } else {
+                u32::from_le_bytes(slice.try_into().unwrap())
+            };
             println!(
                 "0x{:08x} | 0x{:08x}{}",
                 addr + offs as u32,
                 val,
-                if let Some(sval) = hubris.instr_sym(val) {
-                    format!(
-                        " <- {}{}+0x{:x}",
-                        match hubris.instr_mod(val) {
-                            Some(module) if module != "kernel" => {
-                                format!("{}:", module)
-                            }
-                            _ => "".to_string(),
-                        },
-                        sval.0,
-                        val - sval.1
-                    )
+                if let Some(sym) = symbolize(hubris, val) {
+                    format!(" <- {}", sym)
                 } else {
                     "".to_string()
                 }
@@ -255,9 +1367,44 @@ fn readmem(context: &mut ExecutionContext) -> Result<()> {
         return Ok(());
     }
 
-    let mut dumper = Dumper::new();
-    dumper.size = size;
-    dumper.dump(&bytes, addr);
+    if subargs.disassemble {
+        disassemble(hubris, &bytes, addr);
+        return Ok(());
+    }
+
+    if subargs.float || subargs.double {
+        for offs in (0..length).step_by(size) {
+            let mut slice = [0u8; 8];
+            slice[..size].copy_from_slice(&bytes[offs..offs + size]);
+
+            if subargs.big_endian {
+                slice[..size].reverse();
+            }
+
+            let bits = u64::from_le_bytes(slice);
+
+            if subargs.double {
+                println!(
+                    "0x{:08x} | 0x{:016x} | {}",
+                    addr + offs as u32,
+                    bits,
+                    f64::from_bits(bits)
+                );
+            } else {
+                println!(
+                    "0x{:08x} | 0x{:08x} | {}",
+                    addr + offs as u32,
+                    bits,
+                    f32::from_bits(bits as u32)
+                );
+            }
+        }
+
+        return Ok(());
+    }
+
+    warn_unreadable(addr, &unreadable);
+    display(&bytes, &unreadable, addr);
 
     Ok(())
 }
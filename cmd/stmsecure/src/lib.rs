@@ -21,40 +21,204 @@
 //! humility stmsecure unset-secure-bit
 //! ```
 //!
+//! Sectors within a bank may be write protected (and unprotected):
+//!
+//! ```text
+//! humility stmsecure set-write-protect 2 0-3 --doit
+//! humility stmsecure unset-write-protect 2 0-3
+//! ```
+//!
+//! To get the current state of the option bits, use `status` (with `--json`
+//! to emit it as JSON):
+//!
+//! ```text
+//! humility stmsecure status --json
+//! ```
+//!
 //! The STM32 has support for flash bank swapping as well
 //!
 //! ```text
 //! humility stmsecure bank-swap
 //! ```
+//!
+//! Before experimenting with option bits, they may be saved to a file, and
+//! later restored from it.  `restore` displays the option registers that
+//! would change, and only programs them if `--doit` is specified:
+//!
+//! ```text
+//! humility stmsecure backup options.json
+//! humility stmsecure restore options.json --doit
+//! ```
+//!
+//! Any subcommand that modifies the target may be given `--dry-run` (`-n`),
+//! which displays each register that would be written (along with its current
+//! and new value) without writing anything.  (`set-secure-region` and
+//! `restore` behave this way unless `--doit` is specified.)
+//!
+//! Subcommands that may erase flash or render the board unbootable
+//! (`unset-rdp`, `set-secure-region`, `unset-secure-region`, `swap-banks`,
+//! and any `restore` that regresses RDP or changes the secure region)
+//! describe what they are about to do based on the current option bits, and
+//! then prompt for the name of the board (or, if the archive doesn't name
+//! one, for `yes`) before proceeding.  For automation, `--yes` (`-y`) skips
+//! this confirmation:
+//!
+//! ```text
+//! $ humility stmsecure unset-secure-region
+//! humility: attached via ST-Link V3
+//! Unsetting the secure region. This will erase the bank!
+//! this will erase bank 1 (0x08000000-0x080fffff)
+//! this will remove the secure region 0x08000000-0x0800a0ff
+//! are you sure? type the board name ("gimlet-c") to proceed: gimlet-c
+//! ```
+//!
+//! Some option bits (e.g., the bank swap) don't take effect until the part
+//! is reset.  To reset the target once the option bits have been modified,
+//! specify `--reset`; add `--halt` to leave the target halted at its reset
+//! vector.  (`set-secure-region` needs neither, as the ROM entry point that
+//! programs the secure region resets the part itself.)
+//!
+//! ```text
+//! $ humility stmsecure swap-banks --yes --reset
+//! ```
+//!
+//! The location of the flash registers (and of the RSS entry points) varies
+//! by STM32 family.  The family is determined from the chip named in the
+//! archive, or may be specified explicitly with `--family`; `stmsecure` will
+//! refuse to run on a chip for which it doesn't know the register map.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use humility::core::Core;
 use humility_arch_arm::ARMRegister;
 use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
+use serde::{Deserialize, Serialize};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+//
+// The flash registers, key values and RSS entry point for a particular STM32
+// family.  Note that the option bits themselves (RDP, security, bank swap)
+// are assumed to be laid out as they are on the STM32H7.
+//
+struct FlashRegisters {
+    family: Family,
+    opt_key: [u32; 2],
+    key: [u32; 2],
+    keyr1: u32,
+    cr1: u32,
+    sr1: u32,
+    opt_keyr: u32,
+    opt_cr: u32,
+    optsr_cur: u32,
+    optsr_prg: u32,
+    scar_cur1: u32,
+    scar_prg1: u32,
+
+    /// Current and to-be-programmed write protection, per bank
+    wpsn_cur: [u32; 2],
+    wpsn_prg: [u32; 2],
+    nsectors: u32,
 
-const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
-const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+    /// Base of flash and size of each bank, for validating secure regions
+    flash_base: u32,
+    bank_size: u32,
 
-const FLASH_KEY1: u32 = 0x4567_0123;
-const FLASH_KEY2: u32 = 0xCDEF_89AB;
+    /// Read-only status bits in the current option status register
+    optsr_status: u32,
+
+    /// RAM used to pass the secure area to the RSS
+    rss_scratch: u32,
+
+    /// `RSS_resetAndInitializeSecureAreas` entry point
+    rss_secure_areas: u32,
+}
 
-const FLASH_KEYR1: u32 = 0x5200_2004;
-const FLASH_CR1: u32 = 0x5200_200C;
-const FLASH_SR1: u32 = 0x5200_2010;
-const FLASH_OPT_KEYR: u32 = 0x5200_2008;
-const FLASH_OPT_CR: u32 = 0x5200_2018;
-const FLASH_OPTSR_CUR: u32 = 0x5200_201C;
-const FLASH_OPTSR_PRG: u32 = 0x5200_2020;
-const FLASH_SCAR_CUR1: u32 = 0x5200_2030;
-const FLASH_SCAR_PRG1: u32 = 0x5200_2034;
+const STM32H7: FlashRegisters = FlashRegisters {
+    family: Family::Stm32h7,
+    opt_key: [0x0819_2A3B, 0x4C5D_6E7F],
+    key: [0x4567_0123, 0xCDEF_89AB],
+    keyr1: 0x5200_2004,
+    cr1: 0x5200_200C,
+    sr1: 0x5200_2010,
+    opt_keyr: 0x5200_2008,
+    opt_cr: 0x5200_2018,
+    optsr_cur: 0x5200_201C,
+    optsr_prg: 0x5200_2020,
+    scar_cur1: 0x5200_2030,
+    scar_prg1: 0x5200_2034,
+    wpsn_cur: [0x5200_2038, 0x5200_2138],
+    wpsn_prg: [0x5200_203C, 0x5200_213C],
+    nsectors: 8,
+    flash_base: 0x0800_0000,
+    bank_size: 0x0010_0000,
+    optsr_status: 0x4000_0001,
+    rss_scratch: 0x2000_0000,
+    rss_secure_areas: 0x1ff0_8a70,
+};
+
+#[derive(
+    clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+enum Family {
+    Stm32h7,
+}
+
+impl Family {
+    fn from_chip(chip: &str) -> Option<Self> {
+        let chip = chip.to_ascii_lowercase();
+
+        if chip.starts_with("stm32h7") {
+            Some(Family::Stm32h7)
+        } else {
+            None
+        }
+    }
+
+    fn registers(&self) -> &'static FlashRegisters {
+        match self {
+            Family::Stm32h7 => &STM32H7,
+        }
+    }
+}
 
 #[derive(Parser, Debug)]
 #[clap(name = "stmsecure", about = env!("CARGO_PKG_DESCRIPTION"))]
-enum StmSecureArgs {
+struct StmSecureArgs {
+    /// STM32 family (determined from the archive by default)
+    #[clap(long, arg_enum, value_name = "family")]
+    family: Option<Family>,
+
+    /// display the writes that would be performed, without performing them
+    #[clap(long = "dry-run", short = 'n', global = true)]
+    dryrun: bool,
+
+    /// don't prompt for confirmation of operations that may erase flash
+    #[clap(long, short, global = true)]
+    yes: bool,
+
+    /// reset the target once the option bits have been modified
+    #[clap(long, global = true)]
+    reset: bool,
+
+    /// with --reset, leave the target halted at its reset vector
+    #[clap(long, global = true, requires = "reset")]
+    halt: bool,
+
+    #[clap(subcommand)]
+    cmd: StmSecureCommand,
+}
+
+#[derive(Parser, Debug)]
+enum StmSecureCommand {
     /// Show status about secure region settings
-    Status,
+    Status {
+        /// emit status as JSON
+        #[clap(long)]
+        json: bool,
+    },
     /// Enable Read Out Protection (RDP) i.e. can't read flash from debugger
     SetRDP,
     /// Disable Read Out Protection (RDP).
@@ -83,26 +247,73 @@ enum StmSecureArgs {
     /// Swap the flash banks (Bank 1 -> Bank 2 or Bank 2 -> Bank 1)
     /// !!! Make sure secure regions are appropriately programmed !!!
     SwapBanks,
+    /// Write protect the specified sectors (e.g., "0-3,6") of a bank (1 or 2)
+    SetWriteProtect {
+        #[clap(parse(try_from_str = parse_int::parse))]
+        bank: u32,
+        sectors: String,
+        #[clap(long)]
+        doit: bool,
+    },
+    /// Remove write protection from the specified sectors of a bank
+    UnsetWriteProtect {
+        #[clap(parse(try_from_str = parse_int::parse))]
+        bank: u32,
+        sectors: String,
+    },
+    /// Save the current option bits to a file
+    Backup { file: PathBuf },
+    /// Restore option bits previously saved with `backup`
+    /// !!! Lowering RDP or changing the secure region may erase flash !!!
+    Restore {
+        file: PathBuf,
+        #[clap(long)]
+        doit: bool,
+    },
+}
+
+//
+// The contents of an option bit backup.  The version should be bumped if
+// the format changes incompatibly.
+//
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct OptionBackup {
+    version: u32,
+    family: Family,
+    optsr: u32,
+    scar_cur1: u32,
+    swap_bank: bool,
 }
 
-fn stmsecure_unlock_flash(core: &mut dyn Core) -> Result<()> {
-    core.write_word_32(FLASH_KEYR1, FLASH_KEY1)?;
-    core.write_word_32(FLASH_KEYR1, FLASH_KEY2)?;
+fn stmsecure_unlock_flash(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<()> {
+    core.write_word_32(regs.keyr1, regs.key[0])?;
+    core.write_word_32(regs.keyr1, regs.key[1])?;
     Ok(())
 }
 
-fn stmsecure_unlock_option(core: &mut dyn Core) -> Result<()> {
-    core.write_word_32(FLASH_OPT_KEYR, FLASH_OPT_KEY1)?;
-    core.write_word_32(FLASH_OPT_KEYR, FLASH_OPT_KEY2)?;
+fn stmsecure_unlock_option(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<()> {
+    core.write_word_32(regs.opt_keyr, regs.opt_key[0])?;
+    core.write_word_32(regs.opt_keyr, regs.opt_key[1])?;
     Ok(())
 }
 
-fn stmsecure_commit_option(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_commit_option(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<()> {
     // set start bit
-    core.write_word_32(FLASH_OPT_CR, 0x2)?;
+    core.write_word_32(regs.opt_cr, 0x2)?;
 
     loop {
-        let stat = core.read_word_32(FLASH_OPTSR_CUR)?;
+        let stat = core.read_word_32(regs.optsr_cur)?;
         if (stat & 0x1) == 0 {
             break;
         }
@@ -110,86 +321,442 @@ fn stmsecure_commit_option(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
-fn stmsecure_rdpset(core: &mut dyn Core) -> Result<()> {
-    println!("setting rdp to level 1 (You will not be able to read the flash)");
-    stmsecure_unlock_option(core)?;
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
-    core.write_word_32(FLASH_OPTSR_PRG, (optsr & !0x0000_ff00) | 0x0000_bb00)?;
-    stmsecure_commit_option(core)?;
+//
+// A single write that a subcommand will perform, along with the value that it
+// will replace.  Each subcommand computes its writes before applying any of
+// them, allowing them to be displayed (and not applied) with `--dry-run`.
+//
+struct Write {
+    name: &'static str,
+    target: Target,
+    old: Option<u32>,
+    new: u32,
+}
+
+enum Target {
+    Memory(u32),
+    Register(ARMRegister),
+}
+
+impl Write {
+    fn memory(
+        core: &mut dyn Core,
+        name: &'static str,
+        addr: u32,
+        new: u32,
+    ) -> Result<Self> {
+        let old = Some(core.read_word_32(addr)?);
+        Ok(Self { name, target: Target::Memory(addr), old, new })
+    }
+
+    //
+    // We don't read the old value of a register, as that would require
+    // halting the target.
+    //
+    fn register(name: &'static str, reg: ARMRegister, new: u32) -> Self {
+        Self { name, target: Target::Register(reg), old: None, new }
+    }
+
+    fn apply(&self, core: &mut dyn Core) -> Result<()> {
+        match self.target {
+            Target::Memory(addr) => core.write_word_32(addr, self.new),
+            Target::Register(reg) => core.write_reg(reg, self.new),
+        }
+    }
+}
+
+//
+// Displays the writes that we are about to perform, returning true if they
+// should actually be performed.
+//
+fn stmsecure_plan(writes: &[Write], dryrun: bool) -> bool {
+    println!("{:16} {:>10} {:>10} {:>10}", "REGISTER", "ADDR", "OLD", "NEW");
+
+    for w in writes {
+        let addr = match w.target {
+            Target::Memory(addr) => format!("{:#010x}", addr),
+            Target::Register(_) => "-".to_string(),
+        };
+
+        let old = match w.old {
+            Some(old) => format!("{:#010x}", old),
+            None => "-".to_string(),
+        };
+
+        println!("{:16} {:>10} {:>10} {:#010x}", w.name, addr, old, w.new);
+    }
+
+    if dryrun {
+        println!("Not committing anything.");
+    }
+
+    !dryrun
+}
+
+//
+// Operations that can erase flash or brick the board require confirmation:
+// we describe what is about to happen and then have the user type the name
+// of the board (or "yes", if we don't know it), unless `--yes` has been
+// specified or we aren't actually going to do anything.
+//
+struct Confirm {
+    board: Option<String>,
+    yes: bool,
+}
+
+impl Confirm {
+    fn confirm(&self, what: &[String], dryrun: bool) -> Result<()> {
+        for w in what {
+            println!("{w}");
+        }
+
+        if dryrun || self.yes || what.is_empty() {
+            return Ok(());
+        }
+
+        match &self.board {
+            Some(board) => {
+                print!(
+                    "are you sure? type the board name (\"{board}\") \
+                    to proceed: "
+                )
+            }
+            None => print!("are you sure? type \"yes\" to proceed: "),
+        }
+
+        std::io::stdout().flush()?;
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+
+        if input.trim() != self.board.as_deref().unwrap_or("yes") {
+            bail!("not confirmed; aborting (use --yes to skip confirmation)");
+        }
+
+        Ok(())
+    }
+}
+
+//
+// Describes the flash that a bank (1 or 2) occupies.
+//
+fn stmsecure_bank(regs: &FlashRegisters, bank: u32) -> String {
+    let base = regs.flash_base + (bank - 1) * regs.bank_size;
+    format!(
+        "bank {} ({:#010x}-{:#010x})",
+        bank,
+        base,
+        base + regs.bank_size - 1
+    )
+}
+
+//
+// Describes the secure region, if one is set.
+//
+fn stmsecure_region(status: &SecureStatus) -> Option<String> {
+    if status.sec_bit && status.sec_start <= status.sec_end {
+        Some(format!("{:#010x}-{:#010x}", status.sec_start, status.sec_end))
+    } else {
+        None
+    }
+}
+
+//
+// Describes what regressing RDP to level 0 will do.
+//
+fn stmsecure_rdp_regression(
+    regs: &FlashRegisters,
+    status: &SecureStatus,
+) -> Vec<String> {
+    let mut what = vec![format!(
+        "this will regress RDP from {:#x} to level 0, erasing {} and {}",
+        status.rdp_level,
+        stmsecure_bank(regs, 1),
+        stmsecure_bank(regs, 2)
+    )];
+
+    if let Some(region) = stmsecure_region(status) {
+        what.push(if status.erase_on_regression {
+            format!("the secure region {region} will also be erased")
+        } else {
+            format!("the secure region {region} will be preserved")
+        });
+    }
+
+    what
+}
+
+//
+// Programs the option bits with the specified writes via the unlock/commit
+// sequence.
+//
+fn stmsecure_program_option(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    writes: &[Write],
+    dryrun: bool,
+) -> Result<()> {
+    if !stmsecure_plan(writes, dryrun) {
+        return Ok(());
+    }
+
+    stmsecure_unlock_option(core, regs)?;
+
+    for w in writes {
+        w.apply(core)?;
+    }
+
+    stmsecure_commit_option(core, regs)?;
     println!("done.");
     Ok(())
 }
 
-fn stmsecure_rdpunset_nocommit(core: &mut dyn Core) -> Result<()> {
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
-    core.write_word_32(FLASH_OPTSR_PRG, (optsr & !0x0000_ff00) | 0x0000_aa00)?;
-    Ok(())
+//
+// Computes the write to the option status register to change the bits in
+// `mask` to `val`.
+//
+fn stmsecure_optsr_write(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    mask: u32,
+    val: u32,
+) -> Result<Write> {
+    let optsr = core.read_word_32(regs.optsr_cur)?;
+    let new = (optsr & !mask) | val;
+    Write::memory(core, "FLASH_OPTSR_PRG", regs.optsr_prg, new)
 }
 
-fn stmsecure_rdpunset(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_rdpset(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    dryrun: bool,
+) -> Result<()> {
+    println!("setting rdp to level 1 (You will not be able to read the flash)");
+    let w = stmsecure_optsr_write(core, regs, 0x0000_ff00, 0x0000_bb00)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
+}
+
+fn stmsecure_rdpunset(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    confirm: &Confirm,
+    dryrun: bool,
+) -> Result<()> {
     println!(
         "setting rdp level to 0. This may also erase the flash depending
     on your system settings!"
     );
-    stmsecure_unlock_option(core)?;
-    stmsecure_rdpunset_nocommit(core)?;
-    stmsecure_commit_option(core)?;
-    println!("done.");
-    Ok(())
+
+    let status = stmsecure_read_status(core, regs)?;
+
+    if status.rdp_level == 0xaa {
+        println!("RDP is already at level 0; flash will not be erased");
+    } else {
+        confirm.confirm(&stmsecure_rdp_regression(regs, &status), dryrun)?;
+    }
+
+    let w = stmsecure_optsr_write(core, regs, 0x0000_ff00, 0x0000_aa00)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
 }
 
-fn stmsecure_lockbit_set(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_lockbit_set(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    dryrun: bool,
+) -> Result<()> {
     println!("Setting the secure option bit");
-    stmsecure_unlock_option(core)?;
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
-    core.write_word_32(FLASH_OPTSR_PRG, optsr | 0x20_0000)?;
-    stmsecure_commit_option(core)?;
-    println!("done.");
-    Ok(())
+    let w = stmsecure_optsr_write(core, regs, 0x20_0000, 0x20_0000)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
 }
 
-fn stmsecure_lockbit_unset(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_lockbit_unset(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    dryrun: bool,
+) -> Result<()> {
     println!("Unsetting the secure option bit");
-    stmsecure_unlock_option(core)?;
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
-    core.write_word_32(FLASH_OPTSR_PRG, optsr & !0x20_0000)?;
-    stmsecure_commit_option(core)?;
-    println!("done.");
-    Ok(())
+    let w = stmsecure_optsr_write(core, regs, 0x20_0000, 0)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
+}
+
+#[derive(Serialize, Debug)]
+struct SecureStatus {
+    sec_bit: bool,
+    sec_start: u32,
+    sec_end: u32,
+    erase_on_regression: bool,
+    rdp_level: u32,
+    swap_bank: bool,
+    write_protected: Vec<Vec<u32>>,
+}
+
+//
+// Returns the write-protected sectors in each bank.  A cleared bit in WPSN
+// indicates that the corresponding sector is write protected.
+//
+fn stmsecure_read_wrp(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<Vec<Vec<u32>>> {
+    let mut rval = vec![];
+
+    for addr in regs.wpsn_cur {
+        let wpsn = core.read_word_32(addr)?;
+        rval.push(
+            (0..regs.nsectors).filter(|s| wpsn & (1 << s) == 0).collect(),
+        );
+    }
+
+    Ok(rval)
+}
+
+//
+// Parses a list of sectors and sector ranges (e.g., "0-3,6") into a mask.
+//
+fn parse_sectors(sectors: &str, nsectors: u32) -> Result<u32> {
+    let mut mask = 0;
+
+    for range in sectors.split(',') {
+        let (lo, hi) = match range.split_once('-') {
+            Some((lo, hi)) => (lo, hi),
+            None => (range, range),
+        };
+
+        let lo = parse_int::parse::<u32>(lo.trim())?;
+        let hi = parse_int::parse::<u32>(hi.trim())?;
+
+        if lo > hi || hi >= nsectors {
+            bail!(
+                "invalid sector range \"{}\"; sectors are 0-{}",
+                range,
+                nsectors - 1
+            );
+        }
+
+        for sector in lo..=hi {
+            mask |= 1 << sector;
+        }
+    }
+
+    Ok(mask)
+}
+
+fn stmsecure_writeprotect(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    bank: u32,
+    sectors: &str,
+    protect: bool,
+    dryrun: bool,
+) -> Result<()> {
+    const NAMES: [&str; 2] = ["FLASH_WPSN_PRG1R", "FLASH_WPSN_PRG2R"];
+
+    if !(1..=2).contains(&bank) {
+        bail!("bank must be 1 or 2");
+    }
+
+    let ndx = (bank - 1) as usize;
+    let mask = parse_sectors(sectors, regs.nsectors)?;
+    let wpsn = core.read_word_32(regs.wpsn_cur[ndx])?;
+
+    let new = if protect {
+        println!("Write protecting bank {} sectors {}", bank, sectors);
+        wpsn & !mask
+    } else {
+        println!(
+            "Removing write protection from bank {} sectors {}",
+            bank, sectors
+        );
+        wpsn | mask
+    };
+
+    let w = Write::memory(core, NAMES[ndx], regs.wpsn_prg[ndx], new)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
 }
 
-fn stmsecure_status(core: &mut dyn Core) -> Result<()> {
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
+fn stmsecure_read_status(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<SecureStatus> {
+    let optsr = core.read_word_32(regs.optsr_cur)?;
     let rdp = (optsr & 0x0000_ff00) >> 8;
     let sec_en = (optsr & 0x20_0000) == 0x20_0000;
+    let swap_bank = (optsr & 0x8000_0000) == 0x8000_0000;
 
-    let scar_cur1 = core.read_word_32(FLASH_SCAR_CUR1)?;
+    let scar_cur1 = core.read_word_32(regs.scar_cur1)?;
     let dmes1 = (scar_cur1 & 0x8000_0000) == (0x8000_0000);
-    let sec_start = ((scar_cur1 & 0x0000_0FFF) << 8) | 0x0800_0000;
-    let sec_end = (((scar_cur1 & 0x00FF_F000) >> 16) << 8) | 0x0800_00ff;
-
-    println!("Sec bit: {}", sec_en);
-    println!("Start: {:x}", sec_start);
-    println!("End: {:x}", sec_end);
-    println!("Erase on regression: {}", dmes1);
-    println!("RDP: {:x}", rdp);
+    let sec_start = ((scar_cur1 & 0x0000_0FFF) << 8) | regs.flash_base;
+    let sec_end =
+        (((scar_cur1 & 0x00FF_F000) >> 16) << 8) | regs.flash_base | 0xff;
+
+    Ok(SecureStatus {
+        sec_bit: sec_en,
+        sec_start,
+        sec_end,
+        erase_on_regression: dmes1,
+        rdp_level: rdp,
+        swap_bank,
+        write_protected: stmsecure_read_wrp(core, regs)?,
+    })
+}
+
+fn stmsecure_status(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    json: bool,
+) -> Result<()> {
+    let status = stmsecure_read_status(core, regs)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    println!("Sec bit: {}", status.sec_bit);
+    println!("Start: {:x}", status.sec_start);
+    println!("End: {:x}", status.sec_end);
+    println!("Erase on regression: {}", status.erase_on_regression);
+    println!("RDP: {:x}", status.rdp_level);
+    println!("Bank swap: {}", status.swap_bank);
+
+    for (bank, sectors) in status.write_protected.iter().enumerate() {
+        println!(
+            "Write protected (bank {}): {}",
+            bank + 1,
+            if sectors.is_empty() {
+                "none".to_string()
+            } else {
+                sectors
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        );
+    }
     Ok(())
 }
 
 fn stmsecure_setsecureregion(
     core: &mut dyn Core,
+    regs: &FlashRegisters,
     address: u32,
     size: u32,
-    commit: bool,
+    confirm: &Confirm,
+    dryrun: bool,
 ) -> Result<()> {
+    let bank = regs.flash_base..regs.flash_base + regs.bank_size - 1;
+
     // Basic checks to make sure we're not doing anything too weird
-    if !(0x0800_0000..0x081f_ffff).contains(&address) {
+    if !(regs.flash_base..regs.flash_base + regs.bank_size * 2 - 1)
+        .contains(&address)
+    {
         return Err(anyhow!("Secure address out of range: {:x}", address));
     }
 
     // Secure ranges are per bank
     if let Some(result) = address.checked_add(size) {
-        if !(0x0800_0000..0x080f_ffff).contains(&result) {
+        if !bank.contains(&result) {
             return Err(anyhow!(
                 "secure address end size out of range {:x}-{:x}",
                 address,
@@ -206,22 +773,31 @@ fn stmsecure_setsecureregion(
 
     println!("Setting secure region: {:x}-{:x}", address, address + size);
 
-    if !commit {
-        println!("Not committing anything.");
-        return Ok(());
-    }
-
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
+    let optsr = core.read_word_32(regs.optsr_cur)?;
     if (optsr & 0x20_0000) != 0x20_0000 {
         return Err(anyhow!(
             "Set the secure bit before setting the secure region"
         ));
     }
 
+    let status = stmsecure_read_status(core, regs)?;
+
+    let mut what = vec![format!(
+        "this will make {:#010x}-{:#010x} the secure region; if the \
+        application does not boot out of it, the board will be bricked",
+        address,
+        address + size
+    )];
+
+    if let Some(region) = stmsecure_region(&status) {
+        what.push(format!("this will replace the secure region {region}"));
+    }
+
+    confirm.confirm(&what, dryrun)?;
+
     // We have to use the delightful ROM API in order to write this register
-    core.halt()?;
 
-    // Set up the structure at 0x2000_0000
+    // Set up the structure in RAM
     // typedef struct
     // {
     // uint32_t sizeInBytes; /**< pass 0 for an empty secure area */
@@ -229,47 +805,86 @@ fn stmsecure_setsecureregion(
     // uint32_t removeDuringBankErase; /**< if 0, keep area during bank/mass
     // erase. else area will be removed*/ }RSS_SecureArea_t;
     //
-    core.write_word_32(0x2000_0000, size)?;
-    core.write_word_32(0x2000_0004, address)?;
-    // We always remove during bank erase for now, otherwise we could get stuck
-    // with a bricked board
-    core.write_word_32(0x2000_0008, 0x1)?;
-
-    // void RSS_resetAndInitializeSecureAreas(uint32_t nbAreas,
-    // RSS_SecureArea_t* areas);
-    core.write_reg(ARMRegister::R0, 1)?;
-    core.write_reg(ARMRegister::R1, 0x2000_0000)?;
-
-    // STM does not document very well how to call functions but this is the
-    // address of the function we want
-    core.write_reg(ARMRegister::PC, 0x1ff08a70)?;
+    let scratch = regs.rss_scratch;
+
+    let writes = [
+        Write::memory(core, "sizeInBytes", scratch, size)?,
+        Write::memory(core, "startAddress", scratch + 4, address)?,
+        // We always remove during bank erase for now, otherwise we could get
+        // stuck with a bricked board
+        Write::memory(core, "removeDuringBank", scratch + 8, 0x1)?,
+        // void RSS_resetAndInitializeSecureAreas(uint32_t nbAreas,
+        // RSS_SecureArea_t* areas);
+        Write::register("R0", ARMRegister::R0, 1),
+        Write::register("R1", ARMRegister::R1, scratch),
+        // STM does not document very well how to call functions but this is
+        // the address of the function we want
+        Write::register("PC", ARMRegister::PC, regs.rss_secure_areas),
+    ];
+
+    if !stmsecure_plan(&writes, dryrun) {
+        return Ok(());
+    }
+
+    core.halt()?;
+
+    for w in &writes {
+        w.apply(core)?;
+    }
+
     core.run()?;
 
     Ok(())
 }
 
-fn stmsecure_unsetsecureregion(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_unsetsecureregion(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    confirm: &Confirm,
+    dryrun: bool,
+) -> Result<()> {
     println!("Unsetting the secure region. This will erase the bank!");
 
+    let status = stmsecure_read_status(core, regs)?;
+    let mut what = vec![format!("this will erase {}", stmsecure_bank(regs, 1))];
+
+    what.push(match stmsecure_region(&status) {
+        Some(region) => format!("this will remove the secure region {region}"),
+        None => "no secure region is currently set".to_string(),
+    });
+
+    confirm.confirm(&what, dryrun)?;
+
     // This sequence is from the manual section 4.3.10
     // This can also be done with an RDP regression but that has the
     // disadvantage of erasing all flash as opposed to just a bank
-    stmsecure_unlock_option(core)?;
+    //
     // Unset secure region by setting start > end
     // Make sure to set the DMES bit so the secure are gets erased as well
-    core.write_word_32(FLASH_SCAR_PRG1, 0x8000_00ff)?;
-
-    stmsecure_unlock_flash(core)?;
+    let scar =
+        Write::memory(core, "FLASH_SCAR_PRG1", regs.scar_prg1, 0x8000_00ff)?;
 
     // Set BER1 (bank erase) and the start bit to start the erase
-    core.write_word_32(FLASH_CR1, 0x88)?;
+    let cr = Write::memory(core, "FLASH_CR1", regs.cr1, 0x88)?;
+
+    let writes = [scar, cr];
+
+    if !stmsecure_plan(&writes, dryrun) {
+        return Ok(());
+    }
+
+    stmsecure_unlock_option(core, regs)?;
+    writes[0].apply(core)?;
+
+    stmsecure_unlock_flash(core, regs)?;
+    writes[1].apply(core)?;
 
     // This particular sequence will also automatically program the option bits
     // so there is no need to call option commit
 
     // Wait for the flash erase to complete
     loop {
-        let stat = core.read_word_32(FLASH_SR1)?;
+        let stat = core.read_word_32(regs.sr1)?;
         if (stat & 0x4) == 0 {
             break;
         }
@@ -278,40 +893,349 @@ fn stmsecure_unsetsecureregion(core: &mut dyn Core) -> Result<()> {
     Ok(())
 }
 
-fn stmsecure_swapbanks(core: &mut dyn Core) -> Result<()> {
+fn stmsecure_swapbanks(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    confirm: &Confirm,
+    dryrun: bool,
+) -> Result<()> {
     println!("Swapping banks");
-    stmsecure_unlock_option(core)?;
-    let optsr = core.read_word_32(FLASH_OPTSR_CUR)?;
+
+    let status = stmsecure_read_status(core, regs)?;
+    let (from, to) = if status.swap_bank { (2, 1) } else { (1, 2) };
+
+    let mut what = vec![format!(
+        "this will map {} at {:#010x} in place of bank {}; it must contain \
+        a bootable image",
+        stmsecure_bank(regs, to),
+        regs.flash_base,
+        from
+    )];
+
+    if let Some(region) = stmsecure_region(&status) {
+        what.push(format!(
+            "the secure region {region} must be appropriately programmed \
+            for bank {to}"
+        ));
+    }
+
+    confirm.confirm(&what, dryrun)?;
+
+    let optsr = core.read_word_32(regs.optsr_cur)?;
     // Bit 31 is used to swap banks. If it's set, unset it etc.
-    if (optsr & 0x8000_0000) == 0x8000_0000 {
-        core.write_word_32(FLASH_OPTSR_PRG, optsr & !0x8000_0000)?;
+    let w =
+        stmsecure_optsr_write(core, regs, 0x8000_0000, !optsr & 0x8000_0000)?;
+    stmsecure_program_option(core, regs, &[w], dryrun)
+}
+
+//
+// Determine the register map to use, refusing to proceed if we can't be sure
+// that we have the right one.
+//
+fn stmsecure_registers(
+    context: &ExecutionContext,
+    family: Option<Family>,
+) -> Result<&'static FlashRegisters> {
+    let chip = context.archive.as_ref().and_then(|hubris| hubris.chip());
+
+    let detected = match &chip {
+        Some(chip) => match Family::from_chip(chip) {
+            Some(family) => Some(family),
+            None => {
+                bail!("no flash register map for chip {chip}; refusing to run")
+            }
+        },
+        None => None,
+    };
+
+    let family = match (family, detected) {
+        (Some(family), Some(detected)) if family != detected => {
+            bail!(
+                "specified family {family:?} does not match \
+                archive chip {}",
+                chip.unwrap()
+            );
+        }
+        (Some(family), _) | (None, Some(family)) => family,
+        (None, None) => {
+            bail!("can't determine chip family; specify it with --family");
+        }
+    };
+
+    Ok(family.registers())
+}
+
+fn stmsecure_read_backup(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+) -> Result<OptionBackup> {
+    let optsr = core.read_word_32(regs.optsr_cur)? & !regs.optsr_status;
+
+    Ok(OptionBackup {
+        version: BACKUP_VERSION,
+        family: regs.family,
+        optsr,
+        scar_cur1: core.read_word_32(regs.scar_cur1)?,
+        swap_bank: (optsr & 0x8000_0000) != 0,
+    })
+}
+
+fn stmsecure_backup(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    file: &Path,
+) -> Result<()> {
+    let backup = stmsecure_read_backup(core, regs)?;
+
+    std::fs::write(file, serde_json::to_string_pretty(&backup)?)
+        .with_context(|| format!("failed to write {}", file.display()))?;
+
+    println!("OPTSR: {:#010x}", backup.optsr);
+    println!("SCAR1: {:#010x}", backup.scar_cur1);
+    println!("Swap bank: {}", backup.swap_bank);
+    println!("saved option bits to {}", file.display());
+    Ok(())
+}
+
+fn stmsecure_print_diff(before: &OptionBackup, after: &OptionBackup) -> bool {
+    let rows = [
+        ("OPTSR", before.optsr, after.optsr),
+        ("SCAR1", before.scar_cur1, after.scar_cur1),
+        ("SWAP_BANK", before.swap_bank as u32, after.swap_bank as u32),
+    ];
+
+    println!("{:10} {:>10} {:>10}", "REGISTER", "BEFORE", "AFTER");
+
+    let mut changed = false;
+
+    for (name, before, after) in rows {
+        let mark = if before != after {
+            changed = true;
+            "*"
+        } else {
+            ""
+        };
+
+        println!("{:10} {:#010x} {:#010x} {}", name, before, after, mark);
+    }
+
+    changed
+}
+
+fn stmsecure_restore(
+    core: &mut dyn Core,
+    regs: &FlashRegisters,
+    file: &Path,
+    confirm: &Confirm,
+    dryrun: bool,
+) -> Result<()> {
+    let contents = std::fs::read_to_string(file)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+
+    let backup: OptionBackup = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse {}", file.display()))?;
+
+    if backup.version != BACKUP_VERSION {
+        bail!(
+            "backup has version {}; expected version {}",
+            backup.version,
+            BACKUP_VERSION
+        );
+    }
+
+    if backup.family != regs.family {
+        bail!(
+            "backup is for {:?}, but target is {:?}",
+            backup.family,
+            regs.family
+        );
+    }
+
+    //
+    // The bank swap bit is recorded separately; it is authoritative.
+    //
+    let mut optsr = backup.optsr & !regs.optsr_status;
+
+    if backup.swap_bank {
+        optsr |= 0x8000_0000;
     } else {
-        core.write_word_32(FLASH_OPTSR_PRG, optsr | 0x8000_0000)?;
+        optsr &= !0x8000_0000;
+    }
+
+    let before = stmsecure_read_backup(core, regs)?;
+    let target = OptionBackup { optsr, ..backup };
+
+    if !stmsecure_print_diff(&before, &target) {
+        println!("Option bits already match backup; nothing to do.");
+        return Ok(());
+    }
+
+    let status = stmsecure_read_status(core, regs)?;
+    let mut what = vec![];
+
+    if status.rdp_level != 0xaa && (target.optsr & 0x0000_ff00) == 0xaa00 {
+        what.extend(stmsecure_rdp_regression(regs, &status));
+    }
+
+    if target.scar_cur1 != before.scar_cur1 {
+        what.push(format!(
+            "this will change the secure region from {}; if the application \
+            does not boot out of the new region, the board will be bricked",
+            stmsecure_region(&status).unwrap_or_else(|| "none".to_string())
+        ));
+    }
+
+    confirm.confirm(&what, dryrun)?;
+
+    let mut writes = vec![Write::memory(
+        core,
+        "FLASH_OPTSR_PRG",
+        regs.optsr_prg,
+        target.optsr,
+    )?];
+
+    if target.scar_cur1 != before.scar_cur1 {
+        writes.push(Write::memory(
+            core,
+            "FLASH_SCAR_PRG1",
+            regs.scar_prg1,
+            target.scar_cur1,
+        )?);
+    }
+
+    if !stmsecure_plan(&writes, dryrun) {
+        return Ok(());
+    }
+
+    stmsecure_unlock_option(core, regs)?;
+
+    for w in &writes {
+        w.apply(core)?;
+    }
+
+    stmsecure_commit_option(core, regs)?;
+
+    let after = stmsecure_read_backup(core, regs)?;
+
+    println!("Restored option bits:");
+    stmsecure_print_diff(&before, &after);
+
+    if after.optsr != target.optsr || after.scar_cur1 != target.scar_cur1 {
+        bail!("option bits do not match backup after restore");
     }
-    stmsecure_commit_option(core)?;
+
     println!("done.");
     Ok(())
 }
 
+//
+// Determines if the specified command will actually modify the option bits
+// (and will therefore want a reset with --reset).  Note that we exclude
+// setting the secure region:  the RSS entry point that we jump to resets the
+// part itself.
+//
+fn stmsecure_modifies(cmd: &StmSecureCommand, dryrun: bool) -> bool {
+    match cmd {
+        StmSecureCommand::Status { .. } | StmSecureCommand::Backup { .. } => {
+            false
+        }
+        StmSecureCommand::SetSecureRegion { .. } => false,
+        StmSecureCommand::SetWriteProtect { doit, .. }
+        | StmSecureCommand::Restore { doit, .. } => !dryrun && *doit,
+        _ => !dryrun,
+    }
+}
+
+fn stmsecure_reset(core: &mut dyn Core, halt: bool) -> Result<()> {
+    if halt {
+        core.reset_and_halt(std::time::Duration::from_secs(2))?;
+        println!("target reset and halted");
+    } else {
+        core.reset()?;
+        println!("target reset");
+    }
+
+    Ok(())
+}
+
 #[rustfmt::skip::macros(format)]
 fn stmsecure(context: &mut ExecutionContext) -> Result<()> {
-    let core = &mut **context.core.as_mut().unwrap();
     let Subcommand::Other(subargs) = context.cli.cmd.as_ref().unwrap();
 
     let subargs = StmSecureArgs::try_parse_from(subargs)?;
+    let regs = stmsecure_registers(context, subargs.family)?;
+
+    let confirm = Confirm {
+        board: context
+            .archive
+            .as_ref()
+            .and_then(|hubris| hubris.manifest.board.clone()),
+        yes: subargs.yes,
+    };
+
+    let core = &mut **context.core.as_mut().unwrap();
 
-    match subargs {
-        StmSecureArgs::Status => stmsecure_status(core),
-        StmSecureArgs::SetSecureBit => stmsecure_lockbit_set(core),
-        StmSecureArgs::UnsetSecureBit => stmsecure_lockbit_unset(core),
-        StmSecureArgs::SetSecureRegion { address, size, doit } => {
-            stmsecure_setsecureregion(core, address, size, doit)
+    let dryrun = subargs.dryrun;
+    let reset = subargs.reset && stmsecure_modifies(&subargs.cmd, dryrun);
+
+    if subargs.reset && !reset {
+        println!("not resetting target: no option bits will be modified");
+    }
+
+    let rval = match subargs.cmd {
+        StmSecureCommand::Status { json } => stmsecure_status(core, regs, json),
+        StmSecureCommand::SetSecureBit => {
+            stmsecure_lockbit_set(core, regs, dryrun)
+        }
+        StmSecureCommand::UnsetSecureBit => {
+            stmsecure_lockbit_unset(core, regs, dryrun)
+        }
+        StmSecureCommand::SetSecureRegion { address, size, doit } => {
+            stmsecure_setsecureregion(
+                core,
+                regs,
+                address,
+                size,
+                &confirm,
+                dryrun || !doit,
+            )
+        }
+        StmSecureCommand::UnsetSecureRegion => {
+            stmsecure_unsetsecureregion(core, regs, &confirm, dryrun)
+        }
+        StmSecureCommand::SetRDP => stmsecure_rdpset(core, regs, dryrun),
+        StmSecureCommand::UnsetRDP => {
+            stmsecure_rdpunset(core, regs, &confirm, dryrun)
+        }
+        StmSecureCommand::SwapBanks => {
+            stmsecure_swapbanks(core, regs, &confirm, dryrun)
+        }
+        StmSecureCommand::SetWriteProtect { bank, sectors, doit } => {
+            stmsecure_writeprotect(
+                core,
+                regs,
+                bank,
+                &sectors,
+                true,
+                dryrun || !doit,
+            )
         }
-        StmSecureArgs::UnsetSecureRegion => stmsecure_unsetsecureregion(core),
-        StmSecureArgs::SetRDP => stmsecure_rdpset(core),
-        StmSecureArgs::UnsetRDP => stmsecure_rdpunset(core),
-        StmSecureArgs::SwapBanks => stmsecure_swapbanks(core),
+        StmSecureCommand::UnsetWriteProtect { bank, sectors } => {
+            stmsecure_writeprotect(core, regs, bank, &sectors, false, dryrun)
+        }
+        StmSecureCommand::Backup { file } => {
+            stmsecure_backup(core, regs, &file)
+        }
+        StmSecureCommand::Restore { file, doit } => {
+            stmsecure_restore(core, regs, &file, &confirm, dryrun || !doit)
+        }
+    };
+
+    if rval.is_ok() && reset {
+        stmsecure_reset(core, subargs.halt)?;
     }
+
+    rval
 }
 
 pub fn init() -> Command {
@@ -326,3 +1250,24 @@ pub fn init() -> Command {
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_sectors() {
+        assert_eq!(parse_sectors("0", 8).unwrap(), 0x01);
+        assert_eq!(parse_sectors("7", 8).unwrap(), 0x80);
+        assert_eq!(parse_sectors("0-3,6", 8).unwrap(), 0x4f);
+        assert_eq!(parse_sectors(" 1 - 2 ", 8).unwrap(), 0x06);
+        assert_eq!(parse_sectors("0x2,2", 8).unwrap(), 0x04);
+        assert_eq!(parse_sectors("0-7", 8).unwrap(), 0xff);
+
+        assert!(parse_sectors("8", 8).is_err());
+        assert!(parse_sectors("3-1", 8).is_err());
+        assert!(parse_sectors("0-8", 8).is_err());
+        assert!(parse_sectors("", 8).is_err());
+        assert!(parse_sectors("1,,2", 8).is_err());
+    }
+}
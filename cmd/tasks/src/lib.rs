@@ -25,6 +25,23 @@
 //! 11 idle                   0   5 RUNNING
 //! ```
 //!
+//! A task's notification mask (that is, the notifications it is waiting
+//! for) is displayed as `notif:`; notifications that have been posted to a
+//! task but that it has not yet received are displayed as `pending:`.  In
+//! either case, each notification bit is displayed by name (if the archive
+//! names it) and annotated with the IRQ(s) that post it (`irq39`) and, if
+//! the task's timer will post it, the number of ticks until the timer
+//! fires (`T+7`):
+//!
+//! ```console
+//! $ humility tasks
+//! ...
+//!  3 usart_driver           0   2 recv, notif: bit0(irq39)
+//!  9 hiffy                  0   3 notif: bit0(T+7)
+//! 12 net                    1   5 ready, pending: bit0(irq61)
+//! ...
+//! ```
+//!
 //! To see every field in each task, you can use the `-v` flag:
 //!
 //! ```console
@@ -110,9 +127,76 @@
 //!
 //! These options can naturally be combined, e.g. `humility tasks -slvr`.
 //!
+//! To get a quick sense of what a single task is stuck in, `--trace`
+//! unwinds the specified task's stack from its saved state, without
+//! reading the rest of the task table:
+//!
+//! ```console
+//! $ humility tasks --trace ping
+//! humility: attached via ST-Link
+//!  8 ping
+//!    |
+//!    +--->  0x20005fa0 0x08026e42 userlib::sys_send_stub
+//!           0x20006000 0x08026128 userlib::sys_send
+//!           0x20006000 0x0802613a main
+//! ```
+//!
+//! To see how much of each task's stack has been used, use the
+//! `--stack-usage` flag.  Usage is determined by scanning the stack from
+//! its limit, looking for the first word that does not contain the pattern
+//! with which the kernel fills a task's stack (`0xbaddcafe`).  Any task whose
+//! usage exceeds a threshold (90% by default, adjustable via `--threshold`)
+//! is flagged:
+//!
+//! ```console
+//! $ humility tasks --stack-usage
+//! humility: attached via ST-Link
+//! system time = 1764993
+//! ID TASK                 GEN PRI STATE
+//!  0 jefe                   0   0 recv, notif: bit0 bit1(T+7)
+//!    stack usage: 768 of 1024 bytes (75%)
+//!  1 rcc_driver             0   1 recv
+//!    stack usage: 176 of 1024 bytes (17%)
+//! ...
+//!  8 ping               14190   4 wait: send to pong/gen0
+//!    stack usage: 496 of 512 bytes (96%) -- EXCEEDS 90%
+//! ...
+//! ```
+//!
+//! As with `humility stackmargin`, usage reflects only the current
+//! incarnation of a task; a task that has restarted after overflowing its
+//! stack will not reflect that overflow.
+//!
+//! To consume task state from another program (e.g., to compare task
+//! generations across reboots), use the `--json` flag, which emits a JSON
+//! array with an object for each task.  The `state` member is the state as
+//! displayed in the table; `fault` is the reason for a task's fault (or
+//! `null` if the task has not faulted).  If `--stack-usage` is also
+//! specified, `stack_used` and `stack_size` denote the task's stack usage
+//! in bytes:
+//!
+//! ```console
+//! $ humility tasks --json
+//! humility: attached via ST-Link
+//! [{"index":0,"address":536871960,"module":"jefe","generation":0,"current":false,"state":"recv, notif: bit0 bit1(T+7)","fault":null,"stack_used":null,"stack_size":null},...]
+//! ```
+//!
+//! When combined with `--spin`, an array is emitted on its own line for each
+//! pass.
+//!
+//! To watch tasks change over time, use `--watch`: the task table will be
+//! re-read every `--interval` milliseconds (defaulting to 1000), with the
+//! display redrawn in place.  Any task whose generation has changed since the
+//! previous read (that is, any task that has restarted) is highlighted.  Hit
+//! Ctrl-C to exit; the target is left running.
+//!
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{CommandFactory, Parser};
+use colored::Colorize;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::execute;
+use crossterm::terminal::{Clear, ClearType};
 use humility::core::Core;
 use humility::hubris::*;
 use humility::reflect::{self, Format, Load};
@@ -121,8 +205,11 @@ use humility_cli::{ExecutionContext, Subcommand};
 use humility_cmd::{Archive, Attach, Command, CommandKind, Validate};
 use humility_doppel::{self as doppel, Task, TaskDesc, TaskId, TaskState};
 use num_traits::FromPrimitive;
+use serde::Serialize;
 use std::collections::{BTreeMap, HashMap};
 use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(name = "tasks", about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -143,10 +230,48 @@ struct TasksArgs {
     #[clap(long, short = 'S')]
     spin: bool,
 
+    /// repeatedly read tasks, redrawing in place and highlighting restarts
+    #[clap(long, conflicts_with_all = &["spin", "json"])]
+    watch: bool,
+
+    /// interval between reads when watching
+    #[clap(
+        long, value_name = "ms", default_value_t = 1000, requires = "watch",
+        parse(try_from_str = parse_int::parse)
+    )]
+    interval: u64,
+
     /// verbose task output
     #[clap(long, short)]
     verbose: bool,
 
+    /// show stack usage
+    #[clap(long, short = 'u')]
+    stack_usage: bool,
+
+    /// percentage of stack usage above which a task is flagged
+    #[clap(
+        long,
+        value_name = "percent",
+        default_value_t = 90,
+        requires = "stack-usage"
+    )]
+    threshold: u8,
+
+    /// emit task state as JSON
+    #[clap(long, conflicts_with_all = &["registers", "stack", "verbose"])]
+    json: bool,
+
+    /// unwind the specified task's stack from its saved state
+    #[clap(
+        long, value_name = "task",
+        conflicts_with_all = &[
+            "registers", "stack", "spin", "watch", "verbose", "stack-usage",
+            "json", "task",
+        ]
+    )]
+    trace: Option<String>,
+
     /// single task to display
     task: Option<String>,
 }
@@ -186,34 +311,232 @@ fn tasks(context: &mut ExecutionContext) -> Result<()> {
 
     let subargs = TasksArgs::try_parse_from(subargs)?;
 
-    print_tasks(
-        &mut std::io::stdout(),
-        core,
-        hubris,
-        subargs.registers,
-        subargs.stack,
-        subargs.line,
-        subargs.spin,
-        subargs.verbose,
-        subargs.task,
-    )
+    if subargs.threshold > 100 {
+        bail!("threshold must be a percentage between 0 and 100");
+    }
+
+    if let Some(task) = &subargs.trace {
+        core.halt()?;
+        let rval = trace_task(core, hubris, task);
+        core.run()?;
+
+        return rval;
+    }
+
+    let watch = if subargs.watch {
+        if core.is_dump() || core.is_archive() {
+            bail!("can only watch a live target");
+        }
+
+        ctrlc::set_handler(|| DONE.store(true, Ordering::SeqCst))?;
+        execute!(std::io::stdout(), Hide, Clear(ClearType::All))?;
+
+        Some(subargs.interval)
+    } else {
+        None
+    };
+
+    //
+    // We re-read task descriptors (among other things) while the target is
+    // halted, so we can benefit from caching those reads.
+    //
+    core.enable_cache();
+
+    let options = PrintTasksOptions {
+        registers: subargs.registers,
+        stack: subargs.stack,
+        line: subargs.line,
+        spin: subargs.spin,
+        watch,
+        verbose: subargs.verbose,
+        stack_threshold: subargs.stack_usage.then_some(subargs.threshold),
+        json: subargs.json,
+        task: subargs.task,
+    };
+
+    let rval = print_tasks(&mut std::io::stdout(), core, hubris, &options);
+
+    //
+    // Regardless of how we exited, be sure to restore the cursor if we
+    // hid it.
+    //
+    if watch.is_some() {
+        execute!(std::io::stdout(), Show)?;
+    }
+
+    rval
+}
+
+//
+// Set by our Ctrl-C handler when watching.
+//
+static DONE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize)]
+struct TaskRecord {
+    index: u32,
+    address: u32,
+    module: String,
+    generation: Option<u32>,
+    current: bool,
+    state: Option<String>,
+    fault: Option<String>,
+    stack_used: Option<u32>,
+    stack_size: Option<u32>,
+}
+
+//
+// The pattern with which the kernel fills each task's stack before starting
+// it.
+//
+const STACK_FILL: u32 = 0xbaddcafe;
+
+#[derive(Copy, Clone, Debug)]
+struct StackUsage {
+    used: u32,
+    size: u32,
+}
+
+impl StackUsage {
+    fn percentage(&self) -> u32 {
+        if self.size == 0 {
+            0
+        } else {
+            ((self.used as u64 * 100) / self.size as u64) as u32
+        }
+    }
+}
+
+//
+// Determine the stack usage of a task by finding the region that contains
+// its initial stack pointer and then scanning up from the base of that
+// region (that is, the limit of the stack) for the first word that has been
+// written.
+//
+fn stack_usage(
+    core: &mut dyn Core,
+    regions: &BTreeMap<u32, HubrisRegion>,
+    task: HubrisTask,
+    initial: u32,
+) -> Result<StackUsage> {
+    let region = regions
+        .values()
+        .find(|r| {
+            initial > r.base
+                && initial <= r.base + r.size
+                && r.tasks.contains(&task)
+        })
+        .ok_or_else(|| {
+            anyhow!("could not find stack region for {:#x}", initial)
+        })?;
+
+    let size = initial - region.base;
+    let mut stack = vec![0; size as usize];
+    core.read_8(region.base, &mut stack)?;
+
+    let unused = stack
+        .chunks_exact(4)
+        .take_while(|c| {
+            u32::from_le_bytes((*c).try_into().unwrap()) == STACK_FILL
+        })
+        .count() as u32
+        * 4;
+
+    Ok(StackUsage { used: size - unused, size })
+}
+
+//
+// Unwinds a task's stack from its saved state, using the same unwinder (and
+// therefore the same call frame information) as the backtrace displayed
+// with `-s`; the unwinder stops at the top of the task's stack.
+//
+fn trace_task(
+    core: &mut dyn Core,
+    hubris: &HubrisArchive,
+    task_arg: &str,
+) -> Result<()> {
+    let task_t = hubris.lookup_struct_byname("Task")?;
+    let (base, task_count) = hubris.task_table(core)?;
+
+    let i = match hubris.lookup_task(task_arg) {
+        Some(HubrisTask::Task(i)) => *i,
+        _ => match parse_int::parse::<u32>(task_arg) {
+            Ok(i) if i < task_count => i,
+            _ => bail!("\"{}\" is not a valid task", task_arg),
+        },
+    };
+
+    let t = HubrisTask::Task(i);
+    let module = hubris.lookup_module(t)?;
+
+    let mut taskblock = vec![0; task_t.size];
+    core.read_8(base + i * task_t.size as u32, &mut taskblock)?;
+
+    let task_value: reflect::Value =
+        reflect::load(hubris, &taskblock, task_t, 0)?;
+    let task: Task = Task::from_value(&task_value)?;
+    let desc: TaskDesc = task.descriptor.load_from(hubris, core)?;
+
+    let regs = hubris.registers(core, t)?;
+    let stack = hubris
+        .stack(core, t, desc.initial_stack, &regs)
+        .with_context(|| format!("failed to unwind {}", module.name))?;
+
+    println!("{:2} {}", i, module.name);
+
+    let printer = humility_stack::StackPrinter {
+        indent: 3,
+        line: false,
+        additional: false,
+    };
+
+    printer.print(hubris, &stack);
+
+    Ok(())
 }
+
+/// Options controlling what [`print_tasks`] displays; the default displays
+/// each task once, without registers, stacks or stack usage.
+#[derive(Debug, Default)]
+pub struct PrintTasksOptions {
+    /// display registers
+    pub registers: bool,
+    /// display stack backtraces
+    pub stack: bool,
+    /// display line number information with stack backtraces
+    pub line: bool,
+    /// display the tasks repeatedly
+    pub spin: bool,
+    /// redraw the tasks in place at the specified interval (in milliseconds)
+    pub watch: Option<u64>,
+    /// display the task structures
+    pub verbose: bool,
+    /// display stack usage, flagging usage above the specified percentage
+    pub stack_threshold: Option<u8>,
+    /// emit task state as JSON
+    pub json: bool,
+    /// display only the specified task
+    pub task: Option<String>,
+}
+
 #[rustfmt::skip::macros(println)]
-#[allow(clippy::too_many_arguments)]
 pub fn print_tasks(
     w: &mut dyn Write,
     core: &mut dyn Core,
     hubris: &HubrisArchive,
-    registers: bool,
-    stack: bool,
-    line: bool,
-    spin: bool,
-    verbose: bool,
-    task_arg: Option<String>,
+    options: &PrintTasksOptions,
 ) -> Result<()> {
-    let (base, task_count) = hubris.task_table(core)?;
-    log::debug!("task table: {:#x?}, count: {}", base, task_count);
-    let ticks = if core.is_net() { None } else { Some(hubris.ticks(core)?) };
+    let PrintTasksOptions {
+        registers,
+        stack,
+        line,
+        spin,
+        watch,
+        verbose,
+        stack_threshold,
+        json,
+        task: ref task_arg,
+    } = *options;
 
     let task_t = hubris.lookup_struct_byname("Task")?;
     let save = task_t.lookup_member("save")?.offset;
@@ -222,6 +545,12 @@ pub fn print_tasks(
 
     let mut found = false;
 
+    //
+    // When watching, we track the generation of each task to be able to
+    // highlight those tasks that have restarted.
+    //
+    let mut generations: HashMap<u32, u32> = HashMap::new();
+
     let printer = humility_stack::StackPrinter {
         indent: 3,
         line,
@@ -229,6 +558,15 @@ pub fn print_tasks(
     };
 
     loop {
+        //
+        // We re-read the task table and ticks on every pass, as they may
+        // have changed if we are spinning or watching.
+        //
+        let (base, task_count) = hubris.task_table(core)?;
+        log::debug!("task table: {:#x?}, count: {}", base, task_count);
+        let ticks =
+            if core.is_net() { None } else { Some(hubris.ticks(core)?) };
+
         core.halt()?;
 
         let cur = hubris.current_task(core)?;
@@ -302,26 +640,44 @@ pub fn print_tasks(
             }
         }
 
-        let keep_halted = stack || registers || panicked;
+        let keep_halted =
+            stack || registers || stack_threshold.is_some() || panicked;
 
         if !keep_halted {
             core.run()?;
         }
 
-        writeln!(
-            w,
-            "system time = {}",
-            ticks
-                .map(|t| t.to_string())
-                .unwrap_or_else(|| "unavailable-via-net".to_owned())
-        )?;
-        writeln!(
-            w,
-            "{:2} {:21} {:>8} {:3} {:9}",
-            "ID", "TASK", "GEN", "PRI", "STATE"
-        )?;
+        if watch.is_some() {
+            w.flush()?;
+            execute!(
+                std::io::stdout(),
+                MoveTo(0, 0),
+                Clear(ClearType::FromCursorDown)
+            )?;
+        }
+
+        if !json {
+            writeln!(
+                w,
+                "system time = {}",
+                ticks
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| "unavailable-via-net".to_owned())
+            )?;
+            writeln!(
+                w,
+                "{:2} {:21} {:>8} {:3} {:9}",
+                "ID", "TASK", "GEN", "PRI", "STATE"
+            )?;
+        }
 
         let mut any_names_truncated = false;
+        let mut records = vec![];
+
+        let regions = match stack_threshold {
+            Some(_) => Some(hubris.regions(core)?),
+            None => None,
+        };
 
         for (i, addr, task_value, task) in tasks.iter() {
             let i = *i;
@@ -347,6 +703,8 @@ pub fn print_tasks(
                 found = true;
             }
 
+            let current = cur == Some(HubrisTask::Task(i));
+
             let timer = match (task.timer.deadline, ticks) {
                 (Some(deadline), Some(ticks)) => Some(Deadline::Relative {
                     dt: deadline.0 as i64 - ticks as i64,
@@ -359,6 +717,78 @@ pub fn print_tasks(
                 (None, _) => None,
             };
 
+            //
+            // Explain the state of the task (and the reason for its fault,
+            // if it has faulted) -- unless this is the supervisor on a net
+            // core, which we can't meaningfully process.
+            //
+            let (state, fault) = if i == 0 && core.is_net() {
+                (None, None)
+            } else {
+                let fault = explain_fault(hubris, core, i, &regs, task.state)?;
+                let mut buf = vec![];
+
+                explain_state(
+                    &mut buf,
+                    hubris,
+                    i,
+                    &regs,
+                    task.state,
+                    fault.as_deref(),
+                    current,
+                    irqs,
+                    timer,
+                )?;
+
+                //
+                // Notifications that have been posted to the task but not
+                // yet received are displayed along with what posted them.
+                // (Older kernels may lack this field; we quietly skip it.)
+                //
+                let pending = task_value
+                    .as_struct()
+                    .ok()
+                    .and_then(|s| s.get("notifications"))
+                    .and_then(|v| u32::from_value(v).ok())
+                    .unwrap_or(0);
+
+                if pending != 0 {
+                    write!(buf, ", pending:")?;
+                    explain_notifications(
+                        &mut buf, hubris, i, pending, irqs, timer,
+                    )?;
+                }
+
+                (Some(String::from_utf8_lossy(&buf).into_owned()), fault)
+            };
+
+            let usage = match (&regions, &state) {
+                (Some(regions), Some(_)) => {
+                    let desc: TaskDesc =
+                        task.descriptor.load_from(hubris, core)?;
+                    let t = HubrisTask::Task(i);
+                    Some(stack_usage(core, regions, t, desc.initial_stack)?)
+                }
+                _ => None,
+            };
+
+            if json {
+                records.push(TaskRecord {
+                    index: i,
+                    address: *addr,
+                    module: module.to_string(),
+                    generation: state
+                        .as_ref()
+                        .map(|_| u32::from(task.generation)),
+                    current,
+                    state,
+                    fault,
+                    stack_used: usage.map(|u| u.used),
+                    stack_size: usage.map(|u| u.size),
+                });
+                continue;
+            }
+
             let modname = {
                 let mut modname = module.to_string();
                 if modname.len() > 20 {
@@ -369,37 +799,50 @@ pub fn print_tasks(
                 modname
             };
 
-            // Special case for the supervisor on a net core, which we can't
-            // meaningfully process.
-            if i == 0 && core.is_net() {
-                writeln!(
-                    w,
-                    "{:2} {:21} {:>8} {:3} [cannot read supervisor memory]",
-                    i, modname, "?", task.priority.0
-                )?;
-                continue;
-            } else {
+            match state {
+                None => {
+                    writeln!(
+                        w,
+                        "{:2} {:21} {:>8} {:3} [cannot read supervisor memory]",
+                        i, modname, "?", task.priority.0
+                    )?;
+                    continue;
+                }
+                Some(state) => {
+                    let gen = u32::from(task.generation);
+                    let row = format!(
+                        "{:2} {:21} {:>8} {:3} {}",
+                        i, modname, gen, task.priority.0, state
+                    );
+
+                    let restarted = watch.is_some()
+                        && generations
+                            .insert(i, gen)
+                            .map_or(false, |g| g != gen);
+
+                    if restarted {
+                        writeln!(w, "{}", row.reversed())?;
+                    } else {
+                        writeln!(w, "{}", row)?;
+                    }
+                }
+            }
+
+            if let (Some(usage), Some(threshold)) = (usage, stack_threshold) {
+                let pct = usage.percentage();
+
                 write!(
                     w,
-                    "{:2} {:21} {:>8} {:3} ",
-                    i,
-                    modname,
-                    u32::from(task.generation),
-                    task.priority.0
+                    "   stack usage: {} of {} bytes ({}%)",
+                    usage.used, usage.size, pct
                 )?;
+
+                if pct > threshold as u32 {
+                    write!(w, " -- EXCEEDS {}%", threshold)?;
+                }
+
+                writeln!(w)?;
             }
-            explain_state(
-                w,
-                hubris,
-                core,
-                i,
-                &regs,
-                task.state,
-                cur == Some(HubrisTask::Task(i)),
-                irqs,
-                timer,
-            )?;
-            writeln!(w)?;
 
             let desc: TaskDesc = task.descriptor.load_from(hubris, core)?;
             if stack || registers {
@@ -438,6 +881,10 @@ pub fn print_tasks(
             }
         }
 
+        if json {
+            writeln!(w, "{}", serde_json::to_string(&records)?)?;
+        }
+
         if any_names_truncated {
             writeln!(
                 w,
@@ -450,11 +897,18 @@ pub fn print_tasks(
             core.run()?;
         }
 
-        if task_arg.is_some() && !found {
-            bail!("\"{}\" is not a valid task", task_arg.unwrap());
+        if let (Some(task), false) = (task_arg, found) {
+            bail!("\"{}\" is not a valid task", task);
         }
 
-        if !spin {
+        if let Some(interval) = watch {
+            w.flush()?;
+            std::thread::sleep(Duration::from_millis(interval));
+
+            if DONE.load(Ordering::SeqCst) {
+                break;
+            }
+        } else if !spin {
             break;
         }
     }
@@ -472,10 +926,10 @@ enum Deadline {
 fn explain_state(
     w: &mut dyn Write,
     hubris: &HubrisArchive,
-    core: &mut dyn Core,
     task_index: u32,
     regs: &HashMap<(u32, ARMRegister), u32>,
     ts: TaskState,
+    fault: Option<&str>,
     current: bool,
     irqs: Option<&Vec<(u32, u32)>>,
     timer: Option<Deadline>,
@@ -486,8 +940,8 @@ fn explain_state(
                 w, hubris, task_index, regs, current, irqs, timer, ss,
             )?;
         }
-        TaskState::Faulted { fault, original_state } => {
-            explain_fault_info(w, hubris, core, task_index, regs, fault)?;
+        TaskState::Faulted { original_state, .. } => {
+            write!(w, "FAULT: {}", fault.unwrap_or("unknown"))?;
             write!(w, " (was: ")?;
             explain_sched_state(
                 w,
@@ -505,6 +959,25 @@ fn explain_state(
     Ok(())
 }
 
+fn explain_fault(
+    hubris: &HubrisArchive,
+    core: &mut dyn Core,
+    task_index: u32,
+    regs: &HashMap<(u32, ARMRegister), u32>,
+    ts: TaskState,
+) -> Result<Option<String>> {
+    match ts {
+        TaskState::Healthy(_) => Ok(None),
+        TaskState::Faulted { fault, .. } => {
+            let mut buf = vec![];
+            explain_fault_info(
+                &mut buf, hubris, core, task_index, regs, fault,
+            )?;
+            Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn explain_sched_state(
     w: &mut dyn Write,
@@ -573,7 +1046,6 @@ fn explain_fault_info(
 ) -> Result<()> {
     use doppel::FaultInfo;
 
-    write!(w, "FAULT: ")?;
     match fi {
         FaultInfo::DivideByZero => {
             write!(w, "divide by zero")?;
@@ -670,24 +1142,13 @@ fn explain_fault_source(
     Ok(())
 }
 
-/// Heuristic recognition of receive states used by normal programs.
-///
-/// We can print any receive state as a bunch of raw names and bits, but it's
-/// often easier to read if common patterns are summarized.
-///
-/// Goals here include:
-/// - Don't hide information - we should be able to exactly predict the state
-///   representation from what's printed, even if it's pretty-printed.
-///
-/// - Make unusual cases obvious.
-///
-/// - Make common cases unobtrusive and easy to scan.
-fn explain_recv(
+/// Displays each bit set in a notification mask, annotated (where we can)
+/// with its name and with the timer and/or IRQs that post it.
+fn explain_notifications(
     w: &mut dyn Write,
     hubris: &HubrisArchive,
     task_index: u32,
-    src: Option<TaskId>,
-    notmask: u32,
+    mask: u32,
     irqs: Option<&Vec<(u32, u32)>>,
     timer: Option<Deadline>,
 ) -> Result<()> {
@@ -700,12 +1161,11 @@ fn explain_recv(
     let mut note_types = vec![];
     for i in 0..32 {
         let bitmask = 1 << i;
-        if notmask & bitmask == 0 {
+        if mask & bitmask == 0 {
             continue;
         }
 
-        // Collect the IRQs that correspond to this enabled notification mask
-        // bit.
+        // Collect the IRQs that correspond to this notification bit.
         let irqnums = if let Some(irqs) = irqs {
             irqs.iter()
                 .filter(|&&(m, _)| m == bitmask)
@@ -721,6 +1181,70 @@ fn explain_recv(
         note_types.push(NoteInfo { irqs: irqnums, timer: timer_assoc, bit: i });
     }
 
+    let task_mod = hubris.lookup_module(HubrisTask::Task(task_index));
+    let notification_names = if let Ok(task_mod) = task_mod {
+        hubris.manifest.task_notifications.get(&task_mod.name)
+    } else {
+        None
+    };
+    let notification_name = |s: u32| -> Option<&str> {
+        notification_names.and_then(|n| n.get(s as usize)).map(|s| s.as_str())
+    };
+
+    for nt in note_types {
+        let name = notification_name(nt.bit);
+        if let Some(name) = name {
+            write!(w, " {name}")?;
+        } else {
+            write!(w, " bit{}", nt.bit)?;
+        }
+        if !nt.irqs.is_empty() || nt.timer.is_some() {
+            write!(w, "(")?;
+            let mut first = true;
+            if let Some(ts) = nt.timer {
+                write!(w, "{}T", if !first { "/" } else { "" })?;
+                match ts {
+                    Deadline::Relative { dt, .. } => {
+                        write!(w, "{dt:+}")?;
+                    }
+                    Deadline::Absolute { t, .. } => {
+                        write!(w, "={t:}")?;
+                    }
+                }
+                first = false;
+            }
+            for irq in &nt.irqs {
+                write!(w, "{}irq{}", if !first { "/" } else { "" }, irq)?;
+                first = false;
+            }
+            write!(w, ")")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Heuristic recognition of receive states used by normal programs.
+///
+/// We can print any receive state as a bunch of raw names and bits, but it's
+/// often easier to read if common patterns are summarized.
+///
+/// Goals here include:
+/// - Don't hide information - we should be able to exactly predict the state
+///   representation from what's printed, even if it's pretty-printed.
+///
+/// - Make unusual cases obvious.
+///
+/// - Make common cases unobtrusive and easy to scan.
+fn explain_recv(
+    w: &mut dyn Write,
+    hubris: &HubrisArchive,
+    task_index: u32,
+    src: Option<TaskId>,
+    notmask: u32,
+    irqs: Option<&Vec<(u32, u32)>>,
+    timer: Option<Deadline>,
+) -> Result<()> {
     // Display kernel receives as "wait" and others as "recv", noting the
     // explicit source for a closed receive.
     let mut outer_first = false;
@@ -738,48 +1262,10 @@ fn explain_recv(
         }
     }
 
-    let task_mod = hubris.lookup_module(HubrisTask::Task(task_index));
-    let notification_names = if let Ok(task_mod) = task_mod {
-        hubris.manifest.task_notifications.get(&task_mod.name)
-    } else {
-        None
-    };
-    let notification_name = |s: u32| -> Option<&str> {
-        notification_names.and_then(|n| n.get(s as usize)).map(|s| s.as_str())
-    };
-
     // Display notification bits, along with meaning where we can.
     if notmask != 0 {
         write!(w, "{}notif:", if outer_first { "" } else { ", " })?;
-        for nt in note_types {
-            let name = notification_name(nt.bit);
-            if let Some(name) = name {
-                write!(w, " {name}")?;
-            } else {
-                write!(w, " bit{}", nt.bit)?;
-            }
-            if !nt.irqs.is_empty() || nt.timer.is_some() {
-                write!(w, "(")?;
-                let mut first = true;
-                if let Some(ts) = nt.timer {
-                    write!(w, "{}T", if !first { "/" } else { "" })?;
-                    match ts {
-                        Deadline::Relative { dt, .. } => {
-                            write!(w, "{dt:+}")?;
-                        }
-                        Deadline::Absolute { t, .. } => {
-                            write!(w, "={t:}")?;
-                        }
-                    }
-                    first = false;
-                }
-                for irq in &nt.irqs {
-                    write!(w, "{}irq{}", if !first { "/" } else { "" }, irq)?;
-                    first = false;
-                }
-                write!(w, ")")?;
-            }
-        }
+        explain_notifications(w, hubris, task_index, notmask, irqs, timer)?;
     }
 
     // Flag things that are probably bugs
@@ -306,7 +306,10 @@ fn test(context: &mut ExecutionContext) -> Result<()> {
         writeln!(out, "==== Task state")?;
 
         cmd_tasks::print_tasks(
-            &mut out, core, hubris, false, false, false, false, false, None,
+            &mut out,
+            core,
+            hubris,
+            &cmd_tasks::PrintTasksOptions::default(),
         )?;
     }
     println!("Ran a total of {} cases", ran_cases);
@@ -831,3 +831,10 @@ pub fn swoscaler(hubris: &HubrisArchive, core: &mut dyn Core) -> Result<u16> {
         Some(clock) => Ok(((clock * 1000) / debug_clock_mhz) as u16 - 1),
     }
 }
+
+///
+/// The inverse of [`swoscaler`]:  determines the CPU clock frequency (in Hz)
+/// from a clock scaler.
+pub fn swoscaler_clock(scaler: u16) -> u64 {
+    (scaler as u64 + 1) * 2_000_000
+}
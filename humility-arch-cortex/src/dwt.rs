@@ -4,6 +4,7 @@
 
 use crate::debug::Register;
 use crate::register;
+use crate::register_offs;
 use bitfield::bitfield;
 use humility::core::Core;
 
@@ -26,12 +27,12 @@ register!(DWT_CTRL, 0xe000_1000,
     pub sleep_enabled, _: 19;
     pub exception_enabled, _: 18;
     pub cpi_enabled, _: 17;
-    pub exception_trace_enabled, _: 16;
-    pub pc_sampling_enabled, _: 12;
+    pub exception_trace_enabled, set_exception_trace_enabled: 16;
+    pub pc_sampling_enabled, set_pc_sampling_enabled: 12;
     pub _synctap, _set_synctap: 11, 10;
-    pub postcnt_tap, _: 9;
-    pub postcnt_init, _: 8, 5;
-    pub postcnt_reset, _: 4, 1;
+    pub postcnt_tap, set_postcnt_tap: 9;
+    pub postcnt_init, set_postcnt_init: 8, 5;
+    pub postcnt_reset, set_postcnt_reset: 4, 1;
     pub cyccnt_enabled, set_cyccnt_enabled: 0;
 );
 
@@ -54,3 +55,34 @@ impl DWT_CTRL {
         self._set_synctap(val);
     }
 }
+
+//
+// DWT Comparator Registers.  Each comparator has its own set of these
+// registers, at an offset of 0x10 times the comparator number from the base
+// of the DWT; use [`dwt_comparator_base`] to determine the base for a given
+// comparator.
+//
+register_offs!(DWT_COMP, 0x20,
+    pub comp, set_comp: 31, 0;
+);
+
+register_offs!(DWT_MASK, 0x24,
+    pub mask, set_mask: 4, 0;
+);
+
+register_offs!(DWT_FUNCTION, 0x28,
+    pub id, _: 31, 27;
+    pub matched, _: 24;
+    pub datavaddr1, set_datavaddr1: 19, 16;
+    pub datavaddr0, set_datavaddr0: 15, 12;
+    pub datavsize, set_datavsize: 11, 10;
+    pub lnk1ena, _: 9;
+    pub datavmatch, set_datavmatch: 8;
+    pub cycmatch, set_cycmatch: 7;
+    pub emitrange, set_emitrange: 5;
+    pub function, set_function: 3, 0;
+);
+
+pub fn dwt_comparator_base(comparator: u32) -> u32 {
+    DWT_CTRL::ADDRESS + 0x10 * comparator
+}
@@ -8,6 +8,7 @@ use crate::tpiu::*;
 use anyhow::Result;
 use bitfield::bitfield;
 use humility::core::Core;
+use serde::{Deserialize, Serialize};
 
 macro_rules! etm_register {
     ($reg:ty, $offs:expr, $($arg:tt)*) => (
@@ -150,6 +151,29 @@ etm_register!(ETMFFLR, 0x00b,
     pub fifo_full_level, set_fifo_full_level: 7, 0;
 );
 
+//
+// ETM ViewData Event Register
+//
+etm_register!(ETMVDEVR, 0x00c,
+    #[derive(Copy, Clone)]
+    pub struct ETMVDEVR(u32);
+    impl Debug;
+    pub fcn, _: 16, 14;
+    pub resource_b, set_resource_b: 13, 7;
+    pub resource_a, set_resource_a: 6, 0;
+);
+
+//
+// ETM ViewData Control 3 Register
+//
+etm_register!(ETMVDCR3, 0x00f,
+    #[derive(Copy, Clone)]
+    pub struct ETMVDCR3(u32);
+    impl Debug;
+    pub exclude_only, set_exclude_only: 16;
+    pub comparator_select, set_comparator_select: 15, 0;
+);
+
 //
 // ETM Identification Register
 //
@@ -271,6 +295,19 @@ pub enum ETM3Header {
     ExceptionEntry,
     PHeaderFormat1 { e: u8, n: u8 },
     PHeaderFormat2 { e0: bool, e1: bool },
+
+    //
+    // The remaining P-header formats are only found in cycle-accurate mode.
+    // (Format 1 and Format 2 P-headers are found in both modes -- but in
+    // cycle-accurate mode, each atom of a Format 1 P-header takes a cycle,
+    // and a Format 2 P-header takes a single cycle.)  Format 0 denotes a
+    // single cycle in which no instruction executed; Format 3 denotes
+    // `w + 1` cycles, in the last of which an instruction executed if `e`
+    // is set; Format 4 denotes a single atom that took no cycles.
+    //
+    PHeaderFormat0,
+    PHeaderFormat3 { e: bool, w: u8 },
+    PHeaderFormat4 { n: bool },
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -296,7 +333,7 @@ pub enum ETM3ProcessorState {
     Jazelle,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum ETM3Exception {
     HardFault,
     IRQ { irq: u16 },
@@ -325,6 +362,15 @@ pub enum ETM3Payload {
         reason: ETM3SyncReason,
         address: u32,
         processor_state: ETM3ProcessorState,
+        cycles: Option<u32>,
+    },
+    CycleCount {
+        cycles: u32,
+    },
+    Data {
+        addr: Option<u32>,
+        mask: u32,
+        value: Option<u32>,
     },
 }
 
@@ -338,6 +384,7 @@ pub struct ETM3Packet {
 
 pub struct ETM3Config {
     pub alternative_encoding: bool,
+    pub cycle_accurate: bool,
     pub context_id: u8,
     pub data_access: bool,
     pub traceid: u8,
@@ -381,6 +428,13 @@ fn encode(hdr: ETM3Header) -> u8 {
                 | if e0 { 1 << 3 } else { 0 }
                 | if e1 { 1 << 2 } else { 0 }
         }
+        ETM3Header::PHeaderFormat0 => 0b1000_0000,
+        ETM3Header::PHeaderFormat3 { e, w } => {
+            0b1010_0000 | if e { 1 << 6 } else { 0 } | ((w & 0b111) << 2)
+        }
+        ETM3Header::PHeaderFormat4 { n } => {
+            0b1001_0010 | if n { 1 << 2 } else { 0 }
+        }
     }
 }
 
@@ -400,7 +454,7 @@ fn set(table: &mut [Option<ETM3Header>], hdr: ETM3Header) {
     }
 }
 
-fn etm_hdrs() -> Vec<Option<ETM3Header>> {
+fn etm_hdrs(cycle_accurate: bool) -> Vec<Option<ETM3Header>> {
     let mut hdr: Vec<Option<ETM3Header>> = vec![None; 256];
 
     for i in 0..=0b11_1111 {
@@ -447,9 +501,34 @@ fn etm_hdrs() -> Vec<Option<ETM3Header>> {
     set(&mut hdr, ETM3Header::ExceptionExit);
     set(&mut hdr, ETM3Header::ExceptionEntry);
 
-    for e in 0..=0b1111 {
-        for n in 0..=0b1 {
-            set(&mut hdr, ETM3Header::PHeaderFormat1 { e, n });
+    //
+    // In cycle-accurate mode, Format 1 P-headers have only three bits of E
+    // atoms, and the P-header that would denote no atoms at all is instead
+    // a Format 0 P-header.
+    //
+    if cycle_accurate {
+        for e in 0..=0b111 {
+            for n in 0..=0b1 {
+                if e != 0 || n != 0 {
+                    set(&mut hdr, ETM3Header::PHeaderFormat1 { e, n });
+                }
+            }
+        }
+
+        set(&mut hdr, ETM3Header::PHeaderFormat0);
+
+        for w in 0..=0b111 {
+            set(&mut hdr, ETM3Header::PHeaderFormat3 { e: true, w });
+            set(&mut hdr, ETM3Header::PHeaderFormat3 { e: false, w });
+        }
+
+        set(&mut hdr, ETM3Header::PHeaderFormat4 { n: true });
+        set(&mut hdr, ETM3Header::PHeaderFormat4 { n: false });
+    } else {
+        for e in 0..=0b1111 {
+            for n in 0..=0b1 {
+                set(&mut hdr, ETM3Header::PHeaderFormat1 { e, n });
+            }
         }
     }
 
@@ -534,7 +613,7 @@ fn etm_packet_state(
         ETM3Header::NormalData { a, size } => {
             let dsize = if a && config.data_access { compressed(5) } else { 0 };
 
-            expect(dsize + 1 + size)
+            expect(dsize + data_value_size(size))
         }
 
         ETM3Header::Timestamp { .. } => expect(compressed(9)),
@@ -552,6 +631,20 @@ fn etm_packet_state(
     }
 }
 
+//
+// The size field of a Normal Data packet encodes the number of bytes of the
+// data value that follow any data address:  a size of zero denotes a value
+// of zero (and therefore no bytes of value at all).
+//
+fn data_value_size(size: u8) -> u8 {
+    match size & 0b11 {
+        0b00 => 0,
+        0b01 => 1,
+        0b10 => 2,
+        _ => 4,
+    }
+}
+
 fn etm_payload_decode(
     hdr: ETM3Header,
     payload: &[u8],
@@ -622,25 +715,111 @@ fn etm_payload_decode(
         }
     };
 
+    //
+    // Cycle counts are compressed:  each byte contributes seven bits, with
+    // the high bit denoting that another byte follows (for at most five
+    // bytes).  Returns the count and the number of bytes that encode it.
+    //
+    let cycles = || {
+        let mut count: u32 = 0;
+
+        for (i, pld) in payload.iter().take(5).enumerate() {
+            count |= ((pld & 0b0111_1111) as u32) << (i * 7);
+
+            if (pld & 0b1000_0000) == 0 {
+                return (count, i + 1);
+            }
+        }
+
+        (count, std::cmp::min(payload.len(), 5))
+    };
+
+    //
+    // Data addresses are compressed relative to the last data address:  each
+    // byte contributes seven bits, with the high bit denoting that another
+    // byte follows -- with the exception of the fifth byte, which has only
+    // the four remaining bits of address.  Returns the address, its mask and
+    // the number of bytes that encode it.
+    //
+    let daddr = || {
+        let mut addr: u32 = 0;
+        let mut nbits = 0;
+
+        for (i, pld) in payload.iter().take(5).enumerate() {
+            if i == 4 {
+                addr |= ((pld & 0b0000_1111) as u32) << nbits;
+                nbits += 4;
+                break;
+            }
+
+            addr |= ((pld & 0b0111_1111) as u32) << nbits;
+            nbits += 7;
+
+            if (pld & 0b1000_0000) == 0 {
+                break;
+            }
+        }
+
+        let mask = if nbits == 32 { 0 } else { !((1 << nbits) - 1) };
+
+        (addr, mask, (nbits + 6) / 7)
+    };
+
+    let isync = |o: usize, cycles| {
+        let ibyte = payload[o + config.context_id as usize];
+        let addr = &payload[o + config.context_id as usize + 1..][..4];
+        let processor_state = processor_state(ibyte, addr[0]);
+        let a0 = match processor_state {
+            ETM3ProcessorState::Jazelle => addr[0],
+            _ => addr[0] & !0b0000_0001,
+        };
+
+        ETM3Payload::ISync {
+            context: context(o),
+            reason: reason(ibyte),
+            address: u32::from_le_bytes([a0, addr[1], addr[2], addr[3]]),
+            processor_state,
+            cycles,
+        }
+    };
+
     match hdr {
-        ETM3Header::ISync => {
-            let o = config.context_id as usize;
-
-            let ibyte = payload[o];
-            let addr = &payload[o + 1..o + 5];
-            let processor_state = processor_state(ibyte, addr[0]);
-            let a0 = match processor_state {
-                ETM3ProcessorState::Jazelle => addr[0],
-                _ => addr[0] & !0b0000_0001,
+        ETM3Header::ISync => isync(0, None),
+        ETM3Header::ISyncCycleCount => {
+            let (count, o) = cycles();
+            isync(o, Some(count))
+        }
+        ETM3Header::CycleCount => {
+            ETM3Payload::CycleCount { cycles: cycles().0 }
+        }
+        ETM3Header::NormalData { a, size } => {
+            let (addr, mask, o) = if a && config.data_access {
+                let (addr, mask, o) = daddr();
+                (Some(addr), mask, o)
+            } else {
+                (None, 0, 0)
             };
 
-            ETM3Payload::ISync {
-                context: context(0),
-                reason: reason(ibyte),
-                address: u32::from_le_bytes([a0, addr[1], addr[2], addr[3]]),
-                processor_state,
+            let mut value = [0u8; 4];
+            let nbytes = data_value_size(size) as usize;
+            value[..nbytes].copy_from_slice(&payload[o..o + nbytes]);
+
+            ETM3Payload::Data {
+                addr,
+                mask,
+                value: Some(u32::from_le_bytes(value)),
             }
         }
+        ETM3Header::ValueNotTraced { a } => {
+            let (addr, mask) = if a {
+                let (addr, mask, _) = daddr();
+                (Some(addr), mask)
+            } else {
+                (None, 0)
+            };
+
+            ETM3Payload::Data { addr, mask, value: None }
+        }
         ETM3Header::BranchAddress { addr, .. } => {
             let mut target: u32 = (addr as u32) << 1;
             let mut nbits = 7;
@@ -703,8 +882,25 @@ fn etm_payload_decode(
 
 pub fn etm_ingest(
     config: &ETM3Config,
+    readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+    callback: impl FnMut(&ETM3Packet) -> Result<()>,
+) -> Result<()> {
+    etm_ingest_demux(config, &[], readnext, callback, |_| Ok(()))
+}
+
+///
+/// Ingests TPIU-formatted trace in which the ETM's frames are interleaved
+/// with those of other trace sources (e.g., the ITM), as when the formatter
+/// is in continuous mode.  Trace bearing the ETM's trace ID is decoded as
+/// with [`etm_ingest`]; trace bearing any of the trace IDs in `others` is
+/// handed undecoded to `sink`.
+///
+pub fn etm_ingest_demux(
+    config: &ETM3Config,
+    others: &[u8],
     mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
     mut callback: impl FnMut(&ETM3Packet) -> Result<()>,
+    mut sink: impl FnMut(&TPIUPacket) -> Result<()>,
 ) -> Result<()> {
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     enum IngestState {
@@ -720,13 +916,21 @@ pub fn etm_ingest(
     let mut valid = vec![false; 256];
     valid[config.traceid as usize] = true;
 
-    let hdrs = &etm_hdrs();
+    for &id in others {
+        valid[id as usize] = true;
+    }
+
+    let hdrs = &etm_hdrs(config.cycle_accurate);
     let mut hdr = ETM3Header::ASync;
     let mut runlen = 0;
 
     tpiu_ingest(&valid, &mut readnext, |packet| {
         let payload = &mut vec;
 
+        if packet.id != Some(config.traceid) {
+            return sink(packet);
+        }
+
         if state == IngestState::ASyncSearching {
             match packet.datum {
                 0 => runlen += 1,
@@ -782,7 +986,11 @@ pub fn etm_ingest(
             ETM3PacketState::Complete => {}
         }
 
-        if let (IngestState::ISyncSearching, ETM3Header::ISync) = (state, hdr) {
+        if let (
+            IngestState::ISyncSearching,
+            ETM3Header::ISync | ETM3Header::ISyncCycleCount,
+        ) = (state, hdr)
+        {
             //
             // We have our ISync packet -- we can now ingest everything
             // (starting with this packet).
@@ -0,0 +1,872 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//
+// ETMv4 (as found in, e.g., the Cortex-M7) shares its base address and its
+// lock access register with ETMv3, but has an otherwise entirely different
+// programmer's model and an entirely different packet format.
+//
+
+use crate::debug::Register;
+use crate::etm::ETM3Exception;
+use crate::register;
+use crate::tpiu::*;
+use anyhow::{bail, Result};
+use bitfield::bitfield;
+use humility::core::Core;
+
+macro_rules! etmv4_register {
+    ($reg:ty, $offs:expr, $($arg:tt)*) => (
+        register!($reg, 0xe004_1000 + ($offs * 4), $($arg)*);
+    )
+}
+
+//
+// Programming Control Register
+//
+etmv4_register!(TRCPRGCTLR, 0x001,
+    #[derive(Copy, Clone)]
+    pub struct TRCPRGCTLR(u32);
+    impl Debug;
+    pub enable, set_enable: 0;
+);
+
+//
+// Trace Status Register
+//
+etmv4_register!(TRCSTATR, 0x003,
+    #[derive(Copy, Clone)]
+    pub struct TRCSTATR(u32);
+    impl Debug;
+    pub pmstable, _: 1;
+    pub idle, _: 0;
+);
+
+//
+// Trace Configuration Register
+//
+etmv4_register!(TRCCONFIGR, 0x004,
+    #[derive(Copy, Clone)]
+    pub struct TRCCONFIGR(u32);
+    impl Debug;
+    pub return_stack, set_return_stack: 12;
+    pub timestamp, set_timestamp: 11;
+    pub cond, set_cond: 10, 8;
+    pub vmid, set_vmid: 7;
+    pub context_id, set_context_id: 6;
+    pub cycle_counting, set_cycle_counting: 4;
+    pub branch_broadcast, set_branch_broadcast: 3;
+);
+
+//
+// Event Control 0 Register
+//
+etmv4_register!(TRCEVENTCTL0R, 0x008,
+    #[derive(Copy, Clone)]
+    pub struct TRCEVENTCTL0R(u32);
+    impl Debug;
+    pub event, set_event: 31, 0;
+);
+
+//
+// Event Control 1 Register
+//
+etmv4_register!(TRCEVENTCTL1R, 0x009,
+    #[derive(Copy, Clone)]
+    pub struct TRCEVENTCTL1R(u32);
+    impl Debug;
+    pub lpoverride, set_lpoverride: 12;
+    pub atb, set_atb: 11;
+    pub insten, set_insten: 3, 0;
+);
+
+//
+// Stall Control Register
+//
+etmv4_register!(TRCSTALLCTLR, 0x00b,
+    #[derive(Copy, Clone)]
+    pub struct TRCSTALLCTLR(u32);
+    impl Debug;
+    pub istall, set_istall: 8;
+    pub level, set_level: 3, 0;
+);
+
+//
+// Timestamp Control Register
+//
+etmv4_register!(TRCTSCTLR, 0x00c,
+    #[derive(Copy, Clone)]
+    pub struct TRCTSCTLR(u32);
+    impl Debug;
+    pub event, set_event: 7, 0;
+);
+
+//
+// Synchronization Period Register
+//
+etmv4_register!(TRCSYNCPR, 0x00d,
+    #[derive(Copy, Clone)]
+    pub struct TRCSYNCPR(u32);
+    impl Debug;
+    pub period, set_period: 4, 0;
+);
+
+//
+// Cycle Count Control Register
+//
+etmv4_register!(TRCCCCTLR, 0x00e,
+    #[derive(Copy, Clone)]
+    pub struct TRCCCCTLR(u32);
+    impl Debug;
+    pub threshold, set_threshold: 11, 0;
+);
+
+//
+// Branch Broadcast Control Register
+//
+etmv4_register!(TRCBBCTLR, 0x00f,
+    #[derive(Copy, Clone)]
+    pub struct TRCBBCTLR(u32);
+    impl Debug;
+    pub mode, set_mode: 8;
+    pub range, set_range: 7, 0;
+);
+
+//
+// Trace ID Register
+//
+etmv4_register!(TRCTRACEIDR, 0x010,
+    #[derive(Copy, Clone)]
+    pub struct TRCTRACEIDR(u32);
+    impl Debug;
+    pub traceid, set_traceid: 6, 0;
+);
+
+//
+// ViewInst Main Control Register
+//
+etmv4_register!(TRCVICTLR, 0x020,
+    #[derive(Copy, Clone)]
+    pub struct TRCVICTLR(u32);
+    impl Debug;
+    pub trcerr, set_trcerr: 11;
+    pub trcreset, set_trcreset: 10;
+    pub ssstatus, set_ssstatus: 9;
+    pub event, set_event: 7, 0;
+);
+
+//
+// ViewInst Include/Exclude Control Register
+//
+etmv4_register!(TRCVIIECTLR, 0x021,
+    #[derive(Copy, Clone)]
+    pub struct TRCVIIECTLR(u32);
+    impl Debug;
+    pub exclude, set_exclude: 23, 16;
+    pub include, set_include: 7, 0;
+);
+
+//
+// ViewInst Start/Stop Control Register
+//
+etmv4_register!(TRCVISSCTLR, 0x022,
+    #[derive(Copy, Clone)]
+    pub struct TRCVISSCTLR(u32);
+    impl Debug;
+    pub stop, set_stop: 23, 16;
+    pub start, set_start: 7, 0;
+);
+
+//
+// ID Register 8
+//
+etmv4_register!(TRCIDR8, 0x060,
+    #[derive(Copy, Clone)]
+    pub struct TRCIDR8(u32);
+    impl Debug;
+    pub maxspec, _: 31, 0;
+);
+
+//
+// ID Register 0
+//
+etmv4_register!(TRCIDR0, 0x078,
+    #[derive(Copy, Clone)]
+    pub struct TRCIDR0(u32);
+    impl Debug;
+    pub commopt, _: 29;
+    pub tssize, _: 28, 24;
+    pub qsupp, _: 16, 15;
+    pub qfilt, _: 14;
+    pub condtype, _: 13, 12;
+    pub numevent, _: 11, 10;
+    pub retstack, _: 9;
+    pub trccci, _: 7;
+    pub trccond, _: 6;
+    pub trcbb, _: 5;
+    pub trcdata, _: 4, 3;
+    pub instp0, _: 2, 1;
+);
+
+//
+// ID Register 1 -- which is at the same location as the ETMv3 ETMIDR, and
+// encodes its architecture version in the same bits.
+//
+etmv4_register!(TRCIDR1, 0x079,
+    #[derive(Copy, Clone)]
+    pub struct TRCIDR1(u32);
+    impl Debug;
+    pub designer, _: 31, 24;
+    pub trcarchmaj, _: 11, 8;
+    pub trcarchmin, _: 7, 4;
+    pub revision, _: 3, 0;
+);
+
+//
+// ID Register 2
+//
+etmv4_register!(TRCIDR2, 0x07a,
+    #[derive(Copy, Clone)]
+    pub struct TRCIDR2(u32);
+    impl Debug;
+    pub ccsize, _: 28, 25;
+    pub dvsize, _: 24, 20;
+    pub dasize, _: 19, 15;
+    pub vmidsize, _: 14, 10;
+    pub cidsize, _: 9, 5;
+    pub iasize, _: 4, 0;
+);
+
+//
+// OS Lock Access Register
+//
+etmv4_register!(TRCOSLAR, 0x0c0,
+    #[derive(Copy, Clone)]
+    pub struct TRCOSLAR(u32);
+    impl Debug;
+    pub oslk, set_oslk: 0;
+);
+
+//
+// OS Lock Status Register
+//
+etmv4_register!(TRCOSLSR, 0x0c1,
+    #[derive(Copy, Clone)]
+    pub struct TRCOSLSR(u32);
+    impl Debug;
+    pub oslm1, _: 3;
+    pub oslk, _: 1;
+    pub oslm0, _: 0;
+);
+
+#[derive(Clone, Debug)]
+pub enum ETM4Payload {
+    None,
+    Address { addr: u32 },
+    Atoms { atoms: Vec<bool> },
+    Exception { exception: ETM3Exception },
+    ExceptionReturn,
+    TraceInfo,
+    TraceOn,
+    Overflow,
+    Discard,
+    Timestamp { timestamp: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct ETM4Packet {
+    pub header: u8,
+    pub payload: ETM4Payload,
+    pub offset: usize,
+    pub time: f64,
+}
+
+pub struct ETM4Config {
+    pub traceid: Option<u8>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ETM4PacketState {
+    AwaitingHeader,
+    AwaitingPayload,
+    Complete,
+}
+
+//
+// Many ETMv4 payload fields are variable length, with bit 7 of each byte
+// denoting that another byte follows.  Returns the length of such a field
+// starting at the specified offset, if the field is complete.
+//
+fn etmv4_field_len(payload: &[u8], offs: usize, max: usize) -> Option<usize> {
+    for (i, byte) in payload.iter().skip(offs).enumerate() {
+        if byte & 0x80 == 0 || i + 1 == max {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+fn etmv4_field(payload: &[u8]) -> u64 {
+    payload
+        .iter()
+        .enumerate()
+        .fold(0, |val, (i, byte)| val | (((byte & 0x7f) as u64) << (i * 7)))
+}
+
+//
+// The length of a context payload (that is, the payload of a context
+// packet or the context portion of an address with context packet).  We
+// assume a 32-bit context ID and an 8-bit VMID, though we don't enable
+// tracing either.
+//
+fn etmv4_context_len(payload: &[u8], offs: usize) -> Option<usize> {
+    let info = *payload.get(offs)?;
+    let mut len = 1;
+
+    if info & 0x40 != 0 {
+        len += 4;
+    }
+
+    if info & 0x80 != 0 {
+        len += 1;
+    }
+
+    Some(len)
+}
+
+fn etmv4_packet_state(hdr: u8, payload: &[u8]) -> Result<ETM4PacketState> {
+    use ETM4PacketState::*;
+
+    let complete = |len: usize| {
+        if payload.len() >= len {
+            Complete
+        } else {
+            AwaitingPayload
+        }
+    };
+
+    let field = |offs: usize, max: usize| {
+        if payload.len() <= offs {
+            None
+        } else {
+            etmv4_field_len(payload, offs, max)
+        }
+    };
+
+    Ok(match hdr {
+        //
+        // Extension packets: A-sync, discard, and overflow
+        //
+        0x00 => match payload.first() {
+            None => AwaitingPayload,
+            Some(0x00) => match payload.last() {
+                Some(0x80) => Complete,
+                Some(0x00) if payload.len() < 11 => AwaitingPayload,
+                _ => bail!("malformed A-sync packet"),
+            },
+            Some(0x03) | Some(0x05) => Complete,
+            Some(ext) => bail!("unrecognized extension packet 0x{:x}", ext),
+        },
+
+        //
+        // Trace info: a PLCTL field, followed by a field for each bit set in
+        // PLCTL.
+        //
+        0x01 => match field(0, 1) {
+            None => AwaitingPayload,
+            Some(_) => {
+                let mut offs = 1;
+
+                for bit in 0..4 {
+                    if payload[0] & (1 << bit) == 0 {
+                        continue;
+                    }
+
+                    match field(offs, 5) {
+                        None => return Ok(AwaitingPayload),
+                        Some(len) => offs += len,
+                    }
+                }
+
+                Complete
+            }
+        },
+
+        //
+        // Timestamp, with (0x03) or without (0x02) a cycle count
+        //
+        0x02 | 0x03 => match field(0, 9) {
+            None => AwaitingPayload,
+            Some(len) if hdr == 0x03 => match field(len, 3) {
+                None => AwaitingPayload,
+                Some(_) => Complete,
+            },
+            Some(_) => Complete,
+        },
+
+        //
+        // Trace on, function return, and exception return
+        //
+        0x04 | 0x05 | 0x07 => Complete,
+
+        //
+        // Exception
+        //
+        0x06 => match field(0, 2) {
+            None => AwaitingPayload,
+            Some(_) => Complete,
+        },
+
+        //
+        // Cycle count packets
+        //
+        0x0c | 0x0d => complete(1),
+        0x0e => match field(0, 3) {
+            None => AwaitingPayload,
+            Some(_) => Complete,
+        },
+        0x0f..=0x1f => Complete,
+
+        //
+        // Data synchronization markers, mispredict, and cancel format 2
+        // and 3 packets
+        //
+        0x20..=0x2c | 0x30..=0x3f => Complete,
+
+        //
+        // Commit and cancel format 1
+        //
+        0x2d..=0x2f => match field(0, 5) {
+            None => AwaitingPayload,
+            Some(_) => Complete,
+        },
+
+        //
+        // Ignore and event packets
+        //
+        0x70..=0x7f => Complete,
+
+        //
+        // Context packets
+        //
+        0x80 => Complete,
+        0x81 => match etmv4_context_len(payload, 0) {
+            None => AwaitingPayload,
+            Some(len) => complete(len),
+        },
+
+        //
+        // Address with context packets
+        //
+        0x82 | 0x83 | 0x85 | 0x86 => {
+            let alen = if hdr < 0x85 { 4 } else { 8 };
+
+            match etmv4_context_len(payload, alen) {
+                None => AwaitingPayload,
+                Some(len) => complete(alen + len),
+            }
+        }
+
+        //
+        // Timestamp marker and exact match address
+        //
+        0x88 | 0x90..=0x92 => Complete,
+
+        //
+        // Short address
+        //
+        0x95 | 0x96 => match field(0, 2) {
+            None => AwaitingPayload,
+            Some(_) => Complete,
+        },
+
+        //
+        // Long address, 32-bit and 64-bit
+        //
+        0x9a | 0x9b => complete(4),
+        0x9d | 0x9e => complete(8),
+
+        //
+        // Atoms
+        //
+        0xc0..=0xff => Complete,
+
+        _ => bail!("unrecognized ETMv4 header 0x{:x}", hdr),
+    })
+}
+
+//
+// Decodes atoms, returning a vector of E (true) and N (false) atoms, oldest
+// first.
+//
+fn etmv4_atoms(hdr: u8) -> Vec<bool> {
+    let pattern = |bits: u32, count: usize| {
+        (0..count).map(|i| bits & (1 << i) != 0).collect()
+    };
+
+    match hdr {
+        0xf6 | 0xf7 => pattern((hdr & 0x1) as u32, 1),
+        0xd8..=0xdb => pattern((hdr & 0x3) as u32, 2),
+        0xf8..=0xff => pattern((hdr & 0x7) as u32, 3),
+        0xdc..=0xdf => match hdr & 0x3 {
+            0 => pattern(0b1110, 4),
+            1 => pattern(0b0000, 4),
+            2 => pattern(0b1010, 4),
+            _ => pattern(0b0101, 4),
+        },
+        0xd5 => pattern(0b00000, 5),
+        0xd6 => pattern(0b01010, 5),
+        0xd7 => pattern(0b10101, 5),
+        0xf5 => pattern(0b11110, 5),
+        _ => {
+            //
+            // Format 6: some number of E atoms, followed by either an E or
+            // an N atom.
+            //
+            let count = (hdr & 0x1f) as usize + 3;
+            let mut atoms = vec![true; count];
+            atoms.push(hdr & 0x20 == 0);
+            atoms
+        }
+    }
+}
+
+//
+// On M-profile parts, the exception type is the exception number.
+//
+fn etmv4_exception(number: u16) -> ETM3Exception {
+    match number {
+        1 => ETM3Exception::ProcessorReset,
+        2 => ETM3Exception::NMI,
+        3 => ETM3Exception::HardFault,
+        4 => ETM3Exception::MemManage,
+        5 => ETM3Exception::BusFault,
+        6 => ETM3Exception::UsageFault,
+        11 => ETM3Exception::SVC,
+        12 => ETM3Exception::DebugMonitor,
+        14 => ETM3Exception::PendSV,
+        15 => ETM3Exception::SysTick,
+        16.. => ETM3Exception::IRQ { irq: number - 16 },
+        _ => ETM3Exception::Reserved { exception: number },
+    }
+}
+
+//
+// Pushes an address onto the address history, which is needed to decode
+// both short address packets and exact match address packets.
+//
+fn etmv4_push(history: &mut [u32; 3], addr: u32) -> ETM4Payload {
+    history[2] = history[1];
+    history[1] = history[0];
+    history[0] = addr;
+    ETM4Payload::Address { addr }
+}
+
+fn etmv4_payload_decode(
+    hdr: u8,
+    payload: &[u8],
+    history: &mut [u32; 3],
+) -> ETM4Payload {
+    let byte = |i: usize| payload[i] as u32;
+
+    match hdr {
+        0x00 => match payload[0] {
+            0x03 => ETM4Payload::Discard,
+            0x05 => ETM4Payload::Overflow,
+            _ => ETM4Payload::None,
+        },
+        0x01 => {
+            *history = [0; 3];
+            ETM4Payload::TraceInfo
+        }
+        0x02 | 0x03 => {
+            let len = etmv4_field_len(payload, 0, 9).unwrap();
+
+            let timestamp = if len == 9 {
+                etmv4_field(&payload[..8]) | ((payload[8] as u64) << 56)
+            } else {
+                etmv4_field(&payload[..len])
+            };
+
+            ETM4Payload::Timestamp { timestamp }
+        }
+        0x04 => ETM4Payload::TraceOn,
+        0x06 => {
+            let mut number = ((payload[0] >> 1) & 0x1f) as u16;
+
+            if payload[0] & 0x80 != 0 {
+                number |= ((payload[1] & 0x1f) as u16) << 5;
+            }
+
+            ETM4Payload::Exception { exception: etmv4_exception(number) }
+        }
+        0x07 => ETM4Payload::ExceptionReturn,
+        0x90..=0x92 => {
+            let addr = history[(hdr - 0x90) as usize];
+            etmv4_push(history, addr)
+        }
+        0x95 | 0x96 => {
+            //
+            // Short addresses replace only the low bits of the most recent
+            // address: A[16:2] for A32 (0x95) and A[15:1] for Thumb (0x96).
+            //
+            let shift = if hdr == 0x95 { 2 } else { 1 };
+            let mut addr = (byte(0) & 0x7f) << shift;
+            let mut bits = 7 + shift;
+
+            if payload.len() > 1 {
+                addr |= byte(1) << bits;
+                bits += 8;
+            }
+
+            let mask = (1u32 << bits) - 1;
+            etmv4_push(history, (history[0] & !mask) | addr)
+        }
+        0x82 | 0x83 | 0x9a | 0x9b => {
+            let addr = if hdr == 0x82 || hdr == 0x9a {
+                ((byte(0) & 0x7f) << 2) | ((byte(1) & 0x7f) << 9)
+            } else {
+                ((byte(0) & 0x7f) << 1) | (byte(1) << 8)
+            };
+
+            etmv4_push(history, addr | (byte(2) << 16) | (byte(3) << 24))
+        }
+        0xc0..=0xff => ETM4Payload::Atoms { atoms: etmv4_atoms(hdr) },
+        _ => ETM4Payload::None,
+    }
+}
+
+pub fn etmv4_ingest(
+    config: &ETM4Config,
+    mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+    mut callback: impl FnMut(&ETM4Packet) -> Result<()>,
+) -> Result<()> {
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    enum IngestState {
+        ASyncSearching,
+        TraceInfoSearching,
+        Ingesting,
+    }
+
+    let mut state: IngestState = IngestState::ASyncSearching;
+    let mut pstate = ETM4PacketState::AwaitingHeader;
+    let mut vec = Vec::with_capacity(16);
+    let mut history = [0u32; 3];
+    let mut hdr = 0;
+    let mut runlen = 0;
+
+    let ingest = |packet: &TPIUPacket| -> Result<()> {
+        let payload = &mut vec;
+
+        if state == IngestState::ASyncSearching {
+            match packet.datum {
+                0 => runlen += 1,
+                0x80 => {
+                    if runlen >= 11 {
+                        humility::msg!(
+                            "A-sync alignment synchronization \
+                            packet found at offset {}",
+                            packet.offset
+                        );
+                        state = IngestState::TraceInfoSearching;
+                    }
+
+                    runlen = 0;
+                }
+                _ => {
+                    runlen = 0;
+                }
+            }
+
+            return Ok(());
+        }
+
+        match pstate {
+            ETM4PacketState::AwaitingHeader => {
+                hdr = packet.datum;
+                payload.truncate(0);
+            }
+
+            ETM4PacketState::AwaitingPayload => {
+                payload.push(packet.datum);
+            }
+
+            ETM4PacketState::Complete => {
+                panic!("unexpected packet state");
+            }
+        }
+
+        pstate = match etmv4_packet_state(hdr, payload) {
+            Ok(pstate) => pstate,
+            Err(err) => {
+                bail!("{} at offset {}", err, packet.offset);
+            }
+        };
+
+        match pstate {
+            ETM4PacketState::AwaitingHeader
+            | ETM4PacketState::AwaitingPayload => {
+                return Ok(());
+            }
+            ETM4PacketState::Complete => {}
+        }
+
+        if state == IngestState::TraceInfoSearching && hdr == 0x01 {
+            //
+            // We have our trace info packet -- we can now ingest everything
+            // (starting with this packet).
+            //
+            state = IngestState::Ingesting;
+        }
+
+        if state == IngestState::Ingesting {
+            callback(&ETM4Packet {
+                header: hdr,
+                payload: etmv4_payload_decode(hdr, payload, &mut history),
+                offset: packet.offset,
+                time: packet.time,
+            })?;
+        }
+
+        pstate = ETM4PacketState::AwaitingHeader;
+
+        Ok(())
+    };
+
+    match config.traceid {
+        Some(traceid) => {
+            let mut valid = vec![false; 256];
+            valid[traceid as usize] = true;
+            tpiu_ingest(&valid, &mut readnext, ingest)
+        }
+        None => tpiu_ingest_bypass(&mut readnext, ingest),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_etmv4_atoms() {
+        const E: bool = true;
+        const N: bool = false;
+
+        // Format 1
+        assert_eq!(etmv4_atoms(0xf7), [E]);
+        assert_eq!(etmv4_atoms(0xf6), [N]);
+
+        // Format 2
+        assert_eq!(etmv4_atoms(0xd9), [E, N]);
+
+        // Format 3
+        assert_eq!(etmv4_atoms(0xfd), [E, N, E]);
+
+        // Format 4
+        assert_eq!(etmv4_atoms(0xdc), [N, E, E, E]);
+        assert_eq!(etmv4_atoms(0xdd), [N, N, N, N]);
+
+        // Format 5
+        assert_eq!(etmv4_atoms(0xd5), [N, N, N, N, N]);
+        assert_eq!(etmv4_atoms(0xf5), [N, E, E, E, E]);
+
+        // Format 6
+        assert_eq!(etmv4_atoms(0xc0), [E, E, E, E]);
+        assert_eq!(etmv4_atoms(0xe1), [E, E, E, E, N]);
+    }
+
+    #[test]
+    fn test_etmv4_packet_state() {
+        use ETM4PacketState::*;
+
+        let state = |hdr, payload: &[u8]| etmv4_packet_state(hdr, payload);
+
+        // Atoms and exception return have no payload
+        assert_eq!(state(0xf7, &[]).unwrap(), Complete);
+        assert_eq!(state(0x07, &[]).unwrap(), Complete);
+
+        // A-sync: eleven zero bytes (one of which is the header) and 0x80
+        assert_eq!(state(0x00, &[0x00; 10]).unwrap(), AwaitingPayload);
+
+        let mut sync = vec![0x00; 10];
+        sync.push(0x80);
+        assert_eq!(state(0x00, &sync).unwrap(), Complete);
+        assert!(state(0x00, &[0x00; 11]).is_err());
+
+        // Long address (32-bit Thumb)
+        assert_eq!(state(0x9b, &[0x21, 0x6e, 0x02]).unwrap(), AwaitingPayload);
+        assert_eq!(state(0x9b, &[0x21, 0x6e, 0x02, 0x08]).unwrap(), Complete);
+
+        // Short address, with and without a continuation byte
+        assert_eq!(state(0x96, &[0x10]).unwrap(), Complete);
+        assert_eq!(state(0x96, &[0x90]).unwrap(), AwaitingPayload);
+        assert_eq!(state(0x96, &[0x90, 0x01]).unwrap(), Complete);
+
+        // Exception, with a second byte for larger exception numbers
+        assert_eq!(state(0x06, &[0x16]).unwrap(), Complete);
+        assert_eq!(state(0x06, &[0xb0]).unwrap(), AwaitingPayload);
+        assert_eq!(state(0x06, &[0xb0, 0x01]).unwrap(), Complete);
+
+        // Trace info truncated after a PLCTL that calls for a field
+        assert_eq!(state(0x01, &[]).unwrap(), AwaitingPayload);
+        assert_eq!(state(0x01, &[0x01]).unwrap(), AwaitingPayload);
+        assert_eq!(state(0x01, &[0x01, 0x00]).unwrap(), Complete);
+
+        // Reserved headers
+        assert!(state(0x40, &[]).is_err());
+        assert!(state(0x00, &[0x07]).is_err());
+    }
+
+    #[test]
+    fn test_etmv4_payload_decode() {
+        let mut history = [0u32; 3];
+
+        let mut addr = |hdr, payload: &[u8]| match etmv4_payload_decode(
+            hdr,
+            payload,
+            &mut history,
+        ) {
+            ETM4Payload::Address { addr } => addr,
+            payload => panic!("unexpected payload {:?}", payload),
+        };
+
+        // A long address, followed by a short address that replaces only
+        // its low bits, followed by exact matches against the history
+        assert_eq!(addr(0x9b, &[0x21, 0x6e, 0x02, 0x08]), 0x0802_6e42);
+        assert_eq!(addr(0x96, &[0x10]), 0x0802_6e20);
+        assert_eq!(addr(0x90, &[]), 0x0802_6e20);
+        assert_eq!(addr(0x92, &[]), 0x0802_6e42);
+
+        let mut history = [0u32; 3];
+        let mut decode = |hdr, payload: &[u8]| {
+            etmv4_payload_decode(hdr, payload, &mut history)
+        };
+
+        assert!(matches!(
+            decode(0x06, &[0x16]),
+            ETM4Payload::Exception { exception: ETM3Exception::SVC }
+        ));
+
+        assert!(matches!(
+            decode(0x06, &[0xb0, 0x01]),
+            ETM4Payload::Exception {
+                exception: ETM3Exception::IRQ { irq: 40 }
+            }
+        ));
+
+        assert!(matches!(
+            decode(0x02, &[0x81, 0x01]),
+            ETM4Payload::Timestamp { timestamp: 0x81 }
+        ));
+
+        assert!(matches!(decode(0x00, &[0x05]), ETM4Payload::Overflow));
+        assert!(matches!(decode(0x00, &[0x03]), ETM4Payload::Discard));
+
+        assert!(matches!(
+            decode(0xd9, &[]),
+            ETM4Payload::Atoms { atoms } if atoms == [true, false]
+        ));
+    }
+}
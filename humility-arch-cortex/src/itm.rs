@@ -12,6 +12,7 @@ use anyhow::Result;
 use bitfield::bitfield;
 use humility::core::Core;
 use humility::hubris::HubrisArchive;
+use std::time::{Duration, Instant};
 
 //
 // ITM Trace Enable Register
@@ -45,8 +46,8 @@ register!(ITM_TCR, 0xe000_0e80,
     impl Debug;
     pub itm_busy, _: 23;
     pub traceid, set_traceid: 22, 16;
-    pub timestamp_prescaler, _: 9, 8;
-    pub swo_enable, _: 4;
+    pub timestamp_prescaler, set_timestamp_prescaler: 9, 8;
+    pub swo_enable, set_swo_enable: 4;
     pub dwt_enable, set_dwt_enable: 3;
     pub sync_enable, set_sync_enable: 2;
     pub timestamp_enable, set_timestamp_enable: 1;
@@ -93,6 +94,13 @@ impl ITM_LAR {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ITMExceptionFunction {
+    Entered,
+    Exited,
+    Returned,
+}
+
 #[derive(Debug)]
 pub enum ITMPayload {
     None,
@@ -114,12 +122,21 @@ pub enum ITMPayload {
         port: u32,
         payload: Vec<u8>,
     },
-    #[allow(dead_code)]
     Hardware {
         source: u32,
         payload: [u8; 4],
         len: usize,
     },
+    EventCounter {
+        wrapped: u8,
+    },
+    ExceptionTrace {
+        exception: u16,
+        function: ITMExceptionFunction,
+    },
+    PCSample {
+        pc: Option<u32>,
+    },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -294,6 +311,12 @@ fn itm_payload_decode(hdr: ITMHeader, payload: &[u8]) -> ITMPayload {
             payload: payload.to_vec(),
         },
 
+        ITMHeader::LocalTimestamp2 { ts } => ITMPayload::LocalTimestamp {
+            delayed: false,
+            early: false,
+            timedelta: ts as u32,
+        },
+
         ITMHeader::LocalTimestamp1 { tc } => {
             let mut delta: u32 = 0;
 
@@ -308,15 +331,58 @@ fn itm_payload_decode(hdr: ITMHeader, payload: &[u8]) -> ITMPayload {
             }
         }
 
+        ITMHeader::Hardware { a, .. } => {
+            let value = payload
+                .iter()
+                .rev()
+                .fold(0, |val, byte| (val << 8) | (*byte as u32));
+
+            //
+            // Decode the DWT packets that we know about:  event counter
+            // wrapping (source 0), exception trace (source 1) and periodic
+            // PC sampling (source 2) -- where a one-byte PC sample denotes
+            // that the core is sleeping.
+            //
+            let function = match (value >> 12) & 0b11 {
+                0b01 => Some(ITMExceptionFunction::Entered),
+                0b10 => Some(ITMExceptionFunction::Exited),
+                0b11 => Some(ITMExceptionFunction::Returned),
+                _ => None,
+            };
+
+            match (a, payload.len(), function) {
+                (0, 1, _) => ITMPayload::EventCounter { wrapped: payload[0] },
+                (1, 2, Some(function)) => ITMPayload::ExceptionTrace {
+                    exception: (value & 0x1ff) as u16,
+                    function,
+                },
+                (2, 1, _) => ITMPayload::PCSample { pc: None },
+                (2, 4, _) => ITMPayload::PCSample { pc: Some(value) },
+                _ => {
+                    let mut buf = [0; 4];
+                    buf[..payload.len()].copy_from_slice(payload);
+
+                    ITMPayload::Hardware {
+                        source: a as u32,
+                        payload: buf,
+                        len: payload.len(),
+                    }
+                }
+            }
+        }
+
         _ => ITMPayload::None,
     }
 }
 
-pub fn itm_ingest(
-    traceid: Option<u8>,
-    mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
-    mut callback: impl FnMut(&ITMPacket) -> Result<()>,
-) -> Result<()> {
+///
+/// Returns a function that decodes a stream of (deframed) TPIU packets as
+/// ITM packets, handing each to the callback.  This allows ITM trace to be
+/// decoded from a stream that also contains trace from other sources.
+///
+pub fn itm_decoder<'a>(
+    mut callback: impl FnMut(&ITMPacket) -> Result<()> + 'a,
+) -> impl FnMut(&TPIUPacket) -> Result<()> + 'a {
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
     enum IngestState {
         SyncSearching,
@@ -327,11 +393,11 @@ pub fn itm_ingest(
     let mut pstate: ITMPacketState = ITMPacketState::AwaitingHeader;
     let mut vec = Vec::with_capacity(16);
 
-    let hdrs = &itm_hdrs();
+    let hdrs = itm_hdrs();
     let mut hdr = ITMHeader::Sync;
     let mut runlen = 0;
 
-    let process = |packet: &TPIUPacket| -> Result<()> {
+    move |packet: &TPIUPacket| -> Result<()> {
         let payload = &mut vec;
 
         if state == IngestState::SyncSearching {
@@ -413,7 +479,15 @@ pub fn itm_ingest(
         pstate = ITMPacketState::AwaitingHeader;
 
         Ok(())
-    };
+    }
+}
+
+pub fn itm_ingest(
+    traceid: Option<u8>,
+    mut readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+    callback: impl FnMut(&ITMPacket) -> Result<()>,
+) -> Result<()> {
+    let process = itm_decoder(callback);
 
     match traceid {
         Some(traceid) => {
@@ -426,13 +500,13 @@ pub fn itm_ingest(
 }
 
 ///
-/// Enables ITM with an explict clockscaler and traceid.
-pub fn itm_enable_explicit(
+/// Enables TRCENA and configures the trace port (either the SWO or the
+/// TPIU, as the part dictates) with an explicit clockscaler.  This is
+/// required for any trace source (ITM or ETM) to be output.
+pub fn trace_port_enable(
     core: &mut dyn Core,
     coreinfo: &CoreInfo,
     clockscaler: u16,
-    traceid: u8,
-    stimuli: u32,
 ) -> Result<()> {
     //
     // First, enable TRCENA in the DEMCR.
@@ -518,6 +592,20 @@ pub fn itm_enable_explicit(
         log::trace!("{:#x?}", TPIU_ACPR::read(core)?);
     }
 
+    Ok(())
+}
+
+///
+/// Enables ITM with an explict clockscaler and traceid.
+pub fn itm_enable_explicit(
+    core: &mut dyn Core,
+    coreinfo: &CoreInfo,
+    clockscaler: u16,
+    traceid: u8,
+    stimuli: u32,
+) -> Result<()> {
+    trace_port_enable(core, coreinfo, clockscaler)?;
+
     //
     // Unlock the ITM.
     //
@@ -573,6 +661,59 @@ pub fn itm_enable_explicit(
     Ok(())
 }
 
+///
+/// Enables DWT hardware packets on an enabled ITM:  periodic PC sampling
+/// every specified number of cycles (rounded to what the DWT can
+/// represent), and/or exception trace.
+pub fn itm_enable_dwt(
+    core: &mut dyn Core,
+    sample: Option<u32>,
+    exceptions: bool,
+) -> Result<()> {
+    let mut dwt = DWT_CTRL::read(core)?;
+
+    if let Some(cycles) = sample {
+        //
+        // The PC sample period is the POSTCNT reload value (4 bits) plus
+        // one, times either 64 or 1024 cycles (depending on CYCTAP).
+        //
+        let (tap, shift) =
+            if cycles <= 16 << 6 { (false, 6) } else { (true, 10) };
+        let reset = ((cycles >> shift).max(1) - 1).min(0xf);
+
+        dwt.set_postcnt_tap(tap);
+        dwt.set_postcnt_reset(reset);
+        dwt.set_postcnt_init(reset);
+        dwt.set_cyccnt_enabled(true);
+        dwt.set_pc_sampling_enabled(true);
+    }
+
+    dwt.set_exception_trace_enabled(exceptions);
+    dwt.write(core)?;
+
+    //
+    // Finally, enable the forwarding of DWT packets to the ITM.
+    //
+    let mut tcr = ITM_TCR::read(core)?;
+    tcr.set_dwt_enable(sample.is_some() || exceptions);
+    tcr.write(core)?;
+
+    Ok(())
+}
+
+///
+/// Enables local timestamps on an enabled ITM.  The timestamp counter is
+/// clocked (without prescaling) by the processor clock.
+pub fn itm_enable_timestamps(core: &mut dyn Core) -> Result<()> {
+    let mut tcr = ITM_TCR::read(core)?;
+    tcr.set_timestamp_prescaler(0);
+    tcr.set_swo_enable(false);
+    tcr.set_timestamp_enable(true);
+    tcr.write(core)?;
+
+    Ok(())
+}
+
 ///
 /// Enables ITM by pulling clock scaler values from the specified Hubris
 /// archive.
@@ -603,3 +744,157 @@ pub fn itm_enable_ingest(
         Some(traceid)
     })
 }
+
+//
+// The CPU frequencies (in MHz) of common parts, from which we derive the
+// clock scalers that we try when detecting the clock scaler.
+//
+const SWOSCALER_CANDIDATE_MHZ: &[u64] = &[
+    16, 48, 64, 72, 80, 84, 96, 100, 120, 144, 160, 168, 180, 200, 216, 240,
+    280, 400, 480, 550,
+];
+
+//
+// How long we capture SWV data for each candidate clock scaler.  The DWT
+// emits a synchronization packet every 8M cycles, so this is long enough to
+// see at least one even for the slowest of our candidate frequencies.
+//
+const SWOSCALER_CAPTURE: Duration = Duration::from_millis(600);
+
+//
+// Counts the ITM synchronization packets (at least five zero bytes followed
+// by 0x80) in the specified ITM data.
+//
+fn itm_sync_count(bytes: &[u8]) -> usize {
+    let mut count = 0;
+    let mut zeros = 0;
+
+    for &b in bytes {
+        match b {
+            0 => zeros += 1,
+            0x80 if zeros >= 5 => {
+                count += 1;
+                zeros = 0;
+            }
+            _ => zeros = 0,
+        }
+    }
+
+    count
+}
+
+//
+// Quietly extracts the data for the specified trace ID from TPIU-formatted
+// data, assuming that frames begin at the specified offset.  (We can't use
+// [`tpiu_ingest`] here, as it is rightfully vocal about the garbage that we
+// expect to see with most candidate clock scalers.)
+//
+fn tpiu_deframe(bytes: &[u8], offset: usize, traceid: u8) -> Vec<u8> {
+    let mut rval = vec![];
+    let mut id = None;
+
+    for frame in bytes.get(offset..).unwrap_or(&[]).chunks_exact(16) {
+        let aux = frame[15];
+
+        for i in 0..8 {
+            let b = frame[i * 2];
+            let bit = (aux >> i) & 1;
+            let next = if i < 7 { Some(frame[i * 2 + 1]) } else { None };
+
+            //
+            // An even byte with its low bit set is an ID change; the
+            // corresponding auxiliary bit indicates whether the change takes
+            // effect after the byte that follows it (rather than before).
+            //
+            let (before, after) = if b & 1 != 0 {
+                let old = id;
+                id = Some(b >> 1);
+
+                if bit != 0 {
+                    (old, None)
+                } else {
+                    (None, id)
+                }
+            } else {
+                if id == Some(traceid) {
+                    rval.push((b & !1) | bit);
+                }
+
+                (None, id)
+            };
+
+            if let Some(next) = next {
+                if before.or(after) == Some(traceid) {
+                    rval.push(next);
+                }
+            }
+        }
+    }
+
+    rval
+}
+
+///
+/// Determines the clock scaler by sweeping a set of candidate values:  for
+/// each, ITM is enabled with the candidate scaler and SWV data is briefly
+/// captured with the target running.  The candidate that yields the most
+/// ITM synchronization packets is selected; if no candidate yields any,
+/// `None` is returned.  The target is left halted.
+pub fn itm_detect_swoscaler(
+    core: &mut dyn Core,
+    coreinfo: &CoreInfo,
+    traceid: u8,
+    stimuli: u32,
+) -> Result<Option<u16>> {
+    let bypass = coreinfo.address(CoreSightComponent::SWO).is_some();
+    let mut best: Option<(u16, usize)> = None;
+
+    for mhz in SWOSCALER_CANDIDATE_MHZ {
+        //
+        // As with the scaler derived from the archive, we assume a 2 MHz
+        // SWO clock.
+        //
+        let scaler = (mhz / 2 - 1) as u16;
+
+        core.halt()?;
+        itm_enable_explicit(core, coreinfo, scaler, traceid, stimuli)?;
+
+        //
+        // Discard anything that was captured with the previous candidate.
+        //
+        core.read_swv()?;
+        core.run()?;
+
+        let start = Instant::now();
+        let mut bytes = vec![];
+
+        while start.elapsed() < SWOSCALER_CAPTURE {
+            bytes.extend(core.read_swv()?);
+        }
+
+        core.halt()?;
+
+        let count = if bypass {
+            itm_sync_count(&bytes)
+        } else {
+            (0..16)
+                .map(|o| itm_sync_count(&tpiu_deframe(&bytes, o, traceid)))
+                .max()
+                .unwrap_or(0)
+        };
+
+        log::debug!(
+            "scaler {} ({} MHz): {} bytes, {} sync packets",
+            scaler,
+            mhz,
+            bytes.len(),
+            count
+        );
+
+        if count > best.map_or(0, |(_, c)| c) {
+            best = Some((scaler, count));
+        }
+    }
+
+    Ok(best.map(|(scaler, _)| scaler))
+}
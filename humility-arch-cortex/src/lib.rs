@@ -5,6 +5,7 @@
 pub mod debug;
 pub mod dwt;
 pub mod etm;
+pub mod etmv4;
 pub mod itm;
 pub mod scs;
 pub mod swo;
@@ -7,6 +7,7 @@ use crate::register;
 use anyhow::Result;
 use bitfield::bitfield;
 use humility::core::Core;
+use std::collections::BTreeMap;
 
 register!(TPIU_SSPSR, 0xe004_0000,
     #[derive(Copy, Clone)]
@@ -147,6 +148,7 @@ enum TPIUState {
 
 const TPIU_FRAME_SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
 const TPIU_ID_NULL: u8 = 0;
+const TPIU_ID_RESERVED: u8 = 0x70;
 
 fn tpiu_next_state(state: TPIUState, byte: u8, offset: usize) -> TPIUState {
     let sync = &TPIU_FRAME_SYNC;
@@ -560,3 +562,32 @@ pub fn tpiu_ingest(
 
     Ok(())
 }
+
+///
+/// Ingests formatted TPIU data without knowledge of the trace sources that
+/// it contains, returning the number of bytes found for each trace ID.
+/// Because any (non-reserved) ID is considered valid, this is more likely
+/// than a filtered ingest to accept a bogus frame while searching for
+/// synchronization -- but any such frame will show up as a small number of
+/// bytes for an unexpected ID, and the actual sources will dominate.
+///
+pub fn tpiu_detect_ids(
+    readnext: impl FnMut() -> Result<Option<(u8, f64)>>,
+) -> Result<BTreeMap<u8, usize>> {
+    let mut valid = vec![false; 256];
+    let mut ids = BTreeMap::new();
+
+    for id in TPIU_ID_NULL + 1..TPIU_ID_RESERVED {
+        valid[id as usize] = true;
+    }
+
+    tpiu_ingest(&valid, readnext, |packet| {
+        if let Some(id) = packet.id {
+            *ids.entry(id).or_insert(0) += 1;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(ids)
+}
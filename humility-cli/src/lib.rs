@@ -36,8 +36,8 @@ pub struct Cli {
     pub version: bool,
 
     /// If a system has multiple debug probes attached, the specific probe to
-    /// use.  If specifying a USB probe, its index can be used (e.g.,
-    /// "usb-0"); if specifying an exact probe, this is of the form
+    /// use.  If specifying a USB probe, its index or serial number can be
+    /// used (e.g., "usb-0"); if specifying an exact probe, this is of the form
     /// "vid:pid[:serial]". If set to "archive", the archive is used rather
     /// than any attached debug probe. This may also be set via the
     /// HUMILITY_PROBE environment variable. Run "humility doc" for more
@@ -57,6 +57,11 @@ pub struct Cli {
     #[clap(long, short, group = "hubris")]
     pub dump: Option<String>,
 
+    /// Use the archive specified with --archive with a dump, even if the
+    /// dump was taken with a different archive.
+    #[clap(long, requires_all = &["archive", "dump"])]
+    pub force: bool,
+
     /// IP address of remote Hubris instance. This may also be set via the
     /// HUMILITY_IP environment variable. Run "humility doc" for more
     /// information on running Humility over a network.
@@ -267,21 +272,19 @@ impl ExecutionContext {
         }
 
         //
-        // Check to see if we have both a dump and an archive.  Because these
-        // conflict with one another but because we allow both of them to be
-        // set with an environment variable, we need to manually resolve this:
-        // we want to allow an explicitly set value (that is, via the command
-        // line) to win the conflict.
+        // Check to see if we have both a dump and an archive.  If both are
+        // specified on the command line, the archive is used with the dump
+        // (once it has been checked against the dump).  But because we allow
+        // both of them to be set with an environment variable, we need to
+        // otherwise resolve this:  we want to allow an explicitly set value
+        // (that is, via the command line) to win the conflict.
         //
         if cli.dump.is_some() && cli.archive.is_some() {
             match (
                 m.occurrences_of("dump") == 1,
                 m.occurrences_of("archive") == 1,
             ) {
-                (true, true) => {
-                    msg!("cannot specify both a dump and an archive");
-                    std::process::exit(1);
-                }
+                (true, true) => {}
 
                 (false, false) => {
                     msg!(
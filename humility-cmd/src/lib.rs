@@ -4,9 +4,11 @@
 
 use anyhow::{bail, Result};
 use clap::Command as ClapCommand;
+use colored::{ColoredString, Colorize};
 use humility::core::Core;
 use humility::hubris::*;
 use humility_cli::Cli;
+use std::ops::Range;
 use std::time::Duration;
 
 #[allow(dead_code)]
@@ -194,6 +196,9 @@ pub struct Dumper {
 
     /// Print the ASCII translation of characters in the right margin
     pub ascii: bool,
+
+    /// Interpret words as big-endian rather than little-endian
+    pub big_endian: bool,
 }
 
 impl Dumper {
@@ -206,16 +211,127 @@ impl Dumper {
             hanging: false,
             header: true,
             ascii: true,
+            big_endian: false,
         }
     }
 
     pub fn dump(&self, bytes: &[u8], addr: u32) {
+        self.dump_diff(bytes, None, addr);
+    }
+
+    ///
+    /// Like [`Dumper::dump`], but highlights any word that differs from
+    /// the corresponding word in `previous` (which, if present, must be the
+    /// same length as `bytes`).
+    ///
+    pub fn dump_diff(&self, bytes: &[u8], previous: Option<&[u8]>, addr: u32) {
+        self.dump_marked(bytes, previous, &[], addr, None, None);
+    }
+
+    ///
+    /// Like [`Dumper::dump`], but displays any word that overlaps one of the
+    /// `unreadable` ranges (as offsets into `bytes`, as returned by
+    /// [`humility::core::Core::try_read_8`]) as `??` rather than its value.
+    ///
+    pub fn dump_partial(
+        &self,
+        bytes: &[u8],
+        unreadable: &[Range<usize>],
+        addr: u32,
+    ) {
+        self.dump_marked(bytes, None, unreadable, addr, None, None);
+    }
+
+    ///
+    /// Like [`Dumper::dump_partial`], but additionally calls `annotate` with
+    /// the address and length of the memory displayed on each line, and
+    /// displays any annotation that it returns in the right margin.
+    ///
+    pub fn dump_annotated(
+        &self,
+        bytes: &[u8],
+        unreadable: &[Range<usize>],
+        addr: u32,
+        annotate: &dyn Fn(u32, usize) -> Option<String>,
+    ) {
+        self.dump_marked(bytes, None, unreadable, addr, Some(annotate), None);
+    }
+
+    ///
+    /// Like [`Dumper::dump_annotated`] (with an optional `annotate`), but
+    /// colors the output:  zeroes are dimmed, printable ASCII is highlighted,
+    /// and any aligned word for which `pointer` returns true is marked as a
+    /// likely pointer.
+    ///
+    pub fn dump_colored(
+        &self,
+        bytes: &[u8],
+        unreadable: &[Range<usize>],
+        addr: u32,
+        annotate: Option<&dyn Fn(u32, usize) -> Option<String>>,
+        pointer: &dyn Fn(u32) -> bool,
+    ) {
+        self.dump_marked(
+            bytes,
+            None,
+            unreadable,
+            addr,
+            annotate,
+            Some(pointer),
+        );
+    }
+
+    fn dump_marked(
+        &self,
+        bytes: &[u8],
+        previous: Option<&[u8]>,
+        unreadable: &[Range<usize>],
+        addr: u32,
+        annotate: Option<&dyn Fn(u32, usize) -> Option<String>>,
+        pointer: Option<&dyn Fn(u32) -> bool>,
+    ) {
         let size = self.size;
         let width = self.width;
         let mut addr = addr;
         let mut indent = if self.hanging { 0 } else { self.indent };
 
-        let print = |line: &[u8], addr, offs, indent| {
+        let is_unreadable = |start: usize, len: usize| {
+            unreadable.iter().any(|r| r.start < start + len && start < r.end)
+        };
+
+        let print = |line: &[u8],
+                     prev: Option<&[u8]>,
+                     start: usize,
+                     addr: u32,
+                     offs: usize,
+                     indent| {
+            //
+            // Determines if the aligned word containing the specified index
+            // into the line looks like a pointer.
+            //
+            let is_pointer = |i: usize| {
+                let Some(pointer) = pointer else {
+                    return false;
+                };
+
+                let w = i & !3;
+
+                if w < offs
+                    || w - offs + 4 > line.len()
+                    || is_unreadable(start + w - offs, 4)
+                {
+                    return false;
+                }
+
+                let word = line[w - offs..w - offs + 4].try_into().unwrap();
+
+                pointer(if self.big_endian {
+                    u32::from_be_bytes(word)
+                } else {
+                    u32::from_le_bytes(word)
+                })
+            };
+
             print!(
                 "{:indent$}0x{:0width$x} | ",
                 "",
@@ -232,20 +348,43 @@ impl Dumper {
 
                 let slice = &line[i - offs..i - offs + size];
 
-                print!(
-                    "{:0width$x} ",
-                    match size {
-                        1 => u32::from(line[i - offs]),
-                        2 => u32::from(u16::from_le_bytes(
+                if is_unreadable(start + i - offs, size) {
+                    print!("{} ", "?".repeat(size * 2));
+                    continue;
+                }
+
+                let val = format!(
+                    "{:0width$x}",
+                    match (size, self.big_endian) {
+                        (1, _) => u32::from(line[i - offs]),
+                        (2, false) => u32::from(u16::from_le_bytes(
+                            slice.try_into().unwrap()
+                        )),
+                        (2, true) => u32::from(u16::from_be_bytes(
                             slice.try_into().unwrap()
                         )),
-                        4 => u32::from_le_bytes(slice.try_into().unwrap()),
+                        (4, false) => {
+                            u32::from_le_bytes(slice.try_into().unwrap())
+                        }
+                        (4, true) => {
+                            u32::from_be_bytes(slice.try_into().unwrap())
+                        }
                         _ => {
                             panic!("invalid size");
                         }
                     },
                     width = size * 2
                 );
+
+                match prev {
+                    Some(prev) if prev[i - offs..i - offs + size] != *slice => {
+                        print!("{} ", val.reversed())
+                    }
+                    _ if pointer.is_some() => {
+                        print!("{} ", colorize(&val, slice, is_pointer(i)))
+                    }
+                    _ => print!("{} ", val),
+                }
             }
 
             if self.ascii {
@@ -254,18 +393,33 @@ impl Dumper {
                 for i in 0..width {
                     if i < offs || i - offs >= line.len() {
                         print!(" ");
+                    } else if is_unreadable(start + i - offs, 1) {
+                        print!("?");
                     } else {
-                        let c = line[i - offs] as char;
+                        let b = line[i - offs];
+                        let c = b as char;
 
-                        if c.is_ascii() && !c.is_ascii_control() {
-                            print!("{}", c);
+                        let c = if c.is_ascii() && !c.is_ascii_control() {
+                            c.to_string()
                         } else {
-                            print!(".");
+                            ".".to_string()
+                        };
+
+                        if pointer.is_some() {
+                            print!("{}", colorize(&c, &[b], is_pointer(i)));
+                        } else {
+                            print!("{}", c);
                         }
                     }
                 }
             }
 
+            if let Some(note) =
+                annotate.and_then(|f| f(addr + offs as u32, line.len()))
+            {
+                print!(" <- {}", note);
+            }
+
             println!();
         };
 
@@ -294,20 +448,46 @@ impl Dumper {
         // Print our first line.
         //
         let lim = std::cmp::min(width - offs, bytes.len());
-        print(&bytes[0..lim], addr, offs, indent);
+        let previous = previous.filter(|p| p.len() == bytes.len());
+        print(
+            &bytes[0..lim],
+            previous.map(|p| &p[0..lim]),
+            0,
+            addr,
+            offs,
+            indent,
+        );
         indent = self.indent;
 
         if lim < bytes.len() {
             let lines = bytes[lim..].chunks(width);
+            let mut prev = previous.map(|p| p[lim..].chunks(width));
 
-            for line in lines {
+            for (n, line) in lines.enumerate() {
                 addr += width as u32;
-                print(line, addr, 0, indent);
+                let p = prev.as_mut().and_then(|p| p.next());
+                print(line, p, lim + n * width, addr, 0, indent);
             }
         }
     }
 }
 
+//
+// Colors the display of the specified bytes:  words that look like pointers
+// are cyan, zeroes are dimmed, and printable ASCII is green.
+//
+fn colorize(s: &str, bytes: &[u8], pointer: bool) -> ColoredString {
+    if pointer {
+        s.cyan()
+    } else if bytes.iter().all(|&b| b == 0) {
+        s.dimmed()
+    } else if bytes.iter().all(|&b| b.is_ascii() && !b.is_ascii_control()) {
+        s.green()
+    } else {
+        s.normal()
+    }
+}
+
 impl Default for Dumper {
     fn default() -> Self {
         Self::new()
@@ -14,10 +14,10 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt;
-use std::fs;
 use std::io::Read;
 use std::io::Write;
 use std::net::TcpStream;
+use std::ops::Range;
 use std::path::Path;
 use std::rc::Rc;
 use std::str;
@@ -27,6 +27,12 @@ use thiserror::Error;
 
 use goblin::elf::Elf;
 
+//
+// The largest register selector of the core register file, as read by
+// Core::read_reg_all().  Selectors beyond this are floating point registers.
+//
+pub const CORE_REG_MAX: u16 = 31;
+
 pub trait Core {
     fn info(&self) -> (String, Option<String>);
 
@@ -35,7 +41,64 @@ pub trait Core {
     }
 
     fn read_8(&mut self, addr: u32, data: &mut [u8]) -> Result<()>;
+
+    /// Like [`Core::read_8`], but reads as much of the specified memory as
+    /// can be read rather than failing outright, returning the ranges (as
+    /// offsets into `data`) that could not be read; these are zero-filled.
+    /// An error is returned only if none of the memory could be read.  By
+    /// default, if reading the memory in its entirety fails, each word is
+    /// read individually.
+    fn try_read_8(
+        &mut self,
+        addr: u32,
+        data: &mut [u8],
+    ) -> Result<Vec<Range<usize>>> {
+        let err = match self.read_8(addr, data) {
+            Ok(()) => return Ok(vec![]),
+            Err(err) => err,
+        };
+
+        let mut failed: Vec<Range<usize>> = vec![];
+        let mut offs = 0;
+
+        while offs < data.len() {
+            let a = addr.wrapping_add(offs as u32);
+            let len = std::cmp::min(4 - (a & 3) as usize, data.len() - offs);
+            let word = &mut data[offs..offs + len];
+
+            if self.read_8(a, word).is_err() {
+                word.fill(0);
+
+                match failed.last_mut() {
+                    Some(last) if last.end == offs => last.end = offs + len,
+                    _ => failed.push(offs..offs + len),
+                }
+            }
+
+            offs += len;
+        }
+
+        if failed.len() == 1 && failed[0] == (0..data.len()) {
+            return Err(err);
+        }
+
+        Ok(failed)
+    }
+
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32>;
+
+    /// Reads the core register file (that is, every register other than
+    /// the floating point registers).  By default, each register is read in
+    /// turn with [`Core::read_reg`].
+    fn read_reg_all(&mut self) -> Result<BTreeMap<ARMRegister, u32>> {
+        use num_traits::FromPrimitive;
+
+        (0..=CORE_REG_MAX)
+            .filter_map(ARMRegister::from_u16)
+            .map(|reg| Ok((reg, self.read_reg(reg)?)))
+            .collect()
+    }
+
     fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()>;
     fn init_swv(&mut self) -> Result<()>;
     fn read_swv(&mut self) -> Result<Vec<u8>>;
@@ -61,6 +124,10 @@ pub trait Core {
         Ok(())
     }
 
+    fn timeout(&self) -> Result<Option<Duration>> {
+        Ok(None)
+    }
+
     fn read_word_32(&mut self, addr: u32) -> Result<u32> {
         let mut buf = [0; 4];
         self.read_8(addr, &mut buf)?;
@@ -73,6 +140,33 @@ pub trait Core {
         Ok(u64::from_le_bytes(buf))
     }
 
+    /// Reads each of the specified `(address, length)` regions, returning
+    /// their contents in the order given.  Cores for which each read incurs
+    /// substantial overhead may override this to coalesce nearby regions
+    /// into fewer reads.
+    fn read_regions(
+        &mut self,
+        regions: &[(u32, usize)],
+    ) -> Result<Vec<Vec<u8>>> {
+        regions
+            .iter()
+            .map(|&(addr, len)| {
+                let mut buf = vec![0; len];
+                self.read_8(addr, &mut buf)?;
+                Ok(buf)
+            })
+            .collect()
+    }
+
+    /// Writes each of the specified `(address, contents)` regions, in the
+    /// order given.  Cores for which each write incurs substantial overhead
+    /// may override this to coalesce adjacent regions into fewer writes.
+    /// Note that a caller that wants the writes to appear atomic to the
+    /// target should halt it around this call.
+    fn write_regions(&mut self, regions: &[(u32, &[u8])]) -> Result<()> {
+        regions.iter().try_for_each(|&(addr, data)| self.write_8(addr, data))
+    }
+
     ///
     /// Called to load a flash image.
     ///
@@ -111,6 +205,14 @@ pub trait Core {
     fn recv(&self, _buf: &mut [u8], _agent: NetAgent) -> Result<usize> {
         bail!("cannot receive from network");
     }
+
+    /// Enables caching of memory reads made while the target is halted, if
+    /// the core supports it.  Cached contents are discarded whenever the
+    /// target is run, stepped, reset or written to.
+    fn enable_cache(&mut self) {}
+
+    /// Discards any cached memory contents
+    fn invalidate_cache(&mut self) {}
 }
 
 pub struct UnattachedCore {
@@ -223,6 +325,7 @@ pub struct ProbeCore {
     halted: u32,
     unhalted_read: BTreeMap<u32, u32>,
     can_flash: bool,
+    cache: Option<HashMap<(u32, usize), Vec<u8>>>,
 }
 
 impl ProbeCore {
@@ -245,7 +348,88 @@ impl ProbeCore {
             halted: 0,
             unhalted_read: humility_arch_arm::unhalted_read_regions(),
             can_flash,
+            cache: None,
+        }
+    }
+
+    //
+    // Satisfies a read from our cache, if we have one and it contains the
+    // specified region.
+    //
+    fn cache_read(&self, addr: u32, data: &mut [u8]) -> bool {
+        match self.cache.as_ref().and_then(|c| c.get(&(addr, data.len()))) {
+            Some(contents) => {
+                data.copy_from_slice(contents);
+                true
+            }
+            None => false,
+        }
+    }
+
+    //
+    // Records the result of a read in our cache -- but only if the target is
+    // halted, as memory may otherwise change out from under us.
+    //
+    fn cache_insert(&mut self, addr: u32, data: &[u8]) {
+        if self.halted > 0 {
+            if let Some(ref mut cache) = self.cache {
+                cache.insert((addr, data.len()), data.to_vec());
+            }
+        }
+    }
+
+    fn read_word_32_uncached(&mut self, addr: u32) -> Result<u32> {
+        log::trace!("reading word at {:x}", addr);
+        let mut rval = 0;
+
+        if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
+            if addr + 4 < range.0 + range.1 {
+                let mut core = self.session.core(0)?;
+                return core.read_word_32(addr).with_context(|| {
+                    format!(
+                        "failed to perform unhalted word read at address \
+                        {addr:#x}",
+                    )
+                });
+            }
         }
+
+        self.halt_and_read(|core| {
+            rval = core.read_word_32(addr).with_context(|| {
+                format!(
+                    "failed to perform halted word read at address {addr:#x}"
+                )
+            })?;
+
+            Ok(())
+        })?;
+
+        Ok(rval)
+    }
+
+    fn read_8_uncached(&mut self, addr: u32, data: &mut [u8]) -> Result<()> {
+        if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
+            if addr + (data.len() as u32) < range.0 + range.1 {
+                let mut core = self.session.core(0)?;
+                return core.read_8(addr, data).with_context(|| {
+                    format!(
+                        "failed to perform unhalted read at address \
+                        {addr:#x} for length {}",
+                        data.len()
+                    )
+                });
+            }
+        }
+
+        self.halt_and_read(|core| {
+            core.read_8(addr, data).with_context(|| {
+                format!(
+                    "failed to perform halted read at address \
+                    {addr:#x} for length {}",
+                    data.len()
+                )
+            })
+        })
     }
 
     fn halt_and_read(
@@ -277,6 +461,118 @@ impl ProbeCore {
 
 pub const CORE_MAX_READSIZE: usize = 65536; // 64K ought to be enough for anyone
 
+//
+// When coalescing reads, the largest gap between regions that we will read
+// through rather than issue a separate read.
+//
+const CORE_COALESCE_GAP: u64 = 64;
+
+///
+/// Reads the specified `(address, length)` regions by coalescing regions
+/// that are adjacent, overlapping or nearby into single reads of no more
+/// than [`CORE_MAX_READSIZE`].  Returns the contents of each region in the
+/// order given.
+///
+pub fn read_regions_coalesced(
+    core: &mut dyn Core,
+    regions: &[(u32, usize)],
+) -> Result<Vec<Vec<u8>>> {
+    let mut order = (0..regions.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| regions[i].0);
+
+    let mut rval = vec![vec![]; regions.len()];
+    let mut first = 0;
+
+    while first < order.len() {
+        let base = regions[order[first]].0 as u64;
+        let mut end = base + regions[order[first]].1 as u64;
+        let mut last = first + 1;
+
+        while last < order.len() {
+            let (addr, len) = regions[order[last]];
+            let next = end.max(addr as u64 + len as u64);
+
+            if addr as u64 > end + CORE_COALESCE_GAP
+                || next - base > CORE_MAX_READSIZE as u64
+            {
+                break;
+            }
+
+            end = next;
+            last += 1;
+        }
+
+        let mut buf = vec![0; (end - base) as usize];
+        core.read_8(base as u32, &mut buf)?;
+
+        for &i in &order[first..last] {
+            let (addr, len) = regions[i];
+            let offs = (addr as u64 - base) as usize;
+            rval[i] = buf[offs..offs + len].to_vec();
+        }
+
+        first = last;
+    }
+
+    Ok(rval)
+}
+
+///
+/// Writes the specified `(address, contents)` regions by coalescing regions
+/// that are adjacent or overlapping into single writes of no more than
+/// [`CORE_MAX_READSIZE`].  (Unlike reads, we can't coalesce regions that
+/// are merely nearby, as that would write through the gap between them.)
+/// Where regions overlap, a region later in the order given takes
+/// precedence, as it would had each been written in turn.
+///
+pub fn write_regions_coalesced(
+    core: &mut dyn Core,
+    regions: &[(u32, &[u8])],
+) -> Result<()> {
+    let mut order = (0..regions.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| regions[i].0);
+
+    let mut first = 0;
+
+    while first < order.len() {
+        let base = regions[order[first]].0 as u64;
+        let mut end = base + regions[order[first]].1.len() as u64;
+        let mut last = first + 1;
+
+        while last < order.len() {
+            let (addr, data) = regions[order[last]];
+            let next = end.max(addr as u64 + data.len() as u64);
+
+            if addr as u64 > end || next - base > CORE_MAX_READSIZE as u64 {
+                break;
+            }
+
+            end = next;
+            last += 1;
+        }
+
+        //
+        // Apply the regions to our buffer in their original order to give
+        // later regions precedence over any that they overlap.
+        //
+        let mut group = order[first..last].to_vec();
+        group.sort_unstable();
+
+        let mut buf = vec![0; (end - base) as usize];
+
+        for i in group {
+            let (addr, data) = regions[i];
+            let offs = (addr as u64 - base) as usize;
+            buf[offs..offs + data.len()].copy_from_slice(data);
+        }
+
+        core.write_8(base as u32, &buf)?;
+        first = last;
+    }
+
+    Ok(())
+}
+
 #[rustfmt::skip::macros(anyhow, bail)]
 impl Core for ProbeCore {
     fn info(&self) -> (String, Option<String>) {
@@ -293,30 +589,14 @@ impl Core for ProbeCore {
     }
 
     fn read_word_32(&mut self, addr: u32) -> Result<u32> {
-        log::trace!("reading word at {:x}", addr);
-        let mut rval = 0;
+        let mut buf = [0; 4];
 
-        if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
-            if addr + 4 < range.0 + range.1 {
-                let mut core = self.session.core(0)?;
-                return core.read_word_32(addr).with_context(|| {
-                    format!(
-                        "failed to perform unhalted word read at address \
-                        {addr:#x}",
-                    )
-                });
-            }
+        if self.cache_read(addr, &mut buf) {
+            return Ok(u32::from_le_bytes(buf));
         }
 
-        self.halt_and_read(|core| {
-            rval = core.read_word_32(addr).with_context(|| {
-                format!(
-                    "failed to perform halted word read at address {addr:#x}"
-                )
-            })?;
-
-            Ok(())
-        })?;
+        let rval = self.read_word_32_uncached(addr)?;
+        self.cache_insert(addr, &rval.to_le_bytes());
 
         Ok(rval)
     }
@@ -327,28 +607,25 @@ impl Core for ProbeCore {
                 data.len(), addr, CORE_MAX_READSIZE);
         }
 
-        if let Some(range) = self.unhalted_read.range(..=addr).next_back() {
-            if addr + (data.len() as u32) < range.0 + range.1 {
-                let mut core = self.session.core(0)?;
-                return core.read_8(addr, data).with_context(|| {
-                    format!(
-                        "failed to perform unhalted read at address \
-                        {addr:#x} for length {}",
-                        data.len()
-                    )
-                });
-            }
+        if self.cache_read(addr, data) {
+            return Ok(());
         }
 
-        self.halt_and_read(|core| {
-            core.read_8(addr, data).with_context(|| {
-                format!(
-                    "failed to perform halted read at address \
-                    {addr:#x} for length {}",
-                    data.len()
-                )
-            })
-        })
+        self.read_8_uncached(addr, data)?;
+        self.cache_insert(addr, data);
+
+        Ok(())
+    }
+
+    fn read_regions(
+        &mut self,
+        regions: &[(u32, usize)],
+    ) -> Result<Vec<Vec<u8>>> {
+        read_regions_coalesced(self, regions)
+    }
+
+    fn write_regions(&mut self, regions: &[(u32, &[u8])]) -> Result<()> {
+        write_regions_coalesced(self, regions)
     }
 
     fn read_reg(&mut self, reg: ARMRegister) -> Result<u32> {
@@ -360,7 +637,32 @@ impl Core for ProbeCore {
         ))?)
     }
 
+    fn read_reg_all(&mut self) -> Result<BTreeMap<ARMRegister, u32>> {
+        use num_traits::{FromPrimitive, ToPrimitive};
+
+        //
+        // This is a per-register fallback:  probe-rs offers no way to read
+        // several core registers in one transaction, so each register is
+        // still read with its own transaction.  We merely acquire the core
+        // once for the entire register file rather than reacquiring it for
+        // each register (as read_reg must).
+        //
+        let mut core = self.session.core(0)?;
+
+        (0..=CORE_REG_MAX)
+            .filter_map(ARMRegister::from_u16)
+            .map(|reg| {
+                let addr = Into::<probe_rs::CoreRegisterAddress>::into(
+                    ARMRegister::to_u16(&reg).unwrap(),
+                );
+
+                Ok((reg, core.read_core_reg(addr)?))
+            })
+            .collect()
+    }
+
     fn write_reg(&mut self, reg: ARMRegister, value: u32) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         use num_traits::ToPrimitive;
 
@@ -375,12 +677,14 @@ impl Core for ProbeCore {
     }
 
     fn write_word_32(&mut self, addr: u32, data: u32) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         core.write_word_32(addr, data)?;
         Ok(())
     }
 
     fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         core.write_8(addr, data)?;
         Ok(())
@@ -400,6 +704,7 @@ impl Core for ProbeCore {
         self.halted -= 1;
 
         if self.halted == 0 {
+            self.invalidate_cache();
             let mut core = self.session.core(0)?;
             core.run()?;
         }
@@ -408,6 +713,7 @@ impl Core for ProbeCore {
     }
 
     fn step(&mut self) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         core.step()?;
         Ok(())
@@ -433,6 +739,8 @@ impl Core for ProbeCore {
     }
 
     fn load(&mut self, path: &Path) -> Result<()> {
+        self.invalidate_cache();
+
         #[derive(Debug, Default)]
         struct LoadProgress {
             /// total bytes that need to be erased
@@ -522,12 +830,14 @@ impl Core for ProbeCore {
     }
 
     fn reset(&mut self) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         core.reset()?;
         Ok(())
     }
 
     fn reset_and_halt(&mut self, dur: std::time::Duration) -> Result<()> {
+        self.invalidate_cache();
         let mut core = self.session.core(0)?;
         core.reset_and_halt(dur)?;
         Ok(())
@@ -558,6 +868,18 @@ impl Core for ProbeCore {
         self.halted += 1;
         Ok(())
     }
+
+    fn enable_cache(&mut self) {
+        if self.cache.is_none() {
+            self.cache = Some(HashMap::new());
+        }
+    }
+
+    fn invalidate_cache(&mut self) {
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+    }
 }
 
 const OPENOCD_COMMAND_DELIMITER: u8 = 0x1a;
@@ -1205,18 +1527,21 @@ impl Core for GDBCore {
 }
 
 pub struct DumpCore {
-    contents: Vec<u8>,
+    contents: DumpContents,
     regions: BTreeMap<u32, (u32, usize)>,
     registers: HashMap<ARMRegister, u32>,
 }
 
 impl DumpCore {
     fn new(dump: &str, hubris: &HubrisArchive) -> Result<DumpCore> {
-        let mut file = fs::File::open(dump)?;
         let mut regions = BTreeMap::new();
 
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)?;
+        //
+        // Rather than read the entire dump into memory (which can be slow
+        // for a large dump), we map it (if it's uncompressed) and let reads
+        // be satisfied directly out of the mapping.
+        //
+        let contents = dump_contents(dump)?;
 
         let elf = Elf::parse(&contents).map_err(|e| {
             anyhow!("failed to parse {} as an ELF file: {}", dump, e)
@@ -1314,6 +1639,17 @@ impl Core for DumpCore {
         }
     }
 
+    fn read_reg_all(&mut self) -> Result<BTreeMap<ARMRegister, u32>> {
+        use num_traits::ToPrimitive;
+
+        Ok(self
+            .registers
+            .iter()
+            .filter(|(reg, _)| reg.to_u16().unwrap() <= CORE_REG_MAX)
+            .map(|(&reg, &val)| (reg, val))
+            .collect())
+    }
+
     fn write_reg(&mut self, _reg: ARMRegister, _value: u32) -> Result<()> {
         bail!("cannot write register on a dump");
     }
@@ -1468,59 +1804,85 @@ pub enum ProbeError {
     NoProbeFound,
 }
 
-fn parse_probe(probe: &str) -> (&str, Option<usize>) {
-    if probe.contains('-') {
-        let str = probe.to_owned();
-        let pieces: Vec<&str> = str.split('-').collect();
+//
+// A USB probe may be selected by its index (e.g., "usb-0") or by its serial
+// number (e.g., "usb-004000343137510939383538").
+//
+#[derive(Copy, Clone, Debug)]
+enum UsbProbeSelector<'a> {
+    Index(usize),
+    Serial(&'a str),
+}
 
-        if pieces[0] == "usb" && pieces.len() == 2 {
-            if let Ok(val) = pieces[1].parse::<usize>() {
-                ("usb", Some(val))
-            } else {
-                (probe, None)
-            }
-        } else {
-            (probe, None)
-        }
-    } else {
-        (probe, None)
+fn parse_probe(probe: &str) -> (&str, Option<UsbProbeSelector<'_>>) {
+    match probe.strip_prefix("usb-") {
+        Some(suffix) if !suffix.is_empty() => match suffix.parse::<usize>() {
+            Ok(val) => ("usb", Some(UsbProbeSelector::Index(val))),
+            Err(_) => ("usb", Some(UsbProbeSelector::Serial(suffix))),
+        },
+        _ => (probe, None),
     }
 }
 
-fn get_usb_probe(index: Option<usize>) -> Result<probe_rs::DebugProbeInfo> {
+//
+// Finds the specified USB probe, returning it along with its index.
+//
+fn get_usb_probe(
+    selector: Option<UsbProbeSelector>,
+) -> Result<(usize, probe_rs::DebugProbeInfo)> {
     let probes = Probe::list_all();
 
     if probes.is_empty() {
         return Err(ProbeError::NoProbeFound.into());
     }
 
-    if let Some(index) = index {
-        if index < probes.len() {
-            Ok(probes[index].clone())
-        } else {
+    match selector {
+        Some(UsbProbeSelector::Index(index)) => {
+            if index < probes.len() {
+                Ok((index, probes[index].clone()))
+            } else {
+                bail!(
+                    "index ({}) exceeds max probe index ({})",
+                    index,
+                    probes.len() - 1
+                );
+            }
+        }
+        Some(UsbProbeSelector::Serial(serial)) => probes
+            .iter()
+            .position(|p| p.serial_number.as_deref() == Some(serial))
+            .map(|index| (index, probes[index].clone()))
+            .ok_or_else(|| {
+                anyhow!("no USB probe found with serial number {}", serial)
+            }),
+        None if probes.len() == 1 => Ok((0, probes[0].clone())),
+        None => {
             bail!(
-                "index ({}) exceeds max probe index ({})",
-                index,
-                probes.len() - 1
+                "multiple USB probes detected; must explicitly append \
+                index or serial number (e.g., \"-p usb-0\")"
             );
         }
-    } else if probes.len() == 1 {
-        Ok(probes[0].clone())
-    } else {
-        bail!(
-            "multiple USB probes detected; must \
-            explicitly append index (e.g., \"-p usb-0\")"
-        );
+    }
+}
+
+//
+// Describes the USB probe that was selected, for purposes of indicating
+// which probe we attached to when more than one may be present.
+//
+fn describe_usb_probe(index: usize, info: &probe_rs::DebugProbeInfo) -> String {
+    match &info.serial_number {
+        Some(serial) => format!("usb-{index}, serial {serial}"),
+        None => format!("usb-{index}"),
     }
 }
 
 #[rustfmt::skip::macros(anyhow, bail)]
 pub fn attach_to_probe(probe: &str) -> Result<Box<dyn Core>> {
-    let (probe, index) = parse_probe(probe);
+    let (probe, selector) = parse_probe(probe);
 
     match probe {
         "usb" => {
-            let probe_info = get_usb_probe(index)?;
+            let (index, probe_info) = get_usb_probe(selector)?;
 
             let res = probe_info.open();
 
@@ -1537,7 +1899,11 @@ pub fn attach_to_probe(probe: &str) -> Result<Box<dyn Core>> {
 
             let probe = res?;
 
-            crate::msg!("Opened probe {}", probe_info.identifier);
+            crate::msg!(
+                "Opened probe {} ({})",
+                probe_info.identifier,
+                describe_usb_probe(index, &probe_info)
+            );
             Ok(Box::new(UnattachedCore::new(
                 probe,
                 probe_info.identifier.clone(),
@@ -1573,11 +1939,11 @@ pub fn attach_to_chip(
     hubris: &HubrisArchive,
     chip: Option<&str>,
 ) -> Result<Box<dyn Core>> {
-    let (probe, index) = parse_probe(probe);
+    let (probe, selector) = parse_probe(probe);
 
     match probe {
         "usb" => {
-            let probe_info = get_usb_probe(index)?;
+            let (index, probe_info) = get_usb_probe(selector)?;
 
             let res = probe_info.open();
 
@@ -1609,7 +1975,15 @@ pub fn attach_to_chip(
                 None => (probe.attach("armv7m")?, false),
             };
 
-            crate::msg!("attached via {name}");
+            //
+            // If a specific probe was selected, indicate which one.
+            //
+            if selector.is_some() {
+                let desc = describe_usb_probe(index, &probe_info);
+                crate::msg!("attached via {name} ({desc})");
+            } else {
+                crate::msg!("attached via {name}");
+            }
 
             Ok(Box::new(ProbeCore::new(
                 session,
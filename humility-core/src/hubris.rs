@@ -38,6 +38,473 @@ const OXIDE_NT_BASE: u32 = 0x1de << 20;
 const OXIDE_NT_HUBRIS_ARCHIVE: u32 = OXIDE_NT_BASE + 1;
 const OXIDE_NT_HUBRIS_REGISTERS: u32 = OXIDE_NT_BASE + 2;
 const OXIDE_NT_HUBRIS_TASK: u32 = OXIDE_NT_BASE + 3;
+const OXIDE_NT_HUBRIS_CHECKSUMS: u32 = OXIDE_NT_BASE + 4;
+const OXIDE_NT_HUBRIS_IDENTITY: u32 = OXIDE_NT_BASE + 5;
+
+//
+// Returns the number of bytes needed to pad `size` to 4-byte alignment, as
+// required for notes and segments in a dump.
+//
+fn dump_pad(size: u32) -> u32 {
+    (4 - (size & 0b11)) & 0b11
+}
+
+//
+// Returns the size of a note in a dump, including its header and padding.
+//
+fn dump_note_size(note: &goblin::elf::note::Nhdr32) -> u32 {
+    size_of::<goblin::elf::note::Nhdr32>() as u32
+        + note.n_namesz
+        + dump_pad(note.n_namesz)
+        + note.n_descsz
+        + dump_pad(note.n_descsz)
+}
+
+//
+// Returns the header of the checksums note of a dump with the specified
+// number of segments.  The note consists of the base address and checksum
+// of each segment.
+//
+fn dump_checksums_note(nsegments: usize) -> goblin::elf::note::Nhdr32 {
+    goblin::elf::note::Nhdr32 {
+        n_namesz: (OXIDE_NT_NAME.len() + 1) as u32,
+        n_descsz: nsegments as u32 * 8,
+        n_type: OXIDE_NT_HUBRIS_CHECKSUMS,
+    }
+}
+
+//
+// Writes the checksums note of a dump, which follows the contents of its
+// segments so that the checksums can be determined as the segments are
+// written.
+//
+fn write_dump_checksums<W: std::io::Write>(
+    segments: &[(u32, u32)],
+    checksums: &[u32],
+    file: &mut W,
+) -> Result<()> {
+    let pad = [0u8; 4];
+    let note = dump_checksums_note(segments.len());
+
+    let mut bytes = [0x0u8; size_of::<goblin::elf::note::Nhdr32>()];
+    bytes.pwrite_with(&note, 0, scroll::LE)?;
+    file.write_all(&bytes)?;
+
+    file.write_all(OXIDE_NT_NAME.as_bytes())?;
+    let npad = 1 + dump_pad(note.n_namesz) as usize;
+    file.write_all(&pad[0..npad])?;
+
+    for (&(base, _), &crc) in segments.iter().zip(checksums) {
+        file.write_all(&base.to_le_bytes())?;
+        file.write_all(&crc.to_le_bytes())?;
+    }
+
+    let npad = dump_pad(note.n_descsz) as usize;
+    file.write_all(&pad[0..npad])?;
+
+    Ok(())
+}
+
+//
+// Returns the checksum (a CRC-32) of the contents of a dump segment.
+//
+fn dump_checksum(contents: &[u8]) -> u32 {
+    let mut crc = crc_any::CRCu32::crc32();
+    crc.digest(contents);
+    crc.get_crc()
+}
+
+//
+// Returns the registers that we include in a whole-system dump, along with
+// their indices.
+//
+fn dump_registers() -> impl Iterator<Item = (u16, ARMRegister)> {
+    (0..31).filter_map(|i| ARMRegister::from_u16(i).map(|reg| (i, reg)))
+}
+
+//
+// Reads the registers that we include in a whole-system dump from `core`.
+//
+fn dump_register_values(
+    core: &mut dyn crate::core::Core,
+) -> Result<Vec<(u16, u32)>> {
+    register_values(&core.read_reg_all()?, dump_registers())
+}
+
+//
+// Returns the registers that we include in a task dump -- namely, those that
+// can be recovered from the task's saved state -- along with their indices.
+//
+fn task_dump_registers() -> impl Iterator<Item = (u16, ARMRegister)> {
+    dump_registers().filter(|&(i, _)| i <= ARMRegister::PSR as u16)
+}
+
+//
+// Selects the specified registers from `all`, failing if any is missing.
+//
+fn register_values(
+    all: &BTreeMap<ARMRegister, u32>,
+    regs: impl Iterator<Item = (u16, ARMRegister)>,
+) -> Result<Vec<(u16, u32)>> {
+    regs.map(|(i, reg)| match all.get(&reg) {
+        Some(&val) => Ok((i, val)),
+        None => Err(anyhow!("failed to read register {}", reg)),
+    })
+    .collect()
+}
+
+/// The compression of a dump file.  When a dump is written, its compression
+/// is denoted by its extension; when a dump is read, its compression is
+/// instead detected from its contents (see [`dump_contents`]).
+#[derive(clap::ArgEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DumpCompression {
+    Zstd,
+    Gzip,
+}
+
+impl DumpCompression {
+    pub const ALL: [DumpCompression; 2] =
+        [DumpCompression::Zstd, DumpCompression::Gzip];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DumpCompression::Zstd => "zst",
+            DumpCompression::Gzip => "gz",
+        }
+    }
+
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| {
+            Path::new(filename).extension()
+                == Some(std::ffi::OsStr::new(c.extension()))
+        })
+    }
+}
+
+/// The contents of a dump file.  An uncompressed dump is mapped rather
+/// than read into memory (which can be slow for a large dump); a compressed
+/// dump must be decompressed into memory.
+pub enum DumpContents {
+    Mapped(memmap2::Mmap),
+    Decompressed(Vec<u8>),
+}
+
+impl std::ops::Deref for DumpContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            DumpContents::Mapped(contents) => contents,
+            DumpContents::Decompressed(contents) => contents,
+        }
+    }
+}
+
+/// Returns the contents of the specified dump file, decompressing it if it
+/// is compressed.  Compression is determined by the file's contents rather
+/// than its name.
+pub fn dump_contents(dumpfile: &str) -> Result<DumpContents> {
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    let file = fs::File::open(dumpfile)
+        .with_context(|| format!("failed to open {}", dumpfile))?;
+
+    //
+    // Safety: the mapping is only unsound if the underlying file is
+    // modified while we have it mapped; we have no reason to expect that a
+    // dump will be modified as we are examining it.
+    //
+    let contents = unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("failed to map {}", dumpfile))?;
+
+    let mut decompressed = vec![];
+
+    if contents.starts_with(&ZSTD_MAGIC) {
+        zstd::stream::copy_decode(&contents[..], &mut decompressed)
+            .with_context(|| format!("failed to decompress {}", dumpfile))?;
+    } else if contents.starts_with(&GZIP_MAGIC) {
+        flate2::read::GzDecoder::new(&contents[..])
+            .read_to_end(&mut decompressed)
+            .with_context(|| format!("failed to decompress {}", dumpfile))?;
+    } else {
+        return Ok(DumpContents::Mapped(contents));
+    }
+
+    Ok(DumpContents::Decompressed(decompressed))
+}
+
+/// A dump being written incrementally; see [`HubrisArchive::dump_stream`].
+pub struct DumpStream {
+    file: std::fs::File,
+    filename: String,
+    task: Option<DumpTask>,
+    segments: Vec<(u32, u32, u32)>,
+    written: BTreeMap<u32, u32>,
+    end: u32,
+}
+
+impl DumpStream {
+    /// Returns the name of the file to which the dump is being written
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Writes contents at the specified address, which must lie entirely
+    /// within one of the dump's segments.
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let len = data.len() as u32;
+
+        let Some(&(base, _, offset)) =
+            self.segments.iter().find(|&&(base, size, _)| {
+                addr >= base && addr + len <= base + size
+            })
+        else {
+            bail!("{len} bytes at {addr:#x} are not within a dump segment");
+        };
+
+        self.file.seek(SeekFrom::Start((offset + (addr - base)) as u64))?;
+        self.file.write_all(data)?;
+        self.written.insert(addr, len);
+
+        Ok(())
+    }
+
+    /// Finishes the dump, reading any contents that have not been written
+    /// (and the registers, for a whole-system dump) from `core`.
+    pub fn finish(
+        mut self,
+        hubris: &HubrisArchive,
+        core: &mut dyn crate::core::Core,
+        started: Option<Instant>,
+    ) -> Result<()> {
+        use indicatif::{HumanBytes, HumanDuration};
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let started = started.unwrap_or_else(Instant::now);
+        let mut total = 0;
+        let mut bytes = vec![0; 1024];
+
+        for &(base, size, offset) in &self.segments {
+            let mut addr = base;
+            total += size as usize;
+
+            //
+            // Fill in any gaps in what has been written, e.g. for segments
+            // that are not written by the dumper at all.
+            //
+            while addr < base + size {
+                let next = match self.written.range(..=addr).next_back() {
+                    Some((&waddr, &wlen)) if waddr + wlen > addr => {
+                        addr = waddr + wlen;
+                        continue;
+                    }
+                    _ => match self.written.range(addr..).next() {
+                        Some((&waddr, _)) => waddr.min(base + size),
+                        None => base + size,
+                    },
+                };
+
+                self.file
+                    .seek(SeekFrom::Start((offset + (addr - base)) as u64))?;
+
+                while addr < next {
+                    let nbytes =
+                        std::cmp::min((next - addr) as usize, bytes.len());
+                    core.read_8(addr, &mut bytes[0..nbytes])?;
+                    self.file.write_all(&bytes[0..nbytes])?;
+                    addr += nbytes as u32;
+                }
+            }
+        }
+
+        //
+        // With our contents complete, read each segment back to determine
+        // its checksum.
+        //
+        let mut checksums = vec![];
+
+        for &(_, size, offset) in &self.segments {
+            let mut crc = crc_any::CRCu32::crc32();
+            let mut remaining = size as usize;
+
+            self.file.seek(SeekFrom::Start(offset as u64))?;
+
+            while remaining > 0 {
+                let nbytes = std::cmp::min(remaining, bytes.len());
+                self.file.read_exact(&mut bytes[0..nbytes])?;
+                crc.digest(&bytes[0..nbytes]);
+                remaining -= nbytes;
+            }
+
+            checksums.push(crc.get_crc());
+        }
+
+        let regs = match self.task {
+            Some(_) => vec![],
+            None => dump_register_values(core)?,
+        };
+
+        let segments = self
+            .segments
+            .iter()
+            .map(|&(base, size, _)| (base, size))
+            .collect::<Vec<_>>();
+
+        self.file.seek(SeekFrom::Start(self.end as u64))?;
+        write_dump_checksums(&segments, &checksums, &mut self.file)?;
+
+        self.file.seek(SeekFrom::Start(0))?;
+        hubris.write_dump_header(
+            self.task,
+            &regs,
+            &segments,
+            &mut self.file,
+        )?;
+
+        msg!(
+            "dumped {} in {}",
+            HumanBytes(total as u64),
+            HumanDuration(started.elapsed())
+        );
+
+        Ok(())
+    }
+}
+
+/// The identity of an archive, as recorded in each dump taken with it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpIdentity {
+    pub gitrev: Option<String>,
+    pub imageid: Option<Vec<u8>>,
+}
+
+impl fmt::Display for DumpIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.gitrev {
+            Some(gitrev) => write!(f, "git rev {}", gitrev.trim())?,
+            None => write!(f, "unknown git rev")?,
+        }
+
+        match &self.imageid {
+            Some(imageid) => write!(f, ", image ID {:x?}", imageid),
+            None => write!(f, ", unknown image ID"),
+        }
+    }
+}
+
+/// Reads the identity of the archive with which the specified dump was
+/// taken, returning `None` if the dump doesn't record it (as is the case for
+/// dumps taken by older versions of Humility).
+pub fn dump_identity(dumpfile: &str) -> Result<Option<DumpIdentity>> {
+    let contents = dump_contents(dumpfile)?;
+    let elf = Elf::parse(&contents).map_err(|e| {
+        anyhow!("failed to parse {} as an ELF file: {}", dumpfile, e)
+    })?;
+
+    if let Some(notes) = elf.iter_note_headers(&contents) {
+        for note in notes {
+            let note = note.map_err(|e| anyhow!("bad note: {}", e))?;
+
+            if note.name == OXIDE_NT_NAME
+                && note.n_type == OXIDE_NT_HUBRIS_IDENTITY
+            {
+                let identity = serde_json::from_slice(note.desc)
+                    .context("failed to parse identity note")?;
+                return Ok(Some(identity));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// The checksum recorded for a segment of a dump, along with the checksum
+/// of the segment's contents as found in the dump
+#[derive(Debug)]
+pub struct DumpSegmentChecksum {
+    pub base: u32,
+    pub size: u32,
+    pub recorded: u32,
+    pub computed: u32,
+}
+
+impl DumpSegmentChecksum {
+    pub fn is_corrupt(&self) -> bool {
+        self.recorded != self.computed
+    }
+}
+
+/// Verifies the contents of each segment of the specified dump against its
+/// recorded checksum, returning `None` if the dump has no checksums (as is
+/// the case for dumps taken by older versions of Humility).
+pub fn verify_dump_checksums(
+    dumpfile: &str,
+) -> Result<Option<Vec<DumpSegmentChecksum>>> {
+    let contents = dump_contents(dumpfile)?;
+    let elf = Elf::parse(&contents).map_err(|e| {
+        anyhow!("failed to parse {} as an ELF file: {}", dumpfile, e)
+    })?;
+
+    let mut recorded = None;
+
+    if let Some(notes) = elf.iter_note_headers(&contents) {
+        for note in notes {
+            let note = note.map_err(|e| anyhow!("bad note: {}", e))?;
+
+            if note.name == OXIDE_NT_NAME
+                && note.n_type == OXIDE_NT_HUBRIS_CHECKSUMS
+            {
+                recorded = Some(
+                    note.desc
+                        .chunks_exact(8)
+                        .map(|c| {
+                            let base = c[..4].try_into().unwrap();
+                            let crc = c[4..].try_into().unwrap();
+                            (u32::from_le_bytes(base), u32::from_le_bytes(crc))
+                        })
+                        .collect::<HashMap<_, _>>(),
+                );
+            }
+        }
+    }
+
+    let Some(recorded) = recorded else {
+        return Ok(None);
+    };
+
+    let mut rval = vec![];
+
+    for phdr in elf
+        .program_headers
+        .iter()
+        .filter(|h| h.p_type == goblin::elf::program_header::PT_LOAD)
+    {
+        let base = phdr.p_vaddr as u32;
+
+        let Some(&crc) = recorded.get(&base) else {
+            bail!("no checksum recorded for segment at {:#x}", base);
+        };
+
+        let start = phdr.p_offset as usize;
+        let range = start..start + phdr.p_filesz as usize;
+
+        let Some(segment) = contents.get(range) else {
+            bail!("segment at {:#x} extends beyond end of dump", base);
+        };
+
+        rval.push(DumpSegmentChecksum {
+            base,
+            size: phdr.p_filesz as u32,
+            recorded: crc,
+            computed: dump_checksum(segment),
+        });
+    }
+
+    Ok(Some(rval))
+}
 
 const MAX_HUBRIS_VERSION: u32 = 8;
 
@@ -1683,9 +2150,11 @@ impl HubrisArchive {
         doneness: HubrisArchiveDoneness,
     ) -> Result<()> {
         //
-        // We expect the dump to be an ELF core dump.
+        // We expect the dump to be an ELF core dump.  As we only need the
+        // notes, we map the dump (if uncompressed) rather than reading the
+        // whole thing.
         //
-        let contents = fs::read(dumpfile)?;
+        let contents = dump_contents(dumpfile)?;
         let elf = Elf::parse(&contents).map_err(|e| {
             anyhow!("failed to parse {} as an ELF file: {}", dumpfile, e)
         })?;
@@ -1700,6 +2169,15 @@ impl HubrisArchive {
 
                         match note.n_type {
                             OXIDE_NT_HUBRIS_ARCHIVE => {
+                                //
+                                // If we have already loaded an archive, it
+                                // has been explicitly specified; use it
+                                // rather than the one in the dump.
+                                //
+                                if !self.archive.is_empty() {
+                                    continue;
+                                }
+
                                 if doneness == HubrisArchiveDoneness::Cook {
                                     self.load_archive(note.desc)?;
                                 }
@@ -1709,6 +2187,19 @@ impl HubrisArchive {
                             OXIDE_NT_HUBRIS_REGISTERS => {
                                 self.load_registers(note.desc)?;
                             }
+                            OXIDE_NT_HUBRIS_CHECKSUMS => {
+                                //
+                                // Checksums are only examined when verifying
+                                // a dump; see verify_dump_checksums().
+                                //
+                            }
+                            OXIDE_NT_HUBRIS_IDENTITY => {
+                                //
+                                // As we are loading the archive from the
+                                // dump itself, its identity necessarily
+                                // matches; see dump_identity().
+                                //
+                            }
                             OXIDE_NT_HUBRIS_TASK => {
                                 match DumpTask::read_from_prefix(note.desc) {
                                     Some(task) => {
@@ -2383,6 +2874,14 @@ impl HubrisArchive {
         self.imageid.as_ref().map(|i| i.1.as_slice())
     }
 
+    /// Returns the identity of this archive, as recorded in its dumps
+    pub fn identity(&self) -> DumpIdentity {
+        DumpIdentity {
+            gitrev: self.manifest.gitrev.clone(),
+            imageid: self.image_id().map(|id| id.to_vec()),
+        }
+    }
+
     pub fn member_offset(
         &self,
         structure: &HubrisStruct,
@@ -2690,8 +3189,31 @@ impl HubrisArchive {
         core: &mut dyn crate::core::Core,
         t: HubrisTask,
     ) -> Result<BTreeMap<ARMRegister, u32>> {
-        let (base, _) = self.task_table(core)?;
+        //
+        // If this is a dump of this task that recorded the task's registers,
+        // we take them from there.
+        //
+        if self.task_dump() == Some(t) && !self.registers.is_empty() {
+            return Ok(self.registers.iter().map(|(&r, &v)| (r, v)).collect());
+        }
+
         let cur = self.current_task(core)?;
+        self.task_registers(core, t, cur)
+    }
+
+    //
+    // Returns the registers of the specified task, given the current task
+    // (if any):  the registers of the current task are taken from the core
+    // if it is running in userland; otherwise, registers are recovered from
+    // the task's saved state and its stack.
+    //
+    fn task_registers(
+        &self,
+        core: &mut dyn crate::core::Core,
+        t: HubrisTask,
+        cur: Option<HubrisTask>,
+    ) -> Result<BTreeMap<ARMRegister, u32>> {
+        let (base, _) = self.task_table(core)?;
 
         let module = self.lookup_module(t)?;
         let mut rval = BTreeMap::new();
@@ -2732,19 +3254,7 @@ impl HubrisArchive {
                 };
 
             if userland {
-                for i in 0..=31 {
-                    let reg = match ARMRegister::from_u16(i) {
-                        Some(r) => r,
-                        None => {
-                            continue;
-                        }
-                    };
-
-                    let val = core.read_reg(reg)?;
-                    rval.insert(reg, val);
-                }
-
-                return Ok(rval);
+                return core.read_reg_all();
             }
         };
 
@@ -3199,19 +3709,200 @@ impl HubrisArchive {
         dumpfile: Option<&str>,
         started: Option<Instant>,
     ) -> Result<()> {
-        use indicatif::{HumanBytes, HumanDuration};
-        use indicatif::{ProgressBar, ProgressStyle};
+        let segments = self.dump_segments(core, task, true)?;
+        self.dump_with_segments(core, task, &segments, dumpfile, started)
+    }
+
+    /// Writes a dump consisting of the specified `(start, size)` segments,
+    /// which are expected to be a subset of those returned by
+    /// `dump_segments`.  A `dumpfile` of `-` writes the dump to standard
+    /// output.
+    pub fn dump_with_segments(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: Option<DumpTask>,
+        segments: &[(u32, u32)],
+        dumpfile: Option<&str>,
+        started: Option<Instant>,
+    ) -> Result<()> {
         use std::io::Write;
 
-        let segments = self.dump_segments(core, task, true)?;
-        let nsegs = segments.len();
+        let filename = self.dump_filename(task, dumpfile, None)?;
 
-        macro_rules! pad {
-            ($size:expr) => {
-                ((4 - ($size & 0b11)) & 0b11) as u32
-            };
+        //
+        // A dump file of "-" denotes standard output, allowing the dump to be
+        // piped elsewhere; everything else that we emit goes to stderr.
+        //
+        if filename == "-" {
+            msg!("dumping to standard output");
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            self.write_dump(core, task, segments, &mut out, started)?;
+            out.flush()?;
+            return Ok(());
+        }
+
+        let mut file =
+            OpenOptions::new().write(true).create_new(true).open(&filename)?;
+
+        msg!("dumping to {filename}");
+
+        //
+        // If the file name indicates that the dump is to be compressed, we
+        // compress it as it is written.
+        //
+        match DumpCompression::from_filename(&filename) {
+            Some(DumpCompression::Zstd) => {
+                let mut out = zstd::stream::Encoder::new(file, 0)?;
+                self.write_dump(core, task, segments, &mut out, started)?;
+                out.finish()?;
+            }
+            Some(DumpCompression::Gzip) => {
+                let mut out = flate2::write::GzEncoder::new(
+                    file,
+                    flate2::Compression::default(),
+                );
+                self.write_dump(core, task, segments, &mut out, started)?;
+                out.finish()?;
+            }
+            None => {
+                self.write_dump(core, task, segments, &mut file, started)?;
+            }
         }
 
+        Ok(())
+    }
+
+    /// Creates a dump to which segment contents are written as they become
+    /// available (via [`DumpStream::write`]), rather than all at once at the
+    /// end; any contents that are not written are read from the core when
+    /// the dump is finished.
+    pub fn dump_stream(
+        &self,
+        task: Option<DumpTask>,
+        segments: &[(u32, u32)],
+        dumpfile: Option<&str>,
+    ) -> Result<DumpStream> {
+        let filename = self.dump_filename(task, dumpfile, None)?;
+
+        if filename == "-" {
+            bail!("cannot stream a dump to standard output");
+        }
+
+        if DumpCompression::from_filename(&filename).is_some() {
+            bail!("cannot stream a compressed dump");
+        }
+
+        //
+        // We open the file for reading as well as writing, as we read back
+        // each segment to determine its checksum when the dump is finished.
+        //
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&filename)?;
+
+        msg!("dumping to {filename}");
+
+        //
+        // We don't yet know our register values, but we know which registers
+        // we will have -- and therefore the size of our header.  Write it
+        // now with placeholder values to learn where our segments go; it
+        // will be rewritten when the dump is finished.
+        //
+        let regs = match task {
+            Some(_) => vec![],
+            None => dump_registers().map(|(i, _)| (i, 0)).collect(),
+        };
+
+        let mut offset =
+            self.write_dump_header(task, &regs, segments, &mut file)?;
+
+        let segments = segments
+            .iter()
+            .map(|&(base, size)| {
+                let seg = (base, size, offset);
+                offset += size + dump_pad(size);
+                seg
+            })
+            .collect::<Vec<_>>();
+
+        Ok(DumpStream {
+            file,
+            filename,
+            task,
+            segments,
+            written: BTreeMap::new(),
+            end: offset,
+        })
+    }
+
+    /// Returns the name of the file to which a dump will be written: either
+    /// `dumpfile` or, if that isn't specified, the first unused name of the
+    /// form `hubris.core.[task.]N` (with an extension denoting the specified
+    /// compression, if any).  A name is considered used if a dump by that
+    /// name exists with any compression.
+    pub fn dump_filename(
+        &self,
+        task: Option<DumpTask>,
+        dumpfile: Option<&str>,
+        compression: Option<DumpCompression>,
+    ) -> Result<String> {
+        Ok(match dumpfile {
+            Some(filename) if filename == "-" => filename.to_owned(),
+            Some(filename) => match compression {
+                Some(c)
+                    if DumpCompression::from_filename(filename) != Some(c) =>
+                {
+                    format!("{filename}.{}", c.extension())
+                }
+                _ => filename.to_owned(),
+            },
+            None => {
+                let prefix = match task {
+                    Some(task) => {
+                        let t = HubrisTask::Task(task.id as u32);
+                        format!("hubris.core.{}.", self.lookup_module(t)?.name)
+                    }
+                    None => "hubris.core.".to_string(),
+                };
+
+                let exists = |f: &str| {
+                    std::fs::File::open(f).is_ok()
+                        || DumpCompression::ALL.iter().any(|c| {
+                            let f = format!("{f}.{}", c.extension());
+                            std::fs::File::open(f).is_ok()
+                        })
+                };
+
+                let filename = (0..)
+                    .map(|i| format!("{prefix}{i}"))
+                    .find(|f| !exists(f))
+                    .unwrap();
+
+                match compression {
+                    Some(c) => format!("{filename}.{}", c.extension()),
+                    None => filename,
+                }
+            }
+        })
+    }
+
+    //
+    // Writes the ELF header, program headers, and notes for a dump, returning
+    // the offset at which segment contents begin.  These are all of a size
+    // that depends only on the task (if any) and the segments.  The one
+    // exception is the checksums note:  its program header is written here,
+    // but the note itself follows the segment contents and is written by
+    // write_dump_checksums().
+    //
+    fn write_dump_header<W: std::io::Write>(
+        &self,
+        task: Option<DumpTask>,
+        regs: &[(u16, u32)],
+        segments: &[(u32, u32)],
+        file: &mut W,
+    ) -> Result<u32> {
         let pad = [0u8; 4];
 
         let ctx = goblin::container::Ctx::new(
@@ -3221,40 +3912,27 @@ impl HubrisArchive {
 
         let oxide = String::from(OXIDE_NT_NAME);
 
-        let notesz = |note: &goblin::elf::note::Nhdr32| {
-            size_of::<goblin::elf::note::Nhdr32>() as u32
-                + note.n_namesz
-                + pad!(note.n_namesz)
-                + note.n_descsz
-                + pad!(note.n_descsz)
-        };
-
         let mut notes = vec![];
-        let mut regs = vec![];
-
-        match task {
-            Some(_) => {
-                notes.push(goblin::elf::note::Nhdr32 {
-                    n_namesz: (oxide.len() + 1) as u32,
-                    n_descsz: std::mem::size_of::<DumpTask>() as u32,
-                    n_type: OXIDE_NT_HUBRIS_TASK,
-                });
-            }
+        let identity = serde_json::to_vec(&self.identity())?;
 
-            None => {
-                for i in 0..31 {
-                    if let Some(reg) = ARMRegister::from_u16(i) {
-                        let val = core.read_reg(reg)?;
-                        regs.push((i, val));
-                    }
-                }
+        if task.is_some() {
+            notes.push(goblin::elf::note::Nhdr32 {
+                n_namesz: (oxide.len() + 1) as u32,
+                n_descsz: std::mem::size_of::<DumpTask>() as u32,
+                n_type: OXIDE_NT_HUBRIS_TASK,
+            });
+        }
 
-                notes.push(goblin::elf::note::Nhdr32 {
-                    n_namesz: (oxide.len() + 1) as u32,
-                    n_descsz: regs.len() as u32 * 8,
-                    n_type: OXIDE_NT_HUBRIS_REGISTERS,
-                });
-            }
+        //
+        // A task dump has registers only if we were able to recover the
+        // task's saved state.
+        //
+        if task.is_none() || !regs.is_empty() {
+            notes.push(goblin::elf::note::Nhdr32 {
+                n_namesz: (oxide.len() + 1) as u32,
+                n_descsz: regs.len() as u32 * 8,
+                n_type: OXIDE_NT_HUBRIS_REGISTERS,
+            });
         }
 
         notes.push(goblin::elf::note::Nhdr32 {
@@ -3263,41 +3941,29 @@ impl HubrisArchive {
             n_type: OXIDE_NT_HUBRIS_ARCHIVE,
         });
 
+        //
+        // So that the archive with which a dump was taken can be identified
+        // without extracting the archive itself, we record its git revision
+        // and image ID.
+        //
+        notes.push(goblin::elf::note::Nhdr32 {
+            n_namesz: (oxide.len() + 1) as u32,
+            n_descsz: identity.len() as u32,
+            n_type: OXIDE_NT_HUBRIS_IDENTITY,
+        });
+
         let mut header = goblin::elf::header::Header::new(ctx);
         header.e_machine = goblin::elf::header::EM_ARM;
         header.e_type = goblin::elf::header::ET_CORE;
         header.e_phoff = header.e_ehsize as u64;
-        header.e_phnum = (notes.len() + nsegs) as u16;
+        header.e_phnum = (notes.len() + segments.len() + 1) as u16;
 
         let mut offset = header.e_phoff as u32
             + (header.e_phentsize * header.e_phnum) as u32;
 
-        let filename = match dumpfile {
-            Some(filename) => filename.to_owned(),
-            None => {
-                let prefix = match task {
-                    Some(task) => {
-                        let t = HubrisTask::Task(task.id as u32);
-                        format!("hubris.core.{}.", self.lookup_module(t)?.name)
-                    }
-                    None => "hubris.core.".to_string(),
-                };
-
-                (0..)
-                    .map(|i| format!("{prefix}{i}"))
-                    .find(|f| std::fs::File::open(f).is_err())
-                    .unwrap()
-            }
-        };
-
         //
         // Write our ELF header
         //
-        let mut file =
-            OpenOptions::new().write(true).create_new(true).open(&filename)?;
-
-        msg!("dumping to {filename}");
-
         file.iowrite_with(header, ctx)?;
 
         let mut bytes = [0x0u8; goblin::elf32::program_header::SIZEOF_PHDR];
@@ -3306,7 +3972,7 @@ impl HubrisArchive {
         // Write our program headers, starting with our note headers.
         //
         for note in &notes {
-            let size = notesz(note);
+            let size = dump_note_size(note);
 
             let phdr = goblin::elf32::program_header::ProgramHeader {
                 p_type: goblin::elf::program_header::PT_NOTE,
@@ -3322,9 +3988,9 @@ impl HubrisArchive {
             offset += size;
         }
 
-        let mut total = 0;
+        let data = offset;
 
-        for (base, size) in &segments {
+        for (base, size) in segments {
             let seg_phdr = goblin::elf32::program_header::ProgramHeader {
                 p_type: goblin::elf::program_header::PT_LOAD,
                 p_flags: goblin::elf::program_header::PF_R,
@@ -3338,10 +4004,24 @@ impl HubrisArchive {
             bytes.pwrite_with(seg_phdr, 0, ctx.le)?;
             file.write_all(&bytes)?;
 
-            offset += *size + pad!(*size);
-            total += *size;
+            offset += *size + dump_pad(*size);
         }
 
+        //
+        // Finally, the program header for our checksums note, which follows
+        // the segment contents.
+        //
+        let phdr = goblin::elf32::program_header::ProgramHeader {
+            p_type: goblin::elf::program_header::PT_NOTE,
+            p_flags: goblin::elf::program_header::PF_R,
+            p_offset: offset,
+            p_filesz: dump_note_size(&dump_checksums_note(segments.len())),
+            ..Default::default()
+        };
+
+        bytes.pwrite_with(phdr, 0, ctx.le)?;
+        file.write_all(&bytes)?;
+
         for note in &notes {
             //
             // Now write our note section, starting with our note header...
@@ -3355,7 +4035,7 @@ impl HubrisArchive {
             //
             let bytes = oxide.as_bytes();
             file.write_all(bytes)?;
-            let npad = 1 + pad!(note.n_namesz) as usize;
+            let npad = 1 + dump_pad(note.n_namesz) as usize;
             file.write_all(&pad[0..npad])?;
 
             //
@@ -3376,6 +4056,10 @@ impl HubrisArchive {
                     file.write_all(&self.archive)?;
                 }
 
+                OXIDE_NT_HUBRIS_IDENTITY => {
+                    file.write_all(&identity)?;
+                }
+
                 OXIDE_NT_HUBRIS_TASK => {
                     file.write_all(task.unwrap().as_bytes())?;
                 }
@@ -3385,13 +4069,86 @@ impl HubrisArchive {
                 }
             }
 
-            let npad = pad!(note.n_descsz) as usize;
+            let npad = dump_pad(note.n_descsz) as usize;
             file.write_all(&pad[0..npad])?;
         }
 
+        Ok(data)
+    }
+
+    //
+    // Reads the registers of the task being dumped from its saved state, if
+    // its task control block is among the dumped segments (which it isn't
+    // for a dump of a task region).  If they can't be read (e.g., because
+    // its stack is not in the dump), we warn and proceed without them.
+    //
+    fn task_dump_register_values(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: DumpTask,
+        segments: &[(u32, u32)],
+    ) -> Vec<(u16, u32)> {
+        let t = HubrisTask::Task(task.id as u32);
+
+        let tcb = self.task_table(core).and_then(|(base, _)| {
+            let task_t = self.lookup_struct_byname("Task")?;
+            Ok(base + task.id as u32 * task_t.size as u32)
+        });
+
+        let dumped = match tcb {
+            Ok(tcb) => segments.iter().any(|&(b, s)| tcb >= b && tcb - b < s),
+            Err(_) => false,
+        };
+
+        if !dumped {
+            return vec![];
+        }
+
+        //
+        // If we can't determine the current task (as when dumping via the
+        // dump agent, which has no kernel memory), the task being dumped
+        // isn't running, and its registers are all in its saved state.
+        //
+        let cur = self.current_task(core).unwrap_or(None);
+
+        match self
+            .task_registers(core, t, cur)
+            .and_then(|all| register_values(&all, task_dump_registers()))
+        {
+            Ok(regs) => regs,
+            Err(err) => {
+                warn!("failed to read task registers: {err}; omitting them");
+                vec![]
+            }
+        }
+    }
+
+    fn write_dump<W: std::io::Write>(
+        &self,
+        core: &mut dyn crate::core::Core,
+        task: Option<DumpTask>,
+        segments: &[(u32, u32)],
+        file: &mut W,
+        started: Option<Instant>,
+    ) -> Result<()> {
+        use indicatif::{HumanBytes, HumanDuration};
+        use indicatif::{ProgressBar, ProgressStyle};
+
+        let regs = match task {
+            Some(task) => self.task_dump_register_values(core, task, segments),
+            None => dump_register_values(core)?,
+        };
+
+        let pad = [0u8; 4];
+        let total = segments.iter().map(|(_, size)| size).sum::<u32>();
+
+        self.write_dump_header(task, &regs, segments, file)?;
+
         //
         // And now we write our segments.  This takes a little while, so
-        // we're going to indicate our progress as we go.
+        // we're going to indicate our progress as we go.  We determine the
+        // checksum of each segment as we write it; the checksums are written
+        // after the segments.
         //
         let mut written = 0;
 
@@ -3403,29 +4160,34 @@ impl HubrisArchive {
                 .template("humility: dumping [{bar:30}] {bytes}/{total_bytes}"),
         );
 
-        for (base, size) in &segments {
-            let mut remain = *size as usize;
-            let mut bytes = vec![0; 1024];
-            let mut addr = *base;
+        let mut bytes = vec![0; 1024];
+        let mut checksums = vec![];
 
-            while remain > 0 {
-                let nbytes =
-                    if remain > bytes.len() { bytes.len() } else { remain };
+        for (base, size) in segments {
+            let mut crc = crc_any::CRCu32::crc32();
+            let mut addr = *base;
+            let end = *base + *size;
 
+            while addr < end {
+                let nbytes = std::cmp::min((end - addr) as usize, bytes.len());
                 core.read_8(addr, &mut bytes[0..nbytes])?;
+                crc.digest(&bytes[0..nbytes]);
                 file.write_all(&bytes[0..nbytes])?;
-                remain -= nbytes;
                 written += nbytes;
                 addr += nbytes as u32;
                 bar.set_position(written as u64);
             }
 
-            let npad = pad!(*size) as usize;
+            let npad = dump_pad(*size) as usize;
             file.write_all(&pad[0..npad])?;
+
+            checksums.push(crc.get_crc());
         }
 
         bar.finish_and_clear();
 
+        write_dump_checksums(segments, &checksums, file)?;
+
         msg!(
             "dumped {} in {}",
             HumanBytes(written as u64),
@@ -6155,7 +6917,7 @@ impl<'a> std::fmt::Display for HubrisType<'a> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum HubrisTarget {
     Direct(u32),
     Indirect,
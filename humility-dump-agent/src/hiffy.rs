@@ -1,7 +1,7 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
-use crate::DumpAgent;
+use crate::{with_retries, DumpAgent, DEFAULT_READ_RETRIES};
 use anyhow::{bail, Result};
 use core::mem::size_of;
 use hif::*;
@@ -9,6 +9,12 @@ use humility::{core::Core, hubris::HubrisArchive};
 use humility_hiffy::HiffyContext;
 use humility_idol::{self as idol, HubrisIdol};
 use humpty::{DumpAreaHeader, DumpSegment, DumpSegmentHeader};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default time to wait for the probe to be unplugged before taking a dump
+const DEFAULT_UNPLUG_WAIT: Duration = Duration::from_secs(10);
 
 /// Represents a dump agent that communicates through the `hiffy` task
 ///
@@ -19,6 +25,10 @@ pub struct HiffyDumpAgent<'a> {
     hubris: &'a HubrisArchive,
     core: &'a mut dyn Core,
     context: HiffyContext<'a>,
+    retries: u32,
+    read_timeout: Option<u32>,
+    unplug_wait: Duration,
+    interrupted: Option<fn() -> bool>,
 }
 
 impl<'a> HiffyDumpAgent<'a> {
@@ -29,6 +39,14 @@ impl<'a> HiffyDumpAgent<'a> {
     ) -> Result<Self> {
         let context = HiffyContext::new(hubris, core, timeout)?;
 
+        //
+        // The Idol interface of the dump agent is the protocol by which we
+        // speak to it; if it doesn't look like what we expect, the agent in
+        // the archive is from a Hubris either too old or too new for us, and
+        // we want to say that rather than fail obscurely later.
+        //
+        Self::check_interface(hubris)?;
+
         //
         // Do some sanity checks on the number of bytes returned by read_dump().
         // If these checks fail, something is really wrong.
@@ -52,11 +70,211 @@ impl<'a> HiffyDumpAgent<'a> {
             );
         }
 
-        Ok(Self { hubris, core, context })
+        Ok(Self {
+            hubris,
+            core,
+            context,
+            retries: DEFAULT_READ_RETRIES,
+            read_timeout: None,
+            unplug_wait: DEFAULT_UNPLUG_WAIT,
+            interrupted: None,
+        })
+    }
+
+    /// Checks that the dump agent's Idol interface has the operations (and
+    /// the arguments to them) that we rely upon
+    fn check_interface(hubris: &HubrisArchive) -> Result<()> {
+        let required: &[(&str, &[&str])] = &[
+            ("read_dump", &["index", "offset"]),
+            ("initialize_dump", &[]),
+            ("add_dump_segment", &["address", "length"]),
+            ("take_dump", &[]),
+        ];
+
+        for (name, args) in required {
+            let op = match hubris.get_idol_command(&format!("DumpAgent.{name}"))
+            {
+                Ok(op) => op,
+                Err(e) => {
+                    bail!(
+                        "dump agent protocol mismatch: DumpAgent.{name} \
+                         is unavailable ({e}); the dump agent in this \
+                         archive is incompatible with this humility"
+                    );
+                }
+            };
+
+            for arg in args.iter() {
+                if !op.args.members.iter().any(|m| m.name == *arg) {
+                    bail!(
+                        "dump agent protocol mismatch: DumpAgent.{name} \
+                         lacks argument \"{arg}\"; the dump agent in this \
+                         archive is incompatible with this humility"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the number of times to retry reads that fail transiently
+    pub fn set_read_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Sets the timeout (in milliseconds) for each HIF program that reads
+    /// dump areas, independent of the timeout used for other operations
+    pub fn set_read_timeout(&mut self, timeout: u32) {
+        self.read_timeout = Some(timeout);
+    }
+
+    /// Sets the time to wait for the probe to be unplugged before a dump is
+    /// taken (when connected via a probe)
+    pub fn set_unplug_wait(&mut self, wait: Duration) {
+        self.unplug_wait = wait;
+    }
+
+    /// Sets a function to be checked while waiting for the probe to be
+    /// unplugged; if it returns true, we stop waiting
+    pub fn set_interrupt_check(&mut self, check: fn() -> bool) {
+        self.interrupted = Some(check);
     }
+
     fn run(&mut self, ops: &[Op]) -> Result<Vec<Result<Vec<u8>, u32>>> {
         self.context.run(self.core, ops, None)
     }
+
+    /// Runs a program that reads dump areas, using our read timeout (if
+    /// one has been set) rather than the context's
+    fn run_read(&mut self, ops: &[Op]) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        let Some(read_timeout) = self.read_timeout else {
+            return self.run(ops);
+        };
+
+        let timeout = self.context.timeout();
+
+        self.context.set_timeout(read_timeout);
+        let rval = self.run(ops);
+        self.context.set_timeout(timeout);
+
+        rval
+    }
+
+    /// Determines if we can still talk to the target
+    ///
+    /// This reads CPUID, which is always present; if it can't be read, the
+    /// probe has been pulled (or has otherwise lost its connection).
+    fn probe_attached(&mut self) -> bool {
+        const CPUID: u32 = 0xe000_ed00;
+
+        if self.core.op_start().is_err() {
+            return false;
+        }
+
+        let rval = self.core.read_word_32(CPUID).is_ok();
+        let _ = self.core.op_done();
+        rval
+    }
+
+    /// Runs a HIF program that waits for the probe to be unplugged
+    ///
+    /// The wait is counted down as the program runs.  Once the probe has been
+    /// pulled we can no longer talk to the target, so if the probe stops
+    /// responding we stop waiting on the program and report that the dump
+    /// will proceed without us.
+    fn run_unplugged(
+        &mut self,
+        ops: &[Op],
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        let wait = self.unplug_wait;
+
+        //
+        // Our program won't complete until our wait has elapsed, so extend
+        // our timeout to account for it.
+        //
+        let timeout = self.context.timeout();
+        let extended = timeout.saturating_add(wait.as_millis() as u32);
+
+        self.context.set_timeout(extended);
+        let rval = self.wait_unplugged(ops, wait);
+        self.context.set_timeout(timeout);
+
+        rval
+    }
+
+    fn wait_unplugged(
+        &mut self,
+        ops: &[Op],
+        wait: Duration,
+    ) -> Result<Vec<Result<Vec<u8>, u32>>> {
+        self.context.start(self.core, ops, None)?;
+
+        let started = Instant::now();
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(
+            ProgressStyle::default_spinner().template("humility: {msg}"),
+        );
+
+        loop {
+            let remaining = wait.saturating_sub(started.elapsed());
+
+            if remaining.is_zero() {
+                bar.set_message("starting dump");
+            } else {
+                bar.set_message(format!(
+                    "dump will start in {}s",
+                    remaining.as_secs() + 1
+                ));
+            }
+
+            if self.interrupted.map_or(false, |check| check()) {
+                bar.finish_and_clear();
+
+                //
+                // The program is running on the target, and we have no way
+                // of stopping it short of resetting the RoT; be clear that
+                // the dump is still coming.
+                //
+                bail!(
+                    "interrupted; the dump will still be taken when the \
+                    wait elapses unless the RoT is reset"
+                );
+            }
+
+            match self.context.done(self.core) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(err) => {
+                    bar.finish_and_clear();
+
+                    if self.probe_attached() {
+                        return Err(err);
+                    }
+
+                    if remaining.is_zero() {
+                        humility::msg!("probe disconnected; dump in progress");
+                    } else {
+                        humility::msg!(
+                            "probe disconnected; dump will start in {}s",
+                            remaining.as_secs() + 1
+                        );
+                    }
+
+                    bail!(
+                        "lost connection to target (as expected); reset RoT \
+                        via SWD once the dump is complete to re-attach, and \
+                        then retrieve the dump with \"humility dump\""
+                    );
+                }
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        bar.finish_and_clear();
+        self.context.results(self.core)
+    }
 }
 
 impl<'a> DumpAgent for HiffyDumpAgent<'a> {
@@ -116,23 +334,25 @@ impl<'a> DumpAgent for HiffyDumpAgent<'a> {
             //
             // If we are connected via a dongle, we will need to be unplugged
             // in order for the dump to operate.  Emit a message to this
-            // effect, and then send a HIF payload that will wait for 10
-            // seconds (100 iterations of 100ms apiece) and then start the dump;
-            // if the dongle has been pulled, the dump will start -- and if
-            // not the dump will fail.  However, because determining the
+            // effect, and then send a HIF payload that will wait for our
+            // unplug wait (in iterations of 100ms apiece) and then start the
+            // dump; if the dongle has been pulled, the dump will start -- and
+            // if not the dump will fail.  However, because determining the
             // presence of the dongle necessitates activating the pins on the
             // RoT, we will lose our connection either way -- and unless the
             // dump fails for an earlier reason, it will look like we lost
             // our SWD connection no matter what.
             //
             humility::msg!(
-                "dump will start in 10 seconds; unplug probe now, and \
-                 reset RoT via SWD after dump is complete to re-attach"
+                "dump will start in {} seconds; unplug probe now, and \
+                 reset RoT via SWD after dump is complete to re-attach",
+                self.unplug_wait.as_secs_f64()
             );
 
             let sleep = self.context.get_function("Sleep", 1)?;
             let ms = 100;
-            let iter = 100;
+            let iter =
+                ((self.unplug_wait.as_millis() + 99) / 100).max(1) as u32;
 
             ops.extend([
                 Op::Push(0),                      // Iterations completed
@@ -159,7 +379,11 @@ impl<'a> DumpAgent for HiffyDumpAgent<'a> {
         self.context.idol_call_ops(&op, &[], &mut ops)?;
         ops.push(Op::Done);
 
-        let results = self.run(ops.as_slice())?;
+        let results = if !self.core.is_net() {
+            self.run_unplugged(ops.as_slice())?
+        } else {
+            self.run(ops.as_slice())?
+        };
 
         if let Err(err) = results[rindex] {
             bail!("failed to take dump: {}", op.strerror(err));
@@ -172,7 +396,8 @@ impl<'a> DumpAgent for HiffyDumpAgent<'a> {
     ///
     /// Under the hood, we combine multiple calls to `DumpAgent.read_dump` into
     /// a single HIF program for efficiency; this should be transparent to the
-    /// caller.
+    /// caller.  If the program fails to run (or the dump agent restarts while
+    /// running it), the program is retried.
     fn read_generic(
         &mut self,
         areas: &mut dyn Iterator<Item = (u8, u32)>,
@@ -208,8 +433,23 @@ impl<'a> DumpAgent for HiffyDumpAgent<'a> {
             }
             ops.push(Op::Done);
 
+            let retries = self.retries;
+
+            let results = with_retries(retries, "dump read", || {
+                let results = self.run_read(&ops)?;
+
+                for r in &results {
+                    if let Err(err) = r {
+                        if op.strerror(*err) == "ServerRestarted" {
+                            bail!("dump agent restarted");
+                        }
+                    }
+                }
+
+                Ok(results)
+            })?;
+
             // Check the results
-            let results = self.run(&ops)?;
             for (r, (index, offset)) in results.iter().zip(pos.into_iter()) {
                 match r {
                     Ok(val) => {
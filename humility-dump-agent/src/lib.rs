@@ -17,7 +17,11 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 use core::mem::size_of;
-use humility::{core::Core, hubris::HubrisFlashMap, msg};
+use humility::{
+    core::{Core, CORE_REG_MAX},
+    hubris::{DumpStream, HubrisFlashMap},
+    msg,
+};
 use humility_arch_arm::ARMRegister;
 use humpty::{
     DumpAreaHeader, DumpRegister, DumpSegment, DumpSegmentData,
@@ -26,6 +30,7 @@ use humpty::{
 use indexmap::IndexMap;
 use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
 use num_traits::FromPrimitive;
+use progress::DumpProgress;
 use std::{
     collections::{BTreeMap, HashMap},
     path::Path,
@@ -34,11 +39,45 @@ use std::{
 use zerocopy::FromBytes;
 
 mod hiffy;
+mod progress;
 mod udp;
 
 pub use hiffy::HiffyDumpAgent;
 pub use udp::UdpDumpAgent;
 
+/// Default number of times to retry a dump read that fails transiently
+pub const DEFAULT_READ_RETRIES: u32 = 3;
+
+//
+// Calls `f`, retrying it up to `retries` times (with exponential backoff) if
+// it fails.  This is for operations that can fail transiently (e.g., over a
+// lossy network); it's up to the caller to only return errors from `f` that
+// are worth retrying.
+//
+fn with_retries<T>(
+    retries: u32,
+    what: &str,
+    mut f: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut delay = std::time::Duration::from_millis(100);
+    let mut attempt = 0;
+
+    loop {
+        match f() {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                msg!(
+                    "{what} failed ({e}); retrying in {} ms ({attempt}/{retries})",
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            r => return r,
+        }
+    }
+}
+
 fn parse_dump_header(buf: &[u8]) -> Result<(DumpAreaHeader, Option<DumpTask>)> {
     let header = DumpAreaHeader::read_from_prefix(buf)
         .ok_or_else(|| anyhow!("failed to parse dump area"))?;
@@ -95,6 +134,12 @@ pub struct DumpAgentCore {
     flash: HubrisFlashMap,
     ram_regions: BTreeMap<u32, Vec<u8>>,
     registers: HashMap<ARMRegister, u32>,
+    compressed: usize,
+    uncompressed: usize,
+    nsegments: usize,
+    stream: Option<(DumpStream, usize)>,
+    resident: usize,
+    check: Option<Vec<String>>,
 }
 
 impl DumpAgentCore {
@@ -103,10 +148,70 @@ impl DumpAgentCore {
             flash,
             ram_regions: Default::default(),
             registers: Default::default(),
+            compressed: 0,
+            uncompressed: 0,
+            nsegments: 0,
+            stream: None,
+            resident: 0,
+            check: None,
+        }
+    }
+
+    /// Returns the total compressed and uncompressed sizes of the data
+    /// segments that have been processed from in situ dumps
+    pub fn compression(&self) -> (usize, usize) {
+        (self.compressed, self.uncompressed)
+    }
+
+    /// Returns the number of segments in the in situ dumps processed
+    pub fn nsegments(&self) -> usize {
+        self.nsegments
+    }
+
+    /// Records data segments that fail to decompress (or that decompress to
+    /// a length other than that expected) rather than failing on them.
+    pub fn check_decompression(&mut self) {
+        self.check = Some(vec![]);
+    }
+
+    /// Returns a description of each decompression failure recorded since
+    /// [`check_decompression`](Self::check_decompression) was called
+    pub fn decompression_failures(&self) -> &[String] {
+        self.check.as_deref().unwrap_or(&[])
+    }
+
+    /// Returns the RAM regions that have been accumulated, in address order
+    pub fn ram_regions(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.ram_regions.iter().map(|(&addr, contents)| (addr, &contents[..]))
+    }
+
+    /// Writes RAM regions to the specified stream as they are added, rather
+    /// than accumulating them, whenever more than `max` bytes are resident.
+    pub fn stream_to(&mut self, stream: DumpStream, max: usize) {
+        self.stream = Some((stream, max));
+    }
+
+    /// Writes any remaining RAM regions to the stream established with
+    /// [`stream_to`](Self::stream_to), returning it to be finished.
+    pub fn take_stream(&mut self) -> Result<Option<DumpStream>> {
+        self.flush()?;
+        Ok(self.stream.take().map(|(stream, _)| stream))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some((stream, _)) = &mut self.stream {
+            for (addr, contents) in std::mem::take(&mut self.ram_regions) {
+                stream.write(addr, &contents)?;
+            }
+
+            self.resident = 0;
         }
+
+        Ok(())
     }
 
     pub fn add_ram_region(&mut self, addr: u32, contents: Vec<u8>) {
+        self.resident += contents.len();
         self.ram_regions.insert(addr, contents);
     }
 
@@ -171,6 +276,27 @@ impl DumpAgentCore {
             bail!("in situ dump is empty");
         }
 
+        self.nsegments += nsegments as usize;
+
+        //
+        // If we are checking decompression, we tally the decompressed length
+        // of the data within each segment to compare against the length in
+        // its header.
+        //
+        let mut expected = vec![];
+
+        if self.check.is_some() {
+            for i in 0..nsegments as usize {
+                let offs = i * size_of::<DumpSegmentHeader>();
+
+                if let Some(h) =
+                    DumpSegmentHeader::read_from_prefix(&dump[offs..])
+                {
+                    expected.push((h.address, h.length as usize, 0));
+                }
+            }
+        }
+
         while offset < dump.len() {
             let segment = match DumpSegment::from(&dump[offset..]) {
                 Some(segment) => segment,
@@ -222,16 +348,59 @@ impl DumpAgentCore {
 
                     let mut contents = vec![0; len];
                     let limit = offset + data.compressed_length as usize;
+                    let addr = data.address;
+
+                    if limit > dump.len() {
+                        bail!("data segment at {addr:#x} is truncated");
+                    }
 
-                    humpty::DumpLzss::decompress(
+                    let rval = humpty::DumpLzss::decompress(
                         lzss::SliceReader::new(&dump[offset..limit]),
                         lzss::SliceWriter::new(&mut contents),
-                    )?;
+                    );
+
+                    if let Some(failures) = &mut self.check {
+                        match rval {
+                            Ok(n) => {
+                                if n != len {
+                                    failures.push(format!(
+                                        "data at {addr:#x} decompressed to \
+                                        {n} bytes; expected {len}"
+                                    ));
+                                }
+
+                                if let Some(e) =
+                                    expected.iter_mut().find(|(base, l, _)| {
+                                        addr >= *base
+                                            && ((addr - base) as usize) < *l
+                                    })
+                                {
+                                    e.2 += n;
+                                }
+                            }
+                            Err(err) => {
+                                failures.push(format!(
+                                    "data at {addr:#x} failed to \
+                                    decompress: {err}"
+                                ));
+                            }
+                        }
+                    } else {
+                        rval?;
+                    }
 
                     self.add_ram_region(
                         data.address,
                         contents[0..len].to_vec(),
                     );
+
+                    if matches!(self.stream, Some((_, max)) if self.resident > max)
+                    {
+                        self.flush()?;
+                    }
+
+                    self.compressed += data.compressed_length as usize;
+                    self.uncompressed += len;
                     offset = limit;
 
                     while offset < dump.len()
@@ -250,6 +419,17 @@ impl DumpAgentCore {
             }
         }
 
+        if let Some(failures) = &mut self.check {
+            for (base, len, found) in expected {
+                if found != len {
+                    failures.push(format!(
+                        "segment at {base:#x} decompressed to {found} bytes; \
+                        header specifies {len}"
+                    ));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -270,6 +450,17 @@ impl Core for DumpAgentCore {
         }
     }
 
+    fn read_reg_all(&mut self) -> Result<BTreeMap<ARMRegister, u32>> {
+        use num_traits::ToPrimitive;
+
+        Ok(self
+            .registers
+            .iter()
+            .filter(|(reg, _)| reg.to_u16().unwrap() <= CORE_REG_MAX)
+            .map(|(&reg, &val)| (reg, val))
+            .collect())
+    }
+
     fn write_reg(&mut self, reg: ARMRegister, _value: u32) -> Result<()> {
         bail!("cannot write register {} over dump agent", reg);
     }
@@ -489,10 +680,11 @@ pub trait DumpAgentExt {
         //
         // Iterate over dump area headers.  Once we've hit an area that hasn't
         // been dumped to, or an invalid area (denoting that all areas are
-        // full), we're done...
+        // full), we're done.  Agents may read ahead of our continuation, so
+        // the range of indices must be bounded rather than left to overflow.
         //
         let results = self.read_generic(
-            (0..).map(|index| (index, 0)),
+            (0..=u8::MAX).map(|index| (index, 0)),
             |index, _offset, val| {
                 let (header, _task) =
                     parse_dump_header_index(index as usize, val)?;
@@ -511,11 +703,33 @@ pub trait DumpAgentExt {
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Returns the number of bytes available for dump contents, summed over
+    /// every dump area
+    fn dump_region_capacity(&mut self) -> Result<u32> {
+        let headers = self.read_dump_headers(true)?;
+        let size = size_of::<DumpAreaHeader>() as u32;
+
+        Ok(headers
+            .iter()
+            .map(|(header, _)| {
+                let length = header.length;
+                length.saturating_sub(size)
+            })
+            .sum())
+    }
+
+    /// Reads a dump into `out`
+    ///
+    /// If `progress` is specified, each area is recorded in the specified
+    /// file as it is read; if the file already contains areas for this dump
+    /// (e.g., from a previous attempt that was interrupted), those areas are
+    /// not read again.  The file is removed once the dump has been read.
     fn read_dump(
         &mut self,
         area: Option<DumpArea>,
         out: &mut DumpAgentCore,
         verbose: bool,
+        progress: Option<&Path>,
     ) -> Result<Option<DumpTask>> {
         let (base, headers, task) = {
             // Read dump headers until the first empty header (DUMPER_NONE)
@@ -561,27 +775,54 @@ pub trait DumpAgentExt {
 
         let total = headers.iter().fold(0, |sum, header| sum + header.written);
 
+        //
+        // Pulling a dump can take minutes (especially over the network), so
+        // if we're verbose we display our progress -- unless we aren't on a
+        // terminal, in which case the progress bar would only clutter any
+        // log.
+        //
         let started = Instant::now();
-        let bar = if verbose {
+        let bar = if verbose && atty::is(atty::Stream::Stderr) {
             let bar = ProgressBar::new(total as u64);
             bar.set_style(ProgressStyle::default_bar().template(
-                "humility: pulling [{bar:30}] {bytes}/{total_bytes}",
+                "humility: pulling [{bar:30}] {bytes}/{total_bytes} \
+                ({bytes_per_sec}, ETA {eta})",
             ));
             Some(bar)
         } else {
             None
         };
 
+        let mut sidecar = match progress {
+            Some(path) => Some(DumpProgress::open(path, &headers, task)?),
+            None => None,
+        };
+
         let mut count = 0;
         let mut contents = vec![];
-        for (ndx, _) in headers.iter().enumerate() {
+        for (ndx, header) in headers.iter().enumerate() {
             let index = (ndx + base).try_into().unwrap();
+
+            if let Some(data) = sidecar.as_ref().and_then(|s| s.get(index)) {
+                count += header.written as usize;
+                if let Some(bar) = &bar {
+                    bar.set_position(count as u64);
+                }
+                contents.extend(data.iter());
+                continue;
+            }
+
             let (_header, data) = self.read_dump_area(index, &mut |size| {
                 count += size;
                 if let Some(bar) = &bar {
                     bar.set_position(count as u64);
                 }
             })?;
+
+            if let Some(sidecar) = &mut sidecar {
+                sidecar.record(index, &data)?;
+            }
+
             contents.extend(data.into_iter());
         }
 
@@ -602,6 +843,10 @@ pub trait DumpAgentExt {
         //
         out.process_dump(&headers[0], &contents, task)?;
 
+        if let Some(sidecar) = sidecar {
+            sidecar.remove()?;
+        }
+
         Ok(task)
     }
 }
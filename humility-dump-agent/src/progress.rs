@@ -0,0 +1,157 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Sidecar file recording the progress of a dump read
+//!
+//! When pulling a large dump over a flaky link, we record each dump area as
+//! it is read so that a subsequent attempt can pick up where we left off.
+//! The file consists of a single line identifying the dump, followed by
+//! records consisting of a little-endian `u32` area index, a little-endian
+//! `u32` length, and that many bytes of area contents.  Records are appended
+//! as areas are read; a truncated record (e.g., from an interrupted write)
+//! is ignored when the file is loaded.
+
+use anyhow::{Context, Result};
+use humpty::{DumpAreaHeader, DumpTask};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub(crate) struct DumpProgress {
+    path: PathBuf,
+    file: File,
+    areas: HashMap<u8, Vec<u8>>,
+}
+
+//
+// Generate the identifying line for a dump.  We use the task's timestamp
+// when we have one; for whole-system dumps (which lack a timestamp), we rely
+// on the location and size of each area being written.
+//
+fn progress_key(headers: &[DumpAreaHeader], task: Option<DumpTask>) -> String {
+    let mut key = match task {
+        Some(task) => {
+            let (id, time) = (task.id, task.time);
+            format!("task {id} time {time}")
+        }
+        None => "system".to_string(),
+    };
+
+    for header in headers {
+        let (address, written) = (header.address, header.written);
+        key += &format!(" {address:#x}:{written}");
+    }
+
+    key
+}
+
+fn parse_records(mut buf: &[u8]) -> HashMap<u8, Vec<u8>> {
+    let mut areas = HashMap::new();
+
+    while buf.len() >= 8 {
+        let index = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let len = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+
+        if buf.len() < 8 + len {
+            break;
+        }
+
+        if let Ok(index) = u8::try_from(index) {
+            areas.insert(index, buf[8..8 + len].to_vec());
+        }
+
+        buf = &buf[8 + len..];
+    }
+
+    areas
+}
+
+impl DumpProgress {
+    /// Opens the progress file at `path`, loading any areas that it records
+    /// for this dump.  A progress file that pertains to a different dump is
+    /// discarded.
+    pub fn open(
+        path: &Path,
+        headers: &[DumpAreaHeader],
+        task: Option<DumpTask>,
+    ) -> Result<Self> {
+        let key = progress_key(headers, task);
+        let mut areas = HashMap::new();
+
+        if let Ok(contents) = std::fs::read(path) {
+            match contents.iter().position(|&c| c == b'\n') {
+                Some(nl) if contents[..nl] == *key.as_bytes() => {
+                    areas = parse_records(&contents[nl + 1..]);
+                }
+                _ => {
+                    humility::msg!(
+                        "ignoring progress in {} from a different dump",
+                        path.display()
+                    );
+                }
+            }
+        }
+
+        //
+        // Rewrite the file from scratch with whatever we are keeping, which
+        // also serves to drop any truncated record.
+        //
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+
+        writeln!(file, "{key}")?;
+
+        let mut rval =
+            Self { path: path.to_path_buf(), file, areas: HashMap::new() };
+
+        let mut indices = areas.keys().copied().collect::<Vec<_>>();
+        indices.sort_unstable();
+
+        for index in indices {
+            rval.record(index, &areas[&index])?;
+        }
+
+        if !rval.areas.is_empty() {
+            humility::msg!(
+                "resuming with {} area{} already read",
+                rval.areas.len(),
+                if rval.areas.len() == 1 { "" } else { "s" }
+            );
+        }
+
+        Ok(rval)
+    }
+
+    /// Returns the previously read contents of the given area, if any
+    pub fn get(&self, index: u8) -> Option<&Vec<u8>> {
+        self.areas.get(&index)
+    }
+
+    /// Records the contents of an area that has been successfully read
+    pub fn record(&mut self, index: u8, data: &[u8]) -> Result<()> {
+        let mut buf = Vec::with_capacity(8 + data.len());
+        buf.extend((index as u32).to_le_bytes());
+        buf.extend((data.len() as u32).to_le_bytes());
+        buf.extend(data);
+
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+        self.areas.insert(index, data.to_vec());
+
+        Ok(())
+    }
+
+    /// Removes the progress file, once the dump has been entirely read
+    pub fn remove(self) -> Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path).with_context(|| {
+            format!("failed to remove {}", self.path.display())
+        })
+    }
+}
@@ -1,39 +1,78 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
-use crate::DumpAgent;
+use crate::{with_retries, DumpAgent, DEFAULT_READ_RETRIES};
 use anyhow::{anyhow, bail, Context, Result};
 use humility::core::{Core, NetAgent};
 use rand::Rng;
+use std::time::Duration;
+
+/// Default number of `ReadDump` requests to have outstanding at once
+const DEFAULT_INFLIGHT: usize = 4;
 
 pub struct UdpDumpAgent<'a> {
     core: &'a mut dyn Core,
+    inflight: usize,
+    retries: u32,
+    read_timeout: Option<Duration>,
 }
 
+type Reply = Result<humpty::udp::Response, humpty::udp::Error>;
+
 impl<'a> UdpDumpAgent<'a> {
     pub fn new(core: &'a mut dyn Core, image_id: &Vec<u8>) -> Result<Self> {
-        let mut udp_dump = Self { core };
+        let mut udp_dump = Self {
+            core,
+            inflight: DEFAULT_INFLIGHT,
+            retries: DEFAULT_READ_RETRIES,
+            read_timeout: None,
+        };
 
         udp_dump.check_imageid(image_id)?;
         Ok(udp_dump)
     }
 
-    /// Sends a remote dump command over the network
-    fn dump_remote_action(
-        &mut self,
-        msg: humpty::udp::Request,
-    ) -> Result<Result<humpty::udp::Response, humpty::udp::Error>> {
-        use humpty::udp::{version, Header, RequestMessage, ResponseMessage};
-        let mut rng = rand::thread_rng();
-        let header =
-            Header { version: version::CURRENT, message_id: rng.gen() };
-        let mut buf = vec![
+    /// Sets the number of reads to have outstanding at once when reading
+    /// dump areas.  Because reads are idempotent, we can pipeline them to
+    /// avoid paying a full network round-trip for each one.
+    pub fn set_inflight(&mut self, inflight: usize) {
+        self.inflight = inflight.max(1);
+    }
+
+    /// Sets the number of times to retry reads that fail in transport
+    pub fn set_read_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Sets the time to wait for each reply when reading dump areas,
+    /// independent of the timeout used for other operations
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = Some(timeout);
+    }
+
+    fn buf() -> Vec<u8> {
+        use humpty::udp::{RequestMessage, ResponseMessage};
+
+        vec![
             0u8;
             std::cmp::max(
                 std::mem::size_of::<RequestMessage>(),
                 std::mem::size_of::<ResponseMessage>()
             )
-        ];
+        ]
+    }
+
+    /// Sends a remote dump command over the network without waiting for a
+    /// reply, returning the header with which the reply can be correlated
+    fn send_request(
+        &mut self,
+        msg: humpty::udp::Request,
+    ) -> Result<humpty::udp::Header> {
+        use humpty::udp::{version, Header};
+        let mut rng = rand::thread_rng();
+        let header =
+            Header { version: version::CURRENT, message_id: rng.gen() };
+        let mut buf = Self::buf();
         let size = hubpack::serialize(&mut buf, &(header, msg))
             .context("failed to serialize message")?;
 
@@ -42,6 +81,14 @@ impl<'a> UdpDumpAgent<'a> {
             .send(&buf[..size], NetAgent::DumpAgent)
             .context("failed to send packet")?;
 
+        Ok(header)
+    }
+
+    /// Receives a single reply from the dump agent
+    fn recv_reply(&mut self) -> Result<(humpty::udp::Header, Reply)> {
+        use humpty::udp::{version, Header};
+        let mut buf = Self::buf();
+
         // Try to receive a reply
         let size = self
             .core
@@ -51,26 +98,24 @@ impl<'a> UdpDumpAgent<'a> {
         let (reply_header, rest): (Header, _) =
             hubpack::deserialize(&buf[..size])
                 .map_err(|_| anyhow!("deserialization of header failed"))?;
-        if reply_header.message_id != header.message_id {
-            bail!(
-                "message ID mismatch: {} != {}",
-                reply_header.message_id,
-                header.message_id
-            );
-        } else if reply_header.version < version::MIN {
+
+        if reply_header.version < version::MIN {
             bail!(
-                "received reply with invalid version: {} < our min ({})",
+                "dump agent protocol v{}, humility expects v{} through v{} \
+                 (dump agent is too old for this humility)",
                 reply_header.version,
-                version::MIN
+                version::MIN,
+                version::CURRENT
             );
         }
 
         let (reply, _) = hubpack::deserialize(rest).map_err(|_| {
             if reply_header.version > version::CURRENT {
                 anyhow!(
-                    "deserialization of body failed, \
-                        version {} > our version {}",
+                    "dump agent protocol v{}, humility expects v{} through \
+                     v{} (humility is too old for this dump agent)",
                     reply_header.version,
+                    version::MIN,
                     version::CURRENT
                 )
             } else {
@@ -80,6 +125,61 @@ impl<'a> UdpDumpAgent<'a> {
             }
         })?;
 
+        Ok((reply_header, reply))
+    }
+
+    /// Sends a `ReadDump` request for each of the given `(index, offset)`
+    /// tuples, and waits for all of their replies, which are returned in
+    /// request order
+    fn read_window(&mut self, window: &[(u8, u32)]) -> Result<Vec<Reply>> {
+        let mut pending = vec![];
+
+        for &(index, offset) in window {
+            let header = self.send_request(humpty::udp::Request::ReadDump {
+                index,
+                offset,
+            })?;
+
+            pending.push((header.message_id, None));
+        }
+
+        let mut outstanding = pending.len();
+
+        while outstanding > 0 {
+            let (header, reply) = self.recv_reply()?;
+
+            //
+            // A reply that we don't recognize is presumably a late reply to a
+            // request that we have given up on; drop it.
+            //
+            if let Some(p) = pending
+                .iter_mut()
+                .find(|p| p.0 == header.message_id && p.1.is_none())
+            {
+                p.1 = Some(reply);
+                outstanding -= 1;
+            }
+        }
+
+        Ok(pending.into_iter().map(|(_, r)| r.unwrap()).collect())
+    }
+
+    /// Sends a remote dump command over the network and waits for its reply
+    fn dump_remote_action(
+        &mut self,
+        msg: humpty::udp::Request,
+    ) -> Result<Reply> {
+        let header = self.send_request(msg)?;
+        let (reply_header, reply) = self.recv_reply()?;
+
+        if reply_header.message_id != header.message_id {
+            bail!(
+                "message ID mismatch: {} != {}",
+                reply_header.message_id,
+                header.message_id
+            );
+        }
+
         Ok(reply)
     }
 
@@ -127,35 +227,83 @@ impl<'a> UdpDumpAgent<'a> {
 
         Ok(())
     }
-}
 
-impl<'a> DumpAgent for UdpDumpAgent<'a> {
-    fn read_generic(
+    /// Reads dump areas from the target, a window of requests at a time
+    ///
+    /// To hide network latency, we send a window of requests before waiting
+    /// for any replies, correlating replies to requests by message ID.  The
+    /// replies are then processed in request order, so the caller sees the
+    /// same semantics as if the reads had been issued one at a time; any
+    /// requests that we sent beyond the point at which reading terminates
+    /// are harmlessly discarded.
+    ///
+    /// If sending requests or receiving replies fails (e.g., because a packet
+    /// was dropped), the window is retried.  Errors reported by the dump
+    /// agent itself are not retried.
+    fn read_areas(
         &mut self,
         areas: &mut dyn Iterator<Item = (u8, u32)>,
         cont: &mut dyn FnMut(u8, u32, &[u8]) -> Result<bool>,
     ) -> Result<Vec<Vec<u8>>> {
         let mut out = vec![];
-        for (index, offset) in areas {
-            let r =
-                self.dump_remote_action(humpty::udp::Request::ReadDump {
-                    index,
-                    offset,
-                })?;
-            match r {
-                Ok(humpty::udp::Response::ReadDump(d)) => {
-                    if !cont(index, offset, &d)? {
-                        break;
-                    } else {
+
+        loop {
+            let window = areas.take(self.inflight).collect::<Vec<(u8, u32)>>();
+
+            if window.is_empty() {
+                break;
+            }
+
+            let retries = self.retries;
+            let replies = with_retries(retries, "dump read", || {
+                self.read_window(&window)
+            })?;
+
+            for (&(index, offset), r) in window.iter().zip(replies) {
+                match r {
+                    Ok(humpty::udp::Response::ReadDump(d)) => {
+                        if !cont(index, offset, &d)? {
+                            return Ok(out);
+                        }
+
                         out.push(d.to_vec())
                     }
+                    Err(humpty::udp::Error::InvalidArea) => return Ok(out),
+                    r => bail!("invalid reply: {r:?}"),
                 }
-                Err(humpty::udp::Error::InvalidArea) => break,
-                _ => bail!("invalid reply: {r:?}"),
             }
         }
+
         Ok(out)
     }
+}
+
+impl<'a> DumpAgent for UdpDumpAgent<'a> {
+    /// Reads dump areas from the target
+    ///
+    /// If a read timeout has been set, it is used in place of the core's
+    /// timeout for the duration of the read, and the core's timeout is
+    /// restored afterwards.
+    fn read_generic(
+        &mut self,
+        areas: &mut dyn Iterator<Item = (u8, u32)>,
+        cont: &mut dyn FnMut(u8, u32, &[u8]) -> Result<bool>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let Some(read_timeout) = self.read_timeout else {
+            return self.read_areas(areas, cont);
+        };
+
+        let timeout = self.core.timeout()?;
+
+        self.core.set_timeout(read_timeout)?;
+        let rval = self.read_areas(areas, cont);
+
+        if let Some(timeout) = timeout {
+            self.core.set_timeout(timeout)?;
+        }
+
+        rval
+    }
 
     fn core(&mut self) -> &mut dyn Core {
         self.core
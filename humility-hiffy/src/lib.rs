@@ -380,6 +380,16 @@ impl<'a> HiffyContext<'a> {
         self.rstack.size
     }
 
+    /// Returns the timeout (in milliseconds) for HIF execution
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+
+    /// Sets the timeout (in milliseconds) for HIF execution
+    pub fn set_timeout(&mut self, timeout: u32) {
+        self.timeout = timeout;
+    }
+
     ///
     /// Convenience routine to indicate the size of a HIF snippet
     ///
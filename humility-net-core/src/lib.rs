@@ -259,6 +259,7 @@ impl NetCore {
                 Some(DumpArea::ByIndex(dump_index as usize)),
                 &mut agent_core,
                 false,
+                None,
             );
 
             // Pop the most recent dump, since we were just using it to read
@@ -313,6 +314,19 @@ impl Core for NetCore {
         Ok(())
     }
 
+    fn timeout(&self) -> Result<Option<Duration>> {
+        //
+        // Our sockets always have the same timeout, so either will do.
+        //
+        let socket =
+            self.dump_agent_socket.as_ref().or(self.udprpc_socket.as_ref());
+
+        match socket {
+            Some(s) => Ok(s.read_timeout()?),
+            None => Ok(None),
+        }
+    }
+
     fn send(&self, buf: &[u8], target: NetAgent) -> Result<usize> {
         match target {
             NetAgent::UdpRpc => {
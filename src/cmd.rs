@@ -39,6 +39,53 @@ pub fn init(
     (cmds, rval)
 }
 
+//
+// When an archive is specified along with a dump, we use it rather than the
+// archive in the dump -- but a dump read with the wrong archive yields
+// garbage, so we refuse to proceed (unless forced) if the archive doesn't
+// match the one with which the dump was taken.
+//
+fn check_dump_identity(
+    hubris: &HubrisArchive,
+    dump: &str,
+    force: bool,
+) -> Result<()> {
+    let Some(theirs) = dump_identity(dump)? else {
+        humility::warn!(
+            "{} does not record the identity of its archive; \
+            cannot check it against the specified archive",
+            dump
+        );
+        return Ok(());
+    };
+
+    let ours = hubris.identity();
+
+    if theirs == ours {
+        return Ok(());
+    }
+
+    if !force {
+        bail!(
+            "{} was taken with a different archive ({}) than the one \
+            specified ({}); use --force to use it anyway",
+            dump,
+            theirs,
+            ours
+        );
+    }
+
+    humility::warn!(
+        "{} was taken with a different archive ({}) than the one \
+        specified ({}); results will likely be incorrect",
+        dump,
+        theirs,
+        ours
+    );
+
+    Ok(())
+}
+
 pub fn subcommand(
     context: &mut ExecutionContext,
     commands: &HashMap<&'static str, Command>,
@@ -72,6 +119,16 @@ pub fn subcommand(
             hubris.load(archive, doneness).with_context(|| {
                 format!("failed to load archive \"{}\"", archive)
             })?;
+
+            if let Some(dump) = &context.cli.dump {
+                if doneness == HubrisArchiveDoneness::Cook {
+                    check_dump_identity(&hubris, dump, context.cli.force)?;
+                }
+
+                hubris.load_dump(dump, doneness).with_context(|| {
+                    format!("failed to load dump \"{}\"", dump)
+                })?;
+            }
         } else if let Some(dump) = &context.cli.dump {
             hubris
                 .load_dump(dump, doneness)